@@ -36,6 +36,15 @@ const ProductCreate = React.lazy(() => import("./pages/product/Create"));
 const CartIndex = React.lazy(() => import("./pages/cart/Index"));
 const DeliveryIndex = React.lazy(() => import("./pages/delivery/Index"));
 const DeliveryShow = React.lazy(() => import("./pages/delivery/Show"));
+const DeliveryEarnings = React.lazy(() => import("./pages/delivery/Earnings"));
+const WebhookIndex = React.lazy(() => import("./pages/webhook/Index"));
+const WalletIndex = React.lazy(() => import("./pages/wallet/Index"));
+const WalletTopUp = React.lazy(() => import("./pages/wallet/TopUp"));
+const WithdrawalIndex = React.lazy(() => import("./pages/withdrawal/Index"));
+const WithdrawalQueue = React.lazy(() => import("./pages/withdrawal/Queue"));
+const TransactionReport = React.lazy(
+  () => import("./pages/transaction/Report")
+);
 
 interface ProtectedRouteProps extends React.PropsWithChildren {
   login: boolean;
@@ -201,7 +210,7 @@ const router = createBrowserRouter([
   {
     path: "/courier/delivery",
     element: (
-      <ProtectedRoute login={true} role="Courier">
+      <ProtectedRoute login={true} role={["Courier", "Admin"]}>
         <DeliveryIndex />
       </ProtectedRoute>
     ),
@@ -212,6 +221,62 @@ const router = createBrowserRouter([
       },
     ],
   },
+  {
+    path: "/courier/earnings",
+    element: (
+      <ProtectedRoute login={true} role="Courier">
+        <DeliveryEarnings />
+      </ProtectedRoute>
+    ),
+  },
+  {
+    path: "/user/report",
+    element: (
+      <ProtectedRoute login={true} role={["Customer", "Admin"]}>
+        <TransactionReport />
+      </ProtectedRoute>
+    ),
+  },
+  {
+    path: "/user/wallet",
+    element: (
+      <ProtectedRoute login={true}>
+        <WalletIndex />
+      </ProtectedRoute>
+    ),
+  },
+  {
+    path: "/user/wallet/topup/:id",
+    element: (
+      <ProtectedRoute login={true}>
+        <WalletTopUp />
+      </ProtectedRoute>
+    ),
+  },
+  {
+    path: "/user/withdrawal",
+    element: (
+      <ProtectedRoute login={true} role={["Customer", "Admin"]}>
+        <WithdrawalIndex />
+      </ProtectedRoute>
+    ),
+  },
+  {
+    path: "/admin/withdrawal",
+    element: (
+      <ProtectedRoute login={true} role="Admin">
+        <WithdrawalQueue />
+      </ProtectedRoute>
+    ),
+  },
+  {
+    path: "/user/webhook",
+    element: (
+      <ProtectedRoute login={true} role={["Customer", "Admin"]}>
+        <WebhookIndex />
+      </ProtectedRoute>
+    ),
+  },
 ]);
 
 const queryClient = new QueryClient({
@@ -49,7 +49,16 @@ export default function ResponsiveAppBar() {
   };
 
   const pages = Object.entries({
-    Delivery: user?.user?.role == "Courier" ? "/courier/delivery" : null,
+    Delivery: ["Courier", "Admin"].includes(user?.user?.role ?? "")
+      ? "/courier/delivery"
+      : null,
+    Earnings: user?.user?.role == "Courier" ? "/courier/earnings" : null,
+    Wallet: user?.user ? "/user/wallet" : null,
+    Withdrawal: ["Customer", "Admin"].includes(user?.user?.role ?? "")
+      ? "/user/withdrawal"
+      : null,
+    "Withdrawal Queue":
+      user?.user?.role == "Admin" ? "/admin/withdrawal" : null,
     Customer: user?.user?.role == "Admin" ? "/admin/account/customer" : null,
     Courier: user?.user?.role == "Admin" ? "/admin/account/courier" : null,
     Products: ["Admin", "Customer"].includes(user?.user?.role || "")
@@ -61,9 +70,15 @@ export default function ResponsiveAppBar() {
     Sale: ["Customer", "Admin"].includes(user?.user?.role ?? "")
       ? "/user/transaction"
       : null,
+    Report: ["Customer", "Admin"].includes(user?.user?.role ?? "")
+      ? "/user/report"
+      : null,
     Cart: ["Customer", "Admin"].includes(user?.user?.role ?? "")
       ? "/user/cart"
       : null,
+    Webhook: ["Customer", "Admin"].includes(user?.user?.role ?? "")
+      ? "/user/webhook"
+      : null,
     // Accounts: user.user?.role == "Admin" ? "/admin/account" : null,
   }).filter(([_, it]) => it != null);
 
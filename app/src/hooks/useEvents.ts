@@ -0,0 +1,75 @@
+import React from "react";
+import { useAuth } from "./useAuth";
+
+/**
+ * Listen to the Server-Sent Events of `url`, calling `onEvent` with the name of every event.
+ * EventSource can't send the Authorization header, so the stream is read with fetch.
+ */
+export function useEvents(
+  url: string | null,
+  onEvent: (event: string) => void
+) {
+  const { token } = useAuth();
+
+  const onEventRef = React.useRef(onEvent);
+  onEventRef.current = onEvent;
+
+  React.useEffect(() => {
+    if (token == null || url == null) {
+      return;
+    }
+
+    const controller = new AbortController();
+
+    const listen = async () => {
+      const response = await fetch(url, {
+        headers: {
+          Accept: "text/event-stream",
+          Authorization: `Bearer ${token}`,
+        },
+        signal: controller.signal,
+      });
+
+      if (!response.ok || response.body == null) {
+        return;
+      }
+
+      const reader = response.body
+        .pipeThrough(new TextDecoderStream())
+        .getReader();
+      let buffer = "";
+
+      for (;;) {
+        const { value, done } = await reader.read();
+        if (done) {
+          return;
+        }
+
+        buffer += value;
+
+        const messages = buffer.split("\n\n");
+        buffer = messages.pop() || "";
+
+        for (const message of messages) {
+          const event = message
+            .split("\n")
+            .find((it) => it.startsWith("event:"))
+            ?.slice("event:".length)
+            .trim();
+
+          if (event) {
+            onEventRef.current(event);
+          }
+        }
+      }
+    };
+
+    listen().catch((e) => {
+      if (!controller.signal.aborted) {
+        console.log(e);
+      }
+    });
+
+    return () => controller.abort();
+  }, [url, token]);
+}
@@ -0,0 +1,25 @@
+export type LedgerReason =
+  | "Purchase"
+  | "Payout"
+  | "Refund"
+  | "Adjustment"
+  | "TopUp"
+  | "Withdrawal"
+  | "WithdrawalRejected";
+
+export interface LedgerEntry {
+  id: string;
+  transfer_id: string;
+  type: "Debit" | "Credit";
+  amount: string;
+  balance: string;
+  reason: LedgerReason;
+  transaction_id: string | null;
+  created_at: string;
+}
+
+export interface GetLedger {
+  balance: string;
+  entries: LedgerEntry[];
+  next: string | null;
+}
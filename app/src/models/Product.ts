@@ -6,6 +6,8 @@ export interface Product {
   description: string;
   price: string;
   stock: string;
+  weight: number;
+  category?: string;
   updated_at: string;
   deleted_at: string;
 }
@@ -15,6 +17,8 @@ export interface ProductForm {
   description: string;
   price: string;
   stock: string;
+  weight: number;
+  category?: string;
 }
 
 export interface GetProduct {
@@ -0,0 +1,26 @@
+export type ReportPeriod = "day" | "week" | "month";
+
+export interface ReportFigures {
+  revenue: string;
+  orders: number;
+  units: number;
+  average_order_value: string;
+}
+
+export interface ReportPeriodFigures extends ReportFigures {
+  start: string;
+}
+
+export interface ReportProductFigures extends ReportFigures {
+  product_id: string;
+  name: string;
+}
+
+export interface Report {
+  from: string;
+  to: string;
+  period: ReportPeriod;
+  total: ReportFigures;
+  periods: ReportPeriodFigures[];
+  products: ReportProductFigures[];
+}
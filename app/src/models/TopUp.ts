@@ -0,0 +1,15 @@
+export type TopUpStatus = "Pending" | "Paid" | "Failed";
+
+export interface TopUp {
+  id: string;
+  amount: string;
+  status: TopUpStatus;
+  provider: string;
+  redirect_url: string | null;
+  created_at: string;
+  updated_at: string;
+}
+
+export interface GetTopUp {
+  topups: TopUp[];
+}
@@ -3,14 +3,31 @@ export interface Transaction {
   user_id: string;
   merchant_id: string;
   price: string;
+  shipping_fee: string;
+  commission: string;
   courier_id?: string;
 
   status: TransactionStatus[];
 
-  products: {
-    id: string;
-    quantity: string;
-  }[];
+  products: TransactionProduct[];
+  address?: TransactionAddress;
+}
+
+export interface TransactionAddress {
+  id: string;
+  label: string;
+  address: string;
+  latitude: number;
+  longitude: number;
+}
+
+export interface TransactionProduct {
+  id: string;
+  quantity: string;
+  price: string;
+  name: string;
+  description: string;
+  category?: string;
 }
 
 export type TransactionStatusType =
@@ -18,22 +35,38 @@ export type TransactionStatusType =
   | { type: "ProcessingInMerchant" }
   | { type: "WaitingForCourier" }
   | { type: "PickedUpByCourier" }
-  | { type: "SendBackToMerchant" }
+  | { type: "SendBackToMerchant"; content: { reason: string } }
   | { type: "WaitingForMerchantWhenSendBack" }
   | { type: "ArrivedInMerchant" }
-  | { type: "ArrivedInDestination" };
+  | { type: "ArrivedInDestination" }
+  | { type: "ArrivedInDestinationConfirmed" }
+  | { type: "Cancelled" }
+  | { type: "RejectedByMerchant"; content: { reason: string } }
+  | { type: "Returned" }
+  | { type: "Expired" };
 
 export interface TransactionStatus {
   type: TransactionStatusType;
   date: string;
+  by?: string;
+  courier_id?: string;
+  proof?: DeliveryProof;
+}
+
+export interface DeliveryProof {
+  content_type: string;
+  recipient_name?: string;
+  note?: string;
 }
 
 export interface GetOrder {
   orders: Transaction[];
+  next_cursor?: string;
 }
 
 export interface GetTransaction {
   transactions: Transaction[];
+  next_cursor?: string;
 }
 
 export function statusToString(status?: TransactionStatus): string {
@@ -46,12 +79,20 @@ export function statusToString(status?: TransactionStatus): string {
     return "Picked Up By Courier";
   } else if (type == "ArrivedInDestination") {
     return "Arrived in Destination";
-  } else if (type == "SendBackToMerchant"){
-    return "Sending Back to Merchant";
+  } else if (status?.type?.type == "SendBackToMerchant") {
+    return `Sending Back to Merchant (${status.type.content.reason})`;
   } else if (type == "WaitingForMerchantWhenSendBack") {
     return "Waiting for Merchant";
   } else if (type == "ArrivedInMerchant") {
     return "Arrived In Merchant";
+  } else if (type == "WaitingForMerchantConfirmation") {
+    return "Waiting for Merchant Confirmation";
+  } else if (type == "RejectedByMerchant") {
+    return "Rejected by Merchant";
+  } else if (type == "Returned") {
+    return "Returned and Refunded";
+  } else if (type == "Expired") {
+    return "Expired and Refunded";
   } else {
     return type || "";
   }
@@ -63,10 +104,33 @@ export interface Delivery {
   merchant_id: string;
   courier_id?: string;
   price: string;
+  shipping_fee: string;
+  address?: TransactionAddress;
 
   status: TransactionStatus[];
 }
 
 export interface GetDelivery {
   deliveries: Delivery[];
+  next_cursor?: string;
+}
+
+export interface CourierEarning {
+  id: string;
+  transaction_id: string;
+  amount: string;
+  created_at: string;
+}
+
+export interface EarningPeriod {
+  start: string;
+  deliveries: number;
+  total: string;
+}
+
+export interface GetEarnings {
+  total: string;
+  daily: EarningPeriod[];
+  weekly: EarningPeriod[];
+  earnings: CourierEarning[];
 }
@@ -0,0 +1,39 @@
+export type WebhookEvent =
+  | "created"
+  | "confirmed"
+  | "picked_up"
+  | "delivered"
+  | "cancelled";
+
+export interface WebhookEndpoint {
+  id: string;
+  url: string;
+  created_at: string;
+  updated_at: string;
+}
+
+export interface CreatedWebhookEndpoint extends WebhookEndpoint {
+  secret: string;
+}
+
+export interface GetWebhookEndpoint {
+  endpoints: WebhookEndpoint[];
+}
+
+export interface WebhookDelivery {
+  id: string;
+  endpoint_id: string;
+  transaction_id: string;
+  event: WebhookEvent;
+  status: "Pending" | "Succeeded" | "Failed";
+  attempts: number;
+  next_attempt_at: string | null;
+  last_status_code: number | null;
+  last_error: string | null;
+  created_at: string;
+  updated_at: string;
+}
+
+export interface GetWebhookDelivery {
+  deliveries: WebhookDelivery[];
+}
@@ -0,0 +1,30 @@
+export type WithdrawalStatusType =
+  | { type: "Pending" }
+  | { type: "Approved" }
+  | { type: "Rejected"; content: { reason: string } };
+
+export interface WithdrawalStatus {
+  type: WithdrawalStatusType;
+  date: string;
+  by: string;
+}
+
+export interface BankAccount {
+  bank_name: string;
+  account_number: string;
+  account_name: string;
+}
+
+export interface Withdrawal {
+  id: string;
+  user_id: string;
+  amount: string;
+  bank: BankAccount;
+  status: WithdrawalStatus[];
+  created_at: string;
+  updated_at: string;
+}
+
+export interface GetWithdrawal {
+  withdrawals: Withdrawal[];
+}
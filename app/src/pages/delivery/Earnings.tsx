@@ -0,0 +1,76 @@
+import Card from "@mui/material/Card";
+import CardContent from "@mui/material/CardContent";
+import CircularProgress from "@mui/material/CircularProgress";
+import Grid from "@mui/material/Grid";
+import Typography from "@mui/material/Typography";
+import AppBar from "../../AppBar";
+import { useAuthSWR } from "../../hooks/useSWR";
+import { EarningPeriod, GetEarnings } from "../../models/Transaction";
+
+export default function Earnings() {
+  const { data, isLoading } = useAuthSWR<GetEarnings>(
+    "/api/v1/delivery/earnings"
+  );
+
+  if (isLoading) {
+    return <CircularProgress />;
+  }
+
+  return (
+    <div className="App">
+      <AppBar />
+
+      <Typography variant="h5" sx={{ m: 2 }}>
+        Total: Rp. {data?.total}
+      </Typography>
+
+      <Grid container>
+        <Grid item xs>
+          <Typography variant="h6">Harian</Typography>
+          {data?.daily.map((it) => (
+            <PeriodCard key={it.start} period={it} />
+          ))}
+        </Grid>
+        <Grid item xs>
+          <Typography variant="h6">Mingguan</Typography>
+          {data?.weekly.map((it) => (
+            <PeriodCard key={it.start} period={it} />
+          ))}
+        </Grid>
+        <Grid item xs>
+          <Typography variant="h6">Pengiriman</Typography>
+          {data?.earnings.map((it) => (
+            <Card key={it.id} sx={{ m: 1 }}>
+              <CardContent>
+                <Typography variant="body2" color="text.secondary">
+                  INV: {it.transaction_id}
+                </Typography>
+                <Typography variant="body2" fontSize={12}>
+                  Rp. {it.amount}
+                </Typography>
+                <Typography variant="body2" fontSize={12}>
+                  {it.created_at}
+                </Typography>
+              </CardContent>
+            </Card>
+          ))}
+        </Grid>
+      </Grid>
+    </div>
+  );
+}
+
+function PeriodCard({ period }: { period: EarningPeriod }) {
+  return (
+    <Card sx={{ m: 1 }}>
+      <CardContent>
+        <Typography variant="body2" color="text.secondary">
+          {period.start}
+        </Typography>
+        <Typography variant="body2" fontSize={12}>
+          {period.deliveries} pengiriman, Rp. {period.total}
+        </Typography>
+      </CardContent>
+    </Card>
+  );
+}
@@ -8,6 +8,8 @@ import Typography from "@mui/material/Typography";
 import { Outlet, useNavigate } from "react-router-dom";
 import AppBar from "../../AppBar";
 import { useAuthSWR } from "../../hooks/useSWR";
+import { useEvents } from "../../hooks/useEvents";
+import { useQueryClient } from "@tanstack/react-query";
 import {
   Delivery,
   GetDelivery,
@@ -21,6 +23,15 @@ import { Product } from "../../models/Product";
 export default function Index() {
   const { data, isLoading } = useAuthSWR<GetDelivery>("/api/v1/delivery");
 
+  // refresh the list and the opened delivery whenever one of them changes.
+  const queryClient = useQueryClient();
+  useEvents("/api/v1/delivery/events", () =>
+    queryClient.invalidateQueries({
+      predicate: (query) =>
+        String(query.queryKey[0]).startsWith("/api/v1/delivery"),
+    })
+  );
+
   if (isLoading) {
     return <CircularProgress />;
   }
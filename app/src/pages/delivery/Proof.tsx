@@ -0,0 +1,51 @@
+import Box from "@mui/material/Box";
+import Button from "@mui/material/Button";
+import Typography from "@mui/material/Typography";
+import axios from "axios";
+import { useAuth } from "../../hooks/useAuth";
+import { TransactionStatus } from "../../models/Transaction";
+import { handleError } from "@/utils/error-handler";
+
+interface DeliveryProofProps {
+  id: string;
+  status: TransactionStatus[];
+}
+
+export default function DeliveryProof({ id, status }: DeliveryProofProps) {
+  const { token } = useAuth();
+  const proof = status.findLast((it) => it.proof != null)?.proof;
+
+  if (proof == null) {
+    return null;
+  }
+
+  const onShowPhoto = async () => {
+    const response = await axios.get(`/api/v1/delivery/${id}/proof`, {
+      responseType: "blob",
+      headers: {
+        Authorization: `Bearer ${token}`,
+      },
+    });
+
+    window.open(URL.createObjectURL(response.data), "_blank");
+  };
+
+  return (
+    <Box>
+      <Typography variant="h5">Bukti Pengiriman:</Typography>
+      {proof.recipient_name && (
+        <Typography variant="body2" fontSize={12}>
+          Penerima: {proof.recipient_name}
+        </Typography>
+      )}
+      {proof.note && (
+        <Typography variant="body2" fontSize={12}>
+          Catatan: {proof.note}
+        </Typography>
+      )}
+      <Button size="small" onClick={handleError(onShowPhoto)}>
+        Lihat Foto
+      </Button>
+    </Box>
+  );
+}
@@ -17,6 +17,7 @@ import { useNavigate, useParams } from "react-router-dom";
 import useSWR from "swr";
 import { dateToString } from "../../helper";
 import { useAuth } from "../../hooks/useAuth";
+import { useUser } from "../../hooks/useUser";
 import { useAuthSWR, useMutateAuth } from "../../hooks/useSWR";
 import { Product } from "../../models/Product";
 import { statusToString, Transaction } from "../../models/Transaction";
@@ -45,6 +46,8 @@ export default function ShowProduct() {
   });
 
   const { token } = useAuth();
+  const user = useUser();
+  const isAdmin = user?.user?.role == "Admin";
 
   const reversedStatus = useMemo(
     () => order?.status?.map((it) => it).reverse(),
@@ -82,13 +85,38 @@ export default function ShowProduct() {
           },
         }
       );
+    } else if (e.type == "ArrivedInDestination") {
+      const photo = await pickPhoto();
+      if (!photo) {
+        return;
+      }
+
+      const form = new FormData();
+      form.append("photo", photo);
+      form.append("recipient_name", window.prompt("Nama penerima") ?? "");
+      form.append("note", window.prompt("Catatan") ?? "");
+
+      await axios.post(`/api/v1/delivery/${id}/arrive`, form, {
+        headers: {
+          Authorization: `Bearer ${token}`,
+        },
+      });
     } else {
+      let content = null;
+      if (e.type == "SendBackToMerchant") {
+        const reason = window.prompt("Alasan pengembalian barang");
+        if (!reason) {
+          return;
+        }
+        content = { reason };
+      }
+
       await axios.post(
         `/api/v1/delivery/${id}/change`,
         {
           type: {
             type: e.type,
-            content: null,
+            content,
           },
         },
         {
@@ -106,6 +134,28 @@ export default function ShowProduct() {
     }
   };
 
+  const onChangeCourier = async (
+    action: "assign" | "reassign" | "unassign"
+  ): Promise<void> => {
+    let body = {};
+    if (action != "unassign") {
+      const courier_id = window.prompt("ID kurir");
+      if (!courier_id) {
+        return;
+      }
+      body = { courier_id };
+    }
+
+    await axios.post(`/api/v1/delivery/${id}/${action}`, body, {
+      headers: {
+        Authorization: `Bearer ${token}`,
+      },
+    });
+
+    queryClient.invalidateQueries(["/api/v1/delivery"]);
+    queryClient.invalidateQueries([`/api/v1/delivery/${id}`]);
+  };
+
   if (isLoading && order == null) {
     return <CircularProgress />;
   }
@@ -129,9 +179,18 @@ export default function ShowProduct() {
             {/* <Typography variant="body2" fontSize={12}> */}
             {/*   Harga: Rp. {order.price} */}
             {/* </Typography> */}
+            <Typography variant="body2" fontSize={12}>
+              Ongkir: Rp. {order.shipping_fee}
+            </Typography>
             <Typography variant="body2" fontSize={12}>
               Status: {statusToString(order.status.at(-1))}
             </Typography>
+            {order.address && (
+              <Typography variant="body2" fontSize={12}>
+                Alamat: {order.address.address} ({order.address.latitude},{" "}
+                {order.address.longitude})
+              </Typography>
+            )}
 
             {/* <Typography variant="h5">Barang:</Typography> */}
             {/* <Stack> */}
@@ -150,23 +209,63 @@ export default function ShowProduct() {
                 return (
                   <Box key={it.date}>
                     {statusToString(it)} {dateToString(it.date)}
+                    {it.courier_id && ` (kurir: ${it.courier_id})`}
                   </Box>
                 );
               })}
             </Stack>
           </CardContent>
 
-          <Selector
-            value={current_type}
-            pickUpForm={pickUpForm}
-            onChangeType={onChangeType}
-          />
+          {isAdmin ? (
+            <CardActions>
+              {order.courier_id == null ? (
+                <Button
+                  size="small"
+                  onClick={handleError(() => onChangeCourier("assign"))}
+                >
+                  Assign Courier
+                </Button>
+              ) : (
+                <>
+                  <Button
+                    size="small"
+                    onClick={handleError(() => onChangeCourier("reassign"))}
+                  >
+                    Reassign Courier
+                  </Button>
+                  <Button
+                    size="small"
+                    onClick={handleError(() => onChangeCourier("unassign"))}
+                  >
+                    Unassign Courier
+                  </Button>
+                </>
+              )}
+            </CardActions>
+          ) : (
+            <Selector
+              value={current_type}
+              pickUpForm={pickUpForm}
+              onChangeType={onChangeType}
+            />
+          )}
         </>
       ) : null}
     </Card>
   );
 }
 
+/** Ask the courier for a photo of the delivered parcel. */
+function pickPhoto(): Promise<File | undefined> {
+  return new Promise((resolve) => {
+    const input = document.createElement("input");
+    input.type = "file";
+    input.accept = "image/jpeg,image/png,image/webp";
+    input.onchange = () => resolve(input.files?.[0]);
+    input.click();
+  });
+}
+
 interface SelectorProps {
   value: string;
   pickUpForm: UseFormReturn<any>;
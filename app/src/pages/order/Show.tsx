@@ -2,21 +2,28 @@ import Card from "@mui/material/Card";
 import CardContent from "@mui/material/CardContent";
 import CircularProgress from "@mui/material/CircularProgress";
 import Typography from "@mui/material/Typography";
-import axios from "axios";
 import React from "react";
 import { useParams } from "react-router-dom";
-import useSWR from "swr";
 import { useAuthSWR } from "../../hooks/useSWR";
-import { Product } from "../../models/Product";
-import { statusToString, Transaction } from "../../models/Transaction";
+import { useEvents } from "../../hooks/useEvents";
+import {
+  statusToString,
+  Transaction,
+  TransactionProduct,
+} from "../../models/Transaction";
 import { User } from "../../models/User";
+import DeliveryProof from "../delivery/Proof";
+import Documents from "../transaction/Documents";
 
 export default function ShowProduct() {
   const { id } = useParams();
 
-  const { data: order, isLoading } = useAuthSWR<Transaction>(
-    `/api/v1/order/${id}`
-  );
+  const {
+    data: order,
+    isLoading,
+    mutate,
+  } = useAuthSWR<Transaction>(`/api/v1/order/${id}`);
+  useEvents(`/api/v1/order/${id}/events`, () => mutate());
   const { data: merchant } = useAuthSWR<User>(
     order ? `/api/v1/account/${order.merchant_id}` : null
   );
@@ -39,10 +46,16 @@ export default function ShowProduct() {
             <Typography variant="body2" fontSize={12}>
               Harga: Rp. {order.price}
             </Typography>
+            <Typography variant="body2" fontSize={12}>
+              Ongkir: Rp. {order.shipping_fee}
+            </Typography>
             <Typography variant="body2" fontSize={12}>
               Status: {statusToString(order.status.at(-1))}
             </Typography>
 
+            <DeliveryProof id={order.id} status={order.status} />
+            <Documents id={order.id} />
+
             <Typography variant="h5">Barang:</Typography>
             {order.products.map((it) => {
               return (
@@ -61,20 +74,11 @@ export default function ShowProduct() {
 function ProductCard({
   product: orderProduct,
 }: {
-  product: { id: string; quantity: string };
+  product: TransactionProduct;
 }) {
-  const { data: product, isLoading } = useSWR<{ data: Product }>(
-    `/api/v1/product/${orderProduct.id}`,
-    (url) => axios.get(url)
-  );
-
-  if (isLoading) {
-    return <CircularProgress />;
-  }
-
   return (
     <div>
-      {orderProduct.quantity} x {product?.data?.name}
+      {orderProduct.quantity} x {orderProduct.name} @ Rp. {orderProduct.price}
     </div>
   );
 }
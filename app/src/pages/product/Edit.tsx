@@ -29,6 +29,8 @@ export default function EditProduct() {
       description: product?.description,
       price: product?.price,
       stock: product?.stock,
+      weight: product?.weight,
+      category: product?.category,
     },
   });
   React.useEffect(() => form.reset(product), [isLoading]);
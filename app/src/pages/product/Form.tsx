@@ -57,6 +57,26 @@ export default function Form({
         error={formState.errors.stock != null}
         helperText={formState.errors.stock?.message}
       />
+      <TextField
+        {...register("weight", {
+          min: { value: 0, message: "Berat harus lebih atau sama dengan 0" },
+          valueAsNumber: true,
+        })}
+        type="number"
+        label="Berat (gram)"
+        defaultValue={formState.defaultValues?.weight}
+        sx={{ m: 2 }}
+        error={formState.errors.weight != null}
+        helperText={formState.errors.weight?.message}
+      />
+      <TextField
+        {...register("category", {
+          setValueAs: (it) => (it === "" ? undefined : it),
+        })}
+        label="Kategori"
+        defaultValue={formState.defaultValues?.category}
+        sx={{ m: 2 }}
+      />
 
       <Button onClick={handleSubmit(handleError(onClick))} disabled={formState.isSubmitting}>
         Submit
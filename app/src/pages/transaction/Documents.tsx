@@ -0,0 +1,111 @@
+import Box from "@mui/material/Box";
+import Button from "@mui/material/Button";
+import Stack from "@mui/material/Stack";
+import TextField from "@mui/material/TextField";
+import axios from "axios";
+import React from "react";
+import { useAuth } from "../../hooks/useAuth";
+import { handleError } from "@/utils/error-handler";
+
+interface DocumentsProps {
+  id: string;
+  packingSlip?: boolean;
+}
+
+export default function Documents({ id, packingSlip }: DocumentsProps) {
+  const { token } = useAuth();
+
+  const open = async (url: string, format: "html" | "pdf") => {
+    const response = await axios.get(url, {
+      params: { format },
+      responseType: "blob",
+      headers: {
+        Authorization: `Bearer ${token}`,
+      },
+    });
+
+    window.open(URL.createObjectURL(response.data), "_blank");
+  };
+
+  return (
+    <Box>
+      <Button
+        size="small"
+        onClick={handleError(() => open(`/api/v1/order/${id}/invoice`, "html"))}
+      >
+        Invoice
+      </Button>
+      <Button
+        size="small"
+        onClick={handleError(() => open(`/api/v1/order/${id}/invoice`, "pdf"))}
+      >
+        Invoice (PDF)
+      </Button>
+      {packingSlip && (
+        <Button
+          size="small"
+          onClick={handleError(() =>
+            open(`/api/v1/transaction/${id}/packing-slip`, "pdf")
+          )}
+        >
+          Packing Slip
+        </Button>
+      )}
+    </Box>
+  );
+}
+
+export function InvoiceDownload() {
+  const { token } = useAuth();
+  const [from, setFrom] = React.useState("");
+  const [to, setTo] = React.useState("");
+
+  const onDownload = async () => {
+    const response = await axios.get("/api/v1/transaction/invoices", {
+      params: {
+        from: new Date(from).toISOString(),
+        // include the whole last day.
+        to: new Date(
+          new Date(to).getTime() + 24 * 60 * 60 * 1000
+        ).toISOString(),
+      },
+      responseType: "blob",
+      headers: {
+        Authorization: `Bearer ${token}`,
+      },
+    });
+
+    const link = document.createElement("a");
+    link.href = URL.createObjectURL(response.data);
+    link.download = "invoices.zip";
+    link.click();
+  };
+
+  return (
+    <Stack direction="row" spacing={1} padding={1} alignItems="center">
+      <TextField
+        size="small"
+        type="date"
+        label="Dari"
+        InputLabelProps={{ shrink: true }}
+        value={from}
+        onChange={(e) => setFrom(e.target.value)}
+      />
+      <TextField
+        size="small"
+        type="date"
+        label="Sampai"
+        InputLabelProps={{ shrink: true }}
+        value={to}
+        onChange={(e) => setTo(e.target.value)}
+      />
+      <Button
+        size="small"
+        disabled={from == "" || to == ""}
+        onClick={handleError(onDownload)}
+      >
+        Download Invoice (ZIP)
+      </Button>
+    </Stack>
+  );
+}
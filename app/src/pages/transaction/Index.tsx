@@ -22,6 +22,7 @@ import ListItemButton from "@mui/material/ListItemButton";
 import ListItemText from "@mui/material/ListItemText";
 import Collapse from "@mui/material/Collapse";
 import List from "@mui/material/List";
+import { InvoiceDownload } from "./Documents";
 
 export default function Index() {
   const { data, isLoading } = useAuthSWR<GetTransaction>("/api/v1/transaction");
@@ -73,6 +74,7 @@ export default function Index() {
 
       <Grid container>
         <Grid item xs>
+          <InvoiceDownload />
           <List>
             {group.map((it) => (
               <GroupList key={it.name} name={it.name} child={it.child ?? null} />
@@ -0,0 +1,173 @@
+import Button from "@mui/material/Button";
+import CircularProgress from "@mui/material/CircularProgress";
+import Grid from "@mui/material/Grid";
+import MenuItem from "@mui/material/MenuItem";
+import Stack from "@mui/material/Stack";
+import Table from "@mui/material/Table";
+import TableBody from "@mui/material/TableBody";
+import TableCell from "@mui/material/TableCell";
+import TableHead from "@mui/material/TableHead";
+import TableRow from "@mui/material/TableRow";
+import TextField from "@mui/material/TextField";
+import Typography from "@mui/material/Typography";
+import axios from "axios";
+import React from "react";
+import AppBar from "../../AppBar";
+import { useAuth } from "../../hooks/useAuth";
+import { useAuthSWR } from "../../hooks/useSWR";
+import {
+  Report as ReportModel,
+  ReportFigures,
+  ReportPeriod,
+} from "../../models/Report";
+import { handleError } from "@/utils/error-handler";
+
+function toDate(date: Date) {
+  return date.toISOString().slice(0, 10);
+}
+
+export default function Report() {
+  const { token } = useAuth();
+  const [period, setPeriod] = React.useState<ReportPeriod>("day");
+  const [from, setFrom] = React.useState(() =>
+    toDate(new Date(Date.now() - 30 * 24 * 60 * 60 * 1000))
+  );
+  const [to, setTo] = React.useState(() => toDate(new Date()));
+
+  const params = new URLSearchParams({
+    from: new Date(from).toISOString(),
+    // include the whole last day.
+    to: new Date(
+      new Date(to).getTime() + 24 * 60 * 60 * 1000
+    ).toISOString(),
+    period,
+  });
+
+  const { data, isLoading } = useAuthSWR<ReportModel>(
+    `/api/v1/transaction/reports?${params}`
+  );
+
+  const onExport = async () => {
+    params.set("format", "csv");
+
+    const response = await axios.get(`/api/v1/transaction/reports?${params}`, {
+      responseType: "blob",
+      headers: {
+        Authorization: `Bearer ${token}`,
+      },
+    });
+
+    const link = document.createElement("a");
+    link.href = URL.createObjectURL(response.data);
+    link.download = `report-${from}-${to}.csv`;
+    link.click();
+  };
+
+  return (
+    <div className="App">
+      <AppBar />
+
+      <Stack direction="row" spacing={1} padding={2} alignItems="center">
+        <TextField
+          size="small"
+          type="date"
+          label="Dari"
+          InputLabelProps={{ shrink: true }}
+          value={from}
+          onChange={(e) => setFrom(e.target.value)}
+        />
+        <TextField
+          size="small"
+          type="date"
+          label="Sampai"
+          InputLabelProps={{ shrink: true }}
+          value={to}
+          onChange={(e) => setTo(e.target.value)}
+        />
+        <TextField
+          size="small"
+          select
+          label="Periode"
+          value={period}
+          onChange={(e) => setPeriod(e.target.value as ReportPeriod)}
+        >
+          <MenuItem value="day">Harian</MenuItem>
+          <MenuItem value="week">Mingguan</MenuItem>
+          <MenuItem value="month">Bulanan</MenuItem>
+        </TextField>
+        <Button size="small" onClick={handleError(onExport)}>
+          Export CSV
+        </Button>
+      </Stack>
+
+      {isLoading || data == null ? (
+        <CircularProgress />
+      ) : (
+        <>
+          <Typography variant="h6" sx={{ mx: 2 }}>
+            Total: Rp. {data.total.revenue}, {data.total.orders} pesanan,{" "}
+            {data.total.units} barang, rata-rata Rp.{" "}
+            {data.total.average_order_value}
+          </Typography>
+
+          <Grid container>
+            <Grid item xs>
+              <FiguresTable
+                label="Periode"
+                rows={data.periods.map((it) => ({
+                  key: it.start,
+                  name: it.start.slice(0, 10),
+                  figures: it,
+                }))}
+              />
+            </Grid>
+            <Grid item xs>
+              <FiguresTable
+                label="Barang"
+                rows={data.products.map((it) => ({
+                  key: it.product_id,
+                  name: it.name,
+                  figures: it,
+                }))}
+              />
+            </Grid>
+          </Grid>
+        </>
+      )}
+    </div>
+  );
+}
+
+interface FiguresTableProps {
+  label: string;
+  rows: { key: string; name: string; figures: ReportFigures }[];
+}
+
+function FiguresTable({ label, rows }: FiguresTableProps) {
+  return (
+    <Table size="small" sx={{ m: 1 }}>
+      <TableHead>
+        <TableRow>
+          <TableCell>{label}</TableCell>
+          <TableCell align="right">Pesanan</TableCell>
+          <TableCell align="right">Barang</TableCell>
+          <TableCell align="right">Pendapatan</TableCell>
+          <TableCell align="right">Rata-rata</TableCell>
+        </TableRow>
+      </TableHead>
+      <TableBody>
+        {rows.map((it) => (
+          <TableRow key={it.key}>
+            <TableCell>{it.name}</TableCell>
+            <TableCell align="right">{it.figures.orders}</TableCell>
+            <TableCell align="right">{it.figures.units}</TableCell>
+            <TableCell align="right">Rp. {it.figures.revenue}</TableCell>
+            <TableCell align="right">
+              Rp. {it.figures.average_order_value}
+            </TableCell>
+          </TableRow>
+        ))}
+      </TableBody>
+    </Table>
+  );
+}
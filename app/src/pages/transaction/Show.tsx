@@ -12,13 +12,17 @@ import axios from "axios";
 import React, { useMemo } from "react";
 import { useForm } from "react-hook-form";
 import { useParams } from "react-router-dom";
-import useSWR, { mutate } from "swr";
 import { dateToString } from "../../helper";
 import { useAuth } from "../../hooks/useAuth";
 import { useAuthSWR, useMutateAuth } from "../../hooks/useSWR";
-import { Product } from "../../models/Product";
-import { statusToString, Transaction } from "../../models/Transaction";
+import {
+  statusToString,
+  Transaction,
+  TransactionProduct,
+} from "../../models/Transaction";
 import { User } from "../../models/User";
+import DeliveryProof from "../delivery/Proof";
+import Documents from "./Documents";
 
 export default function ShowProduct() {
   const { id } = useParams();
@@ -47,6 +51,41 @@ export default function ShowProduct() {
     return <CircularProgress />;
   }
 
+  const onAccept = async (): Promise<void> => {
+      await axios.post(
+        `/api/v1/transaction/${id}/accept`,
+        {},
+        {
+          headers: {
+            Authorization: `Bearer ${token}`,
+          },
+        }
+      );
+
+      mutateNow();
+      mutateAuth("/api/v1/transaction");
+  };
+
+  const onReject = async (): Promise<void> => {
+      const reason = window.prompt("Alasan menolak pesanan");
+      if (!reason) {
+        return;
+      }
+
+      await axios.post(
+        `/api/v1/transaction/${id}/reject`,
+        { reason },
+        {
+          headers: {
+            Authorization: `Bearer ${token}`,
+          },
+        }
+      );
+
+      mutateNow();
+      mutateAuth("/api/v1/transaction");
+  };
+
   const onConfirm = async (): Promise<void> => {
       await axios.post(
         `/api/v1/transaction/${id}/confirm`,
@@ -62,6 +101,36 @@ export default function ShowProduct() {
       mutateAuth("/api/v1/transaction");
   };
 
+  const onReship = async (): Promise<void> => {
+      await axios.post(
+        `/api/v1/transaction/${id}/reship`,
+        {},
+        {
+          headers: {
+            Authorization: `Bearer ${token}`,
+          },
+        }
+      );
+
+      mutateNow();
+      mutateAuth("/api/v1/transaction");
+  };
+
+  const onRefund = async (): Promise<void> => {
+      await axios.post(
+        `/api/v1/transaction/${id}/refund`,
+        {},
+        {
+          headers: {
+            Authorization: `Bearer ${token}`,
+          },
+        }
+      );
+
+      mutateNow();
+      mutateAuth("/api/v1/transaction");
+  };
+
   return (
     <Card sx={{ m: 2 }}>
       {order ? (
@@ -76,6 +145,12 @@ export default function ShowProduct() {
             <Typography variant="body2" fontSize={12}>
               Harga: Rp. {order.price}
             </Typography>
+            <Typography variant="body2" fontSize={12}>
+              Ongkir: Rp. {order.shipping_fee}
+            </Typography>
+            <Typography variant="body2" fontSize={12}>
+              Komisi: Rp. {order.commission}
+            </Typography>
             <Typography variant="body2" fontSize={12}>
               Status: {statusToString(order.status.at(-1))}
             </Typography>
@@ -101,8 +176,32 @@ export default function ShowProduct() {
                 );
               })}
             </Stack>
+
+            <DeliveryProof id={order.id} status={order.status} />
+            <Documents id={order.id} packingSlip />
           </CardContent>
 
+          {order?.status.at(-1)?.type?.type == "WaitingForMerchantConfirmation" && (
+            <CardActions>
+              <Button
+                size="small"
+                color="primary"
+                onClick={confirmForm.handleSubmit(handleError(onAccept))}
+                disabled={confirmForm.formState.isSubmitting}
+              >
+                Accept
+              </Button>
+              <Button
+                size="small"
+                color="error"
+                onClick={confirmForm.handleSubmit(handleError(onReject))}
+                disabled={confirmForm.formState.isSubmitting}
+              >
+                Reject
+              </Button>
+            </CardActions>
+          )}
+
           {order?.status.at(-1)?.type?.type == "ProcessingInMerchant" && (
             <CardActions>
               <Button
@@ -115,6 +214,27 @@ export default function ShowProduct() {
               </Button>
             </CardActions>
           )}
+
+          {order?.status.at(-1)?.type?.type == "ArrivedInMerchant" && (
+            <CardActions>
+              <Button
+                size="small"
+                color="primary"
+                onClick={confirmForm.handleSubmit(handleError(onReship))}
+                disabled={confirmForm.formState.isSubmitting}
+              >
+                Re-ship
+              </Button>
+              <Button
+                size="small"
+                color="error"
+                onClick={confirmForm.handleSubmit(handleError(onRefund))}
+                disabled={confirmForm.formState.isSubmitting}
+              >
+                Refund
+              </Button>
+            </CardActions>
+          )}
         </>
       ) : null}
     </Card>
@@ -124,20 +244,11 @@ export default function ShowProduct() {
 function ProductCard({
   product: orderProduct,
 }: {
-  product: { id: string; quantity: string };
+  product: TransactionProduct;
 }) {
-  const { data: product, isLoading } = useSWR<{ data: Product }>(
-    `/api/v1/product/${orderProduct.id}`,
-    (url) => axios.get(url)
-  );
-
-  if (isLoading) {
-    return <CircularProgress />;
-  }
-
   return (
     <div>
-      {orderProduct.quantity} x {product?.data?.name}
+      {orderProduct.quantity} x {orderProduct.name} @ Rp. {orderProduct.price}
     </div>
   );
 }
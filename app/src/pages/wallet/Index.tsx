@@ -0,0 +1,115 @@
+import Button from "@mui/material/Button";
+import Card from "@mui/material/Card";
+import CardContent from "@mui/material/CardContent";
+import CircularProgress from "@mui/material/CircularProgress";
+import Typography from "@mui/material/Typography";
+import axios from "axios";
+import React from "react";
+import { useNavigate } from "react-router-dom";
+import AppBar from "../../AppBar";
+import { useAuth } from "../../hooks/useAuth";
+import { useAuthSWR } from "../../hooks/useSWR";
+import { useUser } from "../../hooks/useUser";
+import { GetLedger, LedgerReason } from "../../models/Ledger";
+import { TopUp } from "../../models/TopUp";
+import { handleError } from "@/utils/error-handler";
+
+const reasons: Record<LedgerReason, string> = {
+  Purchase: "Pembelian",
+  Payout: "Pembayaran",
+  Refund: "Pengembalian dana",
+  Adjustment: "Penyesuaian",
+  TopUp: "Top up",
+  Withdrawal: "Penarikan",
+  WithdrawalRejected: "Penarikan ditolak",
+};
+
+export default function Index() {
+  const [before, setBefore] = React.useState<string[]>([]);
+  const cursor = before.at(-1);
+
+  const { data, isLoading } = useAuthSWR<GetLedger>(
+    cursor == null
+      ? "/api/v1/account/me/ledger"
+      : `/api/v1/account/me/ledger?before=${cursor}`
+  );
+
+  const { token } = useAuth();
+  const user = useUser();
+  const navigate = useNavigate();
+
+  const onTopUp = handleError(async () => {
+    const amount = window.prompt("Jumlah top up");
+    if (!amount) {
+      return;
+    }
+
+    const response = await axios.post<TopUp>(
+      "/api/v1/wallet/topup",
+      { amount },
+      {
+        headers: {
+          Authorization: `Bearer ${token}`,
+        },
+      }
+    );
+
+    if (response.data.redirect_url) {
+      navigate(response.data.redirect_url);
+    }
+  });
+
+  if (isLoading) {
+    return <CircularProgress />;
+  }
+
+  return (
+    <div className="App">
+      <AppBar />
+
+      <Typography variant="h5" sx={{ m: 2 }}>
+        Saldo: Rp. {data?.balance}
+      </Typography>
+
+      {user?.user?.role != "Courier" && (
+        <Button sx={{ mx: 2 }} variant="contained" onClick={onTopUp}>
+          Top Up
+        </Button>
+      )}
+
+      {data?.entries.map((it) => (
+        <Card key={it.id} sx={{ m: 1 }}>
+          <CardContent>
+            <Typography variant="body2" color="text.secondary">
+              {reasons[it.reason]}
+              {it.transaction_id && ` - INV: ${it.transaction_id}`}
+            </Typography>
+            <Typography
+              variant="body1"
+              color={it.type == "Credit" ? "success.main" : "error.main"}
+            >
+              {it.type == "Credit" ? "+" : "-"} Rp. {it.amount}
+            </Typography>
+            <Typography variant="body2" fontSize={12}>
+              Saldo: Rp. {it.balance}
+            </Typography>
+            <Typography variant="body2" fontSize={12}>
+              {it.created_at}
+            </Typography>
+          </CardContent>
+        </Card>
+      ))}
+
+      {before.length > 0 && (
+        <Button onClick={() => setBefore(before.slice(0, -1))}>
+          Sebelumnya
+        </Button>
+      )}
+      {data?.next && (
+        <Button onClick={() => setBefore([...before, data.next!])}>
+          Berikutnya
+        </Button>
+      )}
+    </div>
+  );
+}
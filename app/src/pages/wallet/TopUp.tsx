@@ -0,0 +1,79 @@
+import Button from "@mui/material/Button";
+import Card from "@mui/material/Card";
+import CardActions from "@mui/material/CardActions";
+import CardContent from "@mui/material/CardContent";
+import CircularProgress from "@mui/material/CircularProgress";
+import Typography from "@mui/material/Typography";
+import { useQueryClient } from "@tanstack/react-query";
+import axios from "axios";
+import { useNavigate, useParams } from "react-router-dom";
+import AppBar from "../../AppBar";
+import { useAuth } from "../../hooks/useAuth";
+import { useAuthSWR } from "../../hooks/useSWR";
+import { TopUp as TopUpModel } from "../../models/TopUp";
+import { handleError } from "@/utils/error-handler";
+
+const statuses: Record<TopUpModel["status"], string> = {
+  Pending: "Menunggu pembayaran",
+  Paid: "Dibayar",
+  Failed: "Gagal",
+};
+
+// payment page of the local fake provider, a real gateway hosts its own.
+export default function TopUp() {
+  const { id } = useParams();
+  const url = `/api/v1/wallet/topup/${id}`;
+  const { data, isLoading } = useAuthSWR<TopUpModel>(url);
+
+  const { token } = useAuth();
+  const queryClient = useQueryClient();
+  const navigate = useNavigate();
+
+  const pay = (status: "paid" | "failed") =>
+    handleError(async () => {
+      await axios.post(
+        `${url}/fake`,
+        { status },
+        {
+          headers: {
+            Authorization: `Bearer ${token}`,
+          },
+        }
+      );
+
+      queryClient.invalidateQueries([url]);
+      navigate("/user/wallet");
+    });
+
+  if (isLoading) {
+    return <CircularProgress />;
+  }
+
+  return (
+    <div className="App">
+      <AppBar />
+
+      <Card sx={{ m: 2 }}>
+        <CardContent>
+          <Typography variant="h5">Top Up Rp. {data?.amount}</Typography>
+          <Typography variant="body2" color="text.secondary">
+            {data && statuses[data.status]}
+          </Typography>
+          <Typography variant="body2" fontSize={12}>
+            {data?.created_at}
+          </Typography>
+        </CardContent>
+        {data?.status == "Pending" && (
+          <CardActions>
+            <Button variant="contained" onClick={pay("paid")}>
+              Bayar
+            </Button>
+            <Button color="error" onClick={pay("failed")}>
+              Batal
+            </Button>
+          </CardActions>
+        )}
+      </Card>
+    </div>
+  );
+}
@@ -0,0 +1,155 @@
+import Button from "@mui/material/Button";
+import Card from "@mui/material/Card";
+import CardActions from "@mui/material/CardActions";
+import CardContent from "@mui/material/CardContent";
+import CircularProgress from "@mui/material/CircularProgress";
+import Grid from "@mui/material/Grid";
+import Typography from "@mui/material/Typography";
+import { useQueryClient } from "@tanstack/react-query";
+import axios from "axios";
+import React from "react";
+import AppBar from "../../AppBar";
+import { useAuth } from "../../hooks/useAuth";
+import { useAuthSWR } from "../../hooks/useSWR";
+import {
+  CreatedWebhookEndpoint,
+  GetWebhookDelivery,
+  GetWebhookEndpoint,
+  WebhookEndpoint,
+} from "../../models/Webhook";
+import { handleError } from "@/utils/error-handler";
+
+export default function Index() {
+  const { data, isLoading } = useAuthSWR<GetWebhookEndpoint>("/api/v1/webhook");
+  const [selected, setSelected] = React.useState<string | null>(null);
+
+  const { token } = useAuth();
+  const queryClient = useQueryClient();
+
+  const onCreate = handleError(async () => {
+    const url = window.prompt("URL endpoint");
+    if (!url) {
+      return;
+    }
+
+    const response = await axios.post<CreatedWebhookEndpoint>(
+      "/api/v1/webhook",
+      { url },
+      {
+        headers: {
+          Authorization: `Bearer ${token}`,
+        },
+      }
+    );
+
+    // the secret is only shown once.
+    window.prompt("Simpan secret ini", response.data.secret);
+
+    queryClient.invalidateQueries(["/api/v1/webhook"]);
+  });
+
+  if (isLoading) {
+    return <CircularProgress />;
+  }
+
+  return (
+    <div className="App">
+      <AppBar />
+
+      <Button sx={{ m: 2 }} variant="contained" onClick={onCreate}>
+        Tambah Endpoint
+      </Button>
+
+      <Grid container>
+        <Grid item xs>
+          {data?.endpoints.map((it) => (
+            <EndpointCard
+              key={it.id}
+              endpoint={it}
+              onSelect={() => setSelected(it.id)}
+            />
+          ))}
+        </Grid>
+        <Grid item xs>
+          {selected && <DeliveryLog id={selected} />}
+        </Grid>
+      </Grid>
+    </div>
+  );
+}
+
+function EndpointCard({
+  endpoint,
+  onSelect,
+}: {
+  endpoint: WebhookEndpoint;
+  onSelect: () => void;
+}) {
+  const { token } = useAuth();
+  const queryClient = useQueryClient();
+
+  const onDelete = handleError(async () => {
+    await axios.delete(`/api/v1/webhook/${endpoint.id}`, {
+      headers: {
+        Authorization: `Bearer ${token}`,
+      },
+    });
+
+    queryClient.invalidateQueries(["/api/v1/webhook"]);
+  });
+
+  return (
+    <Card sx={{ m: 1 }}>
+      <CardContent>
+        <Typography variant="body2">{endpoint.url}</Typography>
+        <Typography variant="body2" fontSize={12} color="text.secondary">
+          {endpoint.created_at}
+        </Typography>
+      </CardContent>
+      <CardActions>
+        <Button onClick={onSelect}>Log</Button>
+        <Button color="error" onClick={onDelete}>
+          Hapus
+        </Button>
+      </CardActions>
+    </Card>
+  );
+}
+
+function DeliveryLog({ id }: { id: string }) {
+  const { data, isLoading } = useAuthSWR<GetWebhookDelivery>(
+    `/api/v1/webhook/${id}/deliveries`
+  );
+
+  if (isLoading) {
+    return <CircularProgress />;
+  }
+
+  return (
+    <>
+      {data?.deliveries.map((it) => (
+        <Card key={it.id} sx={{ m: 1 }}>
+          <CardContent>
+            <Typography variant="body2" color="text.secondary">
+              {it.event} - INV: {it.transaction_id}
+            </Typography>
+            <Typography variant="body2" fontSize={12}>
+              {it.status}, {it.attempts} percobaan
+              {it.last_status_code != null && ` (${it.last_status_code})`}
+            </Typography>
+            {it.last_error && (
+              <Typography variant="body2" fontSize={12} color="error">
+                {it.last_error}
+              </Typography>
+            )}
+            {it.next_attempt_at && (
+              <Typography variant="body2" fontSize={12}>
+                Dicoba lagi: {it.next_attempt_at}
+              </Typography>
+            )}
+          </CardContent>
+        </Card>
+      ))}
+    </>
+  );
+}
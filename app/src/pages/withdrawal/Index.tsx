@@ -0,0 +1,91 @@
+import Button from "@mui/material/Button";
+import CircularProgress from "@mui/material/CircularProgress";
+import TextField from "@mui/material/TextField";
+import { useQueryClient } from "@tanstack/react-query";
+import axios from "axios";
+import { useForm } from "react-hook-form";
+import AppBar from "../../AppBar";
+import { useAuth } from "../../hooks/useAuth";
+import { useAuthSWR } from "../../hooks/useSWR";
+import { BankAccount, GetWithdrawal } from "../../models/Withdrawal";
+import { handleError } from "@/utils/error-handler";
+import WithdrawalCard from "./WithdrawalCard";
+
+interface FormData extends BankAccount {
+  amount: string;
+}
+
+export default function Index() {
+  const { data, isLoading } = useAuthSWR<GetWithdrawal>("/api/v1/withdrawal");
+  const { register, handleSubmit, formState, reset } = useForm<FormData>();
+
+  const { token } = useAuth();
+  const queryClient = useQueryClient();
+
+  const onSubmit = async (e: FormData) => {
+    await axios.post(
+      "/api/v1/withdrawal",
+      {
+        amount: e.amount,
+        bank: {
+          bank_name: e.bank_name,
+          account_number: e.account_number,
+          account_name: e.account_name,
+        },
+      },
+      {
+        headers: {
+          Authorization: `Bearer ${token}`,
+        },
+      }
+    );
+
+    reset();
+    queryClient.invalidateQueries(["/api/v1/withdrawal"]);
+    queryClient.invalidateQueries(["/api/v1/account/me/ledger"]);
+  };
+
+  if (isLoading) {
+    return <CircularProgress />;
+  }
+
+  return (
+    <div className="App">
+      <AppBar />
+
+      <form
+        className="space-y-4 m-4"
+        onSubmit={handleSubmit(handleError(onSubmit))}
+      >
+        <TextField
+          {...register("amount")}
+          label="Jumlah"
+          type="number"
+          fullWidth
+        />
+        <TextField {...register("bank_name")} label="Nama Bank" fullWidth />
+        <TextField
+          {...register("account_number")}
+          label="Nomor Rekening"
+          fullWidth
+        />
+        <TextField
+          {...register("account_name")}
+          label="Nama Pemilik Rekening"
+          fullWidth
+        />
+        <Button
+          type="submit"
+          variant="contained"
+          disabled={formState.isSubmitting}
+        >
+          Tarik Saldo
+        </Button>
+      </form>
+
+      {data?.withdrawals.map((it) => (
+        <WithdrawalCard key={it.id} withdrawal={it} />
+      ))}
+    </div>
+  );
+}
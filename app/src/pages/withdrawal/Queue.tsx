@@ -0,0 +1,76 @@
+import Button from "@mui/material/Button";
+import CircularProgress from "@mui/material/CircularProgress";
+import Typography from "@mui/material/Typography";
+import { useQueryClient } from "@tanstack/react-query";
+import axios from "axios";
+import AppBar from "../../AppBar";
+import { useAuth } from "../../hooks/useAuth";
+import { useAuthSWR } from "../../hooks/useSWR";
+import { GetWithdrawal, Withdrawal } from "../../models/Withdrawal";
+import { handleError } from "@/utils/error-handler";
+import WithdrawalCard from "./WithdrawalCard";
+
+export default function Queue() {
+  const { data, isLoading } = useAuthSWR<GetWithdrawal>(
+    "/api/v1/withdrawal/queue"
+  );
+
+  if (isLoading) {
+    return <CircularProgress />;
+  }
+
+  return (
+    <div className="App">
+      <AppBar />
+
+      {data?.withdrawals.length == 0 && (
+        <Typography sx={{ m: 2 }}>Tidak ada penarikan</Typography>
+      )}
+
+      {data?.withdrawals.map((it) => (
+        <QueueItem key={it.id} withdrawal={it} />
+      ))}
+    </div>
+  );
+}
+
+function QueueItem({ withdrawal }: { withdrawal: Withdrawal }) {
+  const { token } = useAuth();
+  const queryClient = useQueryClient();
+
+  const headers = {
+    Authorization: `Bearer ${token}`,
+  };
+
+  const onApprove = handleError(async () => {
+    await axios.post(`/api/v1/withdrawal/${withdrawal.id}/approve`, null, {
+      headers,
+    });
+
+    queryClient.invalidateQueries(["/api/v1/withdrawal/queue"]);
+  });
+
+  const onReject = handleError(async () => {
+    const reason = window.prompt("Alasan penolakan");
+    if (!reason) {
+      return;
+    }
+
+    await axios.post(
+      `/api/v1/withdrawal/${withdrawal.id}/reject`,
+      { reason },
+      { headers }
+    );
+
+    queryClient.invalidateQueries(["/api/v1/withdrawal/queue"]);
+  });
+
+  return (
+    <WithdrawalCard withdrawal={withdrawal}>
+      <Button onClick={onApprove}>Setujui</Button>
+      <Button color="error" onClick={onReject}>
+        Tolak
+      </Button>
+    </WithdrawalCard>
+  );
+}
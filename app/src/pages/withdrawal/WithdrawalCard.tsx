@@ -0,0 +1,44 @@
+import Card from "@mui/material/Card";
+import CardActions from "@mui/material/CardActions";
+import CardContent from "@mui/material/CardContent";
+import Typography from "@mui/material/Typography";
+import React from "react";
+import { Withdrawal, WithdrawalStatusType } from "../../models/Withdrawal";
+
+const statuses: Record<WithdrawalStatusType["type"], string> = {
+  Pending: "Menunggu persetujuan",
+  Approved: "Disetujui",
+  Rejected: "Ditolak",
+};
+
+export function statusText(status: WithdrawalStatusType) {
+  return status.type == "Rejected"
+    ? `${statuses.Rejected}: ${status.content.reason}`
+    : statuses[status.type];
+}
+
+export default function WithdrawalCard({
+  withdrawal,
+  children,
+}: {
+  withdrawal: Withdrawal;
+  children?: React.ReactNode;
+}) {
+  return (
+    <Card sx={{ m: 1 }}>
+      <CardContent>
+        <Typography variant="body1">Rp. {withdrawal.amount}</Typography>
+        <Typography variant="body2" color="text.secondary">
+          {withdrawal.bank.bank_name} {withdrawal.bank.account_number} a.n.{" "}
+          {withdrawal.bank.account_name}
+        </Typography>
+        {withdrawal.status.map((it, idx) => (
+          <Typography key={idx} variant="body2" fontSize={12}>
+            {it.date} - {statusText(it.type)}
+          </Typography>
+        ))}
+      </CardContent>
+      {children && <CardActions>{children}</CardActions>}
+    </Card>
+  );
+}
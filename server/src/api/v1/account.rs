@@ -3,6 +3,7 @@ use std::str::FromStr;
 use argon2::Argon2;
 use axum::{
     extract::{Path, State},
+    http::StatusCode,
     Json,
 };
 use bson::oid::ObjectId;
@@ -11,9 +12,12 @@ use serde::{Deserialize, Serialize};
 use time::OffsetDateTime;
 use validator::Validate;
 
-use crate::{error::Error, util::DecimalString};
+use crate::{error::Error, mongo_ext::with_transaction, util::DecimalString};
 
-use super::auth::{RegisterResponse, UserAccess, UserCollection, UserModel, UserRole};
+use super::{
+    auth::{RegisterResponse, UserAccess, UserCollection, UserModel, UserRole},
+    ledger::Ledger,
+};
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct IndexResponse {
@@ -74,6 +78,8 @@ pub struct AccountRequest {
 
 pub async fn create(
     State(accounts): State<UserCollection>,
+    State(ledger): State<Ledger>,
+    State(mongo): State<mongodb::Client>,
     State(argon): State<Argon2<'_>>,
     user: UserAccess,
     Json(request): Json<AccountRequest>,
@@ -85,22 +91,38 @@ pub async fn create(
         super::auth::UserRole::Admin => {}
     }
 
-    let insert = super::auth::create_user(
-        accounts,
-        argon,
+    let balance = request.balance.map(|it| it.0).unwrap_or(Decimal::ZERO);
+
+    if balance.is_sign_negative() {
+        return Err(Error::CustomStr(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Balance can't be negative",
+        ));
+    }
+
+    let model = super::auth::new_user(
+        &argon,
         super::auth::CreateUserRequest {
             name: request.name,
             email: request.email,
             password: request.password,
             confirm_password: request.confirm_password,
-            balance: request
-                .balance
-                .map(|it| it.0)
-                .unwrap_or_else(|| Decimal::from(0)),
             role: request.role,
         },
-    )
-    .await?;
+    )?;
+
+    // the user only exists along with its opening balance.
+    let insert = with_transaction!(mongo, |session| {
+        super::auth::insert_user_with_session(&accounts, &model, &mut session).await?;
+
+        if balance.is_zero() {
+            return Ok(model.clone());
+        }
+
+        ledger
+            .adjust_with_session(model.id, balance, user.id, &mut session)
+            .await
+    })?;
 
     Ok(Json(insert.into()))
 }
@@ -129,9 +151,13 @@ pub struct UpdateRequest {
     pub role: Option<UserRole>,
 }
 
+/// Update an account as an admin. A new balance is recorded as an adjustment in the ledger.
+#[allow(clippy::too_many_arguments)]
 pub async fn update(
     user: UserAccess,
     State(accounts): State<UserCollection>,
+    State(ledger): State<Ledger>,
+    State(mongo): State<mongodb::Client>,
     State(argon): State<Argon2<'_>>,
     Path(account_id): Path<String>,
     Json(request): Json<UpdateRequest>,
@@ -147,52 +173,72 @@ pub async fn update(
 
     let account_id = ObjectId::from_str(&account_id).map_err(|_| Error::NoResource)?;
 
-    let account = accounts
-        .find_one(bson::doc! {"_id": account_id}, None)
-        .await?
-        .ok_or_else(|| Error::NoResource)?;
-
-    if let Some(email) = &request.email {
-        if email != &account.email {
-            let count = accounts
-                .count_documents(
-                    bson::doc! {
-                        "email": email
-                    },
-                    None,
-                )
-                .await?;
-
-            if count > 0 {
-                return Err(Error::MustUniqueError("email".to_string()));
+    let password = request
+        .password
+        .as_deref()
+        .map(|it| crate::util::hash_password(&argon, it))
+        .transpose()?;
+
+    let account = with_transaction!(mongo, |session| {
+        let account = accounts
+            .find_one_with_session(bson::doc! {"_id": account_id}, None, &mut session)
+            .await?
+            .ok_or_else(|| Error::NoResource)?;
+
+        if let Some(email) = &request.email {
+            if email != &account.email {
+                let count = accounts
+                    .count_documents_with_session(
+                        bson::doc! {
+                            "email": email
+                        },
+                        None,
+                        &mut session,
+                    )
+                    .await?;
+
+                if count > 0 {
+                    return Err(Error::MustUniqueError("email".to_string()));
+                }
             }
         }
-    }
 
-    let account = UserModel {
-        id: account.id,
-        name: request.name.unwrap_or(account.name),
-        email: request.email.unwrap_or(account.email),
-        password: request
-            .password
-            .map(|it| crate::util::hash_password(&argon, &it))
-            .unwrap_or(Ok(account.password))?,
-        role: request.role.unwrap_or(account.role),
-        balance: request.balance.map(Into::into).unwrap_or(account.balance),
-        updated_at: OffsetDateTime::now_utc().into(),
-        created_at: account.created_at,
-        // deleted_at: account.deleted_at,
-    };
+        let mut account = UserModel {
+            id: account.id,
+            name: request.name.clone().unwrap_or(account.name),
+            email: request.email.clone().unwrap_or(account.email),
+            password: password.clone().unwrap_or(account.password),
+            role: request.role.unwrap_or(account.role),
+            balance: account.balance,
+            updated_at: OffsetDateTime::now_utc().into(),
+            created_at: account.created_at,
+            // deleted_at: account.deleted_at,
+        };
+
+        let mut update = bson::to_document(&account)?;
+        // the balance only change through the ledger.
+        update.remove("balance");
+
+        accounts
+            .update_exists_one_by_id_with_session(
+                account_id,
+                bson::doc! {
+                    "$set": update
+                },
+                None,
+                &mut session,
+            )
+            .await?;
 
-    // accounts.find_one_by_id
-    accounts
-        .update_exists_one_by_id(
-            account_id,
-            bson::doc! {
-                "$set": bson::to_document(&account)?
-            },
-        )
-        .await?;
+        if let Some(balance) = &request.balance {
+            account.balance = ledger
+                .adjust_with_session(account_id, balance.0, user.id, &mut session)
+                .await?
+                .balance;
+        }
+
+        Ok(account)
+    })?;
 
     Ok(Json(account.into()))
 }
@@ -239,6 +285,8 @@ mod tests {
 
         let it = super::create(
             bootstrap.user_collection(),
+            bootstrap.state(),
+            bootstrap.mongo_client(),
             bootstrap.argon(),
             bootstrap.user_access(),
             Json(super::AccountRequest {
@@ -268,6 +316,8 @@ mod tests {
 
         let it = super::create(
             bootstrap.user_collection(),
+            bootstrap.state(),
+            bootstrap.mongo_client(),
             bootstrap.argon(),
             bootstrap.user_access(),
             Json(super::AccountRequest {
@@ -285,6 +335,8 @@ mod tests {
         let _it = super::update(
             bootstrap.user_access(),
             bootstrap.user_collection(),
+            bootstrap.state(),
+            bootstrap.mongo_client(),
             bootstrap.argon(),
             Path(it.id.to_string()),
             Json(super::UpdateRequest {
@@ -302,6 +354,8 @@ mod tests {
         let _it = super::update(
             bootstrap.user_access(),
             bootstrap.user_collection(),
+            bootstrap.state(),
+            bootstrap.mongo_client(),
             bootstrap.argon(),
             Path(it.id.to_string()),
             Json(super::UpdateRequest {
@@ -319,6 +373,8 @@ mod tests {
         let error = super::update(
             bootstrap.user_access(),
             bootstrap.user_collection(),
+            bootstrap.state(),
+            bootstrap.mongo_client(),
             bootstrap.argon(),
             Path(it.id.to_string()),
             Json(super::UpdateRequest {
@@ -342,6 +398,8 @@ mod tests {
 
         let it = super::create(
             bootstrap.user_collection(),
+            bootstrap.state(),
+            bootstrap.mongo_client(),
             bootstrap.argon(),
             bootstrap.user_access(),
             Json(super::AccountRequest {
@@ -383,6 +441,8 @@ mod tests {
         let error = super::update(
             bootstrap.user_access(),
             bootstrap.user_collection(),
+            bootstrap.state(),
+            bootstrap.mongo_client(),
             bootstrap.argon(),
             Path(id.to_string()),
             Json(super::UpdateRequest {
@@ -422,6 +482,8 @@ mod tests {
                 .await;
             let error = super::create(
                 bootstrap.user_collection(),
+                bootstrap.state(),
+                bootstrap.mongo_client(),
                 bootstrap.argon(),
                 bootstrap.user_access(),
                 Json(super::AccountRequest {
@@ -440,6 +502,8 @@ mod tests {
             let error = super::update(
                 bootstrap.user_access(),
                 bootstrap.user_collection(),
+                bootstrap.state(),
+                bootstrap.mongo_client(),
                 bootstrap.argon(),
                 Path(id.to_string()),
                 Json(super::UpdateRequest {
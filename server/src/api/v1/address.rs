@@ -0,0 +1,505 @@
+use axum::{extract::State, http::StatusCode, Json};
+use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use validator::Validate;
+
+use crate::{
+    error::Error,
+    mongo_ext::{with_transaction, Collection},
+    util::{FormattedDateTime, ObjectIdString, PathObjectId},
+};
+
+use super::auth::UserAccess;
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AddressModel {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user_id: ObjectId,
+
+    pub label: String,
+    pub address: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub is_default: bool,
+
+    pub created_at: bson::DateTime,
+    pub updated_at: bson::DateTime,
+    pub deleted_at: Option<bson::DateTime>,
+}
+
+#[derive(Clone)]
+pub struct AddressCollection(pub Collection<AddressModel>);
+
+impl std::ops::Deref for AddressCollection {
+    type Target = Collection<AddressModel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl AddressCollection {
+    /// Returns address `id` when it belongs to `user_id`.
+    pub async fn find_owned_with_session(
+        &self,
+        user_id: ObjectId,
+        id: ObjectId,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<Option<AddressModel>, Error> {
+        Ok(self
+            .find_exists_one_by_id_with_session(id, session)
+            .await?
+            .filter(|it| it.user_id == user_id))
+    }
+
+    pub async fn find_default_with_session(
+        &self,
+        user_id: ObjectId,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<Option<AddressModel>, Error> {
+        self.find_one_with_session(
+            bson::doc! {
+                "user_id": user_id,
+                "is_default": true,
+                "deleted_at": null,
+            },
+            None,
+            session,
+        )
+        .await
+        .map_err(Into::into)
+    }
+
+    /// Returns the address an order of `user_id` is delivered to, `id` when given or else
+    /// the default address.
+    pub async fn find_delivery_address_with_session(
+        &self,
+        user_id: ObjectId,
+        id: Option<ObjectId>,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<AddressModel, Error> {
+        match id {
+            Some(id) => self
+                .find_owned_with_session(user_id, id, session)
+                .await?
+                .ok_or(Error::NoResource),
+            None => self
+                .find_default_with_session(user_id, session)
+                .await?
+                .ok_or(Error::CustomStr(
+                    StatusCode::UNPROCESSABLE_ENTITY,
+                    "Please add a delivery address first",
+                )),
+        }
+    }
+
+    /// Make `id` the only default address of `user_id`.
+    async fn set_default_with_session(
+        &self,
+        user_id: ObjectId,
+        id: ObjectId,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<(), Error> {
+        self.update_many_with_session(
+            bson::doc! {
+                "user_id": user_id,
+                "_id": { "$ne": id },
+                "is_default": true,
+            },
+            bson::doc! {
+                "$set": { "is_default": false }
+            },
+            None,
+            session,
+        )
+        .await?;
+
+        self.update_exists_one_by_id_with_session(
+            id,
+            bson::doc! {
+                "$set": { "is_default": true }
+            },
+            None,
+            session,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Address {
+    pub id: ObjectIdString,
+    pub user_id: ObjectIdString,
+
+    pub label: String,
+    pub address: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub is_default: bool,
+
+    pub created_at: FormattedDateTime,
+    pub updated_at: FormattedDateTime,
+}
+
+impl From<AddressModel> for Address {
+    fn from(value: AddressModel) -> Self {
+        Self {
+            id: value.id.into(),
+            user_id: value.user_id.into(),
+            label: value.label,
+            address: value.address,
+            latitude: value.latitude,
+            longitude: value.longitude,
+            is_default: value.is_default,
+
+            created_at: value.created_at.into(),
+            updated_at: value.updated_at.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct IndexResponse {
+    pub addresses: Vec<Address>,
+}
+
+pub async fn index(
+    State(addresses): State<AddressCollection>,
+    user: UserAccess,
+) -> Result<Json<IndexResponse>, Error> {
+    let mut cursor = addresses
+        .find_exists(
+            bson::doc! {
+                "user_id": user.id,
+            },
+            None,
+        )
+        .await?;
+
+    let mut result = vec![];
+
+    while cursor.advance().await? {
+        result.push(cursor.deserialize_current()?.into());
+    }
+
+    Ok(Json(IndexResponse { addresses: result }))
+}
+
+pub async fn show(
+    State(addresses): State<AddressCollection>,
+    user: UserAccess,
+    PathObjectId(id): PathObjectId,
+) -> Result<Json<Address>, Error> {
+    let address = addresses
+        .find_exists_one_by_id(id)
+        .await?
+        .filter(|it| it.user_id == user.id)
+        .ok_or(Error::NoResource)?;
+
+    Ok(Json(address.into()))
+}
+
+#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+pub struct AddressRequest {
+    #[validate(length(min = 1, max = 124))]
+    pub label: String,
+
+    #[validate(length(min = 1, max = 1024))]
+    pub address: String,
+
+    #[validate(range(min = -90.0, max = 90.0))]
+    pub latitude: f64,
+
+    #[validate(range(min = -180.0, max = 180.0))]
+    pub longitude: f64,
+
+    #[serde(default)]
+    pub is_default: bool,
+}
+
+/// Create an address for the current user. The first address is always the default.
+pub async fn create(
+    State(addresses): State<AddressCollection>,
+    State(mongo): State<mongodb::Client>,
+    user: UserAccess,
+    Json(request): Json<AddressRequest>,
+) -> Result<Json<Address>, Error> {
+    request.validate()?;
+
+    let address = with_transaction!(mongo, |session| {
+        let is_default = request.is_default
+            || addresses
+                .find_default_with_session(user.id, &mut session)
+                .await?
+                .is_none();
+
+        let address = AddressModel {
+            id: ObjectId::new(),
+            user_id: user.id,
+            label: request.label.clone(),
+            address: request.address.clone(),
+            latitude: request.latitude,
+            longitude: request.longitude,
+            is_default,
+
+            created_at: OffsetDateTime::now_utc().into(),
+            updated_at: OffsetDateTime::now_utc().into(),
+            deleted_at: None,
+        };
+
+        addresses
+            .insert_one_with_session(&address, None, &mut session)
+            .await?;
+
+        if is_default {
+            addresses
+                .set_default_with_session(user.id, address.id, &mut session)
+                .await?;
+        }
+
+        Ok(address)
+    })?;
+
+    Ok(Json(address.into()))
+}
+
+pub async fn update(
+    State(addresses): State<AddressCollection>,
+    State(mongo): State<mongodb::Client>,
+    user: UserAccess,
+    PathObjectId(id): PathObjectId,
+    Json(request): Json<AddressRequest>,
+) -> Result<Json<Address>, Error> {
+    request.validate()?;
+
+    let address = with_transaction!(mongo, |session| {
+        let mut address = addresses
+            .find_owned_with_session(user.id, id, &mut session)
+            .await?
+            .ok_or(Error::NoResource)?;
+
+        address.label = request.label.clone();
+        address.address = request.address.clone();
+        address.latitude = request.latitude;
+        address.longitude = request.longitude;
+        // unsetting the default is done by choosing another default address.
+        address.is_default = address.is_default || request.is_default;
+        address.updated_at = OffsetDateTime::now_utc().into();
+
+        addresses
+            .update_exists_one_by_id_with_session(
+                id,
+                bson::doc! {
+                    "$set": bson::to_document(&address)?
+                },
+                None,
+                &mut session,
+            )
+            .await?;
+
+        if address.is_default {
+            addresses
+                .set_default_with_session(user.id, id, &mut session)
+                .await?;
+        }
+
+        Ok(address)
+    })?;
+
+    Ok(Json(address.into()))
+}
+
+pub async fn set_default(
+    State(addresses): State<AddressCollection>,
+    State(mongo): State<mongodb::Client>,
+    user: UserAccess,
+    PathObjectId(id): PathObjectId,
+) -> Result<Json<Address>, Error> {
+    let address = with_transaction!(mongo, |session| {
+        let mut address = addresses
+            .find_owned_with_session(user.id, id, &mut session)
+            .await?
+            .ok_or(Error::NoResource)?;
+
+        addresses
+            .set_default_with_session(user.id, id, &mut session)
+            .await?;
+
+        address.is_default = true;
+
+        Ok(address)
+    })?;
+
+    Ok(Json(address.into()))
+}
+
+/// Delete an address. Orders keep their own copy of the address, so they are not affected.
+pub async fn delete(
+    State(addresses): State<AddressCollection>,
+    user: UserAccess,
+    PathObjectId(id): PathObjectId,
+) -> Result<(), Error> {
+    addresses
+        .find_exists_one_by_id(id)
+        .await?
+        .filter(|it| it.user_id == user.id)
+        .ok_or(Error::NoResource)?;
+
+    addresses.soft_delete_one_by_id(id).await?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use axum::Json;
+
+    use crate::{api::v1::tests::bootstrap, error::Error};
+
+    use super::AddressRequest;
+
+    fn request(label: &str, is_default: bool) -> Json<AddressRequest> {
+        Json(AddressRequest {
+            label: label.to_string(),
+            address: format!("{label} street"),
+            latitude: -7.25,
+            longitude: 112.75,
+            is_default,
+        })
+    }
+
+    #[tokio::test]
+    pub async fn test_address_crud() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let Json(address) = super::create(
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.user_access(),
+            request("office", false),
+        )
+        .await
+        .unwrap();
+        assert!(!address.is_default);
+
+        let Json(updated) = super::update(
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.user_access(),
+            address.id.into(),
+            request("new office", false),
+        )
+        .await
+        .unwrap();
+        assert_eq!(updated.label, "new office");
+
+        let Json(shown) = super::show(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            address.id.into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(shown.label, "new office");
+
+        super::delete(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            address.id.into(),
+        )
+        .await
+        .unwrap();
+
+        let Json(index) = super::index(bootstrap.state(), bootstrap.user_access())
+            .await
+            .unwrap();
+        assert!(index.addresses.iter().all(|it| it.id != address.id));
+    }
+
+    #[tokio::test]
+    pub async fn test_only_one_default_address() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let Json(first) = super::create(
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.user_access(),
+            request("first", true),
+        )
+        .await
+        .unwrap();
+
+        let Json(second) = super::create(
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.user_access(),
+            request("second", false),
+        )
+        .await
+        .unwrap();
+        assert!(!second.is_default);
+
+        let _ = super::set_default(
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.user_access(),
+            second.id.into(),
+        )
+        .await
+        .unwrap();
+
+        let Json(index) = super::index(bootstrap.state(), bootstrap.user_access())
+            .await
+            .unwrap();
+
+        let defaults = index
+            .addresses
+            .iter()
+            .filter(|it| it.is_default)
+            .collect::<Vec<_>>();
+        assert_eq!(defaults.len(), 1);
+        assert_eq!(defaults[0].id, second.id);
+        assert_ne!(defaults[0].id, first.id);
+    }
+
+    #[tokio::test]
+    pub async fn test_cannot_access_other_user_address() {
+        let bootstrap = bootstrap().await;
+        let other = bootstrap.derive_customer().await;
+
+        let Json(address) = super::create(
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.user_access(),
+            request("home", false),
+        )
+        .await
+        .unwrap();
+
+        let error = super::show(other.state(), other.user_access(), address.id.into())
+            .await
+            .expect_err("cannot see other user address");
+        assert_matches!(error, Error::NoResource);
+
+        let error = super::update(
+            other.state(),
+            other.state(),
+            other.user_access(),
+            address.id.into(),
+            request("mine", true),
+        )
+        .await
+        .expect_err("cannot update other user address");
+        assert_matches!(error, Error::NoResource);
+
+        let error = super::delete(other.state(), other.user_access(), address.id.into())
+            .await
+            .expect_err("cannot delete other user address");
+        assert_matches!(error, Error::NoResource);
+    }
+}
@@ -44,7 +44,8 @@ pub struct UserModel {
     pub password: String,
     pub role: UserRole,
 
-    #[serde(default)]
+    /// Only changed through [`super::ledger::Ledger`].
+    #[serde(default, with = "crate::util::decimal128")]
     pub balance: Decimal,
 
     pub created_at: bson::DateTime,
@@ -93,7 +94,7 @@ where
             .await
             .map_err(|_| Error::Unauthorized(UnauthorizedType::InvalidAccessToken))?;
 
-        let jwt = JwtState::from_ref(&state);
+        let jwt = JwtState::from_ref(state);
 
         Self::from_token(&jwt, token.token())
     }
@@ -127,7 +128,7 @@ pub struct RefreshClaim(pub RefreshTokenClaims, pub String);
 
 impl RefreshClaim {
     pub fn from_token(jwt_state: &JwtState, refresh_token: String) -> Result<Self, Error> {
-        let token = decode_refresh_token(&jwt_state, &refresh_token)
+        let token = decode_refresh_token(jwt_state, &refresh_token)
             .map_err(|_| Error::Unauthorized(UnauthorizedType::InvalidRefreshToken))?;
 
         Ok(Self(token.claims, refresh_token))
@@ -242,21 +243,38 @@ pub struct CreateUserRequest {
     #[validate(must_match = "password")]
     pub confirm_password: String,
 
-    pub balance: Decimal,
-
     pub role: UserRole,
 }
 
+/// Validate `request` and build its user with an empty wallet, money is added through
+/// [`super::ledger::Ledger`]. Nothing is stored yet.
+pub fn new_user(argon: &Argon2<'_>, request: CreateUserRequest) -> Result<UserModel, Error> {
+    request.validate()?;
+
+    Ok(UserModel {
+        id: ObjectId::new(),
+        name: request.name,
+        email: request.email,
+        password: hash_password(argon, &request.password)?,
+        role: request.role,
+        balance: Decimal::ZERO,
+        created_at: OffsetDateTime::now_utc().into(),
+        updated_at: OffsetDateTime::now_utc().into(),
+    })
+}
+
+/// Create a user with an empty wallet, see [`new_user`].
 pub async fn create_user(
     users: UserCollection,
     argon: Argon2<'_>,
     request: CreateUserRequest,
 ) -> Result<UserModel, Error> {
-    request.validate()?;
+    let model = new_user(&argon, request)?;
+
     let count = users
         .count_documents(
             bson::doc! {
-                "email": &request.email
+                "email": &model.email
             },
             None,
         )
@@ -266,21 +284,36 @@ pub async fn create_user(
         return Err(Error::MustUniqueError("email".to_string()));
     }
 
-    let model = UserModel {
-        id: ObjectId::new(),
-        name: request.name,
-        email: request.email,
-        password: hash_password(&argon, &request.password)?,
-        role: request.role,
-        balance: request.balance,
-        created_at: OffsetDateTime::now_utc().into(),
-        updated_at: OffsetDateTime::now_utc().into(),
-    };
     users.insert_one(&model, None).await?;
 
     Ok(model)
 }
 
+/// Store `model` made by [`new_user`]. Must be called inside a mongo transaction.
+pub async fn insert_user_with_session(
+    users: &UserCollection,
+    model: &UserModel,
+    session: &mut mongodb::ClientSession,
+) -> Result<(), Error> {
+    let count = users
+        .count_documents_with_session(
+            bson::doc! {
+                "email": &model.email
+            },
+            None,
+            session,
+        )
+        .await?;
+
+    if count > 0 {
+        return Err(Error::MustUniqueError("email".to_string()));
+    }
+
+    users.insert_one_with_session(model, None, session).await?;
+
+    Ok(())
+}
+
 pub async fn register(
     State(users): State<UserCollection>,
     State(argon): State<Argon2<'_>>,
@@ -294,7 +327,6 @@ pub async fn register(
             email: request.email,
             password: request.password,
             confirm_password: request.confirm_password,
-            balance: Decimal::from(0),
             role: UserRole::Customer,
         },
     )
@@ -555,7 +587,7 @@ mod test {
         .await
         .unwrap();
 
-        let _ = super::logout(
+        super::logout(
             bootstrap.refresh_token_collection(),
             super::RefreshClaim::from_token(
                 &bootstrap.app_state.jwt_state,
@@ -787,7 +819,7 @@ mod test {
     pub async fn test_refresh_token_invalid() {
         let bootstrap = bootstrap().await;
 
-        let token = bootstrap.user_token();
+        let _token = bootstrap.user_token();
 
         let (mut parts, _) = axum::http::request::Request::get("http://localhost")
             // .header("Cookie", format!("refresh_token=Bearer {}", token))
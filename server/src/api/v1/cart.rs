@@ -6,11 +6,22 @@ use serde::{Deserialize, Serialize};
 
 use crate::{
     error::Error,
-    mongo_ext::Collection,
+    mongo_ext::{with_transaction, Collection},
     util::{BigIntString, ObjectIdString, PathObjectId},
 };
 
-use super::{auth::UserAccess, product::ProductCollection};
+use super::{
+    address::AddressCollection,
+    auth::UserAccess,
+    idempotency::{self, IdempotencyCollection, IdempotencyKey, IdempotencyScope},
+    ledger::Ledger,
+    product::ProductCollection,
+    shipping::ShippingRate,
+    transaction::{
+        webhook::Webhooks, ProductOrderRequest, Transaction, TransactionAddress,
+        TransactionCollection, TransactionModel,
+    },
+};
 
 #[derive(Clone)]
 pub struct CartCollection(pub Collection<CartModel>);
@@ -23,6 +34,34 @@ impl std::ops::Deref for CartCollection {
     }
 }
 
+impl CartCollection {
+    /// Everything in the cart of `user_id`.
+    pub async fn find_by_user_with_session(
+        &self,
+        user_id: ObjectId,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<Vec<CartModel>, Error> {
+        let mut cursor = self
+            .find_with_session(
+                bson::doc! {
+                    "user_id": user_id,
+                    "deleted_at": null,
+                },
+                None,
+                session,
+            )
+            .await?;
+
+        let mut result = vec![];
+
+        while cursor.advance(session).await? {
+            result.push(cursor.deserialize_current()?);
+        }
+
+        Ok(result)
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug)]
 pub struct CartModel {
     #[serde(rename = "_id")]
@@ -124,7 +163,7 @@ pub async fn create(
         id: ObjectId::new(),
         user_id: user.id,
         product_id: request.product_id.into(),
-        merchant_id: product.user_id.into(),
+        merchant_id: product.user_id,
         quantity: request.quantity.into(),
     };
 
@@ -137,9 +176,9 @@ pub async fn create(
     let model = carts
         .find_one_and_update(
             bson::doc! {
-                "user_id": model.user_id.clone(),
-                "product_id": model.product_id.clone(),
-                "merchant_id": model.merchant_id.clone(),
+                "user_id": model.user_id,
+                "product_id": model.product_id,
+                "merchant_id": model.merchant_id,
             },
             bson::doc! {"$set": doc},
             mongodb::options::FindOneAndUpdateOptions::builder()
@@ -173,13 +212,172 @@ pub async fn delete(
     Ok(())
 }
 
+#[derive(Serialize, Deserialize, Debug)]
+pub struct CheckoutResponse {
+    pub orders: Vec<TransactionModel>,
+}
+
+/// What a checkout is for, hashed to tell if an `Idempotency-Key` is reused for another
+/// request. A checkout empties the cart, so a retry of it finds an empty cart: the key is
+/// remembered with the cart it leaves behind, and once items are added back or the address
+/// changed, the same key is another request.
+#[derive(Serialize)]
+struct CheckoutHash<'a> {
+    address_id: ObjectId,
+    carts: Vec<(ObjectId, &'a BigInt)>,
+}
+
+fn checkout_hash(address_id: ObjectId, carts: &[CartModel]) -> Result<String, Error> {
+    let mut carts = carts
+        .iter()
+        .map(|it| (it.product_id, &it.quantity))
+        .collect::<Vec<_>>();
+    carts.sort_by_key(|(product_id, _)| *product_id);
+
+    idempotency::request_hash(&CheckoutHash { address_id, carts })
+}
+
+/// Buy everything in the user cart, creating one order per merchant.
+#[allow(clippy::too_many_arguments)]
+pub async fn checkout(
+    State(carts): State<CartCollection>,
+    State(transactions): State<TransactionCollection>,
+    State(products): State<ProductCollection>,
+    State(ledger): State<Ledger>,
+    State(mongo): State<mongodb::Client>,
+    State(idempotency): State<IdempotencyCollection>,
+    State(addresses): State<AddressCollection>,
+    State(shipping_rate): State<ShippingRate>,
+    State(webhooks): State<Webhooks>,
+    user: UserAccess,
+    IdempotencyKey(key): IdempotencyKey,
+) -> Result<Json<CheckoutResponse>, Error> {
+    let response = with_transaction!(mongo, |session| {
+        let address = addresses
+            .find_delivery_address_with_session(user.id, None, &mut session)
+            .await?;
+
+        let cart = carts
+            .find_by_user_with_session(user.id, &mut session)
+            .await?;
+
+        if let Some(key) = &key {
+            let response = idempotency
+                .find_response_with_session(
+                    user.id,
+                    IdempotencyScope::Checkout,
+                    key,
+                    &checkout_hash(address.id, &cart)?,
+                    &mut session,
+                )
+                .await?;
+
+            if let Some(response) = response {
+                return Ok(response);
+            }
+        }
+
+        let address_id = address.id;
+
+        let created = checkout_with_session(
+            &carts,
+            &transactions,
+            &products,
+            &ledger,
+            &addresses,
+            &shipping_rate,
+            &webhooks,
+            user.id,
+            address.into(),
+            &cart,
+            &mut session,
+        )
+        .await?;
+
+        let response = CheckoutResponse {
+            orders: created.into_iter().map(Into::into).collect(),
+        };
+
+        if let Some(key) = &key {
+            idempotency
+                .insert_response_with_session(
+                    user.id,
+                    IdempotencyScope::Checkout,
+                    key,
+                    &checkout_hash(address_id, &[])?,
+                    &response,
+                    &mut session,
+                )
+                .await?;
+        }
+
+        Ok(response)
+    })?;
+
+    Ok(Json(response))
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn checkout_with_session(
+    carts: &CartCollection,
+    transactions: &TransactionCollection,
+    products: &ProductCollection,
+    ledger: &Ledger,
+    addresses: &AddressCollection,
+    shipping_rate: &ShippingRate,
+    webhooks: &Webhooks,
+    user_id: ObjectId,
+    address: TransactionAddress,
+    cart: &[CartModel],
+    session: &mut mongodb::ClientSession,
+) -> Result<Vec<Transaction>, Error> {
+    let orders = cart
+        .iter()
+        .map(|it| ProductOrderRequest {
+            product_id: it.product_id.into(),
+            quantity: it.quantity.clone().into(),
+        })
+        .collect::<Vec<_>>();
+
+    let created = super::transaction::place_orders_with_session(
+        transactions,
+        products,
+        ledger,
+        addresses,
+        shipping_rate,
+        webhooks,
+        user_id,
+        address,
+        &orders,
+        true,
+        session,
+    )
+    .await?;
+
+    carts
+        .delete_many_with_session(
+            bson::doc! {
+                "_id": {
+                    "$in": cart.iter().map(|it| it.id).collect::<Vec<_>>()
+                }
+            },
+            None,
+            session,
+        )
+        .await?;
+
+    Ok(created)
+}
+
 #[cfg(test)]
 mod tests {
     use assert_matches::assert_matches;
     use axum::Json;
 
+    use rust_decimal::Decimal;
+
     use crate::{
-        api::v1::{auth::UserRole, tests::bootstrap},
+        api::v1::{auth::UserRole, idempotency::IdempotencyKey, tests::bootstrap},
         error::Error,
         util::PathObjectId,
     };
@@ -357,7 +555,7 @@ mod tests {
 
         let first = create(1).await.unwrap();
 
-        let _ = super::delete(
+        super::delete(
             bootstrap.cart_collection(),
             customer.user_access(),
             PathObjectId(first.id.0),
@@ -404,4 +602,192 @@ mod tests {
         .await
         .expect_err("deleting other user cart");
     }
+
+    #[tokio::test]
+    async fn test_checkout_split_by_merchant() {
+        let bootstrap = bootstrap().await;
+        let merchant = bootstrap.derive_customer().await;
+
+        let first = bootstrap.create_product(1000, 2).await;
+        let second = merchant.create_product(500, 1).await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(3_000))
+            .await;
+
+        for (product, quantity) in [(&first, 2), (&second, 1)] {
+            let _ = super::create(
+                bootstrap.cart_collection(),
+                bootstrap.product_collection(),
+                customer.user_access(),
+                Json(super::CreateRequest {
+                    product_id: product.id,
+                    quantity: crate::util::BigIntString(quantity.into()),
+                }),
+            )
+            .await
+            .unwrap();
+        }
+
+        let Json(response) = super::checkout(
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            customer.user_access(),
+            IdempotencyKey(None),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(response.orders.len(), 2);
+        assert_eq!(response.orders[0].merchant_id, bootstrap.user_id());
+        assert_eq!(response.orders[0].price.0, Decimal::from(2_000));
+        assert_eq!(response.orders[1].merchant_id, merchant.user_id());
+        assert_eq!(response.orders[1].price.0, Decimal::from(500));
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(500));
+
+        let Json(response) = super::index(customer.user_access(), bootstrap.cart_collection())
+            .await
+            .unwrap();
+        assert_eq!(response.carts.len(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_checkout_is_all_or_nothing() {
+        let bootstrap = bootstrap().await;
+        let merchant = bootstrap.derive_customer().await;
+
+        let first = bootstrap.create_product(1000, 1).await;
+        let second = merchant.create_product(1000, 1).await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(1_500))
+            .await;
+
+        for product in [&first, &second] {
+            let _ = super::create(
+                bootstrap.cart_collection(),
+                bootstrap.product_collection(),
+                customer.user_access(),
+                Json(super::CreateRequest {
+                    product_id: product.id,
+                    quantity: crate::util::BigIntString(1.into()),
+                }),
+            )
+            .await
+            .unwrap();
+        }
+
+        let error = super::checkout(
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            customer.user_access(),
+            IdempotencyKey(None),
+        )
+        .await
+        .expect_err("balance is not enough for the whole cart");
+        assert_matches!(error, Error::InsufficientFund);
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(1_500));
+
+        let Json(response) = super::index(customer.user_access(), bootstrap.cart_collection())
+            .await
+            .unwrap();
+        assert_eq!(response.carts.len(), 2);
+
+        let count = bootstrap
+            .app_state
+            .transaction_collection
+            .count_documents(None, None)
+            .await
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[tokio::test]
+    async fn test_checkout_replayed_until_cart_changes() {
+        let bootstrap = bootstrap().await;
+        let product = bootstrap.create_product(1000, 10).await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(3_000))
+            .await;
+
+        let add_to_cart = || async {
+            let _ = super::create(
+                bootstrap.cart_collection(),
+                bootstrap.product_collection(),
+                customer.user_access(),
+                Json(super::CreateRequest {
+                    product_id: product.id,
+                    quantity: crate::util::BigIntString(1.into()),
+                }),
+            )
+            .await
+            .unwrap();
+        };
+
+        let checkout = || {
+            super::checkout(
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                customer.user_access(),
+                IdempotencyKey(Some("checkout-1".to_string())),
+            )
+        };
+
+        add_to_cart().await;
+        let Json(first) = checkout().await.unwrap();
+        let Json(second) = checkout().await.unwrap();
+
+        assert_eq!(first.orders.len(), 1);
+        assert_eq!(first.orders[0].id, second.orders[0].id);
+
+        let balance = || async {
+            crate::api::v1::auth::UserModel::from_id(
+                customer.user_id(),
+                &bootstrap.app_state.user_collection,
+            )
+            .await
+            .unwrap()
+            .balance
+        };
+        assert_eq!(balance().await, Decimal::from(2_000));
+
+        // the cart isn't the one the key was used for anymore.
+        add_to_cart().await;
+        let error = checkout().await.expect_err("key used for another cart");
+        assert_matches!(error, Error::IdempotencyKeyReused);
+
+        assert_eq!(balance().await, Decimal::from(2_000));
+    }
 }
@@ -0,0 +1,274 @@
+//! Platform commission, deducted from the merchant payout when a transaction completes and
+//! credited to the platform revenue account.
+
+use std::collections::HashMap;
+
+use axum::{extract::State, Json};
+use bson::oid::ObjectId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    error::Error,
+    mongo_ext::Collection,
+    util::{DecimalString, FormattedDateTime, ObjectIdString},
+};
+
+use super::{
+    auth::{UserAccess, UserRole},
+    transaction::Transaction,
+};
+
+/// Commission table, loaded from the `PLATFORM_COMMISSION` environment variable as json.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CommissionRate {
+    /// Percentage of the price, e.g. `5` for 5%.
+    pub percent: Decimal,
+    /// Percentage for products of a category, overriding `percent`.
+    #[serde(default)]
+    pub categories: HashMap<String, Decimal>,
+}
+
+impl Default for CommissionRate {
+    fn default() -> Self {
+        Self {
+            percent: Decimal::from(5),
+            categories: HashMap::new(),
+        }
+    }
+}
+
+impl CommissionRate {
+    pub fn new_from_env() -> Self {
+        match std::env::var("PLATFORM_COMMISSION") {
+            Ok(it) => serde_json::from_str(&it)
+                .expect("PLATFORM_COMMISSION must be a valid commission table."),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn percent_of(&self, category: Option<&str>) -> Decimal {
+        category
+            .and_then(|it| self.categories.get(it))
+            .copied()
+            .unwrap_or(self.percent)
+    }
+
+    /// Commission taken from the price of `transaction`, calculated for each line item with
+    /// the percentage of its category.
+    pub fn commission(&self, transaction: &Transaction) -> Decimal {
+        let lines = transaction
+            .products
+            .iter()
+            .map(|it| {
+                let quantity =
+                    Decimal::from_str_exact(&it.quantity.to_string()).unwrap_or_default();
+                (it.price * quantity, self.percent_of(it.category.as_deref()))
+            })
+            .collect::<Vec<_>>();
+
+        let lines_price = lines.iter().map(|it| it.0).sum::<Decimal>();
+
+        // transactions made before line items kept their price only know the total.
+        let commission = if lines_price == transaction.price {
+            lines
+                .into_iter()
+                .map(|(price, percent)| price * percent)
+                .sum::<Decimal>()
+        } else {
+            transaction.price * self.percent
+        };
+
+        (commission / Decimal::ONE_HUNDRED)
+            .round_dp(2)
+            .min(transaction.price)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct PlatformRevenueModel {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub transaction_id: ObjectId,
+    pub merchant_id: ObjectId,
+    pub amount: Decimal,
+
+    pub created_at: bson::DateTime,
+}
+
+/// The platform revenue account, one entry for every commission taken.
+#[derive(Clone)]
+pub struct PlatformRevenueCollection(pub Collection<PlatformRevenueModel>);
+
+impl std::ops::Deref for PlatformRevenueCollection {
+    type Target = Collection<PlatformRevenueModel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl PlatformRevenueCollection {
+    /// Must be called in the same mongo transaction as the merchant payout.
+    pub async fn insert_with_session(
+        &self,
+        transaction: &Transaction,
+        amount: Decimal,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<(), Error> {
+        self.insert_one_with_session(
+            PlatformRevenueModel {
+                id: ObjectId::new(),
+                transaction_id: transaction.id,
+                merchant_id: transaction.merchant_id,
+                amount,
+                created_at: OffsetDateTime::now_utc().into(),
+            },
+            None,
+            session,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PlatformRevenue {
+    pub id: ObjectIdString,
+    pub transaction_id: ObjectIdString,
+    pub merchant_id: ObjectIdString,
+    pub amount: DecimalString,
+
+    pub created_at: FormattedDateTime,
+}
+
+impl From<PlatformRevenueModel> for PlatformRevenue {
+    fn from(value: PlatformRevenueModel) -> Self {
+        Self {
+            id: value.id.into(),
+            transaction_id: value.transaction_id.into(),
+            merchant_id: value.merchant_id.into(),
+            amount: value.amount.into(),
+            created_at: value.created_at.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RevenueResponse {
+    pub total: DecimalString,
+    /// Newest first.
+    pub revenues: Vec<PlatformRevenue>,
+}
+
+pub async fn index(
+    State(revenues): State<PlatformRevenueCollection>,
+    user: UserAccess,
+) -> Result<Json<RevenueResponse>, Error> {
+    match user.role {
+        UserRole::Admin => {}
+        UserRole::Customer | UserRole::Courier => return Err(Error::Forbidden),
+    }
+
+    let mut cursor = revenues
+        .find(
+            None,
+            mongodb::options::FindOptions::builder()
+                .sort(bson::doc! { "created_at": -1 })
+                .build(),
+        )
+        .await?;
+
+    let mut models = vec![];
+
+    while cursor.advance().await? {
+        models.push(cursor.deserialize_current()?);
+    }
+
+    Ok(Json(RevenueResponse {
+        total: models
+            .iter()
+            .map(|it: &PlatformRevenueModel| it.amount)
+            .sum::<Decimal>()
+            .into(),
+        revenues: models.into_iter().map(Into::into).collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use bson::oid::ObjectId;
+    use num_bigint::BigInt;
+    use rust_decimal::Decimal;
+
+    use crate::{
+        api::v1::{
+            tests::{bootstrap, TransactionBuilder},
+            transaction::{ProductTransaction, Transaction},
+        },
+        error::Error,
+    };
+
+    use super::CommissionRate;
+
+    fn line(price: i64, quantity: i64, category: Option<&str>) -> ProductTransaction {
+        ProductTransaction {
+            id: ObjectId::new(),
+            quantity: BigInt::from(quantity),
+            price: Decimal::from(price),
+            name: "".to_string(),
+            description: "".to_string(),
+            category: category.map(ToString::to_string),
+        }
+    }
+
+    fn transaction(price: i64, products: Vec<ProductTransaction>) -> Transaction {
+        TransactionBuilder::default()
+            .price(Decimal::from(price))
+            .products(products)
+            .build()
+    }
+
+    #[test]
+    fn test_commission_per_category() {
+        let rate = CommissionRate {
+            percent: Decimal::from(5),
+            categories: [("electronics".to_string(), Decimal::new(25, 1))].into(),
+        };
+
+        let it = transaction(
+            30_000,
+            vec![
+                line(10_000, 2, Some("electronics")),
+                line(5_000, 1, Some("food")),
+                line(5_000, 1, None),
+            ],
+        );
+
+        // 2.5% of 20_000 + 5% of 10_000
+        assert_eq!(rate.commission(&it), Decimal::from(1_000));
+    }
+
+    #[test]
+    fn test_commission_without_line_price() {
+        let rate = CommissionRate::default();
+
+        // orders made before line items kept their price.
+        let it = transaction(10_000, vec![line(0, 1, Some("electronics"))]);
+
+        assert_eq!(rate.commission(&it), Decimal::from(500));
+    }
+
+    #[tokio::test]
+    pub async fn test_only_admin_see_revenue() {
+        let customer = bootstrap().await.derive_customer().await;
+
+        let error = super::index(customer.state(), customer.user_access())
+            .await
+            .expect_err("only admin can see the platform revenue");
+        assert_matches!(error, Error::Forbidden);
+    }
+}
@@ -0,0 +1,287 @@
+//! Courier earnings. Every completed delivery credit its shipping fee to the courier and
+//! leave an entry here, so the courier can see what they earned and for which delivery.
+
+use std::collections::BTreeMap;
+
+use axum::{extract::State, Json};
+use bson::oid::ObjectId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use time::{Date, OffsetDateTime};
+
+use crate::{
+    error::Error,
+    mongo_ext::Collection,
+    util::{DecimalString, FormattedDateTime, ObjectIdString},
+};
+
+use super::auth::{UserAccess, UserRole};
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CourierEarningModel {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub courier_id: ObjectId,
+    pub transaction_id: ObjectId,
+    pub amount: Decimal,
+
+    pub created_at: bson::DateTime,
+}
+
+#[derive(Clone)]
+pub struct CourierEarningCollection(pub Collection<CourierEarningModel>);
+
+impl std::ops::Deref for CourierEarningCollection {
+    type Target = Collection<CourierEarningModel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl CourierEarningCollection {
+    /// Record `amount` earned by `courier_id` for delivering `transaction_id`. Must be
+    /// called in the same mongo transaction as the balance change.
+    pub async fn insert_with_session(
+        &self,
+        courier_id: ObjectId,
+        transaction_id: ObjectId,
+        amount: Decimal,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<(), Error> {
+        self.insert_one_with_session(
+            CourierEarningModel {
+                id: ObjectId::new(),
+                courier_id,
+                transaction_id,
+                amount,
+                created_at: OffsetDateTime::now_utc().into(),
+            },
+            None,
+            session,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct CourierEarning {
+    pub id: ObjectIdString,
+    pub transaction_id: ObjectIdString,
+    pub amount: DecimalString,
+
+    pub created_at: FormattedDateTime,
+}
+
+impl From<CourierEarningModel> for CourierEarning {
+    fn from(value: CourierEarningModel) -> Self {
+        Self {
+            id: value.id.into(),
+            transaction_id: value.transaction_id.into(),
+            amount: value.amount.into(),
+            created_at: value.created_at.into(),
+        }
+    }
+}
+
+/// Total earned in a day or in a week starting on monday, in UTC.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct EarningPeriod {
+    /// First day of the period, formatted as `YYYY-MM-DD`.
+    pub start: String,
+    pub deliveries: u64,
+    pub total: DecimalString,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EarningsResponse {
+    pub total: DecimalString,
+    /// Newest first.
+    pub daily: Vec<EarningPeriod>,
+    /// Newest first.
+    pub weekly: Vec<EarningPeriod>,
+    /// Newest first.
+    pub earnings: Vec<CourierEarning>,
+}
+
+/// Group `earnings` into periods, `start_of` maps a day to the first day of its period.
+fn group_by_period(
+    earnings: &[CourierEarningModel],
+    start_of: impl Fn(Date) -> Date,
+) -> Vec<EarningPeriod> {
+    let mut periods = BTreeMap::<Date, (u64, Decimal)>::new();
+
+    for it in earnings {
+        let day = OffsetDateTime::from(it.created_at).date();
+        let period = periods.entry(start_of(day)).or_default();
+        period.0 += 1;
+        period.1 += it.amount;
+    }
+
+    periods
+        .into_iter()
+        .rev()
+        .map(|(start, (deliveries, total))| EarningPeriod {
+            start: start.to_string(),
+            deliveries,
+            total: total.into(),
+        })
+        .collect()
+}
+
+fn start_of_week(day: Date) -> Date {
+    day - time::Duration::days(day.weekday().number_days_from_monday().into())
+}
+
+/// Earnings of the current courier, with totals per day and per week.
+pub async fn index(
+    State(earnings): State<CourierEarningCollection>,
+    user: UserAccess,
+) -> Result<Json<EarningsResponse>, Error> {
+    match user.role {
+        UserRole::Customer => return Err(Error::Forbidden),
+        UserRole::Courier | UserRole::Admin => {}
+    }
+
+    let mut cursor = earnings
+        .find(
+            bson::doc! {
+                "courier_id": user.id,
+            },
+            mongodb::options::FindOptions::builder()
+                .sort(bson::doc! { "created_at": -1 })
+                .build(),
+        )
+        .await?;
+
+    let mut models = vec![];
+
+    while cursor.advance().await? {
+        models.push(cursor.deserialize_current()?);
+    }
+
+    Ok(Json(EarningsResponse {
+        total: models.iter().map(|it| it.amount).sum::<Decimal>().into(),
+        daily: group_by_period(&models, |it| it),
+        weekly: group_by_period(&models, start_of_week),
+        earnings: models.into_iter().map(Into::into).collect(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use axum::Json;
+    use bson::oid::ObjectId;
+    use rust_decimal::Decimal;
+    use time::{Date, Month, OffsetDateTime};
+
+    use crate::{
+        api::v1::{shipping::ShippingRate, tests::bootstrap},
+        error::Error,
+    };
+
+    use super::{group_by_period, start_of_week, CourierEarningModel};
+
+    fn may_2023(day: u8, hour: u8) -> OffsetDateTime {
+        Date::from_calendar_date(2023, Month::May, day)
+            .unwrap()
+            .with_hms(hour, 0, 0)
+            .unwrap()
+            .assume_utc()
+    }
+
+    fn earning(created_at: OffsetDateTime, amount: i64) -> CourierEarningModel {
+        CourierEarningModel {
+            id: ObjectId::new(),
+            courier_id: ObjectId::new(),
+            transaction_id: ObjectId::new(),
+            amount: Decimal::from(amount),
+            created_at: created_at.into(),
+        }
+    }
+
+    #[test]
+    fn test_group_by_period() {
+        // 2023-05-01 is a monday
+        let earnings = [
+            earning(may_2023(8, 9), 8_000),
+            earning(may_2023(7, 23), 15_000),
+            earning(may_2023(1, 0), 8_000),
+            earning(may_2023(1, 18), 10_000),
+        ];
+
+        let daily = group_by_period(&earnings, |it| it);
+        assert_eq!(
+            daily
+                .iter()
+                .map(|it| (it.start.as_str(), it.deliveries, it.total.0))
+                .collect::<Vec<_>>(),
+            vec![
+                ("2023-05-08", 1, Decimal::from(8_000)),
+                ("2023-05-07", 1, Decimal::from(15_000)),
+                ("2023-05-01", 2, Decimal::from(18_000)),
+            ]
+        );
+
+        let weekly = group_by_period(&earnings, start_of_week);
+        assert_eq!(
+            weekly
+                .iter()
+                .map(|it| (it.start.as_str(), it.deliveries, it.total.0))
+                .collect::<Vec<_>>(),
+            vec![
+                ("2023-05-08", 1, Decimal::from(8_000)),
+                ("2023-05-01", 3, Decimal::from(33_000)),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_courier_earn_on_delivery() {
+        let mut root = bootstrap().await;
+        root.app_state.shipping_rate = ShippingRate::default();
+
+        let merchant = root.derive_customer().await;
+        let courier = root.derive_courier().await;
+        let customer = root
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer
+            .create_pickedup_transaction(&merchant, &courier, 1)
+            .await;
+
+        let Json(before) = super::index(courier.state(), courier.user_access())
+            .await
+            .unwrap();
+        assert!(before.earnings.is_empty());
+
+        courier.arrive(*transaction.id).await.unwrap();
+
+        let Json(after) = super::index(courier.state(), courier.user_access())
+            .await
+            .unwrap();
+        assert_eq!(after.earnings.len(), 1);
+        assert_eq!(after.earnings[0].transaction_id, transaction.id);
+        assert_eq!(after.earnings[0].amount.0, Decimal::from(8_000));
+        assert_eq!(after.total.0, Decimal::from(8_000));
+        assert_eq!(after.daily.len(), 1);
+        assert_eq!(after.daily[0].total.0, Decimal::from(8_000));
+        assert_eq!(after.weekly[0].deliveries, 1);
+    }
+
+    #[tokio::test]
+    pub async fn test_customer_cannot_see_earnings() {
+        let customer = bootstrap().await.derive_customer().await;
+
+        let error = super::index(customer.state(), customer.user_access())
+            .await
+            .expect_err("customer has no earnings");
+        assert_matches!(error, Error::Forbidden);
+    }
+}
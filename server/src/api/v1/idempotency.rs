@@ -0,0 +1,165 @@
+//! `Idempotency-Key` support, so a client can safely retry a request that charge the
+//! user without doing it twice.
+
+use axum::{extract::FromRequestParts, http::request::Parts, http::StatusCode};
+use bson::oid::ObjectId;
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::{
+    error::Error,
+    mongo_ext::{is_duplicate_key, Collection},
+};
+
+/// How long a key is remembered before the TTL index remove it.
+pub const IDEMPOTENCY_KEY_TTL: std::time::Duration = std::time::Duration::from_secs(24 * 60 * 60);
+
+/// Value of the `Idempotency-Key` header, `None` when the client didn't send one.
+#[derive(Debug, Clone)]
+pub struct IdempotencyKey(pub Option<String>);
+
+#[axum::async_trait]
+impl<S> FromRequestParts<S> for IdempotencyKey
+where
+    S: Send + Sync,
+{
+    type Rejection = Error;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let key = match parts.headers.get("Idempotency-Key") {
+            Some(it) => it,
+            None => return Ok(Self(None)),
+        };
+
+        let key = key
+            .to_str()
+            .ok()
+            .filter(|it| !it.is_empty() && it.len() <= 255)
+            .ok_or(Error::CustomStr(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Idempotency-Key must be between 1 and 255 visible ascii characters",
+            ))?;
+
+        Ok(Self(Some(key.to_string())))
+    }
+}
+
+/// Endpoint a key belongs to, the same key may be used once for each.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum IdempotencyScope {
+    Order,
+    Checkout,
+    TopUp,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+pub struct IdempotencyModel<T> {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user_id: ObjectId,
+    pub scope: IdempotencyScope,
+    pub key: String,
+    pub request_hash: String,
+    pub response: T,
+
+    pub created_at: bson::DateTime,
+}
+
+#[derive(Clone)]
+pub struct IdempotencyCollection(pub Collection<IdempotencyModel<bson::Bson>>);
+
+impl std::ops::Deref for IdempotencyCollection {
+    type Target = Collection<IdempotencyModel<bson::Bson>>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Hash of the request body, used to detect a key reused for another request.
+pub fn request_hash(request: &impl Serialize) -> Result<String, Error> {
+    use base64::Engine;
+
+    let body = serde_json::to_vec(request)
+        .map_err(|err| Error::CustomStatus(StatusCode::INTERNAL_SERVER_ERROR, err.into()))?;
+
+    Ok(base64::engine::general_purpose::STANDARD.encode(Sha256::digest(body)))
+}
+
+impl IdempotencyCollection {
+    /// Returns the response stored for `key`, or `None` when the key hasn't been used
+    /// yet. Fails with [`Error::IdempotencyKeyReused`] when the key was used for a
+    /// request with another hash.
+    pub async fn find_response_with_session<T>(
+        &self,
+        user_id: ObjectId,
+        scope: IdempotencyScope,
+        key: &str,
+        request_hash: &str,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<Option<T>, Error>
+    where
+        T: DeserializeOwned + Send + Sync + Unpin,
+    {
+        let model = self
+            .clone_with_type::<IdempotencyModel<T>>()
+            .find_one_with_session(
+                bson::doc! {
+                    "user_id": user_id,
+                    "scope": bson::to_bson(&scope)?,
+                    "key": key,
+                },
+                None,
+                session,
+            )
+            .await?;
+
+        match model {
+            Some(model) if model.request_hash != request_hash => Err(Error::IdempotencyKeyReused),
+            Some(model) => Ok(Some(model.response)),
+            None => Ok(None),
+        }
+    }
+
+    /// Remember `response` as the result of `key`. Must be called in the same mongo
+    /// transaction as the request it belongs to. Fails with a `409` when a concurrent request
+    /// with the same key finished first, its retry then replays that response.
+    pub async fn insert_response_with_session<T>(
+        &self,
+        user_id: ObjectId,
+        scope: IdempotencyScope,
+        key: &str,
+        request_hash: &str,
+        response: &T,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<(), Error>
+    where
+        T: Serialize,
+    {
+        let result = self
+            .insert_one_with_session(
+                IdempotencyModel {
+                    id: ObjectId::new(),
+                    user_id,
+                    scope,
+                    key: key.to_string(),
+                    request_hash: request_hash.to_string(),
+                    response: bson::to_bson(response)?,
+                    created_at: time::OffsetDateTime::now_utc().into(),
+                },
+                None,
+                session,
+            )
+            .await;
+
+        match result {
+            Ok(_) => Ok(()),
+            Err(err) if is_duplicate_key(&err) => Err(Error::CustomStr(
+                StatusCode::CONFLICT,
+                "A request with this Idempotency-Key is already being processed",
+            )),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
@@ -0,0 +1,639 @@
+//! Wallet ledger. Every money movement is a transfer of immutable debit and credit entries
+//! that sum to zero, written in the same mongo transaction as the balance change. Money
+//! only leave or enter a wallet through [`Ledger::transfer_with_session`].
+
+use axum::{
+    extract::{FromRef, Query, State},
+    http::StatusCode,
+    Json,
+};
+use bson::oid::ObjectId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    app::AppState,
+    error::Error,
+    mongo_ext::Collection,
+    util::{decimal128, DecimalString, FormattedDateTime, ObjectIdString},
+};
+
+use super::auth::{UserCollection, UserModel};
+
+pub const DEFAULT_LIMIT: u32 = 50;
+pub const MAX_LIMIT: u32 = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "type", content = "user_id")]
+pub enum LedgerAccount {
+    /// Balance of a user.
+    Wallet(ObjectId),
+    /// Paid by buyers and not yet released to merchants and couriers.
+    Escrow,
+    /// Commission kept by the platform.
+    Revenue,
+    /// Held for requested withdrawals until an admin approves or rejects them.
+    Withdrawal,
+    /// Outside of the platform, e.g. money added or removed by an admin.
+    External,
+}
+
+/// A debit takes money out of an account, a credit puts money in.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerEntryType {
+    Debit,
+    Credit,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LedgerReason {
+    Purchase,
+    Payout,
+    Refund,
+    Adjustment,
+    /// Paid through a [`crate::payment::PaymentProvider`].
+    TopUp,
+    /// Held when requested, then paid out once approved.
+    Withdrawal,
+    /// Hold of a rejected withdrawal given back.
+    WithdrawalRejected,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LedgerEntryModel {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    /// Shared by every entry of the same transfer.
+    pub transfer_id: ObjectId,
+    pub account: LedgerAccount,
+    pub r#type: LedgerEntryType,
+    /// Always positive, [`Self::type`] tells the direction.
+    pub amount: Decimal,
+    /// Balance of the wallet right after this entry, `None` for the other accounts.
+    pub balance: Option<Decimal>,
+    pub reason: LedgerReason,
+    pub transaction_id: Option<ObjectId>,
+    /// `None` when made by the server.
+    pub by: Option<ObjectId>,
+
+    pub created_at: bson::DateTime,
+}
+
+#[derive(Clone)]
+pub struct LedgerEntryCollection(pub Collection<LedgerEntryModel>);
+
+impl std::ops::Deref for LedgerEntryCollection {
+    type Target = Collection<LedgerEntryModel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Money moved from the `debit` accounts to the `credit` accounts, both sides must add up
+/// to the same amount.
+#[derive(Debug, Clone)]
+pub struct Transfer {
+    pub reason: LedgerReason,
+    pub transaction_id: Option<ObjectId>,
+    pub by: Option<ObjectId>,
+    pub debit: Vec<(LedgerAccount, Decimal)>,
+    pub credit: Vec<(LedgerAccount, Decimal)>,
+}
+
+impl Transfer {
+    /// `amount` moved from `from` to `to`.
+    pub fn new(
+        reason: LedgerReason,
+        from: LedgerAccount,
+        to: LedgerAccount,
+        amount: Decimal,
+    ) -> Self {
+        Self {
+            reason,
+            transaction_id: None,
+            by: None,
+            debit: vec![(from, amount)],
+            credit: vec![(to, amount)],
+        }
+    }
+
+    pub fn transaction(self, transaction_id: ObjectId) -> Self {
+        Self {
+            transaction_id: Some(transaction_id),
+            ..self
+        }
+    }
+
+    pub fn by(self, by: ObjectId) -> Self {
+        Self {
+            by: Some(by),
+            ..self
+        }
+    }
+
+    fn check(&self) -> Result<(), Error> {
+        let legs = || self.debit.iter().chain(self.credit.iter());
+
+        let debit = self.debit.iter().map(|it| it.1).sum::<Decimal>();
+        let credit = self.credit.iter().map(|it| it.1).sum::<Decimal>();
+
+        if legs().any(|it| it.1.is_sign_negative()) || debit != credit {
+            return Err(Error::CustomStatus(
+                StatusCode::INTERNAL_SERVER_ERROR,
+                anyhow::anyhow!("unbalanced transfer {self:?}"),
+            ));
+        }
+
+        Ok(())
+    }
+}
+
+/// The wallets and their history.
+#[derive(Clone)]
+pub struct Ledger {
+    pub users: UserCollection,
+    pub entries: LedgerEntryCollection,
+}
+
+impl FromRef<AppState> for Ledger {
+    fn from_ref(input: &AppState) -> Self {
+        Self {
+            users: input.user_collection.clone(),
+            entries: input.ledger_entry_collection.clone(),
+        }
+    }
+}
+
+impl Ledger {
+    /// Apply `transfer` to the wallets it touches with `$inc` and record its entries. A wallet
+    /// is never debited below 0, [`Error::InsufficientFund`] is returned instead. Must be
+    /// called inside a mongo transaction.
+    pub async fn transfer_with_session(
+        &self,
+        transfer: Transfer,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<(), Error> {
+        transfer.check()?;
+
+        let transfer_id = ObjectId::new();
+        let created_at = OffsetDateTime::now_utc().into();
+
+        let legs = transfer
+            .debit
+            .iter()
+            .map(|it| (LedgerEntryType::Debit, it))
+            .chain(
+                transfer
+                    .credit
+                    .iter()
+                    .map(|it| (LedgerEntryType::Credit, it)),
+            );
+
+        let mut entries = vec![];
+
+        for (r#type, (account, amount)) in legs {
+            if amount.is_zero() {
+                continue;
+            }
+
+            let balance = match account {
+                LedgerAccount::Wallet(user_id) => Some(
+                    self.apply_with_session(*user_id, r#type, *amount, session)
+                        .await?,
+                ),
+                _ => None,
+            };
+
+            entries.push(LedgerEntryModel {
+                id: ObjectId::new(),
+                transfer_id,
+                account: *account,
+                r#type,
+                amount: *amount,
+                balance,
+                reason: transfer.reason,
+                transaction_id: transfer.transaction_id,
+                by: transfer.by,
+                created_at,
+            });
+        }
+
+        if !entries.is_empty() {
+            self.entries
+                .insert_many_with_session(entries, None, session)
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Change the balance of `user_id` by `amount`, returning the new balance.
+    async fn apply_with_session(
+        &self,
+        user_id: ObjectId,
+        r#type: LedgerEntryType,
+        amount: Decimal,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<Decimal, Error> {
+        let (filter, change) = match r#type {
+            LedgerEntryType::Debit => (
+                bson::doc! {
+                    "_id": user_id,
+                    "balance": { "$gte": decimal128::to_bson(&amount) },
+                },
+                -amount,
+            ),
+            LedgerEntryType::Credit => (bson::doc! { "_id": user_id }, amount),
+        };
+
+        let user = self
+            .users
+            .find_one_and_update_with_session(
+                filter,
+                bson::doc! { "$inc": { "balance": decimal128::to_bson(&change) } },
+                mongodb::options::FindOneAndUpdateOptions::builder()
+                    .return_document(mongodb::options::ReturnDocument::After)
+                    .build(),
+                session,
+            )
+            .await?;
+
+        if let Some(user) = user {
+            return Ok(user.balance);
+        }
+
+        let exists = self
+            .users
+            .find_one_with_session(bson::doc! { "_id": user_id }, None, session)
+            .await?
+            .is_some();
+
+        Err(if exists {
+            Error::InsufficientFund
+        } else {
+            Error::NoResource
+        })
+    }
+
+    /// Set the balance of `user_id` to `balance` as an adjustment made by `by`, returning
+    /// the user with its new balance. Must be called inside a mongo transaction.
+    pub async fn adjust_with_session(
+        &self,
+        user_id: ObjectId,
+        balance: Decimal,
+        by: ObjectId,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<UserModel, Error> {
+        if balance.is_sign_negative() {
+            return Err(Error::CustomStr(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Balance can't be negative",
+            ));
+        }
+
+        let mut user = self
+            .users
+            .find_one_with_session(bson::doc! { "_id": user_id }, None, session)
+            .await?
+            .ok_or(Error::NoResource)?;
+
+        let wallet = LedgerAccount::Wallet(user_id);
+        let change = balance - user.balance;
+
+        let transfer = if change.is_sign_negative() {
+            Transfer::new(
+                LedgerReason::Adjustment,
+                wallet,
+                LedgerAccount::External,
+                -change,
+            )
+        } else {
+            Transfer::new(
+                LedgerReason::Adjustment,
+                LedgerAccount::External,
+                wallet,
+                change,
+            )
+        };
+
+        self.transfer_with_session(transfer.by(by), session).await?;
+
+        user.balance = balance;
+
+        Ok(user)
+    }
+}
+
+impl LedgerEntryCollection {
+    /// Entry of the balance `user` had before the ledger existed.
+    pub async fn insert_opening_balance_with_session(
+        &self,
+        user: &UserModel,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<(), mongodb::error::Error> {
+        let transfer_id = ObjectId::new();
+        let created_at = OffsetDateTime::now_utc().into();
+        let (r#type, other) = if user.balance.is_sign_negative() {
+            (LedgerEntryType::Debit, LedgerEntryType::Credit)
+        } else {
+            (LedgerEntryType::Credit, LedgerEntryType::Debit)
+        };
+
+        let entry = |account, r#type, balance| LedgerEntryModel {
+            id: ObjectId::new(),
+            transfer_id,
+            account,
+            r#type,
+            amount: user.balance.abs(),
+            balance,
+            reason: LedgerReason::Adjustment,
+            transaction_id: None,
+            by: None,
+            created_at,
+        };
+
+        self.insert_many_with_session(
+            [
+                entry(LedgerAccount::External, other, None),
+                entry(LedgerAccount::Wallet(user.id), r#type, Some(user.balance)),
+            ],
+            None,
+            session,
+        )
+        .await?;
+
+        Ok(())
+    }
+
+    /// Entries of the money `transaction_id` had in escrow before the ledger existed.
+    pub async fn insert_opening_escrow_with_session(
+        &self,
+        transaction_id: ObjectId,
+        amount: Decimal,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<(), mongodb::error::Error> {
+        let transfer_id = ObjectId::new();
+        let created_at = OffsetDateTime::now_utc().into();
+
+        let entry = |account, r#type| LedgerEntryModel {
+            id: ObjectId::new(),
+            transfer_id,
+            account,
+            r#type,
+            amount,
+            balance: None,
+            reason: LedgerReason::Adjustment,
+            transaction_id: Some(transaction_id),
+            by: None,
+            created_at,
+        };
+
+        self.insert_many_with_session(
+            [
+                entry(LedgerAccount::External, LedgerEntryType::Debit),
+                entry(LedgerAccount::Escrow, LedgerEntryType::Credit),
+            ],
+            None,
+            session,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+/// A wallet entry, as seen by its owner.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct LedgerEntry {
+    pub id: ObjectIdString,
+    pub transfer_id: ObjectIdString,
+    pub r#type: LedgerEntryType,
+    pub amount: DecimalString,
+    pub balance: DecimalString,
+    pub reason: LedgerReason,
+    pub transaction_id: Option<ObjectIdString>,
+
+    pub created_at: FormattedDateTime,
+}
+
+impl From<LedgerEntryModel> for LedgerEntry {
+    fn from(value: LedgerEntryModel) -> Self {
+        Self {
+            id: value.id.into(),
+            transfer_id: value.transfer_id.into(),
+            r#type: value.r#type,
+            amount: value.amount.into(),
+            balance: value.balance.unwrap_or_default().into(),
+            reason: value.reason,
+            transaction_id: value.transaction_id.map(Into::into),
+            created_at: value.created_at.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct LedgerQuery {
+    /// `next` of the previous page.
+    pub before: Option<ObjectIdString>,
+    /// Between 1 and [`MAX_LIMIT`], defaults to [`DEFAULT_LIMIT`].
+    pub limit: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LedgerResponse {
+    pub balance: DecimalString,
+    /// Newest first.
+    pub entries: Vec<LedgerEntry>,
+    /// `before` of the next page, when there is one.
+    pub next: Option<ObjectIdString>,
+}
+
+/// Wallet history of the current user.
+pub async fn index(
+    State(ledger): State<Ledger>,
+    user: UserModel,
+    Query(query): Query<LedgerQuery>,
+) -> Result<Json<LedgerResponse>, Error> {
+    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT);
+
+    let mut filter = bson::doc! {
+        "account.type": "Wallet",
+        "account.user_id": user.id,
+    };
+
+    if let Some(before) = query.before {
+        filter.insert("_id", bson::doc! { "$lt": ObjectId::from(before) });
+    }
+
+    let mut cursor = ledger
+        .entries
+        .find(
+            filter,
+            mongodb::options::FindOptions::builder()
+                .sort(bson::doc! { "_id": -1 })
+                .limit(i64::from(limit) + 1)
+                .build(),
+        )
+        .await?;
+
+    let mut entries: Vec<LedgerEntryModel> = vec![];
+
+    while cursor.advance().await? {
+        entries.push(cursor.deserialize_current()?);
+    }
+
+    let next = if entries.len() > limit as usize {
+        entries.pop();
+        entries.last().map(|it| it.id.into())
+    } else {
+        None
+    };
+
+    Ok(Json(LedgerResponse {
+        balance: user.balance.into(),
+        entries: entries.into_iter().map(Into::into).collect(),
+        next,
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use axum::extract::Query;
+    use bson::oid::ObjectId;
+    use rust_decimal::Decimal;
+
+    use crate::{
+        api::v1::tests::bootstrap, error::Error, mongo_ext::with_transaction, util::PathObjectId,
+    };
+
+    use super::{Ledger, LedgerAccount, LedgerEntryType, LedgerQuery, LedgerReason, Transfer};
+
+    #[test]
+    fn test_transfer_must_balance() {
+        let wallet = LedgerAccount::Wallet(ObjectId::new());
+
+        let transfer = Transfer::new(
+            LedgerReason::Purchase,
+            wallet,
+            LedgerAccount::Escrow,
+            Decimal::from(1_000),
+        );
+        transfer.check().unwrap();
+
+        let unbalanced = Transfer {
+            credit: vec![(LedgerAccount::Escrow, Decimal::from(900))],
+            ..transfer.clone()
+        };
+        assert_matches!(unbalanced.check(), Err(Error::CustomStatus(..)));
+
+        let negative = Transfer::new(
+            LedgerReason::Purchase,
+            wallet,
+            LedgerAccount::Escrow,
+            Decimal::from(-1_000),
+        );
+        assert_matches!(negative.check(), Err(Error::CustomStatus(..)));
+    }
+
+    #[tokio::test]
+    async fn test_order_lifecycle_recorded() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(10_000.into())
+            .await;
+        let merchant = bootstrap.derive_customer().await;
+
+        let transaction = customer.create_transaction(&merchant, 2).await;
+        let price = transaction.price.0 + transaction.shipping_fee.0;
+
+        let _ = crate::api::v1::transaction::cancel_order(
+            bootstrap.state(),
+            customer.user_access(),
+            PathObjectId(*transaction.id),
+        )
+        .await
+        .unwrap();
+
+        let axum::Json(history) = super::index(
+            bootstrap.state(),
+            customer.user_model.clone(),
+            Query(LedgerQuery::default()),
+        )
+        .await
+        .unwrap();
+
+        // newest first: refund, purchase and the balance set by the test.
+        let entries = history
+            .entries
+            .iter()
+            .map(|it| (it.reason, it.r#type, it.amount.0, it.balance.0))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            entries[..2],
+            [
+                (
+                    LedgerReason::Refund,
+                    LedgerEntryType::Credit,
+                    price,
+                    Decimal::from(10_000)
+                ),
+                (
+                    LedgerReason::Purchase,
+                    LedgerEntryType::Debit,
+                    price,
+                    Decimal::from(10_000) - price
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_debit_never_below_zero() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(1_000.into())
+            .await;
+
+        let ledger: Ledger = bootstrap.state().0;
+        let mongo = bootstrap.connection().clone();
+        let wallet = LedgerAccount::Wallet(customer.user_model.id);
+
+        let result: Result<(), Error> = async {
+            with_transaction!(mongo, |session| {
+                ledger
+                    .transfer_with_session(
+                        Transfer::new(
+                            LedgerReason::Purchase,
+                            wallet,
+                            LedgerAccount::Escrow,
+                            Decimal::from(1_001),
+                        ),
+                        &mut session,
+                    )
+                    .await
+            })
+        }
+        .await;
+        assert_matches!(result, Err(Error::InsufficientFund));
+
+        let history = super::index(
+            bootstrap.state(),
+            customer.user_model.clone(),
+            Query(LedgerQuery {
+                before: None,
+                limit: Some(1),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(history.balance.0, Decimal::from(1_000));
+        assert!(history.next.is_none());
+    }
+}
@@ -1,10 +1,18 @@
 pub mod account;
+pub mod address;
 pub mod auth;
 pub mod cart;
+pub mod commission;
+pub mod earning;
+pub mod idempotency;
+pub mod ledger;
 pub mod product;
+pub mod shipping;
 pub mod token;
 pub mod transaction;
 pub mod user;
+pub mod wallet;
+pub mod withdrawal;
 
 #[cfg(test)]
 pub mod tests {
@@ -152,23 +160,20 @@ pub mod tests {
         }
 
         pub async fn with_balance(mut self, balance: Decimal) -> Self {
-            self.app_state
-                .user_collection
-                .update_one(
-                    bson::doc! {
-                        "_id": self.user_id()
-                    },
-                    bson::doc! {
-                        "$set": {
-                            "balance": bson::to_bson(&balance).unwrap()
-                        }
-                    },
-                    None,
-                )
-                .await
-                .unwrap();
+            let ledger = super::ledger::Ledger::from_ref(&self.app_state);
+            let mongo = self.app_state.mongo_client.clone();
+            let id = self.user_id();
+
+            let user: Result<_, crate::error::Error> = async {
+                crate::mongo_ext::with_transaction!(mongo, |session| {
+                    ledger
+                        .adjust_with_session(id, balance, id, &mut session)
+                        .await
+                })
+            }
+            .await;
 
-            self.user_model.balance = balance;
+            self.user_model.balance = user.unwrap().balance;
 
             self
         }
@@ -227,6 +232,8 @@ pub mod tests {
                     description: "".to_string(),
                     price: Decimal::from(price),
                     stock: BigInt::from(stock).into(),
+                    weight: 0,
+                    category: None,
                 }),
             )
             .await
@@ -246,7 +253,7 @@ pub mod tests {
             );
             let mut products = vec![];
 
-            for it in 0..product {
+            for _it in 0..product {
                 let p = from.create_product(1_000, 1).await;
                 products.push(super::transaction::ProductOrderRequest {
                     product_id: p.id,
@@ -257,10 +264,18 @@ pub mod tests {
             let Json(transaction) = super::transaction::insert_order(
                 self.transaction_collection(),
                 self.product_collection(),
-                self.user_collection(),
+                self.state(),
                 self.mongo_client(),
+                self.state(),
+                self.state(),
+                self.state(),
+                self.state(),
                 self.user_model.clone(),
-                Json(super::transaction::InsertOrderRequest { products }),
+                super::idempotency::IdempotencyKey(None),
+                Json(super::transaction::InsertOrderRequest {
+                    products,
+                    address_id: None,
+                }),
             )
             .await
             .unwrap();
@@ -268,17 +283,34 @@ pub mod tests {
             transaction
         }
 
-        pub async fn create_confirmed_transaction(
+        pub async fn create_accepted_transaction(
             &self,
             merchant: &Self,
             product: i64,
         ) -> super::transaction::TransactionModel {
             let transaction = self.create_transaction(merchant, product).await;
 
+            super::transaction::accept(
+                self.state(),
+                merchant.user_access(),
+                crate::util::PathObjectId(*transaction.id),
+            )
+            .await
+            .unwrap()
+            .0
+        }
+
+        pub async fn create_confirmed_transaction(
+            &self,
+            merchant: &Self,
+            product: i64,
+        ) -> super::transaction::TransactionModel {
+            let transaction = self.create_accepted_transaction(merchant, product).await;
+
             super::transaction::confirm_processing(
                 self.state(),
                 merchant.user_access(),
-                crate::util::PathObjectId(*transaction.id.clone()),
+                crate::util::PathObjectId(*transaction.id),
             )
             .await
             .unwrap()
@@ -293,22 +325,80 @@ pub mod tests {
         ) -> super::transaction::TransactionModel {
             let transaction = self.create_confirmed_transaction(merchant, product).await;
 
-            super::transaction::pickup(
-                self.state(),
-                courier.user_access(),
-                transaction.id.clone().into(),
-            )
-            .await
-            .unwrap();
+            super::transaction::pickup(self.state(), courier.user_access(), transaction.id.into())
+                .await
+                .unwrap();
+
+            super::transaction::show_order(self.state(), self.user_access(), transaction.id.into())
+                .await
+                .unwrap()
+                .0
+        }
+
+        pub async fn create_delivered_transaction(
+            &self,
+            merchant: &Self,
+            courier: &Self,
+            product: i64,
+        ) -> super::transaction::TransactionModel {
+            let transaction = self
+                .create_pickedup_transaction(merchant, courier, product)
+                .await;
 
-            super::transaction::show_order(
+            courier.arrive(*transaction.id).await.unwrap();
+
+            super::transaction::show_order(self.state(), self.user_access(), transaction.id.into())
+                .await
+                .unwrap()
+                .0
+        }
+
+        /// Deliver `transaction` as this courier, with a proof of delivery.
+        pub async fn arrive(&self, transaction: ObjectId) -> Result<(), crate::error::Error> {
+            super::transaction::proof::arrive(
+                self.state(),
                 self.state(),
                 self.user_access(),
-                transaction.id.clone().into(),
+                crate::util::PathObjectId(transaction),
+                multipart(&[
+                    ("photo", Some("image/jpeg"), PHOTO),
+                    ("recipient_name", None, b"recipient"),
+                ])
+                .await,
             )
             .await
-            .unwrap()
-            .0
+        }
+
+        pub async fn create_returned_transaction(
+            &self,
+            merchant: &Self,
+            courier: &Self,
+            product: i64,
+        ) -> super::transaction::TransactionModel {
+            let transaction = self
+                .create_pickedup_transaction(merchant, courier, product)
+                .await;
+
+            for it in [
+                super::transaction::TransactionStatusType::SendBackToMerchant {
+                    reason: "recipient refused".to_string(),
+                },
+                super::transaction::TransactionStatusType::ArrivedInMerchant,
+            ] {
+                super::transaction::change_delivery(
+                    self.state(),
+                    courier.user_access(),
+                    transaction.id.into(),
+                    Json(super::transaction::ChangeDeliveryRequest { r#type: it }),
+                )
+                .await
+                .unwrap();
+            }
+
+            super::transaction::show_order(self.state(), self.user_access(), transaction.id.into())
+                .await
+                .unwrap()
+                .0
         }
     }
 
@@ -330,12 +420,30 @@ pub mod tests {
                 password: password.to_string(),
                 confirm_password: password.to_string(),
                 role,
-                balance: Decimal::from(0),
             },
         )
         .await
         .unwrap();
 
+        // every test user has a default address to order to.
+        let _ = super::address::create(
+            State(app.address_collection.clone()),
+            State(app.mongo_client.clone()),
+            UserAccess {
+                id: user.id,
+                role: user.role,
+            },
+            Json(super::address::AddressRequest {
+                label: "home".to_string(),
+                address: format!("{email} street"),
+                latitude: -7.2575,
+                longitude: 112.7521,
+                is_default: true,
+            }),
+        )
+        .await
+        .unwrap();
+
         let (_, token) =
             super::token::generate_refresh_token_model(&app.jwt_state, &app.argon, &user).unwrap();
 
@@ -345,12 +453,127 @@ pub mod tests {
         )
     }
 
+    /// Starts like a JPEG, enough to pass as a proof of delivery photo.
+    pub const PHOTO: &[u8] = b"\xff\xd8\xff\xe0photo";
+
+    /// A `multipart/form-data` body made of `fields`, given as `(name, content type, content)`
+    /// where only files have a content type.
+    pub async fn multipart(fields: &[(&str, Option<&str>, &[u8])]) -> axum::extract::Multipart {
+        use axum::extract::FromRequest;
+
+        const BOUNDARY: &str = "test-boundary";
+
+        let mut body = vec![];
+
+        for (name, content_type, content) in fields {
+            let header = match content_type {
+                Some(it) => format!(
+                    "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"; \
+                     filename=\"{name}\"\r\nContent-Type: {it}\r\n\r\n"
+                ),
+                None => {
+                    format!(
+                        "--{BOUNDARY}\r\nContent-Disposition: form-data; name=\"{name}\"\r\n\r\n"
+                    )
+                }
+            };
+
+            body.extend_from_slice(header.as_bytes());
+            body.extend_from_slice(content);
+            body.extend_from_slice(b"\r\n");
+        }
+
+        body.extend_from_slice(format!("--{BOUNDARY}--\r\n").as_bytes());
+
+        let request = axum::http::Request::builder()
+            .header(
+                axum::http::header::CONTENT_TYPE,
+                format!("multipart/form-data; boundary={BOUNDARY}"),
+            )
+            .body(axum::body::Body::from(body))
+            .unwrap();
+
+        axum::extract::Multipart::from_request(request, &())
+            .await
+            .unwrap()
+    }
+
+    /// Transaction that isn't stored, for the tests of pure functions. Everything is empty or
+    /// zero until set.
+    pub struct TransactionBuilder(super::transaction::Transaction);
+
+    impl Default for TransactionBuilder {
+        fn default() -> Self {
+            Self(super::transaction::Transaction {
+                id: ObjectId::new(),
+                user_id: ObjectId::new(),
+                merchant_id: ObjectId::new(),
+                courier_id: None,
+                price: Decimal::ZERO,
+                shipping_fee: Decimal::ZERO,
+                commission: Decimal::ZERO,
+                status: vec![],
+                products: vec![],
+                address: None,
+                paid_on_arrival: false,
+                created_at: bson::DateTime::now(),
+                updated_at: bson::DateTime::now(),
+            })
+        }
+    }
+
+    impl TransactionBuilder {
+        pub fn buyer(mut self, id: ObjectId) -> Self {
+            self.0.user_id = id;
+            self
+        }
+
+        pub fn merchant(mut self, id: ObjectId) -> Self {
+            self.0.merchant_id = id;
+            self
+        }
+
+        pub fn courier(mut self, id: Option<ObjectId>) -> Self {
+            self.0.courier_id = id;
+            self
+        }
+
+        pub fn price(mut self, price: Decimal) -> Self {
+            self.0.price = price;
+            self
+        }
+
+        pub fn shipping_fee(mut self, shipping_fee: Decimal) -> Self {
+            self.0.shipping_fee = shipping_fee;
+            self
+        }
+
+        /// Current and only status, changed by `by`.
+        pub fn status(
+            mut self,
+            status: super::transaction::TransactionStatusType,
+            by: Option<ObjectId>,
+        ) -> Self {
+            self.0.status = vec![super::transaction::TransactionStatus::new(status, by)];
+            self
+        }
+
+        pub fn products(mut self, products: Vec<super::transaction::ProductTransaction>) -> Self {
+            self.0.products = products;
+            self
+        }
+
+        pub fn build(self) -> super::transaction::Transaction {
+            self.0
+        }
+    }
+
     pub async fn bootstrap() -> Bootstrap {
         dotenvy::dotenv().unwrap();
         let mongodb_url = &std::env::var("MONGODB_URI")
             .expect("Cannot retreive JWT_SECRET_KEY from environment variable.");
 
-        let database_name = format!("ecommerce-test-{}", ObjectId::new().to_string());
+        let database_name = format!("ecommerce-test-{}", ObjectId::new());
         {
             let mut vec = BOOTSTRAP_LOCK.lock().unwrap();
             vec.insert(database_name.clone());
@@ -367,9 +590,36 @@ pub mod tests {
                 .unwrap(),
         );
         let jwt_state = JwtState::new_from_env();
-        let app_state = AppState::new(argon, jwt_state, mongodb_url, &database_name)
-            .await
-            .unwrap();
+        let payment_gateway =
+            crate::payment::PaymentGateway(Arc::new(crate::payment::FakeProvider::new("secret")));
+        let mut app_state = AppState::new(
+            argon,
+            jwt_state,
+            payment_gateway,
+            mongodb_url,
+            &database_name,
+        )
+        .await
+        .unwrap();
+        // orders cost exactly their product price and merchants get all of it, unless a test
+        // set its own rate.
+        app_state.shipping_rate = super::shipping::ShippingRate {
+            brackets: vec![],
+            per_km: Decimal::ZERO,
+            per_kg: Decimal::ZERO,
+        };
+        app_state.commission_rate = super::commission::CommissionRate {
+            percent: Decimal::ZERO,
+            categories: Default::default(),
+        };
+        // webhook tests receive on 127.0.0.1.
+        app_state.webhook_policy = super::transaction::webhook::WebhookPolicy {
+            allow_private: true,
+        };
+        app_state.http_client = app_state.webhook_policy.client();
+        app_state.file_storage = crate::storage::FileStorage(Arc::new(
+            crate::storage::LocalStorage::new(std::env::temp_dir().join(&database_name)),
+        ));
         let password = "password";
         let (user, session) =
             create_user(&app_state, "example@example.com", password, UserRole::Admin).await;
@@ -394,4 +644,70 @@ pub mod tests {
     pub async fn wait_bootstrap() {
         //
     }
+
+    #[tokio::test(flavor = "multi_thread")]
+    async fn test_parallel_orders_never_oversell() {
+        use assert_matches::assert_matches;
+
+        use super::transaction::{InsertOrderRequest, ProductOrderRequest};
+        use crate::error::Error;
+
+        let bootstrap = bootstrap().await;
+        let merchant = bootstrap.derive_customer().await;
+        let product = merchant.create_product(1_000, 3).await;
+
+        let mut tasks = tokio::task::JoinSet::new();
+
+        for _ in 0..10 {
+            let buyer = bootstrap
+                .derive_customer()
+                .await
+                .with_balance(Decimal::from(1_000))
+                .await;
+
+            tasks.spawn(async move {
+                super::transaction::insert_order(
+                    buyer.state(),
+                    buyer.state(),
+                    buyer.state(),
+                    buyer.state(),
+                    buyer.state(),
+                    buyer.state(),
+                    buyer.state(),
+                    buyer.state(),
+                    buyer.user_model.clone(),
+                    super::idempotency::IdempotencyKey(None),
+                    Json(InsertOrderRequest {
+                        address_id: None,
+                        products: vec![ProductOrderRequest {
+                            product_id: product.id,
+                            quantity: BigIntString(1.into()),
+                        }],
+                    }),
+                )
+                .await
+            });
+        }
+
+        let mut ordered = 0;
+
+        while let Some(result) = tasks.join_next().await {
+            match result.unwrap() {
+                Ok(_) => ordered += 1,
+                Err(err) => assert_matches!(err, Error::CustomStr(_, _)),
+            }
+        }
+
+        assert_eq!(ordered, 3);
+
+        let product = bootstrap
+            .app_state
+            .product_collection
+            .find_exists_one_by_id(*product.id)
+            .await
+            .unwrap()
+            .unwrap();
+
+        assert_eq!(product.stock, BigInt::from(0));
+    }
 }
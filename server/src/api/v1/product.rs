@@ -28,8 +28,15 @@ pub struct ProductModel {
     pub name: String,
     pub description: String,
 
+    #[serde(with = "crate::util::bigint_i64")]
     pub stock: BigInt,
     pub price: Decimal,
+    /// In gram, used for the shipping fee.
+    #[serde(default)]
+    pub weight: i64,
+    /// Used to pick the platform commission, see [`super::commission::CommissionRate`].
+    #[serde(default)]
+    pub category: Option<String>,
 
     pub created_at: bson::DateTime,
     pub updated_at: bson::DateTime,
@@ -56,6 +63,8 @@ pub struct Product {
 
     pub stock: BigIntString,
     pub price: Decimal,
+    pub weight: i64,
+    pub category: Option<String>,
 
     pub created_at: FormattedDateTime,
     pub updated_at: FormattedDateTime,
@@ -72,6 +81,8 @@ impl From<ProductModel> for Product {
 
             stock: product.stock.into(),
             price: product.price,
+            weight: product.weight,
+            category: product.category,
 
             created_at: product.created_at.into(),
             updated_at: product.updated_at.into(),
@@ -129,6 +140,11 @@ pub struct CreateRequest {
 
     pub price: Decimal,
     pub stock: BigIntString,
+    /// In gram.
+    #[serde(default)]
+    pub weight: i64,
+    #[serde(default)]
+    pub category: Option<String>,
 }
 
 #[tracing::instrument(
@@ -150,12 +166,17 @@ pub async fn create(
         super::auth::UserRole::Customer | super::auth::UserRole::Admin => {}
     }
 
-    if request.price < 0.into() || request.stock.0 < 0.into() {
+    if request.price < 0.into() || request.stock.0 < 0.into() || request.weight < 0 {
         return Err(Error::Forbidden).tap_err(|_| {
-            tracing::debug!("tried creating product with stock or price less than 0")
+            tracing::debug!("tried creating product with stock, price or weight less than 0")
         });
     }
 
+    if i64::try_from(&request.stock.0).is_err() {
+        return Err(Error::Forbidden)
+            .tap_err(|_| tracing::debug!("tried creating product with stock out of range"));
+    }
+
     let id = ObjectId::new();
 
     let model = ProductModel {
@@ -164,6 +185,8 @@ pub async fn create(
         name: request.name,
         description: request.description,
         stock: request.stock.into(),
+        weight: request.weight,
+        category: request.category,
         price: request.price,
         created_at: OffsetDateTime::now_utc().into(),
         updated_at: OffsetDateTime::now_utc().into(),
@@ -183,6 +206,11 @@ pub struct UpdateRequest {
 
     pub stock: BigIntString,
     pub price: Decimal,
+    /// In gram.
+    #[serde(default)]
+    pub weight: i64,
+    #[serde(default)]
+    pub category: Option<String>,
 }
 
 #[tracing::instrument(
@@ -206,9 +234,15 @@ pub async fn update(
         crate::api::v1::auth::UserRole::Customer | crate::api::v1::auth::UserRole::Admin => {}
     }
 
-    if request.price < 0.into() || request.stock.0 < 0.into() {
+    if request.price < 0.into() || request.stock.0 < 0.into() || request.weight < 0 {
+        return Err(Error::Forbidden).tap_err(|_| {
+            tracing::debug!("tried setting product stok, price or weight to less than 0")
+        });
+    }
+
+    if i64::try_from(&request.stock.0).is_err() {
         return Err(Error::Forbidden)
-            .tap_err(|_| tracing::debug!("tried setting product stok or price to less than 0"));
+            .tap_err(|_| tracing::debug!("tried setting product stock out of range"));
     }
 
     let product_id = ObjectId::from_str(&product_id).map_err(|_| Error::NoResource)?;
@@ -233,6 +267,8 @@ pub async fn update(
         name: request.name,
         description: request.description,
         stock: request.stock.into(),
+        weight: request.weight,
+        category: request.category,
         price: request.price,
 
         id: product.id,
@@ -336,6 +372,8 @@ mod tests {
                 description: "".to_string(),
                 price: Decimal::from(1),
                 stock: BigInt::from(1).into(),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -367,6 +405,8 @@ mod tests {
                 description: "description".to_string(),
                 price: Decimal::from(1),
                 stock: BigInt::from(1).into(),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -381,6 +421,8 @@ mod tests {
                 description: "up-description".to_string(),
                 price: Decimal::from(10),
                 stock: BigInt::from(10).into(),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -407,6 +449,8 @@ mod tests {
                 description: "description".to_string(),
                 price: Decimal::from(1),
                 stock: BigInt::from(1).into(),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -425,6 +469,8 @@ mod tests {
                 description: "up-description".to_string(),
                 price: Decimal::from(10),
                 stock: BigInt::from(10).into(),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -448,6 +494,8 @@ mod tests {
                 description: "description".to_string(),
                 price: Decimal::from(1),
                 stock: BigInt::from(1).into(),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -481,6 +529,8 @@ mod tests {
                 description: "description".to_string(),
                 price: Decimal::from(1),
                 stock: BigInt::from(1).into(),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -507,7 +557,7 @@ mod tests {
             .derive("customer@email.com", "password", UserRole::Customer)
             .await;
 
-        let Json(product) = super::create(
+        let Json(_product) = super::create(
             bootstrap.product_collection(),
             bootstrap.user_access(),
             Json(CreateRequest {
@@ -515,6 +565,8 @@ mod tests {
                 description: "".to_string(),
                 price: Decimal::from(1),
                 stock: BigInt::from(1).into(),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -548,6 +600,8 @@ mod tests {
                         description: "".to_string(),
                         price: Decimal::from(ok),
                         stock: BigInt::from(err).into(),
+                        weight: 0,
+                        category: None,
                     }),
                 )
                 .await
@@ -562,6 +616,8 @@ mod tests {
                         description: "".to_string(),
                         price: Decimal::from(ok),
                         stock: BigInt::from(err).into(),
+                        weight: 0,
+                        category: None,
                     }),
                 )
                 .await
@@ -586,6 +642,8 @@ mod tests {
                 description: "".to_string(),
                 price: Decimal::from(1),
                 stock: BigInt::from(1).into(),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -602,6 +660,8 @@ mod tests {
                         description: "up-description".to_string(),
                         price: Decimal::from(err),
                         stock: BigInt::from(ok).into(),
+                        weight: 0,
+                        category: None,
                     }),
                 )
                 .await
@@ -617,6 +677,8 @@ mod tests {
                         description: "up-description".to_string(),
                         price: Decimal::from(err),
                         stock: BigInt::from(ok).into(),
+                        weight: 0,
+                        category: None,
                     }),
                 )
                 .await
@@ -643,6 +705,8 @@ mod tests {
                 description: "description".to_string(),
                 price: Decimal::from(1),
                 stock: BigInt::from(1).into(),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -667,6 +731,8 @@ mod tests {
                 description: "up-description".to_string(),
                 price: Decimal::from(10),
                 stock: BigInt::from(10).into(),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -713,6 +779,8 @@ mod tests {
                 description: "test".to_string(),
                 price: Decimal::from(0u64),
                 stock: BigIntString(From::from(0)),
+                weight: 0,
+                category: None,
             }),
         )
         .await
@@ -0,0 +1,136 @@
+//! Shipping fee calculation, based on the distance between the merchant pickup address and
+//! the buyer address, and the parcel weight.
+
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+const EARTH_RADIUS_KM: f64 = 6371.0;
+
+/// Great circle distance in kilometer between two coordinates in degree.
+pub fn haversine_km(from: (f64, f64), to: (f64, f64)) -> f64 {
+    let (lat1, lng1) = (from.0.to_radians(), from.1.to_radians());
+    let (lat2, lng2) = (to.0.to_radians(), to.1.to_radians());
+
+    let a = ((lat2 - lat1) / 2.0).sin().powi(2)
+        + lat1.cos() * lat2.cos() * ((lng2 - lng1) / 2.0).sin().powi(2);
+
+    2.0 * EARTH_RADIUS_KM * a.sqrt().asin()
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShippingBracket {
+    /// Bracket applies to distance up to this many kilometer.
+    pub up_to_km: f64,
+    pub fee: Decimal,
+}
+
+/// Shipping rate table, loaded from the `SHIPPING_RATE` environment variable as json.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ShippingRate {
+    /// Sorted by `up_to_km`.
+    pub brackets: Vec<ShippingBracket>,
+    /// Fee for every started kilometer past the last bracket.
+    pub per_km: Decimal,
+    /// Fee for every started kilogram of the parcel.
+    pub per_kg: Decimal,
+}
+
+impl Default for ShippingRate {
+    fn default() -> Self {
+        Self {
+            brackets: vec![
+                ShippingBracket {
+                    up_to_km: 5.0,
+                    fee: Decimal::from(8_000),
+                },
+                ShippingBracket {
+                    up_to_km: 20.0,
+                    fee: Decimal::from(15_000),
+                },
+                ShippingBracket {
+                    up_to_km: 50.0,
+                    fee: Decimal::from(25_000),
+                },
+            ],
+            per_km: Decimal::from(500),
+            per_kg: Decimal::from(2_000),
+        }
+    }
+}
+
+impl ShippingRate {
+    pub fn new_from_env() -> Self {
+        match std::env::var("SHIPPING_RATE") {
+            Ok(it) => {
+                let mut rate: Self =
+                    serde_json::from_str(&it).expect("SHIPPING_RATE must be a valid rate table.");
+                rate.brackets
+                    .sort_by(|a, b| a.up_to_km.total_cmp(&b.up_to_km));
+                rate
+            }
+            Err(_) => Self::default(),
+        }
+    }
+
+    /// Fee to ship `weight_gram` over `distance_km`.
+    pub fn fee(&self, distance_km: f64, weight_gram: i64) -> Decimal {
+        let distance = match self.brackets.iter().find(|it| distance_km <= it.up_to_km) {
+            Some(bracket) => bracket.fee,
+            None => {
+                let (up_to_km, fee) = self
+                    .brackets
+                    .last()
+                    .map(|it| (it.up_to_km, it.fee))
+                    .unwrap_or_default();
+
+                fee + self.per_km * Decimal::from((distance_km - up_to_km).ceil() as i64)
+            }
+        };
+
+        distance + self.weight_fee(weight_gram)
+    }
+
+    /// Fee to ship `weight_gram` when the distance is unknown, e.g. from a merchant without
+    /// a pickup address, charged as the farthest bracket.
+    pub fn flat_fee(&self, weight_gram: i64) -> Decimal {
+        let distance = self.brackets.last().map(|it| it.fee).unwrap_or_default();
+
+        distance + self.weight_fee(weight_gram)
+    }
+
+    fn weight_fee(&self, weight_gram: i64) -> Decimal {
+        let kilogram = (weight_gram.max(0) + 999) / 1_000;
+
+        self.per_kg * Decimal::from(kilogram)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use super::{haversine_km, ShippingRate};
+
+    #[test]
+    fn test_haversine() {
+        assert_eq!(haversine_km((-7.25, 112.75), (-7.25, 112.75)), 0.0);
+
+        // surabaya to jakarta is about 660km
+        let distance = haversine_km((-7.2575, 112.7521), (-6.2088, 106.8456));
+        assert!((distance - 660.0).abs() < 10.0, "{distance}");
+    }
+
+    #[test]
+    fn test_fee() {
+        let rate = ShippingRate::default();
+
+        assert_eq!(rate.fee(0.0, 0), Decimal::from(8_000));
+        assert_eq!(rate.fee(5.0, 1), Decimal::from(10_000));
+        assert_eq!(rate.fee(10.0, 1_000), Decimal::from(17_000));
+        assert_eq!(rate.fee(10.0, 1_001), Decimal::from(19_000));
+        assert_eq!(rate.fee(52.5, 0), Decimal::from(26_500));
+
+        assert_eq!(rate.flat_fee(0), Decimal::from(25_000));
+        assert_eq!(rate.flat_fee(1_001), Decimal::from(29_000));
+    }
+}
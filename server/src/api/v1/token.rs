@@ -115,12 +115,7 @@ pub fn generate_refresh_token_model_with_exp(
     expired_at: OffsetDateTime,
 ) -> Result<(RefreshTokenModel, String), Error> {
     let id = ObjectId::new();
-    let token = generate_refresh_token_string(
-        jwt_state,
-        id.clone(),
-        user.id.clone(),
-        expired_at.unix_timestamp(),
-    )?;
+    let token = generate_refresh_token_string(jwt_state, id, user.id, expired_at.unix_timestamp())?;
 
     Ok((
         RefreshTokenModel {
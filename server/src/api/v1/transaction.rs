@@ -1,6 +1,10 @@
 use std::collections::HashMap;
 
-use axum::{extract::State, http::StatusCode, Json};
+use axum::{
+    extract::{FromRef, Query, State},
+    http::StatusCode,
+    Json,
+};
 use bson::oid::ObjectId;
 use num_bigint::BigInt;
 use rust_decimal::Decimal;
@@ -9,17 +13,40 @@ use time::OffsetDateTime;
 use validator::Validate;
 
 use crate::{
+    app::AppState,
     error::Error,
-    mongo_ext::Collection,
+    mongo_ext::{with_transaction, Collection},
     util::{BigIntString, DecimalString, FormattedDateTime, ObjectIdString, PathObjectId},
 };
 
 use super::{
-    auth::{UserAccess, UserCollection, UserModel},
+    address::{AddressCollection, AddressModel},
+    auth::{UserAccess, UserCollection, UserModel, UserRole},
+    commission::{CommissionRate, PlatformRevenueCollection},
+    earning::CourierEarningCollection,
+    idempotency::{self, IdempotencyCollection, IdempotencyKey, IdempotencyScope},
+    ledger::{Ledger, LedgerAccount, LedgerReason, Transfer},
     product::ProductCollection,
+    shipping::{self, ShippingRate},
 };
 
-#[derive(Serialize, Deserialize)]
+use self::{
+    events::TransactionEvents,
+    proof::{DeliveryProof, DeliveryProofModel},
+    query::TransactionQuery,
+    state::{Assignment, Effect},
+    webhook::Webhooks,
+};
+
+pub mod events;
+pub mod invoice;
+pub mod proof;
+pub mod query;
+pub mod report;
+pub mod state;
+pub mod webhook;
+
+#[derive(Serialize, Deserialize, Clone)]
 pub struct Transaction {
     #[serde(rename = "_id")]
     pub id: ObjectId,
@@ -27,17 +54,85 @@ pub struct Transaction {
     pub merchant_id: ObjectId,
     pub courier_id: Option<ObjectId>,
     pub price: Decimal,
+    /// Paid by the buyer on top of `price`, released to the courier on delivery.
+    #[serde(default)]
+    pub shipping_fee: Decimal,
+    /// Part of `price` kept by the platform, set when the merchant is paid.
+    #[serde(default)]
+    pub commission: Decimal,
     pub status: Vec<TransactionStatus>,
     pub products: Vec<ProductTransaction>,
+    #[serde(default)]
+    pub address: Option<TransactionAddress>,
+    /// The merchant was paid when the parcel arrived, as orders delivered before the buyer
+    /// had to confirm them were. Confirming these pays nothing again.
+    #[serde(default)]
+    pub paid_on_arrival: bool,
 
     pub created_at: bson::DateTime,
     pub updated_at: bson::DateTime,
 }
 
-#[derive(Serialize, Deserialize)]
+/// A line item of a transaction. Price, name and description are copied from the
+/// product when ordered so the order stays intact when the product is changed later.
+#[derive(Serialize, Deserialize, Clone)]
 pub struct ProductTransaction {
     pub id: ObjectId,
+    #[serde(with = "crate::util::bigint_i64")]
     pub quantity: BigInt,
+
+    #[serde(default)]
+    pub price: Decimal,
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+/// Copy of the delivery address at the time of ordering, so editing or deleting the address
+/// doesn't move an order.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TransactionAddress {
+    pub id: ObjectId,
+    pub label: String,
+    pub address: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl From<AddressModel> for TransactionAddress {
+    fn from(value: AddressModel) -> Self {
+        Self {
+            id: value.id,
+            label: value.label,
+            address: value.address,
+            latitude: value.latitude,
+            longitude: value.longitude,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TransactionAddressModel {
+    pub id: ObjectIdString,
+    pub label: String,
+    pub address: String,
+    pub latitude: f64,
+    pub longitude: f64,
+}
+
+impl From<TransactionAddress> for TransactionAddressModel {
+    fn from(value: TransactionAddress) -> Self {
+        Self {
+            id: value.id.into(),
+            label: value.label,
+            address: value.address,
+            latitude: value.latitude,
+            longitude: value.longitude,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
@@ -47,23 +142,46 @@ pub enum TransactionStatusType {
     ProcessingInMerchant,
     WaitingForCourier,
     PickedUpByCourier,
-    SendBackToMerchant,
+    /// The parcel couldn't be delivered, e.g. the recipient was unreachable or refused it.
+    SendBackToMerchant {
+        reason: String,
+    },
     ArrivedInMerchant,
     ArrivedInDestination,
     ArrivedInDestinationConfirmed,
+    Cancelled,
+    RejectedByMerchant {
+        reason: String,
+    },
+    /// The merchant refunded a parcel that was sent back.
+    Returned,
+    /// The merchant didn't process the order in time, see [`crate::scheduler`].
+    Expired,
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Clone)]
 pub struct TransactionStatus {
     r#type: TransactionStatusType,
     date: bson::DateTime,
+    /// User who made the change, `None` when it was the server itself.
+    #[serde(default)]
+    by: Option<ObjectId>,
+    /// Courier holding the parcel after the change.
+    #[serde(default)]
+    courier_id: Option<ObjectId>,
+    /// Uploaded by the courier along with [`TransactionStatusType::ArrivedInDestination`].
+    #[serde(default)]
+    proof: Option<DeliveryProof>,
 }
 
 impl TransactionStatus {
-    pub fn new(r#type: TransactionStatusType) -> Self {
+    pub fn new(r#type: TransactionStatusType, by: Option<ObjectId>) -> Self {
         Self {
             r#type,
             date: OffsetDateTime::now_utc().into(),
+            by,
+            courier_id: None,
+            proof: None,
         }
     }
 }
@@ -75,8 +193,11 @@ pub struct TransactionModel {
     pub merchant_id: ObjectIdString,
     pub courier_id: Option<ObjectIdString>,
     pub price: DecimalString,
+    pub shipping_fee: DecimalString,
+    pub commission: DecimalString,
     pub status: Vec<TransactionStatusModel>,
     pub products: Vec<ProductTransactionModel>,
+    pub address: Option<TransactionAddressModel>,
 
     pub created_at: FormattedDateTime,
     pub updated_at: FormattedDateTime,
@@ -86,6 +207,9 @@ pub struct TransactionModel {
 pub struct TransactionStatusModel {
     pub r#type: TransactionStatusType,
     date: FormattedDateTime,
+    pub by: Option<ObjectIdString>,
+    pub courier_id: Option<ObjectIdString>,
+    pub proof: Option<DeliveryProofModel>,
 }
 
 impl From<TransactionStatus> for TransactionStatusModel {
@@ -93,6 +217,9 @@ impl From<TransactionStatus> for TransactionStatusModel {
         Self {
             r#type: value.r#type,
             date: value.date.into(),
+            by: value.by.map(Into::into),
+            courier_id: value.courier_id.map(Into::into),
+            proof: value.proof.map(Into::into),
         }
     }
 }
@@ -101,6 +228,10 @@ impl From<TransactionStatus> for TransactionStatusModel {
 pub struct ProductTransactionModel {
     pub id: ObjectIdString,
     pub quantity: BigIntString,
+    pub price: DecimalString,
+    pub name: String,
+    pub description: String,
+    pub category: Option<String>,
 }
 
 impl From<Transaction> for TransactionModel {
@@ -111,8 +242,11 @@ impl From<Transaction> for TransactionModel {
             merchant_id: value.merchant_id.into(),
             courier_id: value.courier_id.map(Into::into),
             price: value.price.into(),
+            shipping_fee: value.shipping_fee.into(),
+            commission: value.commission.into(),
             status: value.status.into_iter().map(|it| it.into()).collect(),
             products: value.products.into_iter().map(|it| it.into()).collect(),
+            address: value.address.map(Into::into),
 
             created_at: value.created_at.into(),
             updated_at: value.updated_at.into(),
@@ -125,6 +259,10 @@ impl From<ProductTransaction> for ProductTransactionModel {
         Self {
             id: value.id.into(),
             quantity: value.quantity.into(),
+            price: value.price.into(),
+            name: value.name,
+            description: value.description,
+            category: value.category,
         }
     }
 }
@@ -132,6 +270,7 @@ impl From<ProductTransaction> for ProductTransactionModel {
 #[derive(Serialize, Deserialize)]
 pub struct OrderIndexResponse {
     orders: Vec<TransactionModel>,
+    next_cursor: Option<String>,
 }
 
 #[derive(Clone)]
@@ -145,31 +284,493 @@ impl std::ops::Deref for TransactionCollection {
     }
 }
 
-#[derive(thiserror::Error, Debug)]
-pub enum TransactionError {}
+#[derive(thiserror::Error, Serialize, Debug)]
+pub enum TransactionError {
+    #[error("Cannot change transaction status from {from:?} to {to:?}")]
+    IllegalTransition {
+        from: TransactionStatusType,
+        to: TransactionStatusType,
+    },
+    #[error("Cannot change the courier of a transaction in {status:?}")]
+    IllegalAssignment { status: TransactionStatusType },
+}
+
+/// Give `transaction.price` and the shipping fee back to the buyer and return every
+/// ordered quantity to the product stock. Must be called inside a mongo transaction.
+async fn refund_with_session(
+    transaction: &Transaction,
+    ledger: &Ledger,
+    products: &ProductCollection,
+    session: &mut mongodb::ClientSession,
+) -> Result<(), Error> {
+    let buyer = ledger
+        .users
+        .find_exists_one_by_id_with_session(transaction.user_id, session)
+        .await?
+        .ok_or(Error::NoResource)?;
+
+    ledger
+        .transfer_with_session(
+            Transfer::new(
+                LedgerReason::Refund,
+                LedgerAccount::Escrow,
+                LedgerAccount::Wallet(buyer.id),
+                transaction.price + transaction.shipping_fee,
+            )
+            .transaction(transaction.id),
+            session,
+        )
+        .await?;
+
+    // product may have been deleted by the merchant, nothing to restore then.
+    for it in transaction.products.iter() {
+        // ordered quantity is never more than the stock, which fit in an i64.
+        let quantity = i64::try_from(&it.quantity).map_err(|_| Error::NoResource)?;
+
+        products
+            .update_exists_one_by_id_with_session(
+                it.id,
+                bson::doc! {
+                    "$inc": {
+                        "stock": quantity
+                    }
+                },
+                None,
+                session,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Release the escrowed `transaction.price` to the merchant, minus the platform commission
+/// which is credited to the platform revenue account. Nothing is released for a deleted
+/// merchant, the price stays in escrow. Must be called inside a mongo transaction.
+async fn payout_with_session(
+    transaction: &mut Transaction,
+    ledger: &Ledger,
+    revenues: &PlatformRevenueCollection,
+    commission: &CommissionRate,
+    session: &mut mongodb::ClientSession,
+) -> Result<(), Error> {
+    if transaction.paid_on_arrival {
+        return Ok(());
+    }
+
+    transaction.commission = commission.commission(transaction);
 
-pub async fn index_order(
-    State(collection): State<TransactionCollection>,
-    user: UserAccess,
-) -> Result<Json<OrderIndexResponse>, Error> {
-    let mut cursor = collection
+    let merchant = ledger
+        .users
+        .find_exists_one_by_id_with_session(transaction.merchant_id, session)
+        .await?;
+
+    if let Some(merchant) = merchant {
+        ledger
+            .transfer_with_session(
+                Transfer {
+                    reason: LedgerReason::Payout,
+                    transaction_id: Some(transaction.id),
+                    by: None,
+                    debit: vec![(LedgerAccount::Escrow, transaction.price)],
+                    credit: vec![
+                        (
+                            LedgerAccount::Wallet(merchant.id),
+                            transaction.price - transaction.commission,
+                        ),
+                        (LedgerAccount::Revenue, transaction.commission),
+                    ],
+                },
+                session,
+            )
+            .await?;
+
+        if transaction.commission > Decimal::ZERO {
+            revenues
+                .insert_with_session(transaction, transaction.commission, session)
+                .await?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Release the escrowed `transaction.shipping_fee` to the courier holding the parcel and
+/// record it as their earning. Must be called inside a mongo transaction.
+async fn pay_courier_with_session(
+    transaction: &Transaction,
+    ledger: &Ledger,
+    earnings: &CourierEarningCollection,
+    session: &mut mongodb::ClientSession,
+) -> Result<(), Error> {
+    let courier = match transaction.courier_id {
+        Some(id) => {
+            ledger
+                .users
+                .find_exists_one_by_id_with_session(id, session)
+                .await?
+        }
+        None => None,
+    };
+
+    if let Some(courier) = courier {
+        ledger
+            .transfer_with_session(
+                Transfer::new(
+                    LedgerReason::Payout,
+                    LedgerAccount::Escrow,
+                    LedgerAccount::Wallet(courier.id),
+                    transaction.shipping_fee,
+                )
+                .transaction(transaction.id),
+                session,
+            )
+            .await?;
+
+        earnings
+            .insert_with_session(
+                courier.id,
+                transaction.id,
+                transaction.shipping_fee,
+                session,
+            )
+            .await?;
+    }
+
+    Ok(())
+}
+
+/// Everything [`change_status`] needs to apply the [`Effect`]s of a transition and publish
+/// it.
+#[derive(Clone)]
+pub struct ChangeStatusState {
+    pub transactions: TransactionCollection,
+    pub products: ProductCollection,
+    pub users: UserCollection,
+    pub ledger: Ledger,
+    pub earnings: CourierEarningCollection,
+    pub revenues: PlatformRevenueCollection,
+    pub commission: CommissionRate,
+    pub events: TransactionEvents,
+    pub webhooks: Webhooks,
+    pub mongo: mongodb::Client,
+}
+
+impl FromRef<AppState> for ChangeStatusState {
+    fn from_ref(input: &AppState) -> Self {
+        Self {
+            transactions: input.transaction_collection.clone(),
+            products: input.product_collection.clone(),
+            users: input.user_collection.clone(),
+            ledger: Ledger::from_ref(input),
+            earnings: input.courier_earning_collection.clone(),
+            revenues: input.platform_revenue_collection.clone(),
+            commission: input.commission_rate.clone(),
+            events: input.transaction_events.clone(),
+            webhooks: Webhooks::from_ref(input),
+            mongo: input.mongo_client.clone(),
+        }
+    }
+}
+
+/// Move transaction `id` to `to` as `user`. The transition is checked against
+/// [`state::transition`] and its side effects are applied in the same mongo transaction.
+async fn change_status(
+    change: &ChangeStatusState,
+    user: &UserAccess,
+    id: ObjectId,
+    to: TransactionStatusType,
+) -> Result<Transaction, Error> {
+    change_status_with_proof(change, user, id, to, None).await
+}
+
+/// Same as [`change_status`], attaching `proof` to the new status. Arriving in destination
+/// require a proof of delivery.
+async fn change_status_with_proof(
+    change: &ChangeStatusState,
+    user: &UserAccess,
+    id: ObjectId,
+    to: TransactionStatusType,
+    proof: Option<DeliveryProof>,
+) -> Result<Transaction, Error> {
+    let transaction = with_transaction!(change.mongo, |session| {
+        change_status_with_session(change, user, id, to.clone(), proof.clone(), &mut session).await
+    })?;
+
+    change.events.publish(&transaction);
+
+    Ok(transaction)
+}
+
+async fn change_status_with_session(
+    change: &ChangeStatusState,
+    user: &UserAccess,
+    id: ObjectId,
+    to: TransactionStatusType,
+    proof: Option<DeliveryProof>,
+    session: &mut mongodb::ClientSession,
+) -> Result<Transaction, Error> {
+    let mut transaction = change
+        .transactions
+        .find_exists_one_by_id_with_session(id, session)
+        .await?
+        .ok_or(Error::Forbidden)?;
+
+    let rule = state::transition(&transaction, user, &to)?;
+
+    if to == TransactionStatusType::ArrivedInDestination && proof.is_none() {
+        return Err(Error::CustomStr(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Proof of delivery is required",
+        ));
+    }
+
+    let status = TransactionStatus {
+        proof,
+        ..TransactionStatus::new(to, Some(user.id))
+    };
+
+    apply_transition_with_session(change, &mut transaction, rule, status, session).await?;
+
+    Ok(transaction)
+}
+
+/// Push `status` into `transaction`, apply the effects of `rule` and queue its webhooks.
+/// `status.by` is `None` when the server itself made the change.
+async fn apply_transition_with_session(
+    change: &ChangeStatusState,
+    transaction: &mut Transaction,
+    rule: state::Rule,
+    status: TransactionStatus,
+    session: &mut mongodb::ClientSession,
+) -> Result<(), Error> {
+    let ChangeStatusState {
+        transactions,
+        products,
+        ledger,
+        earnings,
+        revenues,
+        commission,
+        ..
+    } = change;
+
+    for effect in rule.effects {
+        match effect {
+            Effect::Refund => refund_with_session(transaction, ledger, products, session).await?,
+            Effect::Payout => {
+                payout_with_session(transaction, ledger, revenues, commission, session).await?
+            }
+            Effect::PayCourier => {
+                pay_courier_with_session(transaction, ledger, earnings, session).await?
+            }
+            Effect::AssignCourier => {
+                transaction.courier_id = Some(status.by.ok_or(Error::Forbidden)?)
+            }
+            Effect::UnassignCourier => transaction.courier_id = None,
+        }
+    }
+
+    push_status_with_session(transactions, transaction, status, session).await?;
+
+    change
+        .webhooks
+        .enqueue_with_session(transaction, session)
+        .await
+}
+
+/// Push `status` into `transaction` along with its current courier, and save them.
+async fn push_status_with_session(
+    transactions: &TransactionCollection,
+    transaction: &mut Transaction,
+    status: TransactionStatus,
+    session: &mut mongodb::ClientSession,
+) -> Result<(), Error> {
+    transaction.status.push(TransactionStatus {
+        courier_id: transaction.courier_id,
+        ..status
+    });
+    transaction.updated_at = OffsetDateTime::now_utc().into();
+
+    transactions
+        .update_exists_one_by_id_with_session(
+            transaction.id,
+            bson::doc! {
+                "$set": {
+                    "courier_id": transaction.courier_id,
+                    "commission": bson::to_bson(&transaction.commission)?,
+                    "status": bson::to_bson(&transaction.status)?,
+                    "updated_at": transaction.updated_at,
+                }
+            },
+            None,
+            session,
+        )
+        .await?;
+
+    Ok(())
+}
+
+/// Statuses in which the order waits for the merchant, and expire when left for too long.
+const EXPIRABLE_STATUS: [TransactionStatusType; 2] = [
+    TransactionStatusType::WaitingForMerchantConfirmation,
+    TransactionStatusType::ProcessingInMerchant,
+];
+
+/// Whether the last status of `transaction` is one of `from` since before `before`.
+fn is_stale(
+    transaction: &Transaction,
+    from: &[TransactionStatusType],
+    before: bson::DateTime,
+) -> bool {
+    transaction
+        .status
+        .last()
+        .filter(|it| from.contains(&it.r#type) && it.date < before)
+        .is_some()
+}
+
+/// Expire every order waiting for the merchant since before `before`, refunding the buyer.
+/// Returns how many were expired.
+pub async fn expire_stale(
+    change: &ChangeStatusState,
+    before: OffsetDateTime,
+) -> Result<usize, Error> {
+    transition_stale(
+        change,
+        &EXPIRABLE_STATUS,
+        TransactionStatusType::Expired,
+        before,
+    )
+    .await
+}
+
+/// Confirm every order delivered since before `before` for the buyer, paying the merchant.
+/// Returns how many were confirmed.
+pub async fn confirm_stale(
+    change: &ChangeStatusState,
+    before: OffsetDateTime,
+) -> Result<usize, Error> {
+    transition_stale(
+        change,
+        &[TransactionStatusType::ArrivedInDestination],
+        TransactionStatusType::ArrivedInDestinationConfirmed,
+        before,
+    )
+    .await
+}
+
+/// Move every transaction whose last status is one of `from` since before `before` to `to`
+/// as the server. Each transaction is changed in its own mongo transaction, and is checked
+/// again inside it so running this concurrently never apply the effects twice.
+async fn transition_stale(
+    change: &ChangeStatusState,
+    from: &[TransactionStatusType],
+    to: TransactionStatusType,
+    before: OffsetDateTime,
+) -> Result<usize, Error> {
+    let before = bson::DateTime::from(before);
+
+    let mut cursor = change
+        .transactions
         .find_exists(
             bson::doc! {
-                "user_id": user.id
+                "$expr": {
+                    "$and": [
+                        {
+                            "$in": [
+                                {
+                                    "$getField": {
+                                        "input": { "$last": "$status" },
+                                        "field": "type"
+                                    }
+                                },
+                                bson::to_bson(from)?,
+                            ]
+                        },
+                        {
+                            "$lt": [
+                                {
+                                    "$getField": {
+                                        "input": { "$last": "$status" },
+                                        "field": "date"
+                                    }
+                                },
+                                before,
+                            ]
+                        },
+                    ]
+                },
             },
             None,
         )
         .await?;
 
-    let mut orders = vec![];
+    let mut ids = vec![];
 
     while cursor.advance().await? {
-        let order = cursor.deserialize_current()?;
+        ids.push(cursor.deserialize_current()?.id);
+    }
+
+    let mut changed = 0;
+
+    for id in ids {
+        let result = with_transaction!(change.mongo, |session| {
+            let mut transaction = match change
+                .transactions
+                .find_exists_one_by_id_with_session(id, &mut session)
+                .await?
+            {
+                // a user or another instance may have changed it since it was found.
+                Some(it) if is_stale(&it, from, before) => it,
+                _ => return Ok(None),
+            };
+
+            let rule = state::system_transition(&transaction, &to)?;
+
+            apply_transition_with_session(
+                change,
+                &mut transaction,
+                rule,
+                TransactionStatus::new(to.clone(), None),
+                &mut session,
+            )
+            .await?;
 
-        orders.push(order.into());
+            Ok(Some(transaction))
+        });
+
+        match result {
+            Ok(Some(transaction)) => {
+                tracing::debug!(%id, ?to, "changed stale transaction");
+                change.events.publish(&transaction);
+                changed += 1;
+            }
+            Ok(None) => {}
+            Err(err) => tracing::warn!(%id, ?to, ?err, "failed changing stale transaction"),
+        }
     }
 
-    Ok(Json(OrderIndexResponse { orders }))
+    Ok(changed)
+}
+
+pub async fn index_order(
+    State(collection): State<TransactionCollection>,
+    user: UserAccess,
+    Query(query): Query<TransactionQuery>,
+) -> Result<Json<OrderIndexResponse>, Error> {
+    let (orders, next_cursor) = query::find_page(
+        &collection,
+        bson::doc! {
+            "user_id": user.id
+        },
+        &query,
+    )
+    .await?;
+
+    Ok(Json(OrderIndexResponse {
+        orders: orders.into_iter().map(Into::into).collect(),
+        next_cursor,
+    }))
 }
 
 pub async fn show_order(
@@ -189,6 +790,9 @@ pub async fn show_order(
 #[derive(Serialize, Deserialize, Validate)]
 pub struct InsertOrderRequest {
     pub products: Vec<ProductOrderRequest>,
+    /// Defaults to the buyer default address.
+    #[serde(default)]
+    pub address_id: Option<ObjectIdString>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -197,199 +801,349 @@ pub struct ProductOrderRequest {
     pub quantity: BigIntString,
 }
 
-pub async fn insert_order(
-    State(collection): State<TransactionCollection>,
-    State(products_collection): State<ProductCollection>,
-    State(users): State<UserCollection>,
-    State(mongo): State<mongodb::Client>,
-    user: UserModel,
-    Json(request): Json<InsertOrderRequest>,
-) -> Result<Json<TransactionModel>, Error> {
-    request.validate()?;
+/// Order `orders` for the buyer `user_id`, creating one [`Transaction`] per merchant and
+/// charging each of them to the buyer. Must be called inside a mongo transaction.
+#[allow(clippy::too_many_arguments)]
+pub async fn place_orders_with_session(
+    transactions: &TransactionCollection,
+    products: &ProductCollection,
+    ledger: &Ledger,
+    addresses: &AddressCollection,
+    shipping_rate: &ShippingRate,
+    webhooks: &Webhooks,
+    user_id: ObjectId,
+    address: TransactionAddress,
+    orders: &[ProductOrderRequest],
+    allow_multiple_merchant: bool,
+    session: &mut mongodb::ClientSession,
+) -> Result<Vec<Transaction>, Error> {
+    if orders.iter().any(|it| it.quantity.0 <= BigInt::from(0)) {
+        return Err(Error::CustomStr(
+            StatusCode::FORBIDDEN,
+            "Quantity must be more than 0",
+        ));
+    }
 
-    let ids = request
-        .products
+    let ids = orders
         .iter()
-        .map(|it| it.product_id.clone().into())
+        .map(|it| it.product_id.into())
         .collect::<Vec<ObjectId>>();
 
-    let mut ordered = products_collection
-        .find(
+    let mut cursor = products
+        .find_with_session(
             bson::doc! {
                 "_id": {
                     "$in": ids
-                }
+                },
+                "deleted_at": null,
             },
             None,
+            session,
         )
         .await?;
 
-    let mut merchant_id = None;
-
     let mut ordered_map = HashMap::new();
 
-    while ordered.advance().await? {
-        let model = ordered.deserialize_current()?;
-
-        if let Some(it) = merchant_id {
-            if model.user_id != it {
-                return Err(Error::MismatchMerchant);
-            }
-        } else {
-            merchant_id = Some(model.user_id);
-        }
+    while cursor.advance(session).await? {
+        let model = cursor.deserialize_current()?;
 
         ordered_map.insert(model.id, model);
     }
 
-    let merchant_id = match merchant_id {
-        Some(it) => it,
-        None => {
-            return Err(Error::NoResource);
-        }
-    };
+    // (merchant_id, products, price, weight), in the order the merchant first appear
+    let mut merchants: Vec<(ObjectId, Vec<ProductTransaction>, Decimal, i64)> = vec![];
 
-    // forbidden to order product you own
-    if merchant_id == user.id {
-        return Err(Error::CustomStr(
-            StatusCode::FORBIDDEN,
-            "You cannot buy product that you own",
-        ));
-    }
+    let too_large =
+        || Error::CustomStr(StatusCode::UNPROCESSABLE_ENTITY, "Order total is too large");
 
-    let mut products = vec![];
-    let mut price = Decimal::from(0);
+    for order in orders {
+        let product = ordered_map
+            .get(&order.product_id.0)
+            .ok_or(Error::NoResource)?;
 
-    for order in request.products.iter() {
-        if let Some(product) = ordered_map.get(&order.product_id) {
-            products.push(ProductTransaction {
-                id: *order.product_id,
-                quantity: order.quantity.clone().into(),
-            });
+        // forbidden to order product you own
+        if product.user_id == user_id {
+            return Err(Error::CustomStr(
+                StatusCode::FORBIDDEN,
+                "You cannot buy product that you own",
+            ));
+        }
 
-            price +=
-                Decimal::from_str_exact(&order.quantity.0.to_string()).unwrap() * product.price;
-        } else {
-            return Err(Error::NoResource);
+        // stock fits in an i64, a quantity that doesn't is always more than the stock.
+        let quantity = i64::try_from(&order.quantity.0).map_err(|_| {
+            Error::CustomStr(StatusCode::FORBIDDEN, "quantity must be less than stock")
+        })?;
+        let price = Decimal::from(quantity)
+            .checked_mul(product.price)
+            .ok_or_else(too_large)?;
+        let weight = quantity.saturating_mul(product.weight);
+        let line = ProductTransaction {
+            id: product.id,
+            quantity: order.quantity.0.clone(),
+            price: product.price,
+            name: product.name.clone(),
+            description: product.description.clone(),
+            category: product.category.clone(),
+        };
+
+        match merchants.iter_mut().find(|it| it.0 == product.user_id) {
+            Some((_, lines, total, total_weight)) => {
+                lines.push(line);
+                *total = total.checked_add(price).ok_or_else(too_large)?;
+                *total_weight = total_weight.saturating_add(weight);
+            }
+            None => merchants.push((product.user_id, vec![line], price, weight)),
         }
     }
 
-    if user.balance < price {
-        return Err(Error::InsufficientFund);
+    if merchants.is_empty() {
+        return Err(Error::NoResource);
     }
 
-    let mut session = mongo.start_session(None).await?;
-
-    let transaction_options = mongodb::options::TransactionOptions::builder()
-        .read_concern(mongodb::options::ReadConcern::snapshot())
-        .write_concern(
-            mongodb::options::WriteConcern::builder()
-                .w(mongodb::options::Acknowledgment::Majority)
-                .build(),
-        )
-        .selection_criteria(mongodb::options::SelectionCriteria::ReadPreference(
-            mongodb::options::ReadPreference::Primary,
-        ))
-        .build();
-
-    let _transaction = session.start_transaction(transaction_options).await?;
+    if !allow_multiple_merchant && merchants.len() > 1 {
+        return Err(Error::MismatchMerchant);
+    }
 
-    let quantity = products
-        .iter()
-        .map(|it| it.quantity.clone())
-        .find(|it| it <= &BigInt::from(0));
+    // (merchant_id, products, price, shipping_fee)
+    let mut shipments = vec![];
+
+    for (merchant_id, lines, price, weight) in merchants {
+        // merchants from before the address book may not have a pickup address yet.
+        let shipping_fee = match addresses
+            .find_default_with_session(merchant_id, session)
+            .await?
+        {
+            Some(pickup) => {
+                let distance = shipping::haversine_km(
+                    (pickup.latitude, pickup.longitude),
+                    (address.latitude, address.longitude),
+                );
+
+                shipping_rate.fee(distance, weight)
+            }
+            None => shipping_rate.flat_fee(weight),
+        };
 
-    if quantity.is_some() {
-        return Err(Error::CustomStr(
-            StatusCode::FORBIDDEN,
-            "Quantity must be more than 0",
-        ));
+        shipments.push((merchant_id, lines, price, shipping_fee));
     }
 
-    let transaction = Transaction {
-        id: ObjectId::new(),
-        user_id: user.id,
-        price,
-        merchant_id,
-        courier_id: None,
-        products,
-        status: vec![TransactionStatus::new(
-            TransactionStatusType::ProcessingInMerchant,
-        )],
+    let price = shipments.iter().map(|it| it.2 + it.3).sum::<Decimal>();
 
-        created_at: time::OffsetDateTime::now_utc().into(),
-        updated_at: time::OffsetDateTime::now_utc().into(),
-    };
+    let buyer = ledger
+        .users
+        .find_exists_one_by_id_with_session(user_id, session)
+        .await?
+        .ok_or(Error::NoResource)?;
 
-    collection
-        .insert_one_with_session(&transaction, None, &mut session)
-        .await?;
+    // fail before touching the stock, the ledger still checks every charge.
+    if buyer.balance < price {
+        return Err(Error::InsufficientFund);
+    }
 
-    println!("{}-{} = {}", user.balance, price, user.balance - price);
-    users
-        .update_exists_one_by_id_with_session(
-            user.id,
-            bson::doc! {
-                "$set": {
-                    "balance": bson::to_bson(&(user.balance - price))?
-                }
-            },
-            None,
-            &mut session,
-        )
-        .await?;
+    // decrement only when there is enough stock left, so a concurrent order can never
+    // take the stock below 0.
+    for order in orders {
+        let quantity = i64::try_from(&order.quantity.0).map_err(|_| {
+            Error::CustomStr(StatusCode::FORBIDDEN, "quantity must be less than stock")
+        })?;
 
-    for it in transaction.products.iter() {
-        let stock = &ordered_map[&it.id].stock - &it.quantity.clone();
+        let result = products
+            .update_one_with_session(
+                bson::doc! {
+                    "_id": order.product_id.0,
+                    "deleted_at": null,
+                    "stock": {
+                        "$gte": quantity
+                    }
+                },
+                bson::doc! {
+                    "$inc": {
+                        "stock": -quantity
+                    }
+                },
+                None,
+                session,
+            )
+            .await?;
 
-        if &stock < &BigInt::from(0) {
-            // TODO
+        if result.matched_count == 0 {
             return Err(Error::CustomStr(
                 StatusCode::FORBIDDEN,
                 "quantity must be less than stock",
             ));
         }
-        products_collection
-            .update_exists_one_by_id_with_session(
-                it.id,
-                bson::doc! {
-                    "$set": {
-                        "stock": bson::to_bson(&stock)?
-                    }
-                },
-                None,
-                &mut session,
+    }
+
+    let created = shipments
+        .into_iter()
+        .map(|(merchant_id, products, price, shipping_fee)| Transaction {
+            id: ObjectId::new(),
+            user_id,
+            price,
+            shipping_fee,
+            commission: Decimal::ZERO,
+            merchant_id,
+            courier_id: None,
+            products,
+            address: Some(address.clone()),
+            paid_on_arrival: false,
+            status: vec![TransactionStatus::new(
+                TransactionStatusType::WaitingForMerchantConfirmation,
+                Some(user_id),
+            )],
+
+            created_at: time::OffsetDateTime::now_utc().into(),
+            updated_at: time::OffsetDateTime::now_utc().into(),
+        })
+        .collect::<Vec<_>>();
+
+    transactions
+        .insert_many_with_session(&created, None, session)
+        .await?;
+
+    for it in &created {
+        ledger
+            .transfer_with_session(
+                Transfer::new(
+                    LedgerReason::Purchase,
+                    LedgerAccount::Wallet(user_id),
+                    LedgerAccount::Escrow,
+                    it.price + it.shipping_fee,
+                )
+                .transaction(it.id)
+                .by(user_id),
+                session,
             )
             .await?;
-    }
 
-    session.commit_transaction().await?;
+        webhooks.enqueue_with_session(it, session).await?;
+    }
 
-    Ok(Json(transaction.into()))
+    Ok(created)
+}
+
+#[allow(clippy::too_many_arguments)]
+pub async fn insert_order(
+    State(collection): State<TransactionCollection>,
+    State(products_collection): State<ProductCollection>,
+    State(ledger): State<Ledger>,
+    State(mongo): State<mongodb::Client>,
+    State(idempotency): State<IdempotencyCollection>,
+    State(addresses): State<AddressCollection>,
+    State(shipping_rate): State<ShippingRate>,
+    State(webhooks): State<Webhooks>,
+    user: UserModel,
+    IdempotencyKey(key): IdempotencyKey,
+    Json(request): Json<InsertOrderRequest>,
+) -> Result<Json<TransactionModel>, Error> {
+    request.validate()?;
+
+    let hash = idempotency::request_hash(&request)?;
+
+    let transaction = with_transaction!(mongo, |session| {
+        if let Some(key) = &key {
+            let response = idempotency
+                .find_response_with_session(
+                    user.id,
+                    IdempotencyScope::Order,
+                    key,
+                    &hash,
+                    &mut session,
+                )
+                .await?;
+
+            if let Some(response) = response {
+                return Ok(response);
+            }
+        }
+
+        let address = addresses
+            .find_delivery_address_with_session(
+                user.id,
+                request.address_id.map(Into::into),
+                &mut session,
+            )
+            .await?;
+
+        let transaction: TransactionModel = place_orders_with_session(
+            &collection,
+            &products_collection,
+            &ledger,
+            &addresses,
+            &shipping_rate,
+            &webhooks,
+            user.id,
+            address.into(),
+            &request.products,
+            false,
+            &mut session,
+        )
+        .await?
+        .remove(0)
+        .into();
+
+        if let Some(key) = &key {
+            idempotency
+                .insert_response_with_session(
+                    user.id,
+                    IdempotencyScope::Order,
+                    key,
+                    &hash,
+                    &transaction,
+                    &mut session,
+                )
+                .await?;
+        }
+
+        Ok(transaction)
+    })?;
+
+    Ok(Json(transaction))
+}
+
+pub async fn cancel_order(
+    State(change): State<ChangeStatusState>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+) -> Result<Json<TransactionModel>, Error> {
+    let transaction = change_status(&change, &user, path, TransactionStatusType::Cancelled).await?;
+
+    Ok(Json(transaction.into()))
+}
+
+pub async fn confirm_order(
+    State(change): State<ChangeStatusState>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+) -> Result<Json<TransactionModel>, Error> {
+    let transaction = change_status(
+        &change,
+        &user,
+        path,
+        TransactionStatusType::ArrivedInDestinationConfirmed,
+    )
+    .await?;
+
+    Ok(Json(transaction.into()))
 }
 
 #[derive(Serialize, Deserialize)]
 pub struct TransactionIndexResponse {
     transactions: Vec<TransactionModel>,
+    next_cursor: Option<String>,
 }
 
 pub async fn index(
     State(transactions): State<TransactionCollection>,
     user: UserAccess,
+    Query(query): Query<TransactionQuery>,
 ) -> Result<Json<TransactionIndexResponse>, Error> {
-    let mut cursor = transactions
-        .find_exists(bson::doc! { "merchant_id": user.id }, None)
-        .await?;
-
-    let mut result = vec![];
-
-    while cursor.advance().await? {
-        let val = cursor.deserialize_current()?;
-        result.push(val.into());
-    }
+    let (result, next_cursor) =
+        query::find_page(&transactions, bson::doc! { "merchant_id": user.id }, &query).await?;
 
     Ok(Json(TransactionIndexResponse {
-        transactions: result,
+        transactions: result.into_iter().map(Into::into).collect(),
+        next_cursor,
     }))
 }
 
@@ -407,38 +1161,89 @@ pub async fn show(
     Ok(Json(transaction.into()))
 }
 
+pub async fn accept(
+    State(change): State<ChangeStatusState>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+) -> Result<Json<TransactionModel>, Error> {
+    let transaction = change_status(
+        &change,
+        &user,
+        path,
+        TransactionStatusType::ProcessingInMerchant,
+    )
+    .await?;
+
+    Ok(Json(transaction.into()))
+}
+
+#[derive(Serialize, Deserialize, Validate)]
+pub struct RejectRequest {
+    #[validate(length(min = 1, max = 1024))]
+    pub reason: String,
+}
+
+pub async fn reject(
+    State(change): State<ChangeStatusState>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+    Json(request): Json<RejectRequest>,
+) -> Result<Json<TransactionModel>, Error> {
+    request.validate()?;
+
+    let transaction = change_status(
+        &change,
+        &user,
+        path,
+        TransactionStatusType::RejectedByMerchant {
+            reason: request.reason,
+        },
+    )
+    .await?;
+
+    Ok(Json(transaction.into()))
+}
+
 pub async fn confirm_processing(
-    State(transactions): State<TransactionCollection>,
+    State(change): State<ChangeStatusState>,
     user: UserAccess,
     PathObjectId(path): PathObjectId,
 ) -> Result<Json<TransactionModel>, Error> {
-    let mut transaction = transactions
-        .find_exists_one_by_id(path)
-        .await?
-        .filter(|it| it.merchant_id == user.id)
-        .filter(|it| {
-            it.status
-                .last()
-                // only allow if the last transaction status is processing
-                .filter(|it| matches!(it.r#type, TransactionStatusType::ProcessingInMerchant))
-                .is_some()
-        })
-        .ok_or(Error::Forbidden)?;
+    let transaction = change_status(
+        &change,
+        &user,
+        path,
+        TransactionStatusType::WaitingForCourier,
+    )
+    .await?;
+
+    Ok(Json(transaction.into()))
+}
 
-    transaction.status.push(TransactionStatus::new(
+/// Send a parcel that came back to the merchant to the buyer again.
+pub async fn reship(
+    State(change): State<ChangeStatusState>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+) -> Result<Json<TransactionModel>, Error> {
+    let transaction = change_status(
+        &change,
+        &user,
+        path,
         TransactionStatusType::WaitingForCourier,
-    ));
+    )
+    .await?;
 
-    transactions
-        .update_exists_one_by_id(
-            path,
-            bson::doc! {
-                "$set": {
-                    "status": bson::to_bson(&transaction.status)?,
-                }
-            },
-        )
-        .await?;
+    Ok(Json(transaction.into()))
+}
+
+/// Refund the buyer of a parcel that came back to the merchant and close the transaction.
+pub async fn refund(
+    State(change): State<ChangeStatusState>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+) -> Result<Json<TransactionModel>, Error> {
+    let transaction = change_status(&change, &user, path, TransactionStatusType::Returned).await?;
 
     Ok(Json(transaction.into()))
 }
@@ -451,17 +1256,29 @@ pub struct DeliveryResponse {
     pub courier_id: Option<ObjectIdString>,
     pub status: Vec<TransactionStatusModel>,
     // pub products: Vec<ProductTransactionModel>,
+    /// Paid to the courier once the parcel arrive in destination.
+    pub shipping_fee: DecimalString,
+    /// Only shown to the courier holding the parcel.
+    pub address: Option<TransactionAddressModel>,
     pub created_at: FormattedDateTime,
     pub updated_at: FormattedDateTime,
 }
-impl From<Transaction> for DeliveryResponse {
-    fn from(value: Transaction) -> Self {
+
+impl DeliveryResponse {
+    pub fn new(value: Transaction, user: &UserAccess) -> Self {
+        let address = match user.role {
+            super::auth::UserRole::Admin => value.address,
+            _ => value.address.filter(|_| value.courier_id == Some(user.id)),
+        };
+
         Self {
             id: value.id.into(),
             user_id: value.user_id.into(),
             merchant_id: value.merchant_id.into(),
             courier_id: value.courier_id.map(Into::into),
             status: value.status.into_iter().map(|it| it.into()).collect(),
+            shipping_fee: value.shipping_fee.into(),
+            address: address.map(Into::into),
 
             created_at: value.created_at.into(),
             updated_at: value.updated_at.into(),
@@ -472,57 +1289,76 @@ impl From<Transaction> for DeliveryResponse {
 #[derive(Serialize, Deserialize)]
 pub struct DeliveryIndexResponse {
     deliveries: Vec<DeliveryResponse>,
+    next_cursor: Option<String>,
 }
 
+/// Deliveries `user` may see: the ones waiting for any courier and the ones held by the
+/// current courier, admins see every delivery held by a courier too.
+fn delivery_filter(user: &UserAccess) -> Result<bson::Document, Error> {
+    let waiting = bson::doc! {
+        "$expr": {
+            "$eq": [
+                bson::to_bson(&TransactionStatusType::WaitingForCourier)?,
+                {
+                    "$getField": {
+                        "input": { "$last": "$status" },
+                        "field": "type"
+                    },
+                }
+            ]
+        },
+    };
+
+    Ok(match user.role {
+        super::auth::UserRole::Admin => bson::doc! {
+            "$or": [waiting, { "courier_id": { "$ne": null } }]
+        },
+        super::auth::UserRole::Courier | super::auth::UserRole::Customer => bson::doc! {
+            "$or": [
+                { "$and": [waiting, { "courier_id": null }] },
+                { "courier_id": user.id },
+            ]
+        },
+    })
+}
+
+/// Same as [`delivery_filter`], for a single transaction.
+fn can_see_delivery(transaction: &Transaction, user: &UserAccess) -> bool {
+    let waiting = transaction
+        .status
+        .last()
+        .filter(|it| matches!(it.r#type, TransactionStatusType::WaitingForCourier))
+        .is_some();
+
+    match user.role {
+        super::auth::UserRole::Admin => waiting || transaction.courier_id.is_some(),
+        super::auth::UserRole::Courier | super::auth::UserRole::Customer => {
+            (waiting && transaction.courier_id.is_none()) || transaction.courier_id == Some(user.id)
+        }
+    }
+}
+
+/// Deliveries waiting for a courier and the ones held by the current courier.
 pub async fn index_delivery(
     State(transactions): State<TransactionCollection>,
     user: UserAccess,
+    Query(query): Query<TransactionQuery>,
 ) -> Result<Json<DeliveryIndexResponse>, Error> {
     match user.role {
         super::auth::UserRole::Customer => return Err(Error::Forbidden),
         super::auth::UserRole::Courier | super::auth::UserRole::Admin => {}
     }
-    let mut cursor = transactions
-        .find_exists(
-            bson::doc! {
-                "$expr": {
-                    "$eq": [
-                        bson::to_bson(&TransactionStatusType::WaitingForCourier)?,
-                        {
-                            "$getField": {
-                                "input": { "$last": "$status" },
-                                "field": "type"
-                            },
-                        }
-                    ]
-                },
-                // "status.type":
-            },
-            None,
-        )
-        .await?;
-
-    let mut result = vec![];
-
-    while cursor.advance().await? {
-        let val = cursor.deserialize_current()?;
-        result.push(val.into());
-    }
-
-    let mut cursor = transactions
-        .find_exists(
-            bson::doc! {
-                "courier_id": user.id
-            },
-            None,
-        )
-        .await?;
 
-    while cursor.advance().await? {
-        result.push(cursor.deserialize_current()?.into());
-    }
+    let (result, next_cursor) =
+        query::find_page(&transactions, delivery_filter(&user)?, &query).await?;
 
-    Ok(Json(DeliveryIndexResponse { deliveries: result }))
+    Ok(Json(DeliveryIndexResponse {
+        deliveries: result
+            .into_iter()
+            .map(|it| DeliveryResponse::new(it, &user))
+            .collect(),
+        next_cursor,
+    }))
 }
 
 pub async fn show_delivery(
@@ -538,114 +1374,51 @@ pub async fn show_delivery(
     let transaction = transaction
         .find_exists_one_by_id(path)
         .await?
-        .filter(|it| {
-            it.status
-                .last()
-                .filter(|it| matches!(it.r#type, TransactionStatusType::WaitingForCourier))
-                .is_some()
-                || it.courier_id == Some(user.id)
-        })
+        .filter(|it| can_see_delivery(it, &user))
         .ok_or(Error::Forbidden)?;
 
-    Ok(Json(transaction.into()))
+    Ok(Json(DeliveryResponse::new(transaction, &user)))
 }
 
-#[derive(Serialize, Deserialize)]
+#[derive(Serialize, Deserialize, Validate)]
 pub struct ChangeDeliveryRequest {
-    r#type: TransactionStatusType,
+    #[validate(custom = "validate_delivery_status")]
+    pub r#type: TransactionStatusType,
+}
+
+fn validate_delivery_status(
+    status: &TransactionStatusType,
+) -> Result<(), validator::ValidationError> {
+    match status {
+        TransactionStatusType::SendBackToMerchant { reason }
+            if reason.is_empty() || reason.len() > 1024 =>
+        {
+            Err(validator::ValidationError::new("reason"))
+        }
+        _ => Ok(()),
+    }
 }
 
 pub async fn change_delivery(
-    State(transactions): State<TransactionCollection>,
-    State(users): State<UserCollection>,
+    State(change): State<ChangeStatusState>,
     user: UserAccess,
     PathObjectId(path): PathObjectId,
     Json(request): Json<ChangeDeliveryRequest>,
 ) -> Result<(), Error> {
+    request.validate()?;
+
     match user.role {
         super::auth::UserRole::Customer => return Err(Error::Forbidden),
         super::auth::UserRole::Courier | super::auth::UserRole::Admin => {}
     }
 
-    let mut item = transactions
-        .find_exists_one_by_id(path)
-        .await?
-        .filter(|it| it.courier_id == Some(user.id))
-        .filter(|it| {
-            it.status
-                .last()
-                .filter(|it| match it.r#type {
-                    TransactionStatusType::WaitingForCourier
-                    | TransactionStatusType::ProcessingInMerchant
-                    | TransactionStatusType::ArrivedInDestination
-                    | TransactionStatusType::ArrivedInDestinationConfirmed
-                    | TransactionStatusType::WaitingForMerchantConfirmation
-                    | TransactionStatusType::ArrivedInMerchant => false,
-                    TransactionStatusType::PickedUpByCourier => match request.r#type {
-                        TransactionStatusType::ArrivedInDestination
-                        | TransactionStatusType::SendBackToMerchant => true,
-                        _ => false,
-                    },
-                    TransactionStatusType::SendBackToMerchant => match request.r#type {
-                        TransactionStatusType::ArrivedInMerchant => true,
-                        _ => false,
-                    },
-                })
-                .is_some()
-        })
-        .ok_or(Error::Forbidden)?;
-
-    item.status
-        .push(TransactionStatus::new(request.r#type.clone()));
-
-    let mut courier_id = Some(user.id);
-
-    match &request.r#type {
-        TransactionStatusType::ArrivedInMerchant => {
-            item.status.push(TransactionStatus::new(
-                TransactionStatusType::ProcessingInMerchant,
-            ));
-
-            courier_id = None;
-        }
-        TransactionStatusType::ArrivedInDestination => {
-            courier_id = None;
-
-            let merchant = users.find_exists_one_by_id(item.merchant_id).await?;
-
-            if let Some(merchant) = merchant {
-                users
-                    .update_one(
-                        bson::doc! {
-                            "_id": item.merchant_id,
-                        },
-                        bson::doc! {
-                            "$set": {
-                                "balance": bson::to_bson(&(merchant.balance + item.price)).unwrap()
-                            }
-                        },
-                        None,
-                    )
-                    .await?;
-            }
-        }
-        _ => {}
-    }
-
-    let update = bson::doc! {
-        "$set": {
-            "courier_id": courier_id,
-            "status": bson::to_bson(&item.status)?,
-        }
-    };
-
-    transactions.update_exists_one_by_id(path, update).await?;
+    change_status(&change, &user, path, request.r#type).await?;
 
     Ok(())
 }
 
 pub async fn pickup(
-    State(transaction): State<TransactionCollection>,
+    State(change): State<ChangeStatusState>,
     user: UserAccess,
     PathObjectId(path): PathObjectId,
 ) -> Result<(), Error> {
@@ -654,46 +1427,140 @@ pub async fn pickup(
         super::auth::UserRole::Courier | super::auth::UserRole::Admin => {}
     }
 
-    let mut item = transaction
-        .find_exists_one_by_id(path)
-        .await?
-        .filter(|it| {
-            it.status
-                .last()
-                .filter(|it| matches!(it.r#type, TransactionStatusType::WaitingForCourier))
-                .is_some()
-        })
-        .filter(|it| it.courier_id.is_none())
-        .ok_or(Error::Forbidden)?;
-
-    item.status.push(TransactionStatus::new(
+    change_status(
+        &change,
+        &user,
+        path,
         TransactionStatusType::PickedUpByCourier,
-    ));
+    )
+    .await?;
 
-    transaction
-        .update_exists_one_by_id(
-            path,
-            bson::doc! {
-                "$set": {
-                    "courier_id": user.id,
-                    "status": bson::to_bson(&item.status)?,
-                }
-            },
+    Ok(())
+}
+
+/// Apply `assignment` to transaction `id` as `user`. It is checked against
+/// [`state::assignment`] and recorded in the status history.
+async fn change_courier(
+    change: &ChangeStatusState,
+    user: &UserAccess,
+    id: ObjectId,
+    assignment: Assignment,
+) -> Result<Transaction, Error> {
+    let transaction = with_transaction!(change.mongo, |session| {
+        let mut transaction = change
+            .transactions
+            .find_exists_one_by_id_with_session(id, &mut session)
+            .await?
+            .ok_or(Error::Forbidden)?;
+
+        let to = state::assignment(&transaction, user, assignment)?;
+
+        transaction.courier_id = match assignment {
+            Assignment::Assign(courier) | Assignment::Reassign(courier) => {
+                let courier = change
+                    .users
+                    .find_exists_one_by_id_with_session(courier, &mut session)
+                    .await?
+                    .filter(|it| it.role == UserRole::Courier)
+                    .ok_or(Error::CustomStr(
+                        StatusCode::UNPROCESSABLE_ENTITY,
+                        "User is not a courier",
+                    ))?;
+
+                Some(courier.id)
+            }
+            Assignment::Unassign => None,
+        };
+
+        push_status_with_session(
+            &change.transactions,
+            &mut transaction,
+            TransactionStatus::new(to, Some(user.id)),
+            &mut session,
         )
         .await?;
 
-    Ok(())
+        Ok(transaction)
+    })?;
+
+    change.events.publish(&transaction);
+
+    Ok(transaction)
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct AssignCourierRequest {
+    pub courier_id: ObjectIdString,
+}
+
+/// Give a delivery waiting for a courier to a specific courier, only they can pick it up.
+pub async fn assign_courier(
+    State(change): State<ChangeStatusState>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+    Json(request): Json<AssignCourierRequest>,
+) -> Result<Json<DeliveryResponse>, Error> {
+    let transaction = change_courier(
+        &change,
+        &user,
+        path,
+        Assignment::Assign(request.courier_id.into()),
+    )
+    .await?;
+
+    Ok(Json(DeliveryResponse::new(transaction, &user)))
+}
+
+/// Take a delivery from its courier, e.g. one that went missing, and give it to another.
+pub async fn reassign_courier(
+    State(change): State<ChangeStatusState>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+    Json(request): Json<AssignCourierRequest>,
+) -> Result<Json<DeliveryResponse>, Error> {
+    let transaction = change_courier(
+        &change,
+        &user,
+        path,
+        Assignment::Reassign(request.courier_id.into()),
+    )
+    .await?;
+
+    Ok(Json(DeliveryResponse::new(transaction, &user)))
+}
+
+/// Take a delivery from its courier so any courier can pick it up again.
+pub async fn unassign_courier(
+    State(change): State<ChangeStatusState>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+) -> Result<Json<DeliveryResponse>, Error> {
+    let transaction = change_courier(&change, &user, path, Assignment::Unassign).await?;
+
+    Ok(Json(DeliveryResponse::new(transaction, &user)))
 }
 
 #[cfg(test)]
 mod tests {
     use assert_matches::assert_matches;
-    use axum::Json;
+    use axum::{
+        extract::{FromRef, Query},
+        Json,
+    };
     use num_bigint::BigInt;
     use rust_decimal::Decimal;
+    use time::{Duration, OffsetDateTime};
 
     use crate::{
-        api::v1::{auth::UserRole, transaction::TransactionStatusType},
+        api::v1::{
+            auth::UserRole,
+            commission::CommissionRate,
+            shipping::{self, ShippingRate},
+            transaction::{
+                query::{SortBy, SortOrder, TransactionQuery},
+                ChangeStatusState, TransactionError, TransactionStatusType,
+            },
+        },
         error::Error,
         util::PathObjectId,
     };
@@ -716,10 +1583,16 @@ mod tests {
         let _ = super::insert_order(
             bootstrap.transaction_collection(),
             bootstrap.product_collection(),
-            bootstrap.user_collection(),
+            bootstrap.state(),
             bootstrap.mongo_client(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
             customer.user_model.clone(),
+            super::IdempotencyKey(None),
             Json(super::InsertOrderRequest {
+                address_id: None,
                 products: vec![
                     super::ProductOrderRequest {
                         product_id: first_product.id,
@@ -740,148 +1613,252 @@ mod tests {
     }
 
     #[tokio::test]
-    pub async fn test_cannot_order_same_user() {
+    pub async fn test_order_replayed_with_same_idempotency_key() {
         let bootstrap = bootstrap().await;
 
-        let first_product = bootstrap.create_product(1000, 1000).await;
+        let product = bootstrap.create_product(1000, 10).await;
 
-        let err = super::insert_order(
-            bootstrap.transaction_collection(),
-            bootstrap.product_collection(),
-            bootstrap.user_collection(),
-            bootstrap.mongo_client(),
-            bootstrap.user_model.clone(),
-            Json(super::InsertOrderRequest {
-                products: vec![super::ProductOrderRequest {
-                    product_id: first_product.id,
-                    quantity: BigInt::from(1).into(),
-                }],
-            }),
-        )
-        .await
-        .expect_err("cannot buy from same user");
-        assert_matches!(
-            err,
-            Error::CustomStr(_, "You cannot buy product that you own")
-        );
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(2_000))
+            .await;
+
+        let order = || async {
+            super::insert_order(
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                customer.user_model.clone(),
+                super::IdempotencyKey(Some("order-1".to_string())),
+                Json(super::InsertOrderRequest {
+                    address_id: None,
+                    products: vec![super::ProductOrderRequest {
+                        product_id: product.id,
+                        quantity: BigInt::from(1).into(),
+                    }],
+                }),
+            )
+            .await
+            .unwrap()
+            .0
+        };
+
+        let first = order().await;
+        let second = order().await;
+
+        assert_eq!(first, second);
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(1_000));
     }
 
     #[tokio::test]
-    pub async fn test_cannot_order_when_balance_insufficient() {
+    pub async fn test_cannot_reuse_idempotency_key_for_other_order() {
         let bootstrap = bootstrap().await;
 
-        let first_product = bootstrap.create_product(1000, 1000).await;
-        let second_product = bootstrap.create_product(1000, 1000).await;
+        let product = bootstrap.create_product(1000, 10).await;
 
         let customer = bootstrap
-            .derive("customer@mail.com", "password", UserRole::Customer)
+            .derive_customer()
             .await
-            .with_balance(Decimal::from(1_000))
+            .with_balance(Decimal::from(3_000))
             .await;
 
-        let error = super::insert_order(
-            bootstrap.transaction_collection(),
-            bootstrap.product_collection(),
-            bootstrap.user_collection(),
-            bootstrap.mongo_client(),
-            customer.user_model.clone(),
-            Json(super::InsertOrderRequest {
-                products: vec![
-                    super::ProductOrderRequest {
-                        product_id: first_product.id,
-                        quantity: BigInt::from(1).into(),
-                    },
-                    super::ProductOrderRequest {
-                        product_id: second_product.id,
-                        quantity: BigInt::from(1).into(),
-                    },
-                ],
+        for (quantity, ok) in [(1, true), (2, false)] {
+            let result = super::insert_order(
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                customer.user_model.clone(),
+                super::IdempotencyKey(Some("order-1".to_string())),
+                Json(super::InsertOrderRequest {
+                    address_id: None,
+                    products: vec![super::ProductOrderRequest {
+                        product_id: product.id,
+                        quantity: BigInt::from(quantity).into(),
+                    }],
+                }),
+            )
+            .await;
+
+            if ok {
+                let _ = result.unwrap();
+            } else {
+                assert_matches!(result, Err(Error::IdempotencyKeyReused));
+            }
+        }
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(2_000));
+    }
+
+    #[tokio::test]
+    pub async fn test_order_keeps_product_snapshot() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer.create_transaction(&bootstrap, 1).await;
+        let ordered = &transaction.products[0];
+
+        let _ = crate::api::v1::product::update(
+            bootstrap.user_access(),
+            bootstrap.product_collection(),
+            axum::extract::Path(ordered.id.to_string()),
+            Json(crate::api::v1::product::UpdateRequest {
+                name: "updated".to_string(),
+                description: "updated".to_string(),
+                stock: BigInt::from(10).into(),
+                weight: 0,
+                category: None,
+                price: Decimal::from(5_000),
             }),
         )
         .await
-        .expect_err("Insufficient balance");
-        assert_matches!(error, Error::InsufficientFund);
+        .unwrap();
+
+        let Json(show) = super::show_order(
+            bootstrap.state(),
+            customer.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(show.products[0].name, "test");
+        assert_eq!(show.products[0].price.0, Decimal::from(1_000));
+        assert_eq!(show.price.0, Decimal::from(1_000));
     }
 
     #[tokio::test]
-    pub async fn test_cannot_order_from_multiple_user() {
-        let bootstrap = bootstrap().await;
-        let merchant = bootstrap
-            .derive("mer@mail.com", "password", UserRole::Customer)
-            .await;
+    pub async fn test_order_keeps_address_snapshot() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let product = bootstrap.create_product(1000, 10).await;
 
         let customer = bootstrap
-            .derive("cus@mail.com", "password", UserRole::Customer)
+            .derive_customer()
             .await
-            .with_balance(Decimal::from(2_000))
+            .with_balance(Decimal::from(20_000))
             .await;
 
-        let first_product = bootstrap.create_product(1000, 1000).await;
-        let second_product = merchant.create_product(1000, 1000).await;
+        let Json(address) = crate::api::v1::address::create(
+            customer.state(),
+            customer.state(),
+            customer.user_access(),
+            Json(crate::api::v1::address::AddressRequest {
+                label: "office".to_string(),
+                address: "office street".to_string(),
+                latitude: 1.0,
+                longitude: 2.0,
+                is_default: false,
+            }),
+        )
+        .await
+        .unwrap();
 
-        let err = super::insert_order(
-            bootstrap.transaction_collection(),
-            bootstrap.product_collection(),
-            bootstrap.user_collection(),
-            bootstrap.mongo_client(),
+        let Json(transaction) = super::insert_order(
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
             customer.user_model.clone(),
+            super::IdempotencyKey(None),
             Json(super::InsertOrderRequest {
-                products: vec![
-                    super::ProductOrderRequest {
-                        product_id: first_product.id,
-                        quantity: BigInt::from(1).into(),
-                    },
-                    super::ProductOrderRequest {
-                        product_id: second_product.id,
-                        quantity: BigInt::from(1).into(),
-                    },
-                ],
+                address_id: Some(address.id),
+                products: vec![super::ProductOrderRequest {
+                    product_id: product.id,
+                    quantity: BigInt::from(1).into(),
+                }],
             }),
         )
         .await
-        .expect_err("cannot buy from multiple user");
-        assert_matches!(err, Error::MismatchMerchant);
+        .unwrap();
+
+        crate::api::v1::address::delete(
+            customer.state(),
+            customer.user_access(),
+            address.id.into(),
+        )
+        .await
+        .unwrap();
+
+        let Json(show) = super::show_order(
+            bootstrap.state(),
+            customer.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .unwrap();
+
+        let snapshot = show.address.unwrap();
+        assert_eq!(snapshot.id, address.id);
+        assert_eq!(snapshot.address, "office street");
     }
 
     #[tokio::test]
-    pub async fn test_cannot_order_when_stock_less_than_quantity() {
-        let bootstrap = bootstrap().await;
+    pub async fn test_cannot_order_to_other_user_address() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let product = bootstrap.create_product(1000, 10).await;
 
         let customer = bootstrap
-            .derive("cus@mail.com", "password", UserRole::Customer)
+            .derive_customer()
             .await
             .with_balance(Decimal::from(20_000))
             .await;
 
-        let first_product = bootstrap.create_product(1000, 1).await;
-        let second_product = bootstrap.create_product(1000, 1).await;
+        let Json(addresses) =
+            crate::api::v1::address::index(bootstrap.state(), bootstrap.user_access())
+                .await
+                .unwrap();
 
-        let err = super::insert_order(
-            bootstrap.transaction_collection(),
-            bootstrap.product_collection(),
-            bootstrap.user_collection(),
-            bootstrap.mongo_client(),
+        let error = super::insert_order(
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
             customer.user_model.clone(),
+            super::IdempotencyKey(None),
             Json(super::InsertOrderRequest {
-                products: vec![
-                    super::ProductOrderRequest {
-                        product_id: first_product.id,
-                        quantity: BigInt::from(1).into(),
-                    },
-                    super::ProductOrderRequest {
-                        product_id: second_product.id,
-                        quantity: BigInt::from(2).into(),
-                    },
-                ],
+                address_id: Some(addresses.addresses[0].id),
+                products: vec![super::ProductOrderRequest {
+                    product_id: product.id,
+                    quantity: BigInt::from(1).into(),
+                }],
             }),
         )
         .await
-        .expect_err("stock less than quantity");
-        assert_matches!(err, Error::CustomStr(_, "quantity must be less than stock"));
+        .expect_err("cannot use other user address");
+
+        assert_matches!(error, Error::NoResource);
     }
 
     #[tokio::test]
-    pub async fn test_merchant_can_see_sale() {
+    pub async fn test_only_assigned_courier_see_address() {
         let bootstrap = bootstrap().await.derive_customer().await;
 
         let customer = bootstrap
@@ -890,27 +1867,1246 @@ mod tests {
             .with_balance(Decimal::from(20_000))
             .await;
 
-        let transaction = customer.create_transaction(&bootstrap, 2).await;
-
-        let Json(show) = super::show(
+        let courier = bootstrap.derive_courier().await;
+
+        let transaction = customer.create_confirmed_transaction(&bootstrap, 1).await;
+
+        let Json(delivery) = super::show_delivery(
+            bootstrap.state(),
+            courier.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(delivery.address, None);
+
+        super::pickup(
+            bootstrap.state(),
+            courier.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .unwrap();
+
+        let Json(delivery) = super::show_delivery(
+            bootstrap.state(),
+            courier.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .unwrap();
+        assert_eq!(delivery.address, transaction.address);
+        assert!(delivery.address.is_some());
+    }
+
+    #[tokio::test]
+    pub async fn test_order_charge_shipping_fee() {
+        let mut root = bootstrap().await;
+        root.app_state.shipping_rate = ShippingRate::default();
+
+        let merchant = root.derive_customer().await;
+        let customer = root
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(100_000))
+            .await;
+
+        // merchant is about 11km away from the customer default address
+        let _ = crate::api::v1::address::create(
+            merchant.state(),
+            merchant.state(),
+            merchant.user_access(),
+            Json(crate::api::v1::address::AddressRequest {
+                label: "warehouse".to_string(),
+                address: "warehouse street".to_string(),
+                latitude: -7.2575 + 0.1,
+                longitude: 112.7521,
+                is_default: true,
+            }),
+        )
+        .await
+        .unwrap();
+
+        let Json(product) = crate::api::v1::product::create(
+            merchant.state(),
+            merchant.user_access(),
+            Json(crate::api::v1::product::CreateRequest {
+                name: "heavy".to_string(),
+                description: "".to_string(),
+                price: Decimal::from(10_000),
+                stock: BigInt::from(10).into(),
+                weight: 1_500,
+                category: None,
+            }),
+        )
+        .await
+        .unwrap();
+
+        let Json(transaction) = super::insert_order(
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            customer.user_model.clone(),
+            super::IdempotencyKey(None),
+            Json(super::InsertOrderRequest {
+                address_id: None,
+                products: vec![super::ProductOrderRequest {
+                    product_id: product.id,
+                    quantity: BigInt::from(2).into(),
+                }],
+            }),
+        )
+        .await
+        .unwrap();
+
+        let distance = shipping::haversine_km((-7.2575 + 0.1, 112.7521), (-7.2575, 112.7521));
+        let expected = ShippingRate::default().fee(distance, 3_000);
+
+        assert_eq!(transaction.price.0, Decimal::from(20_000));
+        assert_eq!(transaction.shipping_fee.0, expected);
+        assert_eq!(expected, Decimal::from(15_000 + 3 * 2_000));
+
+        let customer = customer.reload().await;
+        assert_eq!(
+            customer.user_model.balance,
+            Decimal::from(100_000) - Decimal::from(20_000) - expected
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_order_from_merchant_without_pickup_address() {
+        let mut root = bootstrap().await;
+        root.app_state.shipping_rate = ShippingRate::default();
+
+        let merchant = root.derive_customer().await;
+        let customer = root
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(100_000))
+            .await;
+
+        root.app_state
+            .address_collection
+            .delete_many(bson::doc! { "user_id": merchant.user_id() }, None)
+            .await
+            .unwrap();
+
+        let product = merchant.create_product(1_000, 1).await;
+
+        let Json(transaction) = super::insert_order(
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            customer.user_model.clone(),
+            super::IdempotencyKey(None),
+            Json(super::InsertOrderRequest {
+                address_id: None,
+                products: vec![super::ProductOrderRequest {
+                    product_id: product.id,
+                    quantity: BigInt::from(1).into(),
+                }],
+            }),
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(
+            transaction.shipping_fee.0,
+            ShippingRate::default().flat_fee(0)
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_shipping_fee_included_in_balance_check() {
+        let mut root = bootstrap().await;
+        root.app_state.shipping_rate = ShippingRate::default();
+
+        let merchant = root.derive_customer().await;
+        let customer = root
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(1_000))
+            .await;
+
+        let product = merchant.create_product(1_000, 1).await;
+
+        let error = super::insert_order(
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            root.state(),
+            customer.user_model.clone(),
+            super::IdempotencyKey(None),
+            Json(super::InsertOrderRequest {
+                address_id: None,
+                products: vec![super::ProductOrderRequest {
+                    product_id: product.id,
+                    quantity: BigInt::from(1).into(),
+                }],
+            }),
+        )
+        .await
+        .expect_err("balance only cover the product price");
+
+        assert_matches!(error, Error::InsufficientFund);
+    }
+
+    #[tokio::test]
+    pub async fn test_courier_paid_shipping_fee_on_delivery() {
+        let mut root = bootstrap().await;
+        root.app_state.shipping_rate = ShippingRate::default();
+
+        let merchant = root.derive_customer().await;
+        let courier = root.derive_courier().await;
+        let customer = root
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer
+            .create_pickedup_transaction(&merchant, &courier, 1)
+            .await;
+        assert_eq!(transaction.shipping_fee.0, Decimal::from(8_000));
+
+        let courier = courier.reload().await;
+        assert_eq!(courier.user_model.balance, Decimal::from(0));
+
+        courier.arrive(*transaction.id).await.unwrap();
+
+        let courier = courier.reload().await;
+        assert_eq!(courier.user_model.balance, Decimal::from(8_000));
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(11_000));
+    }
+
+    #[tokio::test]
+    pub async fn test_cannot_order_same_user() {
+        let bootstrap = bootstrap().await;
+
+        let first_product = bootstrap.create_product(1000, 1000).await;
+
+        let err = super::insert_order(
+            bootstrap.transaction_collection(),
+            bootstrap.product_collection(),
+            bootstrap.state(),
+            bootstrap.mongo_client(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.user_model.clone(),
+            super::IdempotencyKey(None),
+            Json(super::InsertOrderRequest {
+                address_id: None,
+                products: vec![super::ProductOrderRequest {
+                    product_id: first_product.id,
+                    quantity: BigInt::from(1).into(),
+                }],
+            }),
+        )
+        .await
+        .expect_err("cannot buy from same user");
+        assert_matches!(
+            err,
+            Error::CustomStr(_, "You cannot buy product that you own")
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_cannot_order_when_balance_insufficient() {
+        let bootstrap = bootstrap().await;
+
+        let first_product = bootstrap.create_product(1000, 1000).await;
+        let second_product = bootstrap.create_product(1000, 1000).await;
+
+        let customer = bootstrap
+            .derive("customer@mail.com", "password", UserRole::Customer)
+            .await
+            .with_balance(Decimal::from(1_000))
+            .await;
+
+        let error = super::insert_order(
+            bootstrap.transaction_collection(),
+            bootstrap.product_collection(),
+            bootstrap.state(),
+            bootstrap.mongo_client(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            customer.user_model.clone(),
+            super::IdempotencyKey(None),
+            Json(super::InsertOrderRequest {
+                address_id: None,
+                products: vec![
+                    super::ProductOrderRequest {
+                        product_id: first_product.id,
+                        quantity: BigInt::from(1).into(),
+                    },
+                    super::ProductOrderRequest {
+                        product_id: second_product.id,
+                        quantity: BigInt::from(1).into(),
+                    },
+                ],
+            }),
+        )
+        .await
+        .expect_err("Insufficient balance");
+        assert_matches!(error, Error::InsufficientFund);
+    }
+
+    #[tokio::test]
+    pub async fn test_cannot_order_from_multiple_user() {
+        let bootstrap = bootstrap().await;
+        let merchant = bootstrap
+            .derive("mer@mail.com", "password", UserRole::Customer)
+            .await;
+
+        let customer = bootstrap
+            .derive("cus@mail.com", "password", UserRole::Customer)
+            .await
+            .with_balance(Decimal::from(2_000))
+            .await;
+
+        let first_product = bootstrap.create_product(1000, 1000).await;
+        let second_product = merchant.create_product(1000, 1000).await;
+
+        let err = super::insert_order(
+            bootstrap.transaction_collection(),
+            bootstrap.product_collection(),
+            bootstrap.state(),
+            bootstrap.mongo_client(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            customer.user_model.clone(),
+            super::IdempotencyKey(None),
+            Json(super::InsertOrderRequest {
+                address_id: None,
+                products: vec![
+                    super::ProductOrderRequest {
+                        product_id: first_product.id,
+                        quantity: BigInt::from(1).into(),
+                    },
+                    super::ProductOrderRequest {
+                        product_id: second_product.id,
+                        quantity: BigInt::from(1).into(),
+                    },
+                ],
+            }),
+        )
+        .await
+        .expect_err("cannot buy from multiple user");
+        assert_matches!(err, Error::MismatchMerchant);
+    }
+
+    #[tokio::test]
+    pub async fn test_cannot_order_when_stock_less_than_quantity() {
+        let bootstrap = bootstrap().await;
+
+        let customer = bootstrap
+            .derive("cus@mail.com", "password", UserRole::Customer)
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let first_product = bootstrap.create_product(1000, 1).await;
+        let second_product = bootstrap.create_product(1000, 1).await;
+
+        let err = super::insert_order(
+            bootstrap.transaction_collection(),
+            bootstrap.product_collection(),
+            bootstrap.state(),
+            bootstrap.mongo_client(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            bootstrap.state(),
+            customer.user_model.clone(),
+            super::IdempotencyKey(None),
+            Json(super::InsertOrderRequest {
+                address_id: None,
+                products: vec![
+                    super::ProductOrderRequest {
+                        product_id: first_product.id,
+                        quantity: BigInt::from(1).into(),
+                    },
+                    super::ProductOrderRequest {
+                        product_id: second_product.id,
+                        quantity: BigInt::from(2).into(),
+                    },
+                ],
+            }),
+        )
+        .await
+        .expect_err("stock less than quantity");
+        assert_matches!(err, Error::CustomStr(_, "quantity must be less than stock"));
+    }
+
+    #[tokio::test]
+    pub async fn test_cannot_order_huge_quantity_or_deleted_product() {
+        let bootstrap = bootstrap().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let product = bootstrap.create_product(1000, 1).await;
+
+        let order = |quantity: BigInt| {
+            super::insert_order(
+                bootstrap.transaction_collection(),
+                bootstrap.product_collection(),
+                bootstrap.state(),
+                bootstrap.mongo_client(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                bootstrap.state(),
+                customer.user_model.clone(),
+                super::IdempotencyKey(None),
+                Json(super::InsertOrderRequest {
+                    address_id: None,
+                    products: vec![super::ProductOrderRequest {
+                        product_id: product.id,
+                        quantity: quantity.into(),
+                    }],
+                }),
+            )
+        };
+
+        let err = order(BigInt::from(10).pow(30))
+            .await
+            .expect_err("more than any stock");
+        assert_matches!(err, Error::CustomStr(_, "quantity must be less than stock"));
+
+        bootstrap
+            .product_collection()
+            .soft_delete_one_by_id(product.id.into())
+            .await
+            .unwrap();
+
+        let err = order(BigInt::from(1))
+            .await
+            .expect_err("product is deleted");
+        assert_matches!(err, Error::NoResource);
+    }
+
+    #[tokio::test]
+    pub async fn test_merchant_can_see_sale() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer.create_transaction(&bootstrap, 2).await;
+
+        let Json(show) = super::show(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            PathObjectId(*transaction.id),
+        )
+        .await
+        .expect("merchant can see sale");
+
+        assert_eq!(show, transaction);
+
+        let Json(vec) = super::index(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            Query(Default::default()),
+        )
+        .await
+        .expect("merchant can see sale");
+        assert_eq!(vec.transactions.len(), 1);
+        assert_eq!(vec.transactions[0], transaction);
+    }
+
+    #[tokio::test]
+    pub async fn test_order_paginated() {
+        let merchant = bootstrap().await.derive_customer().await;
+
+        let customer = merchant
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let mut created = vec![];
+        for it in 1..=3 {
+            created.push(customer.create_transaction(&merchant, it).await.id);
+        }
+
+        let page = |cursor: Option<String>| {
+            super::index_order(
+                customer.state(),
+                customer.user_access(),
+                Query(TransactionQuery {
+                    limit: Some(2),
+                    cursor,
+                    ..Default::default()
+                }),
+            )
+        };
+
+        let Json(first) = page(None).await.unwrap();
+        assert_eq!(first.orders.len(), 2);
+        assert!(first.next_cursor.is_some());
+
+        let Json(second) = page(first.next_cursor).await.unwrap();
+        assert_eq!(second.orders.len(), 1);
+        assert_eq!(second.next_cursor, None);
+
+        // newest first, without overlap.
+        let ids = first
+            .orders
+            .iter()
+            .chain(second.orders.iter())
+            .map(|it| it.id)
+            .collect::<Vec<_>>();
+        created.reverse();
+        assert_eq!(ids, created);
+    }
+
+    #[tokio::test]
+    pub async fn test_sale_filtered_and_sorted() {
+        let merchant = bootstrap().await.derive_customer().await;
+
+        let customer = merchant
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        for it in [2, 1, 3] {
+            customer.create_transaction(&merchant, it).await;
+        }
+        let cancelled = customer.create_transaction(&merchant, 1).await;
+        let _ = super::cancel_order(
+            merchant.state(),
+            customer.user_access(),
+            cancelled.id.into(),
+        )
+        .await
+        .unwrap();
+
+        let Json(result) = super::index(
+            merchant.state(),
+            merchant.user_access(),
+            Query(TransactionQuery {
+                status: Some("WaitingForMerchantConfirmation".to_string()),
+                sort: SortBy::Price,
+                order: SortOrder::Asc,
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            result
+                .transactions
+                .iter()
+                .map(|it| it.price.0)
+                .collect::<Vec<_>>(),
+            vec![
+                Decimal::from(1_000),
+                Decimal::from(2_000),
+                Decimal::from(3_000)
+            ]
+        );
+
+        let Json(result) = super::index(
+            merchant.state(),
+            merchant.user_access(),
+            Query(TransactionQuery {
+                min_price: Some(Decimal::from(1_500)),
+                max_price: Some(Decimal::from(2_500)),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.transactions.len(), 1);
+        assert_eq!(result.transactions[0].price.0, Decimal::from(2_000));
+
+        let Json(result) = super::index(
+            merchant.state(),
+            merchant.user_access(),
+            Query(TransactionQuery {
+                status: Some("Cancelled".to_string()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(result.transactions.len(), 1);
+        assert_eq!(result.transactions[0].id, cancelled.id);
+
+        // asking for another merchant doesn't reveal their sales.
+        let other = merchant.derive_customer().await;
+        let Json(result) = super::index(
+            other.state(),
+            other.user_access(),
+            Query(TransactionQuery {
+                merchant_id: Some(merchant.user_id().into()),
+                ..Default::default()
+            }),
+        )
+        .await
+        .unwrap();
+        assert!(result.transactions.is_empty());
+    }
+
+    #[tokio::test]
+    pub async fn test_customer_cannot_see_other_user_sale() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer.create_transaction(&bootstrap, 2).await;
+
+        let error = super::show(
+            bootstrap.state(),
+            customer.user_access(),
+            PathObjectId(*transaction.id),
+        )
+        .await
+        .expect_err("customer cannot see sale");
+        assert_matches!(error, Error::Forbidden);
+
+        let Json(vec) = super::index(
+            bootstrap.state(),
+            customer.user_access(),
+            Query(Default::default()),
+        )
+        .await
+        .expect("customer without sale can still see sale");
+        assert_eq!(vec.transactions.len(), 0);
+    }
+
+    #[tokio::test]
+    pub async fn test_customer_can_see_order() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer.create_transaction(&bootstrap, 2).await;
+
+        let Json(show) = super::show_order(
+            bootstrap.state(),
+            customer.user_access(),
+            PathObjectId(*transaction.id),
+        )
+        .await
+        .expect("customer cannot see sale");
+        assert_eq!(transaction, show);
+
+        let Json(vec) = super::index_order(
+            bootstrap.state(),
+            customer.user_access(),
+            Query(Default::default()),
+        )
+        .await
+        .expect("customer without sale can still see sale");
+        assert_eq!(vec.orders.len(), 1);
+        assert_eq!(vec.orders[0], transaction);
+    }
+
+    #[tokio::test]
+    pub async fn test_customer_cannot_see_other_user_order() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer.create_transaction(&bootstrap, 2).await;
+
+        let error = super::show_order(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            PathObjectId(*transaction.id),
+        )
+        .await
+        .expect_err("customer cannot see order");
+        assert_matches!(error, Error::Forbidden);
+
+        let Json(vec) = super::index_order(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            Query(Default::default()),
+        )
+        .await
+        .expect("customer without sale can still see order");
+        assert_eq!(vec.orders.len(), 0);
+    }
+
+    #[tokio::test]
+    pub async fn test_order_wait_for_merchant_confirmation() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer.create_transaction(&bootstrap, 2).await;
+
+        assert_matches!(
+            transaction.status.last().unwrap().r#type,
+            TransactionStatusType::WaitingForMerchantConfirmation
+        );
+
+        let error = super::confirm_processing(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .expect_err("order must be accepted before processed");
+        assert_matches!(
+            error,
+            Error::TransactionError(TransactionError::IllegalTransition { .. })
+        );
+
+        let Json(result) = super::accept(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .expect("merchant can accept order");
+        assert_matches!(
+            result.status.last().unwrap().r#type,
+            TransactionStatusType::ProcessingInMerchant
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_merchant_can_reject() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer.create_transaction(&bootstrap, 2).await;
+
+        let Json(result) = super::reject(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            transaction.id.into(),
+            Json(super::RejectRequest {
+                reason: "out of stock".to_string(),
+            }),
+        )
+        .await
+        .expect("merchant can reject order");
+
+        assert_matches!(
+            &result.status.last().unwrap().r#type,
+            TransactionStatusType::RejectedByMerchant { reason } if reason == "out of stock"
+        );
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(20_000));
+
+        for it in transaction.products {
+            let product = bootstrap
+                .app_state
+                .product_collection
+                .find_exists_one_by_id(it.id.into())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(product.stock, BigInt::from(1));
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_send_back_require_reason() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let courier = bootstrap.derive_courier().await;
+
+        let transaction = customer
+            .create_pickedup_transaction(&bootstrap, &courier, 2)
+            .await;
+
+        let error = super::change_delivery(
+            bootstrap.state(),
+            courier.user_access(),
+            transaction.id.into(),
+            Json(super::ChangeDeliveryRequest {
+                r#type: TransactionStatusType::SendBackToMerchant {
+                    reason: "".to_string(),
+                },
+            }),
+        )
+        .await
+        .expect_err("reason must not be empty");
+
+        assert_matches!(error, Error::ValidationError(..));
+    }
+
+    #[tokio::test]
+    pub async fn test_merchant_can_reship_returned_parcel() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let courier = bootstrap.derive_courier().await;
+
+        let transaction = customer
+            .create_returned_transaction(&bootstrap, &courier, 2)
+            .await;
+
+        let error = super::reship(
+            bootstrap.state(),
+            customer.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .expect_err("only merchant can reship");
+        assert_matches!(error, Error::Forbidden);
+
+        let Json(result) = super::reship(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .unwrap();
+
+        assert_matches!(
+            result.status.last().unwrap().r#type,
+            TransactionStatusType::WaitingForCourier
+        );
+        assert_eq!(result.courier_id, None);
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(18_000));
+    }
+
+    #[tokio::test]
+    pub async fn test_merchant_can_refund_returned_parcel() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let courier = bootstrap.derive_courier().await;
+
+        let transaction = customer
+            .create_returned_transaction(&bootstrap, &courier, 2)
+            .await;
+
+        assert!(transaction.status.iter().any(|it| matches!(
+            &it.r#type,
+            TransactionStatusType::SendBackToMerchant { reason } if reason == "recipient refused"
+        )));
+
+        let Json(result) = super::refund(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .unwrap();
+
+        assert_matches!(
+            result.status.last().unwrap().r#type,
+            TransactionStatusType::Returned
+        );
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(20_000));
+
+        for it in transaction.products {
+            let product = bootstrap
+                .app_state
+                .product_collection
+                .find_exists_one_by_id(it.id.into())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(product.stock, BigInt::from(1));
+        }
+
+        let error = super::reship(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .expect_err("returned transaction is closed");
+        assert_matches!(
+            error,
+            Error::TransactionError(TransactionError::IllegalTransition { .. })
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_merchant_cannot_reject_confirmed_transaction() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer.create_confirmed_transaction(&bootstrap, 2).await;
+
+        let error = super::reject(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            transaction.id.into(),
+            Json(super::RejectRequest {
+                reason: "out of stock".to_string(),
+            }),
+        )
+        .await
+        .expect_err("transaction already handed to courier");
+        assert_matches!(
+            error,
+            Error::TransactionError(TransactionError::IllegalTransition { .. })
+        );
+
+        let transaction = customer.create_transaction(&bootstrap, 2).await;
+
+        let error = super::reject(
+            customer.state(),
+            customer.user_access(),
+            transaction.id.into(),
+            Json(super::RejectRequest {
+                reason: "out of stock".to_string(),
+            }),
+        )
+        .await
+        .expect_err("only merchant can reject");
+        assert_matches!(error, Error::Forbidden);
+    }
+
+    #[tokio::test]
+    pub async fn test_merchant_can_confirm() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer.create_accepted_transaction(&bootstrap, 2).await;
+
+        let Json(result) = super::confirm_processing(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            PathObjectId(*transaction.id),
+        )
+        .await
+        .expect("merchant can confirm transaction");
+
+        assert_matches!(
+            result.status.last().unwrap().r#type,
+            TransactionStatusType::WaitingForCourier
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_merchant_cannot_confirm_confirmed_transaction() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer.create_accepted_transaction(&bootstrap, 2).await;
+
+        let Json(result) = super::confirm_processing(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            PathObjectId(*transaction.id),
+        )
+        .await
+        .unwrap();
+
+        assert_matches!(
+            result.status.last().unwrap().r#type,
+            TransactionStatusType::WaitingForCourier
+        );
+
+        let error = super::confirm_processing(
+            bootstrap.state(),
+            bootstrap.user_access(),
+            PathObjectId(*transaction.id),
+        )
+        .await
+        .expect_err("transaction already confirmed");
+        assert_matches!(
+            error,
+            Error::TransactionError(TransactionError::IllegalTransition { .. })
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_customer_can_cancel_order() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let transaction = customer.create_transaction(&bootstrap, 2).await;
+
+        let Json(result) = super::cancel_order(
+            bootstrap.state(),
+            customer.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .expect("customer can cancel order");
+
+        assert_matches!(
+            result.status.last().unwrap().r#type,
+            TransactionStatusType::Cancelled
+        );
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(20_000));
+
+        for it in transaction.products {
+            let product = bootstrap
+                .app_state
+                .product_collection
+                .find_exists_one_by_id(it.id.into())
+                .await
+                .unwrap()
+                .unwrap();
+            assert_eq!(product.stock, BigInt::from(1));
+        }
+    }
+
+    #[tokio::test]
+    pub async fn test_stale_order_expired() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+        let courier = bootstrap.derive_courier().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let waiting = customer.create_transaction(&bootstrap, 2).await;
+        let processing = customer.create_accepted_transaction(&bootstrap, 1).await;
+        let picked_up = customer
+            .create_pickedup_transaction(&bootstrap, &courier, 1)
+            .await;
+
+        let change = ChangeStatusState::from_ref(&bootstrap.app_state);
+
+        // nothing is older than an hour yet.
+        let expired = super::expire_stale(&change, OffsetDateTime::now_utc() - Duration::HOUR)
+            .await
+            .unwrap();
+        assert_eq!(expired, 0);
+
+        let expired = super::expire_stale(&change, OffsetDateTime::now_utc() + Duration::SECOND)
+            .await
+            .unwrap();
+        assert_eq!(expired, 2);
+
+        for it in [&waiting, &processing] {
+            let Json(result) =
+                super::show_order(customer.state(), customer.user_access(), it.id.into())
+                    .await
+                    .unwrap();
+            assert_matches!(
+                result.status.last().unwrap().r#type,
+                TransactionStatusType::Expired
+            );
+
+            for it in it.products.iter() {
+                let product = bootstrap
+                    .app_state
+                    .product_collection
+                    .find_exists_one_by_id(it.id.into())
+                    .await
+                    .unwrap()
+                    .unwrap();
+                assert_eq!(product.stock, BigInt::from(1));
+            }
+        }
+
+        let Json(result) = super::show_order(
+            customer.state(),
+            customer.user_access(),
+            picked_up.id.into(),
+        )
+        .await
+        .unwrap();
+        assert_matches!(
+            result.status.last().unwrap().r#type,
+            TransactionStatusType::PickedUpByCourier
+        );
+
+        // only the picked up order is still paid for.
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(19_000));
+    }
+
+    #[tokio::test]
+    pub async fn test_delivered_order_confirmed_for_buyer() {
+        let merchant = bootstrap().await.derive_customer().await;
+        let courier = merchant.derive_courier().await;
+
+        let customer = merchant
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let delivered = customer
+            .create_delivered_transaction(&merchant, &courier, 2)
+            .await;
+        let picked_up = customer
+            .create_pickedup_transaction(&merchant, &courier, 1)
+            .await;
+
+        let change = ChangeStatusState::from_ref(&merchant.app_state);
+
+        let confirmed = super::confirm_stale(&change, OffsetDateTime::now_utc() - Duration::HOUR)
+            .await
+            .unwrap();
+        assert_eq!(confirmed, 0);
+
+        let confirmed = super::confirm_stale(&change, OffsetDateTime::now_utc() + Duration::SECOND)
+            .await
+            .unwrap();
+        assert_eq!(confirmed, 1);
+
+        // running it again, e.g. from another instance, doesn't pay twice.
+        let confirmed = super::confirm_stale(&change, OffsetDateTime::now_utc() + Duration::SECOND)
+            .await
+            .unwrap();
+        assert_eq!(confirmed, 0);
+
+        let Json(result) = super::show_order(
+            customer.state(),
+            customer.user_access(),
+            delivered.id.into(),
+        )
+        .await
+        .unwrap();
+        assert_matches!(
+            result.status.last().unwrap().r#type,
+            TransactionStatusType::ArrivedInDestinationConfirmed
+        );
+
+        let Json(result) = super::show_order(
+            customer.state(),
+            customer.user_access(),
+            picked_up.id.into(),
+        )
+        .await
+        .unwrap();
+        assert_matches!(
+            result.status.last().unwrap().r#type,
+            TransactionStatusType::PickedUpByCourier
+        );
+
+        let merchant = merchant.reload().await;
+        assert_eq!(merchant.user_model.balance, Decimal::from(2_000));
+    }
+
+    #[tokio::test]
+    pub async fn test_customer_cannot_cancel_picked_up_order() {
+        let bootstrap = bootstrap().await.derive_customer().await;
+
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let courier = bootstrap.derive_courier().await;
+
+        let transaction = customer
+            .create_pickedup_transaction(&bootstrap, &courier, 2)
+            .await;
+
+        let error = super::cancel_order(
             bootstrap.state(),
-            bootstrap.user_access(),
-            PathObjectId(*transaction.id.clone()),
+            customer.user_access(),
+            transaction.id.into(),
         )
         .await
-        .expect("merchant can see sale");
-
-        assert_eq!(show, transaction);
+        .expect_err("picked up order cannot be cancelled");
+        assert_matches!(
+            error,
+            Error::TransactionError(TransactionError::IllegalTransition { .. })
+        );
 
-        let Json(vec) = super::index(bootstrap.state(), bootstrap.user_access())
-            .await
-            .expect("merchant can see sale");
-        assert_eq!(vec.transactions.len(), 1);
-        assert_eq!(vec.transactions[0], transaction);
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(18_000));
     }
 
     #[tokio::test]
-    pub async fn test_customer_cannot_see_other_user_sale() {
+    pub async fn test_customer_cannot_cancel_other_user_order() {
         let bootstrap = bootstrap().await.derive_customer().await;
 
         let customer = bootstrap
@@ -921,23 +3117,20 @@ mod tests {
 
         let transaction = customer.create_transaction(&bootstrap, 2).await;
 
-        let error = super::show(
+        let other = bootstrap.derive_customer().await;
+
+        let error = super::cancel_order(
             bootstrap.state(),
-            customer.user_access(),
-            PathObjectId(*transaction.id.clone()),
+            other.user_access(),
+            transaction.id.into(),
         )
         .await
-        .expect_err("customer cannot see sale");
+        .expect_err("cannot cancel other user order");
         assert_matches!(error, Error::Forbidden);
-
-        let Json(vec) = super::index(bootstrap.state(), customer.user_access())
-            .await
-            .expect("customer without sale can still see sale");
-        assert_eq!(vec.transactions.len(), 0);
     }
 
     #[tokio::test]
-    pub async fn test_customer_can_see_order() {
+    pub async fn test_merchant_paid_only_after_customer_confirm() {
         let bootstrap = bootstrap().await.derive_customer().await;
 
         let customer = bootstrap
@@ -946,79 +3139,133 @@ mod tests {
             .with_balance(Decimal::from(20_000))
             .await;
 
-        let transaction = customer.create_transaction(&bootstrap, 2).await;
+        let courier = bootstrap.derive_courier().await;
 
-        let Json(show) = super::show_order(
-            bootstrap.state(),
+        let transaction = customer
+            .create_delivered_transaction(&bootstrap, &courier, 2)
+            .await;
+
+        let merchant = bootstrap.reload().await;
+        assert_eq!(merchant.user_model.balance, Decimal::from(0));
+
+        let Json(result) = super::confirm_order(
+            merchant.state(),
             customer.user_access(),
-            PathObjectId(*transaction.id.clone()),
+            transaction.id.into(),
         )
         .await
-        .expect("customer cannot see sale");
-        assert_eq!(transaction, show);
+        .expect("customer can confirm delivered order");
 
-        let Json(vec) = super::index_order(bootstrap.state(), customer.user_access())
-            .await
-            .expect("customer without sale can still see sale");
-        assert_eq!(vec.orders.len(), 1);
-        assert_eq!(vec.orders[0], transaction);
+        assert_matches!(
+            result.status.last().unwrap().r#type,
+            TransactionStatusType::ArrivedInDestinationConfirmed
+        );
+
+        let merchant = merchant.reload().await;
+        assert_eq!(merchant.user_model.balance, Decimal::from(2_000));
     }
 
     #[tokio::test]
-    pub async fn test_customer_cannot_see_other_user_order() {
-        let bootstrap = bootstrap().await.derive_customer().await;
+    pub async fn test_commission_deducted_from_payout() {
+        let mut root = bootstrap().await;
+        root.app_state.commission_rate = CommissionRate::default();
 
-        let customer = bootstrap
+        let merchant = root.derive_customer().await;
+        let courier = root.derive_courier().await;
+        let customer = root
             .derive_customer()
             .await
             .with_balance(Decimal::from(20_000))
             .await;
 
-        let transaction = customer.create_transaction(&bootstrap, 2).await;
+        let transaction = customer
+            .create_delivered_transaction(&merchant, &courier, 3)
+            .await;
 
-        let error = super::show_order(
-            bootstrap.state(),
-            bootstrap.user_access(),
-            PathObjectId(*transaction.id.clone()),
+        let Json(result) = super::confirm_order(
+            customer.state(),
+            customer.user_access(),
+            transaction.id.into(),
         )
         .await
-        .expect_err("customer cannot see order");
-        assert_matches!(error, Error::Forbidden);
+        .unwrap();
+        assert_eq!(result.commission.0, Decimal::from(150));
+
+        let merchant = merchant.reload().await;
+        assert_eq!(merchant.user_model.balance, Decimal::from(2_850));
 
-        let Json(vec) = super::index_order(bootstrap.state(), bootstrap.user_access())
+        let Json(revenue) = crate::api::v1::commission::index(root.state(), root.user_access())
             .await
-            .expect("customer without sale can still see order");
-        assert_eq!(vec.orders.len(), 0);
+            .unwrap();
+        let revenue = revenue
+            .revenues
+            .iter()
+            .find(|it| it.transaction_id == transaction.id)
+            .expect("commission is credited to the platform");
+        assert_eq!(revenue.amount.0, Decimal::from(150));
+        assert_eq!(revenue.merchant_id, merchant.user_id());
     }
 
     #[tokio::test]
-    pub async fn test_merchant_can_confirm() {
-        let bootstrap = bootstrap().await.derive_customer().await;
+    pub async fn test_payout_to_deleted_merchant_stays_in_escrow() {
+        let mut root = bootstrap().await;
+        root.app_state.commission_rate = CommissionRate::default();
 
-        let customer = bootstrap
+        let merchant = root.derive_customer().await;
+        let courier = root.derive_courier().await;
+        let customer = root
             .derive_customer()
             .await
             .with_balance(Decimal::from(20_000))
             .await;
 
-        let transaction = customer.create_transaction(&bootstrap, 2).await;
+        let transaction = customer
+            .create_delivered_transaction(&merchant, &courier, 3)
+            .await;
 
-        let Json(result) = super::confirm_processing(
-            bootstrap.state(),
-            bootstrap.user_access(),
-            PathObjectId(*transaction.id.clone()),
+        root.app_state
+            .user_collection
+            .soft_delete_one_by_id(merchant.user_id())
+            .await
+            .unwrap();
+
+        let _ = super::confirm_order(
+            customer.state(),
+            customer.user_access(),
+            transaction.id.into(),
         )
         .await
-        .expect("merchant can confirm transaction");
+        .unwrap();
 
-        assert_matches!(
-            result.status.last().unwrap().r#type,
-            TransactionStatusType::WaitingForCourier
+        let Json(revenue) = crate::api::v1::commission::index(root.state(), root.user_access())
+            .await
+            .unwrap();
+        assert!(
+            revenue
+                .revenues
+                .iter()
+                .all(|it| it.transaction_id != transaction.id),
+            "no commission without a payout"
         );
+
+        let payouts = root
+            .app_state
+            .ledger_entry_collection
+            .count_documents(
+                bson::doc! {
+                    "transaction_id": *transaction.id,
+                    "reason": "Payout",
+                    "account.user_id": merchant.user_id(),
+                },
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(payouts, 0);
     }
 
     #[tokio::test]
-    pub async fn test_merchant_cannot_confirm_confirmed_transaction() {
+    pub async fn test_customer_cannot_confirm_undelivered_order() {
         let bootstrap = bootstrap().await.derive_customer().await;
 
         let customer = bootstrap
@@ -1027,29 +3274,26 @@ mod tests {
             .with_balance(Decimal::from(20_000))
             .await;
 
-        let transaction = customer.create_transaction(&bootstrap, 2).await;
+        let courier = bootstrap.derive_courier().await;
 
-        let Json(result) = super::confirm_processing(
+        let transaction = customer
+            .create_pickedup_transaction(&bootstrap, &courier, 2)
+            .await;
+
+        let error = super::confirm_order(
             bootstrap.state(),
-            bootstrap.user_access(),
-            PathObjectId(*transaction.id.clone()),
+            customer.user_access(),
+            transaction.id.into(),
         )
         .await
-        .unwrap();
-
+        .expect_err("order is not delivered yet");
         assert_matches!(
-            result.status.last().unwrap().r#type,
-            TransactionStatusType::WaitingForCourier
+            error,
+            Error::TransactionError(TransactionError::IllegalTransition { .. })
         );
 
-        let error = super::confirm_processing(
-            bootstrap.state(),
-            bootstrap.user_access(),
-            PathObjectId(*transaction.id.clone()),
-        )
-        .await
-        .expect_err("transaction already confirmed");
-        assert_matches!(error, Error::Forbidden);
+        let merchant = bootstrap.reload().await;
+        assert_eq!(merchant.user_model.balance, Decimal::from(0));
     }
 
     #[tokio::test]
@@ -1069,16 +3313,20 @@ mod tests {
         let Json(show) = super::show_delivery(
             bootstrap.state(),
             courier.user_access(),
-            transaction.id.clone().into(),
+            transaction.id.into(),
         )
         .await
         .unwrap();
 
         assert_eq!(show.id, transaction.id);
 
-        let Json(index) = super::index_delivery(bootstrap.state(), courier.user_access())
-            .await
-            .unwrap();
+        let Json(index) = super::index_delivery(
+            bootstrap.state(),
+            courier.user_access(),
+            Query(Default::default()),
+        )
+        .await
+        .unwrap();
 
         assert_eq!(index.deliveries.len(), 1);
         assert_eq!(index.deliveries[0], show);
@@ -1101,7 +3349,7 @@ mod tests {
         super::pickup(
             bootstrap.state(),
             courier.user_access(),
-            transaction.id.clone().into(),
+            transaction.id.into(),
         )
         .await
         .expect("courier can pickup");
@@ -1109,7 +3357,7 @@ mod tests {
         let Json(show) = super::show_order(
             bootstrap.state(),
             customer.user_access(),
-            transaction.id.clone().into(),
+            transaction.id.into(),
         )
         .await
         .unwrap();
@@ -1141,15 +3389,19 @@ mod tests {
         let error = super::show_delivery(
             bootstrap.state(),
             second_courier.user_access(),
-            transaction.id.clone().into(),
+            transaction.id.into(),
         )
         .await
         .expect_err("courier cannot see other courier delivery");
         assert_matches!(error, Error::Forbidden);
 
-        let Json(index) = super::index_delivery(bootstrap.state(), second_courier.user_access())
-            .await
-            .unwrap();
+        let Json(index) = super::index_delivery(
+            bootstrap.state(),
+            second_courier.user_access(),
+            Query(Default::default()),
+        )
+        .await
+        .unwrap();
         assert_eq!(index.deliveries.len(), 0);
     }
 
@@ -1165,11 +3417,12 @@ mod tests {
 
         let courier = bt.derive_courier().await;
 
+        let send_back = TransactionStatusType::SendBackToMerchant {
+            reason: "recipient unreachable".to_string(),
+        };
+
         let ok = [
-            vec![
-                TransactionStatusType::SendBackToMerchant,
-                TransactionStatusType::ArrivedInMerchant,
-            ],
+            vec![send_back.clone(), TransactionStatusType::ArrivedInMerchant],
             vec![TransactionStatusType::ArrivedInDestination],
         ];
 
@@ -1177,11 +3430,15 @@ mod tests {
             let transaction = customer.create_pickedup_transaction(&bt, &courier, 2).await;
 
             for it in statuses {
+                if it == TransactionStatusType::ArrivedInDestination {
+                    courier.arrive(*transaction.id).await.unwrap();
+                    continue;
+                }
+
                 super::change_delivery(
-                    bt.state(),
                     bt.state(),
                     courier.user_access(),
-                    transaction.id.clone().into(),
+                    transaction.id.into(),
                     Json(super::ChangeDeliveryRequest { r#type: it }),
                 )
                 .await
@@ -1194,10 +3451,15 @@ mod tests {
             TransactionStatusType::ProcessingInMerchant,
             TransactionStatusType::WaitingForCourier,
             TransactionStatusType::PickedUpByCourier,
-            TransactionStatusType::SendBackToMerchant,
+            send_back.clone(),
             TransactionStatusType::ArrivedInMerchant,
             TransactionStatusType::ArrivedInDestination,
             TransactionStatusType::ArrivedInDestinationConfirmed,
+            TransactionStatusType::Cancelled,
+            TransactionStatusType::RejectedByMerchant {
+                reason: "".to_string(),
+            },
+            TransactionStatusType::Returned,
         ];
 
         let err = [
@@ -1207,42 +3469,46 @@ mod tests {
                     .filter(|it| {
                         !matches!(
                             it,
-                            TransactionStatusType::SendBackToMerchant
+                            TransactionStatusType::SendBackToMerchant { .. }
                                 | TransactionStatusType::ArrivedInDestination
                         )
                     })
                     .collect::<Vec<_>>(),
+                true,
             ),
             (
-                vec![TransactionStatusType::SendBackToMerchant],
+                vec![send_back.clone()],
                 all.iter()
                     .filter(|it| !matches!(it, TransactionStatusType::ArrivedInMerchant))
                     .collect::<Vec<_>>(),
+                true,
             ),
             (
-                vec![
-                    TransactionStatusType::SendBackToMerchant,
-                    TransactionStatusType::ArrivedInMerchant,
-                ],
+                vec![send_back.clone(), TransactionStatusType::ArrivedInMerchant],
                 all.iter().collect(),
+                false,
             ),
             (
-                vec![
-                    TransactionStatusType::ArrivedInDestination,
-                ],
+                vec![TransactionStatusType::ArrivedInDestination],
                 all.iter().collect(),
+                false,
             ),
         ];
 
-        for (process, test) in err {
+        // courier can only see the illegal state while still holding the parcel
+        for (process, test, assigned) in err {
             let transaction = customer.create_pickedup_transaction(&bt, &courier, 2).await;
 
             for it in process {
+                if it == TransactionStatusType::ArrivedInDestination {
+                    courier.arrive(*transaction.id).await.unwrap();
+                    continue;
+                }
+
                 super::change_delivery(
-                    bt.state(),
                     bt.state(),
                     courier.user_access(),
-                    transaction.id.clone().into(),
+                    transaction.id.into(),
                     Json(super::ChangeDeliveryRequest { r#type: it }),
                 )
                 .await
@@ -1251,17 +3517,303 @@ mod tests {
 
             for it in test {
                 let error = super::change_delivery(
-                    bt.state(),
                     bt.state(),
                     courier.user_access(),
-                    transaction.id.clone().into(),
+                    transaction.id.into(),
                     Json(super::ChangeDeliveryRequest { r#type: it.clone() }),
                 )
                 .await
                 .expect_err("should error");
 
-                assert_matches!(error, Error::Forbidden);
+                if assigned {
+                    assert_matches!(
+                        error,
+                        Error::TransactionError(TransactionError::IllegalTransition { .. })
+                    );
+                } else {
+                    assert_matches!(error, Error::Forbidden);
+                }
             }
         }
     }
+
+    #[tokio::test]
+    pub async fn test_admin_assign_and_reassign_courier() {
+        let admin = bootstrap().await;
+        let merchant = admin.derive_customer().await;
+
+        let customer = admin
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let courier = admin.derive_courier().await;
+        let other_courier = admin.derive_courier().await;
+
+        let transaction = customer.create_confirmed_transaction(&merchant, 2).await;
+
+        let error = super::assign_courier(
+            admin.state(),
+            courier.user_access(),
+            transaction.id.into(),
+            Json(super::AssignCourierRequest {
+                courier_id: courier.user_id().into(),
+            }),
+        )
+        .await
+        .expect_err("only admin can assign a courier");
+        assert_matches!(error, Error::Forbidden);
+
+        let error = super::assign_courier(
+            admin.state(),
+            admin.user_access(),
+            transaction.id.into(),
+            Json(super::AssignCourierRequest {
+                courier_id: customer.user_id().into(),
+            }),
+        )
+        .await
+        .expect_err("only courier can be assigned");
+        assert_matches!(error, Error::CustomStr(..));
+
+        let Json(assigned) = super::assign_courier(
+            admin.state(),
+            admin.user_access(),
+            transaction.id.into(),
+            Json(super::AssignCourierRequest {
+                courier_id: courier.user_id().into(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(assigned.courier_id, Some(courier.user_id().into()));
+
+        // the delivery is no longer offered to other couriers.
+        let Json(index) = super::index_delivery(
+            admin.state(),
+            other_courier.user_access(),
+            Query(Default::default()),
+        )
+        .await
+        .unwrap();
+        assert!(index.deliveries.is_empty());
+
+        let error = super::pickup(
+            admin.state(),
+            other_courier.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .expect_err("only the assigned courier can pick up");
+        assert_matches!(error, Error::Forbidden);
+
+        super::pickup(admin.state(), courier.user_access(), transaction.id.into())
+            .await
+            .expect("assigned courier can pick up");
+
+        let Json(reassigned) = super::reassign_courier(
+            admin.state(),
+            admin.user_access(),
+            transaction.id.into(),
+            Json(super::AssignCourierRequest {
+                courier_id: other_courier.user_id().into(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(reassigned.courier_id, Some(other_courier.user_id().into()));
+
+        let error = courier
+            .arrive(*transaction.id)
+            .await
+            .expect_err("courier no longer hold the parcel");
+        assert_matches!(error, Error::Forbidden);
+
+        other_courier
+            .arrive(*transaction.id)
+            .await
+            .expect("new courier deliver the parcel");
+
+        let Json(show) =
+            super::show_order(admin.state(), customer.user_access(), transaction.id.into())
+                .await
+                .unwrap();
+
+        let history = show
+            .status
+            .iter()
+            .map(|it| (it.r#type.clone(), it.by, it.courier_id))
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            history[3..],
+            [
+                (
+                    TransactionStatusType::WaitingForCourier,
+                    Some(admin.user_id().into()),
+                    Some(courier.user_id().into())
+                ),
+                (
+                    TransactionStatusType::PickedUpByCourier,
+                    Some(courier.user_id().into()),
+                    Some(courier.user_id().into())
+                ),
+                (
+                    TransactionStatusType::PickedUpByCourier,
+                    Some(admin.user_id().into()),
+                    Some(other_courier.user_id().into())
+                ),
+                (
+                    TransactionStatusType::ArrivedInDestination,
+                    Some(other_courier.user_id().into()),
+                    None
+                ),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_admin_unassign_courier() {
+        let admin = bootstrap().await;
+        let merchant = admin.derive_customer().await;
+
+        let customer = admin
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let courier = admin.derive_courier().await;
+        let other_courier = admin.derive_courier().await;
+
+        let transaction = customer
+            .create_pickedup_transaction(&merchant, &courier, 2)
+            .await;
+
+        let Json(unassigned) =
+            super::unassign_courier(admin.state(), admin.user_access(), transaction.id.into())
+                .await
+                .unwrap();
+
+        assert_eq!(unassigned.courier_id, None);
+        let last = unassigned.status.last().unwrap();
+        assert_eq!(last.r#type, TransactionStatusType::WaitingForCourier);
+        assert_eq!(last.by, Some(admin.user_id().into()));
+
+        let error =
+            super::unassign_courier(admin.state(), admin.user_access(), transaction.id.into())
+                .await
+                .expect_err("nobody to unassign");
+        assert_matches!(
+            error,
+            Error::TransactionError(TransactionError::IllegalAssignment { .. })
+        );
+
+        super::pickup(
+            admin.state(),
+            other_courier.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .expect("any courier can pick it up again");
+    }
+
+    #[tokio::test]
+    pub async fn test_arrive_require_proof_of_delivery() {
+        let bt = bootstrap().await.derive_customer().await;
+
+        let customer = bt
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let courier = bt.derive_courier().await;
+
+        let transaction = customer.create_pickedup_transaction(&bt, &courier, 2).await;
+
+        let error = super::change_delivery(
+            bt.state(),
+            courier.user_access(),
+            transaction.id.into(),
+            Json(super::ChangeDeliveryRequest {
+                r#type: TransactionStatusType::ArrivedInDestination,
+            }),
+        )
+        .await
+        .expect_err("arriving require a proof of delivery");
+        assert_matches!(error, Error::CustomStr(..));
+
+        courier.arrive(*transaction.id).await.unwrap();
+
+        let Json(show) =
+            super::show_order(bt.state(), customer.user_access(), transaction.id.into())
+                .await
+                .unwrap();
+
+        let last = show.status.last().unwrap();
+        assert_eq!(last.r#type, TransactionStatusType::ArrivedInDestination);
+        assert_eq!(
+            last.proof.as_ref().unwrap().recipient_name.as_deref(),
+            Some("recipient")
+        );
+    }
+
+    #[tokio::test]
+    pub async fn test_proof_of_delivery_download() {
+        let admin = bootstrap().await;
+        let merchant = admin.derive_customer().await;
+
+        let customer = admin
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(20_000))
+            .await;
+
+        let courier = admin.derive_courier().await;
+        let stranger = admin.derive_customer().await;
+
+        let transaction = customer
+            .create_pickedup_transaction(&merchant, &courier, 2)
+            .await;
+
+        let error = super::proof::show_proof(
+            admin.state(),
+            admin.state(),
+            customer.user_access(),
+            transaction.id.into(),
+        )
+        .await
+        .expect_err("no proof before arriving");
+        assert_matches!(error, Error::NoResource);
+
+        courier.arrive(*transaction.id).await.unwrap();
+
+        for it in [&customer, &merchant, &admin] {
+            let ([(_, content_type), _], photo) = super::proof::show_proof(
+                admin.state(),
+                admin.state(),
+                it.user_access(),
+                transaction.id.into(),
+            )
+            .await
+            .unwrap();
+
+            assert_eq!(content_type, "image/jpeg");
+            assert_eq!(photo, crate::api::v1::tests::PHOTO);
+        }
+
+        for it in [&stranger, &courier] {
+            let error = super::proof::show_proof(
+                admin.state(),
+                admin.state(),
+                it.user_access(),
+                transaction.id.into(),
+            )
+            .await
+            .expect_err("only buyer, merchant and admin can download the proof");
+            assert_matches!(error, Error::Forbidden);
+        }
+    }
 }
@@ -0,0 +1,239 @@
+//! Live transaction updates. Every status appended to a transaction is published to
+//! [`TransactionEvents`] once saved, and streamed as Server-Sent Events to the users allowed
+//! to see the transaction.
+
+use std::{convert::Infallible, sync::Arc};
+
+use axum::{
+    extract::State,
+    response::sse::{Event, KeepAlive, Sse},
+};
+use bson::oid::ObjectId;
+use serde::Serialize;
+use tokio::sync::broadcast;
+use tokio_stream::{
+    wrappers::{errors::BroadcastStreamRecvError, BroadcastStream},
+    Stream, StreamExt,
+};
+
+use crate::{
+    api::v1::auth::{UserAccess, UserRole},
+    error::Error,
+    util::PathObjectId,
+};
+
+use super::{
+    can_see_delivery, DeliveryResponse, Transaction, TransactionCollection, TransactionModel,
+};
+
+/// Updates kept for a slow subscriber before it start missing some.
+const CAPACITY: usize = 256;
+
+/// Broadcast hub of transaction updates, local to this server instance.
+#[derive(Clone)]
+pub struct TransactionEvents(broadcast::Sender<Arc<Transaction>>);
+
+impl Default for TransactionEvents {
+    fn default() -> Self {
+        Self(broadcast::channel(CAPACITY).0)
+    }
+}
+
+impl TransactionEvents {
+    /// Tell the subscribers a status was appended to `transaction`. Must only be called once
+    /// the change is committed.
+    pub fn publish(&self, transaction: &Transaction) {
+        // nobody listening is fine.
+        let _ = self.0.send(Arc::new(transaction.clone()));
+    }
+
+    /// Every update from now on that `visible` maps to something.
+    fn updates<T: Send + 'static>(
+        &self,
+        visible: impl Fn(&Transaction) -> Option<T> + Send + 'static,
+    ) -> impl Stream<Item = Result<T, BroadcastStreamRecvError>> + Send + 'static {
+        BroadcastStream::new(self.0.subscribe()).filter_map(move |it| match it {
+            Ok(it) => visible(&it).map(Ok),
+            Err(err) => Some(Err(err)),
+        })
+    }
+}
+
+/// Updates of the order `id` of the buyer `user_id`, the same ones [`super::show_order`]
+/// shows.
+fn order_updates(
+    events: &TransactionEvents,
+    user_id: ObjectId,
+    id: ObjectId,
+) -> impl Stream<Item = Result<TransactionModel, BroadcastStreamRecvError>> + Send + 'static {
+    events.updates(move |it| (it.id == id && it.user_id == user_id).then(|| it.clone().into()))
+}
+
+/// Updates of the deliveries [`super::show_delivery`] shows to `user`.
+fn delivery_updates(
+    events: &TransactionEvents,
+    user: UserAccess,
+) -> impl Stream<Item = Result<DeliveryResponse, BroadcastStreamRecvError>> + Send + 'static {
+    events.updates(move |it| {
+        can_see_delivery(it, &user).then(|| DeliveryResponse::new(it.clone(), &user))
+    })
+}
+
+/// A `status` event for every update, and a `lagged` event when some were dropped so the
+/// client know it has to fetch again.
+fn into_sse<T: Serialize>(
+    updates: impl Stream<Item = Result<T, BroadcastStreamRecvError>> + Send + 'static,
+) -> Sse<impl Stream<Item = Result<Event, Infallible>>> {
+    let events = updates.filter_map(|it| match it {
+        Ok(it) => Event::default().event("status").json_data(it).ok().map(Ok),
+        Err(BroadcastStreamRecvError::Lagged(..)) => {
+            Some(Ok(Event::default().event("lagged").data("")))
+        }
+    });
+
+    Sse::new(events).keep_alive(KeepAlive::default())
+}
+
+pub async fn order_events(
+    State(events): State<TransactionEvents>,
+    State(transactions): State<TransactionCollection>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Error> {
+    // subscribe first so nothing changed while checking is missed.
+    let updates = order_updates(&events, user.id, path);
+
+    transactions
+        .find_exists_one_by_id(path)
+        .await?
+        .filter(|it| it.user_id == user.id)
+        .ok_or(Error::Forbidden)?;
+
+    Ok(into_sse(updates))
+}
+
+pub async fn delivery_events(
+    State(events): State<TransactionEvents>,
+    user: UserAccess,
+) -> Result<Sse<impl Stream<Item = Result<Event, Infallible>>>, Error> {
+    match user.role {
+        UserRole::Customer => return Err(Error::Forbidden),
+        UserRole::Courier | UserRole::Admin => {}
+    }
+
+    Ok(into_sse(delivery_updates(&events, user)))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use bson::oid::ObjectId;
+    use tokio_stream::{Stream, StreamExt};
+
+    use crate::api::v1::{
+        auth::{UserAccess, UserRole},
+        tests::TransactionBuilder,
+        transaction::{Transaction, TransactionStatusType},
+    };
+
+    use super::{delivery_updates, order_updates, TransactionEvents};
+
+    fn transaction(
+        user_id: ObjectId,
+        status: TransactionStatusType,
+        courier_id: Option<ObjectId>,
+    ) -> Transaction {
+        TransactionBuilder::default()
+            .buyer(user_id)
+            .status(status, None)
+            .courier(courier_id)
+            .build()
+    }
+
+    /// Every update already published, waiting a bit for the last one.
+    async fn received<T, E>(updates: impl Stream<Item = Result<T, E>>) -> Vec<T> {
+        let updates = updates.timeout(Duration::from_millis(50));
+        tokio::pin!(updates);
+
+        let mut result = vec![];
+        while let Some(Ok(Ok(it))) = updates.next().await {
+            result.push(it);
+        }
+
+        result
+    }
+
+    #[tokio::test]
+    async fn test_order_events_only_for_buyer() {
+        let events = TransactionEvents::default();
+        let buyer = ObjectId::new();
+
+        let order = transaction(buyer, TransactionStatusType::ProcessingInMerchant, None);
+        let other_order = transaction(buyer, TransactionStatusType::ProcessingInMerchant, None);
+        let stranger_order = Transaction {
+            id: order.id,
+            ..transaction(
+                ObjectId::new(),
+                TransactionStatusType::ProcessingInMerchant,
+                None,
+            )
+        };
+
+        let updates = order_updates(&events, buyer, order.id);
+
+        events.publish(&other_order);
+        events.publish(&stranger_order);
+        events.publish(&order);
+
+        let received = received(updates).await;
+        assert_eq!(received.len(), 1);
+        assert_eq!(received[0].id, order.id);
+    }
+
+    #[tokio::test]
+    async fn test_delivery_events_follow_visibility() {
+        let events = TransactionEvents::default();
+        let courier = ObjectId::new();
+
+        let waiting = transaction(
+            ObjectId::new(),
+            TransactionStatusType::WaitingForCourier,
+            None,
+        );
+        let mine = transaction(
+            ObjectId::new(),
+            TransactionStatusType::PickedUpByCourier,
+            Some(courier),
+        );
+        let others = transaction(
+            ObjectId::new(),
+            TransactionStatusType::PickedUpByCourier,
+            Some(ObjectId::new()),
+        );
+        let processing = transaction(
+            ObjectId::new(),
+            TransactionStatusType::ProcessingInMerchant,
+            None,
+        );
+
+        let updates = delivery_updates(
+            &events,
+            UserAccess {
+                id: courier,
+                role: UserRole::Courier,
+            },
+        );
+
+        for it in [&waiting, &mine, &others, &processing] {
+            events.publish(it);
+        }
+
+        let received = received(updates)
+            .await
+            .into_iter()
+            .map(|it| *it.id)
+            .collect::<Vec<_>>();
+        assert_eq!(received, vec![waiting.id, mine.id]);
+    }
+}
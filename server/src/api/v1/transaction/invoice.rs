@@ -0,0 +1,1168 @@
+//! Invoices and packing slips, rendered as HTML or PDF on the server.
+//!
+//! A transaction gets its invoice number the first time one of its documents is made. Numbers
+//! count up from 1 without gaps, see [`InvoiceCollection::numbers_for`]. Orders cancelled,
+//! rejected, expired or returned before getting one never do, as no sale happened, while an
+//! order invoiced before it was refunded keeps its number.
+
+use std::{
+    collections::{hash_map::Entry, HashMap},
+    io::{Cursor, Write},
+};
+
+use axum::{
+    extract::{FromRef, Query, State},
+    http::{header, HeaderName, StatusCode},
+};
+use bson::oid::ObjectId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    api::v1::auth::{UserAccess, UserCollection, UserModel, UserRole},
+    app::AppState,
+    error::Error,
+    mongo_ext::{with_transaction, Collection},
+    util::{FormattedDateTime, PathObjectId},
+};
+
+use super::{
+    state::{self, Party},
+    Transaction, TransactionCollection, TransactionStatusType,
+};
+
+/// Most invoices downloaded at once by [`download_invoices`].
+pub const MAX_BULK_INVOICES: usize = 500;
+
+/// Id of the counter of [`InvoiceModel::number`].
+const INVOICE_COUNTER: &str = "invoice";
+
+/// Invoice number given to a transaction.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InvoiceModel {
+    #[serde(rename = "_id")]
+    pub transaction_id: ObjectId,
+    pub number: i64,
+
+    pub created_at: bson::DateTime,
+}
+
+#[derive(Clone)]
+pub struct InvoiceCollection(pub Collection<InvoiceModel>);
+
+impl std::ops::Deref for InvoiceCollection {
+    type Target = Collection<InvoiceModel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CounterModel {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub seq: i64,
+}
+
+#[derive(Clone)]
+pub struct CounterCollection(pub Collection<CounterModel>);
+
+impl std::ops::Deref for CounterCollection {
+    type Target = Collection<CounterModel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl CounterCollection {
+    /// Take the next `count` values of counter `id`, starting from 1, returning the first of
+    /// them.
+    pub async fn next_with_session(
+        &self,
+        id: &str,
+        count: i64,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<i64, Error> {
+        let counter = self
+            .find_one_and_update_with_session(
+                bson::doc! { "_id": id },
+                bson::doc! { "$inc": { "seq": count } },
+                mongodb::options::FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .return_document(mongodb::options::ReturnDocument::After)
+                    .build(),
+                session,
+            )
+            .await?;
+
+        // an upsert returning the document after the update always returns one.
+        Ok(counter.map(|it| it.seq).unwrap_or(count) - count + 1)
+    }
+}
+
+/// Whether `transaction` was refunded, no sale happened so it doesn't get an invoice.
+fn is_void(transaction: &Transaction) -> bool {
+    use TransactionStatusType::*;
+
+    matches!(
+        transaction.status.last().map(|it| &it.r#type),
+        Some(Cancelled | RejectedByMerchant { .. } | Expired | Returned)
+    )
+}
+
+impl InvoiceCollection {
+    /// Invoice numbers of `transactions`, giving the next ones in order to those without one
+    /// yet. Void transactions without a number are left out, see [`is_void`]. Taking the
+    /// numbers and saving them happen in one mongo transaction, so two concurrent calls
+    /// conflict and the retried one finds the saved numbers.
+    pub async fn numbers_for(
+        &self,
+        counters: &CounterCollection,
+        mongo: &mongodb::Client,
+        transactions: &[Transaction],
+    ) -> Result<HashMap<ObjectId, i64>, Error> {
+        let ids = transactions.iter().map(|it| it.id).collect::<Vec<_>>();
+
+        with_transaction!(mongo, |session| {
+            let mut cursor = self
+                .find_with_session(
+                    bson::doc! { "_id": { "$in": ids.clone() } },
+                    None,
+                    &mut session,
+                )
+                .await?;
+
+            let mut numbers = HashMap::new();
+
+            while cursor.advance(&mut session).await? {
+                let invoice = cursor.deserialize_current()?;
+                numbers.insert(invoice.transaction_id, invoice.number);
+            }
+
+            let missing = transactions
+                .iter()
+                .filter(|it| !numbers.contains_key(&it.id) && !is_void(it))
+                .map(|it| it.id)
+                .collect::<Vec<_>>();
+
+            if missing.is_empty() {
+                return Ok(numbers);
+            }
+
+            let first = counters
+                .next_with_session(INVOICE_COUNTER, missing.len() as i64, &mut session)
+                .await?;
+
+            let created_at = OffsetDateTime::now_utc().into();
+            let invoices = missing
+                .into_iter()
+                .zip(first..)
+                .map(|(transaction_id, number)| InvoiceModel {
+                    transaction_id,
+                    number,
+                    created_at,
+                })
+                .collect::<Vec<_>>();
+
+            self.insert_many_with_session(&invoices, None, &mut session)
+                .await?;
+
+            numbers.extend(invoices.iter().map(|it| (it.transaction_id, it.number)));
+
+            Ok(numbers)
+        })
+    }
+}
+
+/// Everything needed to make the documents of a transaction.
+#[derive(Clone)]
+pub struct InvoiceState {
+    pub invoices: InvoiceCollection,
+    pub counters: CounterCollection,
+    pub transactions: TransactionCollection,
+    pub users: UserCollection,
+    pub mongo: mongodb::Client,
+}
+
+impl FromRef<AppState> for InvoiceState {
+    fn from_ref(input: &AppState) -> Self {
+        Self {
+            invoices: input.invoice_collection.clone(),
+            counters: input.counter_collection.clone(),
+            transactions: input.transaction_collection.clone(),
+            users: input.user_collection.clone(),
+            mongo: input.mongo_client.clone(),
+        }
+    }
+}
+
+impl InvoiceState {
+    /// `id`, even when the user was deleted since, as the document still has to name them.
+    async fn user(&self, id: ObjectId) -> Result<UserModel, Error> {
+        self.users
+            .find_one(bson::doc! { "_id": id }, None)
+            .await?
+            .ok_or(Error::NoResource)
+    }
+
+    async fn numbers_for(
+        &self,
+        transactions: &[Transaction],
+    ) -> Result<HashMap<ObjectId, i64>, Error> {
+        self.invoices
+            .numbers_for(&self.counters, &self.mongo, transactions)
+            .await
+    }
+
+    /// Invoice number of `transaction`, failing when it is void.
+    async fn number_for(&self, transaction: &Transaction) -> Result<i64, Error> {
+        self.numbers_for(std::slice::from_ref(transaction))
+            .await?
+            .get(&transaction.id)
+            .copied()
+            .ok_or(Error::CustomStr(
+                StatusCode::NOT_FOUND,
+                "Refunded orders have no invoice",
+            ))
+    }
+}
+
+pub fn invoice_name(number: i64) -> String {
+    format!("INV-{number:06}")
+}
+
+fn format_date(date: bson::DateTime) -> String {
+    let date = OffsetDateTime::from(date);
+
+    format!(
+        "{}-{:02}-{:02} {:02}:{:02} UTC",
+        date.year(),
+        u8::from(date.month()),
+        date.day(),
+        date.hour(),
+        date.minute()
+    )
+}
+
+fn format_price(price: Decimal) -> String {
+    format!("Rp {}", price.round_dp(2))
+}
+
+fn status_name(status: &TransactionStatusType) -> String {
+    use TransactionStatusType::*;
+
+    match status {
+        WaitingForMerchantConfirmation => "Ordered".to_string(),
+        ProcessingInMerchant => "Accepted by merchant".to_string(),
+        WaitingForCourier => "Waiting for courier".to_string(),
+        PickedUpByCourier => "Picked up by courier".to_string(),
+        SendBackToMerchant { reason } => format!("Sent back to merchant: {reason}"),
+        ArrivedInMerchant => "Arrived in merchant".to_string(),
+        ArrivedInDestination => "Delivered".to_string(),
+        ArrivedInDestinationConfirmed => "Received by buyer".to_string(),
+        Cancelled => "Cancelled".to_string(),
+        RejectedByMerchant { reason } => format!("Rejected by merchant: {reason}"),
+        Returned => "Refunded".to_string(),
+        Expired => "Expired".to_string(),
+    }
+}
+
+fn user_name(user: &UserModel) -> String {
+    format!("{} <{}>", user.name, user.email)
+}
+
+/// Content of an invoice or a packing slip, independent of its format.
+#[derive(Debug, Clone, PartialEq)]
+struct Document {
+    title: String,
+    details: Vec<(&'static str, String)>,
+    columns: Vec<&'static str>,
+    rows: Vec<Vec<String>>,
+    totals: Vec<(&'static str, String)>,
+    /// Each status with its date, oldest first.
+    history: Vec<(String, String)>,
+}
+
+impl Document {
+    fn invoice(
+        number: i64,
+        transaction: &Transaction,
+        buyer: &UserModel,
+        merchant: &UserModel,
+    ) -> Self {
+        let rows = transaction
+            .products
+            .iter()
+            .map(|it| {
+                let quantity =
+                    Decimal::from_str_exact(&it.quantity.to_string()).unwrap_or_default();
+
+                vec![
+                    it.name.clone(),
+                    it.quantity.to_string(),
+                    format_price(it.price),
+                    format_price(it.price * quantity),
+                ]
+            })
+            .collect();
+
+        Self {
+            title: format!("Invoice {}", invoice_name(number)),
+            details: vec![
+                ("Order", transaction.id.to_hex()),
+                ("Date", format_date(transaction.created_at)),
+                ("Buyer", user_name(buyer)),
+                ("Merchant", user_name(merchant)),
+                (
+                    "Ship to",
+                    transaction
+                        .address
+                        .as_ref()
+                        .map(|it| it.address.clone())
+                        .unwrap_or_default(),
+                ),
+            ],
+            columns: vec!["Product", "Quantity", "Price", "Subtotal"],
+            rows,
+            totals: vec![
+                ("Subtotal", format_price(transaction.price)),
+                ("Shipping fee", format_price(transaction.shipping_fee)),
+                (
+                    "Total",
+                    format_price(transaction.price + transaction.shipping_fee),
+                ),
+            ],
+            history: transaction
+                .status
+                .iter()
+                .map(|it| (status_name(&it.r#type), format_date(it.date)))
+                .collect(),
+        }
+    }
+
+    /// What goes in the parcel and where it goes, without prices.
+    fn packing_slip(number: i64, transaction: &Transaction, buyer: &UserModel) -> Self {
+        Self {
+            title: format!("Packing slip {}", invoice_name(number)),
+            details: vec![
+                ("Order", transaction.id.to_hex()),
+                ("Date", format_date(transaction.created_at)),
+                ("Recipient", buyer.name.clone()),
+                (
+                    "Ship to",
+                    transaction
+                        .address
+                        .as_ref()
+                        .map(|it| format!("{} ({})", it.address, it.label))
+                        .unwrap_or_default(),
+                ),
+            ],
+            columns: vec!["Product", "Quantity"],
+            rows: transaction
+                .products
+                .iter()
+                .map(|it| vec![it.name.clone(), it.quantity.to_string()])
+                .collect(),
+            totals: vec![],
+            history: vec![],
+        }
+    }
+
+    fn to_html(&self) -> String {
+        let mut html = String::new();
+
+        html.push_str("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n");
+        html.push_str(&format!("<title>{}</title>\n", escape(&self.title)));
+        html.push_str(
+            "<style>\
+             body { font-family: sans-serif; margin: 2em; }\
+             table { border-collapse: collapse; width: 100%; margin: 1em 0; }\
+             th, td { border: 1px solid #ccc; padding: 4px 8px; text-align: left; }\
+             @media print { body { margin: 0; } }\
+             </style>\n",
+        );
+        html.push_str("</head>\n<body>\n");
+        html.push_str(&format!("<h1>{}</h1>\n", escape(&self.title)));
+
+        html.push_str("<table>\n");
+        for (label, value) in &self.details {
+            html.push_str(&format!(
+                "<tr><th>{}</th><td>{}</td></tr>\n",
+                escape(label),
+                escape(value)
+            ));
+        }
+        html.push_str("</table>\n");
+
+        html.push_str("<table>\n<tr>");
+        for it in &self.columns {
+            html.push_str(&format!("<th>{}</th>", escape(it)));
+        }
+        html.push_str("</tr>\n");
+        for row in &self.rows {
+            html.push_str("<tr>");
+            for it in row {
+                html.push_str(&format!("<td>{}</td>", escape(it)));
+            }
+            html.push_str("</tr>\n");
+        }
+        for (label, value) in &self.totals {
+            html.push_str(&format!(
+                "<tr><th colspan=\"{}\">{}</th><td>{}</td></tr>\n",
+                self.columns.len() - 1,
+                escape(label),
+                escape(value)
+            ));
+        }
+        html.push_str("</table>\n");
+
+        if !self.history.is_empty() {
+            html.push_str("<h2>Status</h2>\n<table>\n");
+            for (status, date) in &self.history {
+                html.push_str(&format!(
+                    "<tr><td>{}</td><td>{}</td></tr>\n",
+                    escape(status),
+                    escape(date)
+                ));
+            }
+            html.push_str("</table>\n");
+        }
+
+        html.push_str("</body>\n</html>\n");
+
+        html
+    }
+
+    fn to_pdf(&self) -> Result<Vec<u8>, Error> {
+        let mut pdf = PdfWriter::new(&self.title)?;
+
+        pdf.title(&self.title);
+
+        for (label, value) in &self.details {
+            pdf.row(&[label, value.as_str()], &[0.0, 35.0]);
+        }
+        pdf.gap();
+
+        // the first column takes whatever the others leave.
+        let offsets = match self.columns.len() {
+            2 => vec![0.0, 140.0],
+            _ => vec![0.0, 85.0, 105.0, 140.0],
+        };
+
+        pdf.bold_row(&self.columns, &offsets);
+        for row in &self.rows {
+            let row = row.iter().map(String::as_str).collect::<Vec<_>>();
+            pdf.row(&row, &offsets);
+        }
+        for (label, value) in &self.totals {
+            pdf.row(&[label, value.as_str()], &[105.0, 140.0]);
+        }
+
+        if !self.history.is_empty() {
+            pdf.gap();
+            pdf.bold_row(&["Status"], &[0.0]);
+            for (status, date) in &self.history {
+                pdf.row(&[date.as_str(), status.as_str()], &[0.0, 45.0]);
+            }
+        }
+
+        pdf.finish()
+    }
+}
+
+fn escape(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+
+    for it in text.chars() {
+        match it {
+            '&' => result.push_str("&amp;"),
+            '<' => result.push_str("&lt;"),
+            '>' => result.push_str("&gt;"),
+            '"' => result.push_str("&quot;"),
+            '\'' => result.push_str("&#39;"),
+            it => result.push(it),
+        }
+    }
+
+    result
+}
+
+fn pdf_error(err: printpdf::Error) -> Error {
+    Error::CustomStatus(StatusCode::INTERNAL_SERVER_ERROR, anyhow::anyhow!("{err}"))
+}
+
+/// Write lines of text top to bottom on A4 pages, adding pages as needed.
+struct PdfWriter {
+    document: printpdf::PdfDocumentReference,
+    layer: printpdf::PdfLayerReference,
+    font: printpdf::IndirectFontRef,
+    bold: printpdf::IndirectFontRef,
+    /// Distance of the next line from the bottom, in mm.
+    y: f32,
+}
+
+impl PdfWriter {
+    const WIDTH: f32 = 210.0;
+    const HEIGHT: f32 = 297.0;
+    const MARGIN: f32 = 20.0;
+    const LINE: f32 = 6.0;
+    const FONT_SIZE: f32 = 10.0;
+
+    fn new(title: &str) -> Result<Self, Error> {
+        use printpdf::{BuiltinFont, Mm, PdfDocument};
+
+        let (document, page, layer) =
+            PdfDocument::new(title, Mm(Self::WIDTH), Mm(Self::HEIGHT), "content");
+        let font = document
+            .add_builtin_font(BuiltinFont::Helvetica)
+            .map_err(pdf_error)?;
+        let bold = document
+            .add_builtin_font(BuiltinFont::HelveticaBold)
+            .map_err(pdf_error)?;
+        let layer = document.get_page(page).get_layer(layer);
+
+        Ok(Self {
+            document,
+            layer,
+            font,
+            bold,
+            y: Self::HEIGHT - Self::MARGIN,
+        })
+    }
+
+    fn next_line(&mut self, height: f32) {
+        if self.y - height < Self::MARGIN {
+            let (page, layer) = self.document.add_page(
+                printpdf::Mm(Self::WIDTH),
+                printpdf::Mm(Self::HEIGHT),
+                "content",
+            );
+            self.layer = self.document.get_page(page).get_layer(layer);
+            self.y = Self::HEIGHT - Self::MARGIN;
+        }
+
+        self.y -= height;
+    }
+
+    fn text(&self, text: &str, size: f32, x: f32, bold: bool) {
+        // the builtin fonts only cover latin characters.
+        let text = text
+            .chars()
+            .map(|it| if it.is_ascii() { it } else { '?' })
+            .collect::<String>();
+        let font = if bold { &self.bold } else { &self.font };
+
+        self.layer.use_text(
+            text,
+            size,
+            printpdf::Mm(Self::MARGIN + x),
+            printpdf::Mm(self.y),
+            font,
+        );
+    }
+
+    fn title(&mut self, title: &str) {
+        self.next_line(Self::LINE * 1.5);
+        self.text(title, Self::FONT_SIZE * 1.6, 0.0, true);
+        self.gap();
+    }
+
+    /// One line with `cells` starting `offsets` mm from the left margin.
+    fn write_row(&mut self, cells: &[&str], offsets: &[f32], bold: bool) {
+        self.next_line(Self::LINE);
+        for (cell, offset) in cells.iter().zip(offsets) {
+            self.text(cell, Self::FONT_SIZE, *offset, bold);
+        }
+    }
+
+    fn row(&mut self, cells: &[&str], offsets: &[f32]) {
+        self.write_row(cells, offsets, false)
+    }
+
+    fn bold_row(&mut self, cells: &[&str], offsets: &[f32]) {
+        self.write_row(cells, offsets, true)
+    }
+
+    fn gap(&mut self) {
+        self.next_line(Self::LINE / 2.0);
+    }
+
+    fn finish(self) -> Result<Vec<u8>, Error> {
+        self.document.save_to_bytes().map_err(pdf_error)
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum DocumentFormat {
+    #[default]
+    Html,
+    Pdf,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct DocumentQuery {
+    #[serde(default)]
+    pub format: DocumentFormat,
+}
+
+type DocumentResponse = ([(HeaderName, String); 2], Vec<u8>);
+
+/// Run `work`, rendering PDFs or compressing, on the blocking threads so it doesn't stall
+/// the async runtime.
+async fn blocking<T>(work: impl FnOnce() -> Result<T, Error> + Send + 'static) -> Result<T, Error>
+where
+    T: Send + 'static,
+{
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|err| Error::CustomStatus(StatusCode::INTERNAL_SERVER_ERROR, err.into()))?
+}
+
+async fn render(
+    document: Document,
+    name: &str,
+    format: DocumentFormat,
+) -> Result<DocumentResponse, Error> {
+    let (content_type, extension, body) = match format {
+        DocumentFormat::Html => (
+            "text/html; charset=utf-8",
+            "html",
+            document.to_html().into(),
+        ),
+        DocumentFormat::Pdf => (
+            "application/pdf",
+            "pdf",
+            blocking(move || document.to_pdf()).await?,
+        ),
+    };
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, content_type.to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                format!("inline; filename=\"{name}.{extension}\""),
+            ),
+        ],
+        body,
+    ))
+}
+
+/// The transaction `id` when `user` plays one of `parties` in it.
+async fn find_for(
+    state: &InvoiceState,
+    user: &UserAccess,
+    id: ObjectId,
+    parties: &[Party],
+) -> Result<Transaction, Error> {
+    let transaction = state
+        .transactions
+        .find_exists_one_by_id(id)
+        .await?
+        .ok_or(Error::NoResource)?;
+
+    if state::parties(&transaction, user)
+        .iter()
+        .any(|it| parties.contains(it))
+    {
+        Ok(transaction)
+    } else {
+        Err(Error::Forbidden)
+    }
+}
+
+/// Invoice of an order, for its buyer, its merchant and admins.
+pub async fn show_invoice(
+    State(state): State<InvoiceState>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+    Query(query): Query<DocumentQuery>,
+) -> Result<DocumentResponse, Error> {
+    let transaction = find_for(
+        &state,
+        &user,
+        path,
+        &[Party::Buyer, Party::Merchant, Party::Admin],
+    )
+    .await?;
+
+    let number = state.number_for(&transaction).await?;
+    let buyer = state.user(transaction.user_id).await?;
+    let merchant = state.user(transaction.merchant_id).await?;
+
+    render(
+        Document::invoice(number, &transaction, &buyer, &merchant),
+        &invoice_name(number),
+        query.format,
+    )
+    .await
+}
+
+/// Packing slip of a sale, for its merchant and admins.
+pub async fn show_packing_slip(
+    State(state): State<InvoiceState>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+    Query(query): Query<DocumentQuery>,
+) -> Result<DocumentResponse, Error> {
+    let transaction = find_for(&state, &user, path, &[Party::Merchant, Party::Admin]).await?;
+
+    let number = state.number_for(&transaction).await?;
+    let buyer = state.user(transaction.user_id).await?;
+
+    render(
+        Document::packing_slip(number, &transaction, &buyer),
+        &format!("packing-slip-{}", invoice_name(number)),
+        query.format,
+    )
+    .await
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct InvoiceRangeQuery {
+    /// Sales created at or after.
+    pub from: FormattedDateTime,
+    /// Sales created before.
+    pub to: FormattedDateTime,
+}
+
+/// ZIP archive of `files`, given as `(name, content)`.
+fn zip(files: Vec<(String, Vec<u8>)>) -> Result<Vec<u8>, Error> {
+    let zip_error =
+        |err| Error::CustomStatus(StatusCode::INTERNAL_SERVER_ERROR, anyhow::anyhow!("{err}"));
+
+    let mut writer = zip::ZipWriter::new(Cursor::new(vec![]));
+    let options =
+        zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Deflated);
+
+    for (name, content) in files {
+        writer.start_file(name, options).map_err(zip_error)?;
+        writer
+            .write_all(&content)
+            .map_err(|err| zip_error(err.into()))?;
+    }
+
+    Ok(writer.finish().map_err(zip_error)?.into_inner())
+}
+
+/// PDF invoices of every sale of the current user created in the range, as a ZIP archive.
+/// Void sales without an invoice are left out.
+pub async fn download_invoices(
+    State(state): State<InvoiceState>,
+    user: UserAccess,
+    Query(query): Query<InvoiceRangeQuery>,
+) -> Result<DocumentResponse, Error> {
+    match user.role {
+        UserRole::Courier => return Err(Error::Forbidden),
+        UserRole::Customer | UserRole::Admin => {}
+    }
+
+    let from = bson::DateTime::from(query.from);
+    let to = bson::DateTime::from(query.to);
+
+    if from >= to {
+        return Err(Error::CustomStr(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The range must end after it starts",
+        ));
+    }
+
+    let mut cursor = state
+        .transactions
+        .find_exists(
+            bson::doc! {
+                "merchant_id": user.id,
+                "created_at": { "$gte": from, "$lt": to },
+            },
+            mongodb::options::FindOptions::builder()
+                .sort(bson::doc! { "created_at": 1, "_id": 1 })
+                .limit(MAX_BULK_INVOICES as i64 + 1)
+                .build(),
+        )
+        .await?;
+
+    let mut transactions = vec![];
+
+    while cursor.advance().await? {
+        transactions.push(cursor.deserialize_current()?);
+    }
+
+    if transactions.len() > MAX_BULK_INVOICES {
+        return Err(Error::CustomStr(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Too many invoices, please choose a shorter range",
+        ));
+    }
+
+    let numbers = state.numbers_for(&transactions).await?;
+    let merchant = state.user(user.id).await?;
+    let mut buyers = HashMap::new();
+    let mut documents = vec![];
+
+    for transaction in transactions {
+        let number = match numbers.get(&transaction.id) {
+            Some(it) => *it,
+            None => continue,
+        };
+
+        if let Entry::Vacant(entry) = buyers.entry(transaction.user_id) {
+            entry.insert(state.user(transaction.user_id).await?);
+        }
+
+        let document = Document::invoice(
+            number,
+            &transaction,
+            &buyers[&transaction.user_id],
+            &merchant,
+        );
+
+        documents.push((format!("{}.pdf", invoice_name(number)), document));
+    }
+
+    let archive = blocking(move || {
+        let files = documents
+            .into_iter()
+            .map(|(name, document)| Ok((name, document.to_pdf()?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        zip(files)
+    })
+    .await?;
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/zip".to_string()),
+            (
+                header::CONTENT_DISPOSITION,
+                "attachment; filename=\"invoices.zip\"".to_string(),
+            ),
+        ],
+        archive,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Cursor, Read};
+
+    use assert_matches::assert_matches;
+    use axum::extract::Query;
+    use bson::oid::ObjectId;
+    use num_bigint::BigInt;
+    use rust_decimal::Decimal;
+    use time::OffsetDateTime;
+
+    use crate::{
+        api::v1::{
+            auth::{UserModel, UserRole},
+            tests::{bootstrap, TransactionBuilder},
+            transaction::{ProductTransaction, Transaction, TransactionStatusType},
+        },
+        error::Error,
+        util::PathObjectId,
+    };
+
+    use super::{escape, DocumentFormat, DocumentQuery, InvoiceRangeQuery};
+
+    fn user(name: &str) -> UserModel {
+        UserModel {
+            id: ObjectId::new(),
+            name: name.to_string(),
+            email: format!("{name}@mail.com"),
+            password: String::new(),
+            role: UserRole::Customer,
+            balance: Decimal::ZERO,
+            created_at: bson::DateTime::now(),
+            updated_at: bson::DateTime::now(),
+        }
+    }
+
+    fn transaction(buyer: &UserModel, merchant: &UserModel) -> Transaction {
+        TransactionBuilder::default()
+            .buyer(buyer.id)
+            .merchant(merchant.id)
+            .price(Decimal::from(3_000))
+            .shipping_fee(Decimal::from(500))
+            .status(
+                TransactionStatusType::WaitingForMerchantConfirmation,
+                Some(buyer.id),
+            )
+            .products(vec![ProductTransaction {
+                id: ObjectId::new(),
+                quantity: BigInt::from(3),
+                price: Decimal::from(1_000),
+                name: "<b>Kopi</b>".to_string(),
+                description: String::new(),
+                category: None,
+            }])
+            .build()
+    }
+
+    #[test]
+    fn test_invoice_document() {
+        let buyer = user("buyer");
+        let merchant = user("merchant");
+
+        let document =
+            super::Document::invoice(12, &transaction(&buyer, &merchant), &buyer, &merchant);
+
+        assert_eq!(document.title, "Invoice INV-000012");
+        assert_eq!(
+            document.rows,
+            vec![vec![
+                "<b>Kopi</b>".to_string(),
+                "3".to_string(),
+                "Rp 1000".to_string(),
+                "Rp 3000".to_string(),
+            ]]
+        );
+        assert_eq!(document.totals[2], ("Total", "Rp 3500".to_string()));
+        assert_eq!(document.history[0].0, "Ordered");
+
+        let html = document.to_html();
+        assert!(html.contains(&escape("<b>Kopi</b>")));
+        assert!(!html.contains("<b>Kopi</b>"));
+
+        let pdf = document.to_pdf().unwrap();
+        assert!(pdf.starts_with(b"%PDF-"));
+    }
+
+    #[test]
+    fn test_packing_slip_has_no_price() {
+        let buyer = user("buyer");
+        let merchant = user("merchant");
+
+        let document = super::Document::packing_slip(1, &transaction(&buyer, &merchant), &buyer);
+
+        assert_eq!(document.columns, vec!["Product", "Quantity"]);
+        assert!(document.totals.is_empty());
+        assert!(!document.to_html().contains("Rp "));
+    }
+
+    #[test]
+    fn test_zip() {
+        let archive = super::zip(vec![
+            ("INV-000001.pdf".to_string(), b"first".to_vec()),
+            ("INV-000002.pdf".to_string(), b"second".to_vec()),
+        ])
+        .unwrap();
+
+        let mut archive = zip::ZipArchive::new(Cursor::new(archive)).unwrap();
+        assert_eq!(archive.len(), 2);
+
+        let mut content = String::new();
+        archive
+            .by_name("INV-000002.pdf")
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "second");
+    }
+
+    #[tokio::test]
+    async fn test_invoice_numbered_sequentially() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(10_000.into())
+            .await;
+        let merchant = bootstrap.derive_customer().await;
+
+        let first = customer.create_transaction(&merchant, 1).await;
+        let second = customer.create_transaction(&merchant, 1).await;
+
+        let state: super::InvoiceState = bootstrap.state().0;
+        let find = |id| {
+            let state = state.clone();
+            async move {
+                state
+                    .transactions
+                    .find_exists_one_by_id(id)
+                    .await
+                    .unwrap()
+                    .unwrap()
+            }
+        };
+        let (first, second) = (find(*first.id).await, find(*second.id).await);
+
+        assert_eq!(state.number_for(&second).await.unwrap(), 1);
+        assert_eq!(state.number_for(&first).await.unwrap(), 2);
+        // a number is given once.
+        assert_eq!(state.number_for(&second).await.unwrap(), 1);
+
+        let (headers, body) = super::show_invoice(
+            bootstrap.state(),
+            customer.user_access(),
+            PathObjectId(first.id),
+            Query(DocumentQuery {
+                format: DocumentFormat::Pdf,
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(headers[0].1, "application/pdf");
+        assert!(headers[1].1.contains("INV-000002.pdf"));
+        assert!(body.starts_with(b"%PDF-"));
+    }
+
+    #[tokio::test]
+    async fn test_void_order_has_no_invoice() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(10_000.into())
+            .await;
+        let merchant = bootstrap.derive_customer().await;
+
+        let from = OffsetDateTime::now_utc() - time::Duration::seconds(1);
+        let cancelled = customer.create_transaction(&merchant, 1).await;
+        let _ = crate::api::v1::transaction::cancel_order(
+            bootstrap.state(),
+            customer.user_access(),
+            PathObjectId(*cancelled.id),
+        )
+        .await
+        .unwrap();
+        customer.create_transaction(&merchant, 1).await;
+        let to = OffsetDateTime::now_utc() + time::Duration::seconds(1);
+
+        let error = super::show_invoice(
+            bootstrap.state(),
+            customer.user_access(),
+            PathObjectId(*cancelled.id),
+            Query(DocumentQuery::default()),
+        )
+        .await
+        .expect_err("cancelled order has no invoice");
+        assert_matches!(
+            error,
+            Error::CustomStr(axum::http::StatusCode::NOT_FOUND, _)
+        );
+
+        let (_, body) = super::download_invoices(
+            bootstrap.state(),
+            merchant.user_access(),
+            Query(InvoiceRangeQuery {
+                from: from.into(),
+                to: to.into(),
+            }),
+        )
+        .await
+        .unwrap();
+
+        // the cancelled order didn't take a number.
+        let archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+        assert_eq!(
+            archive.file_names().collect::<Vec<_>>(),
+            vec!["INV-000001.pdf"]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_only_parties_get_documents() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(10_000.into())
+            .await;
+        let merchant = bootstrap.derive_customer().await;
+        let other = bootstrap.derive_customer().await;
+
+        let transaction = customer.create_transaction(&merchant, 1).await;
+
+        for user in [&customer, &merchant] {
+            super::show_invoice(
+                bootstrap.state(),
+                user.user_access(),
+                PathObjectId(*transaction.id),
+                Query(DocumentQuery::default()),
+            )
+            .await
+            .unwrap();
+        }
+
+        let error = super::show_invoice(
+            bootstrap.state(),
+            other.user_access(),
+            PathObjectId(*transaction.id),
+            Query(DocumentQuery::default()),
+        )
+        .await
+        .expect_err("stranger can't see the invoice");
+        assert_matches!(error, Error::Forbidden);
+
+        let error = super::show_packing_slip(
+            bootstrap.state(),
+            customer.user_access(),
+            PathObjectId(*transaction.id),
+            Query(DocumentQuery::default()),
+        )
+        .await
+        .expect_err("only the merchant packs the parcel");
+        assert_matches!(error, Error::Forbidden);
+
+        let (headers, _) = super::show_packing_slip(
+            bootstrap.state(),
+            merchant.user_access(),
+            PathObjectId(*transaction.id),
+            Query(DocumentQuery::default()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(headers[0].1, "text/html; charset=utf-8");
+    }
+
+    #[tokio::test]
+    async fn test_download_invoices_in_range() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(10_000.into())
+            .await;
+        let merchant = bootstrap.derive_customer().await;
+        let other_merchant = bootstrap.derive_customer().await;
+
+        let from = OffsetDateTime::now_utc() - time::Duration::seconds(1);
+        customer.create_transaction(&merchant, 1).await;
+        customer.create_transaction(&merchant, 1).await;
+        customer.create_transaction(&other_merchant, 1).await;
+        let to = OffsetDateTime::now_utc() + time::Duration::seconds(1);
+
+        let (headers, body) = super::download_invoices(
+            bootstrap.state(),
+            merchant.user_access(),
+            Query(InvoiceRangeQuery {
+                from: from.into(),
+                to: to.into(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(headers[0].1, "application/zip");
+
+        let archive = zip::ZipArchive::new(Cursor::new(body)).unwrap();
+        let mut names = archive.file_names().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(names, vec!["INV-000001.pdf", "INV-000002.pdf"]);
+
+        let error = super::download_invoices(
+            bootstrap.state(),
+            merchant.user_access(),
+            Query(InvoiceRangeQuery {
+                from: to.into(),
+                to: from.into(),
+            }),
+        )
+        .await
+        .expect_err("range must be ordered");
+        assert_matches!(error, Error::CustomStr(..));
+    }
+}
@@ -0,0 +1,309 @@
+//! Proof of delivery. The courier upload a photo of the parcel at its destination, with who
+//! received it, when moving a transaction to [`TransactionStatusType::ArrivedInDestination`].
+
+use axum::{
+    extract::{Multipart, State},
+    http::{header, HeaderName, HeaderValue, StatusCode},
+};
+use bson::oid::ObjectId;
+use serde::{Deserialize, Serialize};
+use validator::Validate;
+
+use crate::{
+    api::v1::auth::{UserAccess, UserRole},
+    error::Error,
+    storage::FileStorage,
+    util::PathObjectId,
+};
+
+use super::{
+    state::{self, Party},
+    ChangeStatusState, TransactionCollection, TransactionStatusType,
+};
+
+/// Largest photo accepted, the route body limit must leave room for it.
+pub const MAX_PHOTO_SIZE: usize = 5 * 1024 * 1024;
+
+const PHOTO_CONTENT_TYPES: [&str; 3] = ["image/jpeg", "image/png", "image/webp"];
+
+/// Content type of `photo` told by its magic bytes, `None` when it isn't one of
+/// [`PHOTO_CONTENT_TYPES`].
+fn photo_content_type(photo: &[u8]) -> Option<&'static str> {
+    match photo {
+        [0xff, 0xd8, 0xff, ..] => Some("image/jpeg"),
+        [0x89, b'P', b'N', b'G', 0x0d, 0x0a, 0x1a, 0x0a, ..] => Some("image/png"),
+        [b'R', b'I', b'F', b'F', _, _, _, _, b'W', b'E', b'B', b'P', ..] => Some("image/webp"),
+        _ => None,
+    }
+}
+
+/// Attached to the status entry the proof was uploaded with.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DeliveryProof {
+    /// Storage key of the photo.
+    pub photo: String,
+    pub content_type: String,
+    pub recipient_name: Option<String>,
+    pub note: Option<String>,
+}
+
+/// The photo itself is downloaded from [`show_proof`].
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DeliveryProofModel {
+    pub content_type: String,
+    pub recipient_name: Option<String>,
+    pub note: Option<String>,
+}
+
+impl From<DeliveryProof> for DeliveryProofModel {
+    fn from(value: DeliveryProof) -> Self {
+        Self {
+            content_type: value.content_type,
+            recipient_name: value.recipient_name,
+            note: value.note,
+        }
+    }
+}
+
+/// `multipart/form-data` body of [`arrive`], with a `photo` file and the optional
+/// `recipient_name` and `note` text fields.
+#[derive(Debug, Default, Validate)]
+pub struct ArriveRequest {
+    #[validate(custom = "validate_photo")]
+    pub photo: Vec<u8>,
+    #[validate(custom = "validate_photo_content_type")]
+    pub content_type: String,
+    #[validate(length(min = 1, max = 256))]
+    pub recipient_name: Option<String>,
+    #[validate(length(min = 1, max = 1024))]
+    pub note: Option<String>,
+}
+
+fn validate_photo(photo: &[u8]) -> Result<(), validator::ValidationError> {
+    if photo.is_empty() || photo.len() > MAX_PHOTO_SIZE {
+        Err(validator::ValidationError::new("photo"))
+    } else {
+        Ok(())
+    }
+}
+
+fn validate_photo_content_type(content_type: &str) -> Result<(), validator::ValidationError> {
+    if PHOTO_CONTENT_TYPES.contains(&content_type) {
+        Ok(())
+    } else {
+        Err(validator::ValidationError::new("content_type"))
+    }
+}
+
+impl ArriveRequest {
+    pub async fn from_multipart(mut multipart: Multipart) -> Result<Self, Error> {
+        let mut request = Self::default();
+        let mut has_photo = false;
+
+        while let Some(field) = multipart.next_field().await? {
+            match field.name() {
+                Some("photo") => {
+                    request.content_type = field.content_type().unwrap_or_default().to_string();
+                    request.photo = field.bytes().await?.to_vec();
+                    has_photo = true;
+                }
+                Some("recipient_name") => {
+                    request.recipient_name = Some(field.text().await?).filter(|it| !it.is_empty())
+                }
+                Some("note") => {
+                    request.note = Some(field.text().await?).filter(|it| !it.is_empty())
+                }
+                _ => {}
+            }
+        }
+
+        if !has_photo {
+            return Err(Error::CustomStr(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Proof of delivery require a photo",
+            ));
+        }
+
+        request.validate()?;
+
+        // the declared content type is up to the client, the photo must really be one.
+        if photo_content_type(&request.photo) != Some(request.content_type.as_str()) {
+            return Err(Error::CustomStr(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Photo content doesn't match its content type",
+            ));
+        }
+
+        Ok(request)
+    }
+}
+
+/// Move a picked up transaction to [`TransactionStatusType::ArrivedInDestination`] with a
+/// proof of delivery.
+pub async fn arrive(
+    State(change): State<ChangeStatusState>,
+    State(storage): State<FileStorage>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+    multipart: Multipart,
+) -> Result<(), Error> {
+    match user.role {
+        UserRole::Customer => return Err(Error::Forbidden),
+        UserRole::Courier | UserRole::Admin => {}
+    }
+
+    let request = ArriveRequest::from_multipart(multipart).await?;
+
+    let key = format!("proofs/{path}/{}", ObjectId::new());
+    storage.put(&key, request.photo).await?;
+
+    let proof = DeliveryProof {
+        photo: key.clone(),
+        content_type: request.content_type,
+        recipient_name: request.recipient_name,
+        note: request.note,
+    };
+
+    let result = super::change_status_with_proof(
+        &change,
+        &user,
+        path,
+        TransactionStatusType::ArrivedInDestination,
+        Some(proof),
+    )
+    .await;
+
+    // the photo is saved before the transition is checked, drop it when it didn't happen.
+    if result.is_err() {
+        if let Err(err) = storage.delete(&key).await {
+            tracing::warn!(key, ?err, "failed deleting unused proof of delivery");
+        }
+    }
+
+    result?;
+
+    Ok(())
+}
+
+/// Photo of the proof of delivery, only for the buyer, the merchant and admins.
+pub async fn show_proof(
+    State(transactions): State<TransactionCollection>,
+    State(storage): State<FileStorage>,
+    user: UserAccess,
+    PathObjectId(path): PathObjectId,
+) -> Result<([(HeaderName, HeaderValue); 2], Vec<u8>), Error> {
+    let transaction = transactions
+        .find_exists_one_by_id(path)
+        .await?
+        .filter(|it| {
+            state::parties(it, &user)
+                .iter()
+                .any(|it| matches!(it, Party::Buyer | Party::Merchant | Party::Admin))
+        })
+        .ok_or(Error::Forbidden)?;
+
+    let proof = transaction
+        .status
+        .into_iter()
+        .rev()
+        .find_map(|it| it.proof)
+        .ok_or(Error::NoResource)?;
+
+    let photo = storage.get(&proof.photo).await?.ok_or(Error::NoResource)?;
+
+    // sniffed again rather than trusting the stored content type, proofs uploaded before
+    // the photos were checked may be anything.
+    let content_type = photo_content_type(&photo).unwrap_or("application/octet-stream");
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, HeaderValue::from_static(content_type)),
+            (
+                header::X_CONTENT_TYPE_OPTIONS,
+                HeaderValue::from_static("nosniff"),
+            ),
+        ],
+        photo,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use crate::{
+        api::v1::tests::{multipart, PHOTO},
+        error::Error,
+    };
+
+    use super::ArriveRequest;
+
+    #[tokio::test]
+    async fn test_parse_proof_of_delivery() {
+        let request = ArriveRequest::from_multipart(
+            multipart(&[
+                ("photo", Some("image/jpeg"), PHOTO),
+                ("recipient_name", None, b"Budi"),
+                ("note", None, b""),
+            ])
+            .await,
+        )
+        .await
+        .unwrap();
+
+        assert_eq!(request.photo, PHOTO);
+        assert_eq!(request.content_type, "image/jpeg");
+        assert_eq!(request.recipient_name.as_deref(), Some("Budi"));
+        assert_eq!(request.note, None);
+    }
+
+    #[tokio::test]
+    async fn test_proof_of_delivery_require_photo() {
+        let error =
+            ArriveRequest::from_multipart(multipart(&[("recipient_name", None, b"Budi")]).await)
+                .await
+                .expect_err("photo is required");
+        assert_matches!(error, Error::CustomStr(..));
+
+        let error = ArriveRequest::from_multipart(
+            multipart(&[("photo", Some("application/pdf"), b"photo")]).await,
+        )
+        .await
+        .expect_err("photo must be an image");
+        assert_matches!(error, Error::ValidationError(..));
+
+        let error =
+            ArriveRequest::from_multipart(multipart(&[("photo", Some("image/png"), b"")]).await)
+                .await
+                .expect_err("photo must not be empty");
+        assert_matches!(error, Error::ValidationError(..));
+
+        let error = ArriveRequest::from_multipart(
+            multipart(&[("photo", Some("image/png"), b"<html></html>")]).await,
+        )
+        .await
+        .expect_err("photo content must be an image");
+        assert_matches!(error, Error::CustomStr(..));
+
+        let error =
+            ArriveRequest::from_multipart(multipart(&[("photo", Some("image/png"), PHOTO)]).await)
+                .await
+                .expect_err("photo content must match its content type");
+        assert_matches!(error, Error::CustomStr(..));
+    }
+
+    #[test]
+    fn test_photo_content_type() {
+        assert_eq!(super::photo_content_type(PHOTO), Some("image/jpeg"));
+        assert_eq!(
+            super::photo_content_type(b"\x89PNG\r\n\x1a\n...."),
+            Some("image/png")
+        );
+        assert_eq!(
+            super::photo_content_type(b"RIFF\0\0\0\0WEBPVP8 "),
+            Some("image/webp")
+        );
+        assert_eq!(super::photo_content_type(b"RIFF\0\0\0\0WAVE"), None);
+        assert_eq!(super::photo_content_type(b""), None);
+    }
+}
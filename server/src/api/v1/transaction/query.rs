@@ -0,0 +1,397 @@
+//! Filtering, sorting and cursor based pagination for the transaction lists.
+//!
+//! The cursor is the sort key and id of the last transaction of a page, so the next page
+//! starts right after it even when transactions are added in between.
+
+use axum::http::StatusCode;
+use bson::{oid::ObjectId, Bson, Document};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    error::Error,
+    util::{FormattedDateTime, ObjectIdString},
+};
+
+use super::{Transaction, TransactionCollection};
+
+pub const DEFAULT_LIMIT: u32 = 20;
+pub const MAX_LIMIT: u32 = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortBy {
+    #[default]
+    CreatedAt,
+    UpdatedAt,
+    Price,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SortOrder {
+    Asc,
+    #[default]
+    Desc,
+}
+
+/// Query string of the transaction lists, every filter is optional.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct TransactionQuery {
+    /// Comma separated names of the current status, e.g. `Cancelled,Expired`.
+    pub status: Option<String>,
+    /// Created at or after.
+    pub from: Option<FormattedDateTime>,
+    /// Created before.
+    pub to: Option<FormattedDateTime>,
+    pub merchant_id: Option<ObjectIdString>,
+    pub buyer_id: Option<ObjectIdString>,
+    pub min_price: Option<Decimal>,
+    pub max_price: Option<Decimal>,
+
+    #[serde(default)]
+    pub sort: SortBy,
+    #[serde(default)]
+    pub order: SortOrder,
+    /// Between 1 and [`MAX_LIMIT`], defaults to [`DEFAULT_LIMIT`].
+    pub limit: Option<u32>,
+    /// `next_cursor` of the previous page.
+    pub cursor: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug, PartialEq)]
+struct Cursor {
+    sort: SortBy,
+    /// Sort key of the last transaction, in millisecond for dates.
+    key: String,
+    id: ObjectIdString,
+}
+
+impl Cursor {
+    fn new(sort: SortBy, transaction: &Transaction) -> Self {
+        let key = match sort {
+            SortBy::CreatedAt => transaction.created_at.timestamp_millis().to_string(),
+            SortBy::UpdatedAt => transaction.updated_at.timestamp_millis().to_string(),
+            SortBy::Price => transaction.price.to_string(),
+        };
+
+        Self {
+            sort,
+            key,
+            id: transaction.id.into(),
+        }
+    }
+
+    fn encode(&self) -> String {
+        use base64::Engine;
+
+        // serializing a plain struct can't fail.
+        let json = serde_json::to_vec(self).unwrap_or_default();
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(json)
+    }
+
+    fn decode(cursor: &str, sort: SortBy) -> Result<Self, Error> {
+        use base64::Engine;
+
+        let invalid = Error::CustomStr(StatusCode::UNPROCESSABLE_ENTITY, "Invalid cursor");
+
+        let cursor: Self = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(cursor)
+            .ok()
+            .and_then(|it| serde_json::from_slice(&it).ok())
+            .ok_or(invalid)?;
+
+        if cursor.sort != sort {
+            return Err(Error::CustomStr(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Cursor was made for another sort",
+            ));
+        }
+
+        Ok(cursor)
+    }
+
+    /// The sort key as a value comparable to the sorted field, a mongo expression for
+    /// [`SORT_KEY`].
+    fn key(&self) -> Result<Bson, Error> {
+        let invalid = || Error::CustomStr(StatusCode::UNPROCESSABLE_ENTITY, "Invalid cursor");
+
+        Ok(match self.sort {
+            SortBy::CreatedAt | SortBy::UpdatedAt => Bson::DateTime(bson::DateTime::from_millis(
+                self.key.parse().map_err(|_| invalid())?,
+            )),
+            SortBy::Price => {
+                let key: Decimal = self.key.parse().map_err(|_| invalid())?;
+                bson::bson!({ "$toDecimal": key.to_string() })
+            }
+        })
+    }
+}
+
+/// Field added to every transaction by the pipeline when sorting by price, holding the
+/// price as a decimal.
+const SORT_KEY: &str = "sort_key";
+
+impl TransactionQuery {
+    pub fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).clamp(1, MAX_LIMIT)
+    }
+
+    /// Aggregation pipeline returning one more transaction than [`Self::limit`] among those
+    /// matching `filter` and this query, the extra one tells there is a next page. The query
+    /// only narrows `filter` down, it can't widen what the user is allowed to see.
+    pub fn pipeline(&self, filter: Document) -> Result<Vec<Document>, Error> {
+        let mut filter = bson::doc! {
+            "$and": [filter],
+            "deleted_at": null,
+        };
+
+        let mut created_at = Document::new();
+        if let Some(from) = self.from.clone() {
+            created_at.insert("$gte", bson::DateTime::from(from));
+        }
+        if let Some(to) = self.to.clone() {
+            created_at.insert("$lt", bson::DateTime::from(to));
+        }
+        if !created_at.is_empty() {
+            filter.insert("created_at", created_at);
+        }
+
+        if let Some(it) = self.merchant_id {
+            filter.insert("merchant_id", ObjectId::from(it));
+        }
+        if let Some(it) = self.buyer_id {
+            filter.insert("user_id", ObjectId::from(it));
+        }
+
+        // price is stored as a string, so it has to be compared as a decimal expression.
+        let mut expr = vec![];
+
+        if let Some(status) = &self.status {
+            let names = status
+                .split(',')
+                .map(str::trim)
+                .filter(|it| !it.is_empty())
+                .collect::<Vec<_>>();
+
+            expr.push(bson::bson!({
+                "$in": [
+                    {
+                        "$getField": {
+                            "input": {
+                                "$getField": {
+                                    "input": { "$last": "$status" },
+                                    "field": "type"
+                                }
+                            },
+                            "field": "type"
+                        }
+                    },
+                    names,
+                ]
+            }));
+        }
+        if let Some(min) = self.min_price {
+            expr.push(bson::bson!({
+                "$gte": [{ "$toDecimal": "$price" }, { "$toDecimal": min.to_string() }]
+            }));
+        }
+        if let Some(max) = self.max_price {
+            expr.push(bson::bson!({
+                "$lte": [{ "$toDecimal": "$price" }, { "$toDecimal": max.to_string() }]
+            }));
+        }
+
+        if !expr.is_empty() {
+            filter.insert("$expr", bson::doc! { "$and": expr });
+        }
+
+        // dates are sorted on their own field so the `{user, created_at, _id}` indexes are
+        // used, price is stored as a string and has to be sorted on a decimal copy.
+        let field = match self.sort {
+            SortBy::CreatedAt => "created_at",
+            SortBy::UpdatedAt => "updated_at",
+            SortBy::Price => SORT_KEY,
+        };
+
+        let (direction, after) = match self.order {
+            SortOrder::Asc => (1, "$gt"),
+            SortOrder::Desc => (-1, "$lt"),
+        };
+
+        let mut pipeline = vec![bson::doc! { "$match": filter }];
+
+        if self.sort == SortBy::Price {
+            pipeline.push(bson::doc! {
+                "$addFields": { SORT_KEY: { "$toDecimal": "$price" } }
+            });
+        }
+
+        if let Some(cursor) = &self.cursor {
+            let cursor = Cursor::decode(cursor, self.sort)?;
+            let key = cursor.key()?;
+            let id = ObjectId::from(cursor.id);
+
+            let after_cursor = match self.sort {
+                SortBy::CreatedAt | SortBy::UpdatedAt => bson::doc! {
+                    "$or": [
+                        { field: { after: &key } },
+                        { field: &key, "_id": { after: id } },
+                    ]
+                },
+                SortBy::Price => {
+                    let sort_key = format!("${SORT_KEY}");
+
+                    bson::doc! {
+                        "$expr": {
+                            "$or": [
+                                { after: [&sort_key, &key] },
+                                {
+                                    "$and": [
+                                        { "$eq": [&sort_key, &key] },
+                                        { after: ["$_id", id] },
+                                    ]
+                                },
+                            ]
+                        }
+                    }
+                }
+            };
+
+            pipeline.push(bson::doc! { "$match": after_cursor });
+        }
+
+        pipeline.push(bson::doc! { "$sort": { field: direction, "_id": direction } });
+        pipeline.push(bson::doc! { "$limit": i64::from(self.limit()) + 1 });
+
+        if self.sort == SortBy::Price {
+            pipeline.push(bson::doc! { "$unset": SORT_KEY });
+        }
+
+        Ok(pipeline)
+    }
+}
+
+/// One page of transactions matching `filter` and `query`, with the cursor of the next page
+/// when there is one.
+pub async fn find_page(
+    transactions: &TransactionCollection,
+    filter: Document,
+    query: &TransactionQuery,
+) -> Result<(Vec<Transaction>, Option<String>), Error> {
+    let mut cursor = transactions
+        .aggregate(query.pipeline(filter)?, None)
+        .await?
+        .with_type::<Transaction>();
+
+    let mut result = vec![];
+
+    while cursor.advance().await? {
+        result.push(cursor.deserialize_current()?);
+    }
+
+    let next_cursor = if result.len() > query.limit() as usize {
+        result.pop();
+        result.last().map(|it| Cursor::new(query.sort, it).encode())
+    } else {
+        None
+    };
+
+    Ok((result, next_cursor))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use bson::oid::ObjectId;
+
+    use crate::error::Error;
+
+    use super::{Cursor, SortBy, TransactionQuery, MAX_LIMIT};
+
+    #[test]
+    fn test_cursor_roundtrip() {
+        let cursor = Cursor {
+            sort: SortBy::Price,
+            key: "1500.50".to_string(),
+            id: ObjectId::new().into(),
+        };
+
+        assert_eq!(
+            Cursor::decode(&cursor.encode(), SortBy::Price).unwrap(),
+            cursor
+        );
+
+        let error = Cursor::decode(&cursor.encode(), SortBy::CreatedAt)
+            .expect_err("cursor is bound to its sort");
+        assert_matches!(error, Error::CustomStr(..));
+
+        let error = Cursor::decode("not a cursor", SortBy::Price).expect_err("invalid cursor");
+        assert_matches!(error, Error::CustomStr(..));
+    }
+
+    #[test]
+    fn test_query_cannot_widen_filter() {
+        let own = ObjectId::new();
+        let other = ObjectId::new();
+
+        let query = TransactionQuery {
+            merchant_id: Some(other.into()),
+            ..Default::default()
+        };
+
+        let pipeline = query.pipeline(bson::doc! { "merchant_id": own }).unwrap();
+        let filter = pipeline[0].get_document("$match").unwrap();
+
+        assert_eq!(
+            filter.get_array("$and").unwrap(),
+            &vec![bson::bson!({ "merchant_id": own })]
+        );
+        assert_eq!(filter.get_object_id("merchant_id").unwrap(), other);
+    }
+
+    #[test]
+    fn test_dates_sorted_on_their_field() {
+        let query = |sort| TransactionQuery {
+            sort,
+            cursor: Some(
+                Cursor {
+                    sort,
+                    key: "1500".to_string(),
+                    id: ObjectId::new().into(),
+                }
+                .encode(),
+            ),
+            ..Default::default()
+        };
+
+        let pipeline = query(SortBy::CreatedAt).pipeline(bson::doc! {}).unwrap();
+        assert!(pipeline.iter().all(|it| !it.contains_key("$addFields")));
+        assert_eq!(
+            pipeline[2],
+            bson::doc! { "$sort": { "created_at": -1, "_id": -1 } }
+        );
+
+        let after = pipeline[1].get_document("$match").unwrap();
+        assert!(!after.contains_key("$expr"));
+
+        let pipeline = query(SortBy::Price).pipeline(bson::doc! {}).unwrap();
+        assert!(pipeline[1].contains_key("$addFields"));
+        assert_eq!(
+            pipeline[3],
+            bson::doc! { "$sort": { super::SORT_KEY: -1, "_id": -1 } }
+        );
+    }
+
+    #[test]
+    fn test_limit_clamped() {
+        let query = |limit| TransactionQuery {
+            limit,
+            ..Default::default()
+        };
+
+        assert_eq!(query(None).limit(), super::DEFAULT_LIMIT);
+        assert_eq!(query(Some(0)).limit(), 1);
+        assert_eq!(query(Some(10_000)).limit(), MAX_LIMIT);
+    }
+}
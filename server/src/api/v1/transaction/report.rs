@@ -0,0 +1,571 @@
+//! Sales reports of a merchant, computed by an aggregation over the transactions.
+//!
+//! Every order paid in the range counts, unless it ended cancelled, rejected, expired or
+//! refunded. Revenue is the price of the products, without the shipping fee.
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderName, StatusCode},
+    response::{IntoResponse, Response},
+    Json,
+};
+use bson::{oid::ObjectId, Document};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    api::v1::auth::{UserAccess, UserRole},
+    error::Error,
+    util::{DecimalString, FormattedDateTime, ObjectIdString},
+};
+
+use super::TransactionCollection;
+
+/// Last status of the orders left out of the reports.
+const VOID_STATUS: [&str; 4] = ["Cancelled", "RejectedByMerchant", "Expired", "Returned"];
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportPeriod {
+    #[default]
+    Day,
+    /// Starting on monday.
+    Week,
+    Month,
+}
+
+impl ReportPeriod {
+    fn unit(self) -> &'static str {
+        match self {
+            Self::Day => "day",
+            Self::Week => "week",
+            Self::Month => "month",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum ReportFormat {
+    #[default]
+    Json,
+    Csv,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReportQuery {
+    /// Created at or after.
+    pub from: FormattedDateTime,
+    /// Created before.
+    pub to: FormattedDateTime,
+    #[serde(default)]
+    pub period: ReportPeriod,
+    #[serde(default)]
+    pub format: ReportFormat,
+    /// Admin only, every merchant when not set.
+    pub merchant_id: Option<ObjectIdString>,
+}
+
+/// Sales figures of a period, a product or the whole range.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ReportFigures {
+    pub revenue: DecimalString,
+    pub orders: i64,
+    pub units: i64,
+    pub average_order_value: DecimalString,
+}
+
+impl ReportFigures {
+    fn new(row: &ReportRow) -> Self {
+        let average = if row.orders == 0 {
+            Decimal::ZERO
+        } else {
+            (row.revenue / Decimal::from(row.orders)).round_dp(2)
+        };
+
+        Self {
+            revenue: row.revenue.into(),
+            orders: row.orders,
+            units: row.units,
+            average_order_value: average.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReportPeriodModel {
+    pub start: FormattedDateTime,
+    #[serde(flatten)]
+    pub figures: ReportFigures,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReportProductModel {
+    pub product_id: ObjectIdString,
+    /// Name when last sold.
+    pub name: String,
+    #[serde(flatten)]
+    pub figures: ReportFigures,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct ReportModel {
+    pub from: FormattedDateTime,
+    pub to: FormattedDateTime,
+    pub period: ReportPeriod,
+    pub total: ReportFigures,
+    /// Oldest first, periods without sales are left out.
+    pub periods: Vec<ReportPeriodModel>,
+    /// Most units sold first.
+    pub products: Vec<ReportProductModel>,
+}
+
+/// One group as returned by the pipeline.
+#[derive(Deserialize, Debug)]
+struct ReportRow {
+    #[serde(rename = "_id")]
+    id: Option<bson::Bson>,
+    #[serde(default)]
+    name: String,
+    revenue: Decimal,
+    orders: i64,
+    units: i64,
+}
+
+#[derive(Deserialize, Debug)]
+struct ReportResult {
+    total: Vec<ReportRow>,
+    periods: Vec<ReportRow>,
+    products: Vec<ReportRow>,
+}
+
+/// Turn the sums of a `$group` back into the types of [`ReportRow`], decimals being stored
+/// as strings everywhere else.
+fn convert_sums() -> Document {
+    bson::doc! {
+        "$addFields": {
+            "revenue": { "$toString": "$revenue" },
+            "orders": { "$toLong": "$orders" },
+            "units": { "$toLong": "$units" },
+        }
+    }
+}
+
+/// Aggregation pipeline returning a single [`ReportResult`] for the orders of `merchant_id`,
+/// or of every merchant when `None`.
+fn pipeline(
+    merchant_id: Option<ObjectId>,
+    from: bson::DateTime,
+    to: bson::DateTime,
+    period: ReportPeriod,
+) -> Vec<Document> {
+    let mut filter = bson::doc! {
+        "deleted_at": null,
+        "created_at": { "$gte": from, "$lt": to },
+        "$expr": {
+            "$not": {
+                "$in": [
+                    {
+                        "$getField": {
+                            "input": {
+                                "$getField": {
+                                    "input": { "$last": "$status" },
+                                    "field": "type"
+                                }
+                            },
+                            "field": "type"
+                        }
+                    },
+                    VOID_STATUS.to_vec(),
+                ]
+            }
+        },
+    };
+
+    if let Some(merchant_id) = merchant_id {
+        filter.insert("merchant_id", merchant_id);
+    }
+
+    let units = bson::bson!({ "$sum": { "$sum": "$products.quantity" } });
+
+    let total = vec![
+        bson::doc! {
+            "$group": {
+                "_id": null,
+                "revenue": { "$sum": { "$toDecimal": "$price" } },
+                "orders": { "$sum": 1 },
+                "units": units.clone(),
+            }
+        },
+        convert_sums(),
+    ];
+
+    let periods = vec![
+        bson::doc! {
+            "$group": {
+                "_id": {
+                    "$dateTrunc": {
+                        "date": "$created_at",
+                        "unit": period.unit(),
+                        "startOfWeek": "monday",
+                    }
+                },
+                "revenue": { "$sum": { "$toDecimal": "$price" } },
+                "orders": { "$sum": 1 },
+                "units": units,
+            }
+        },
+        convert_sums(),
+        bson::doc! { "$sort": { "_id": 1 } },
+    ];
+
+    let products = vec![
+        // oldest first so `$last` picks the latest name.
+        bson::doc! { "$sort": { "created_at": 1 } },
+        bson::doc! { "$unwind": "$products" },
+        bson::doc! {
+            "$group": {
+                "_id": "$products.id",
+                "name": { "$last": "$products.name" },
+                "revenue": {
+                    "$sum": {
+                        "$multiply": [{ "$toDecimal": "$products.price" }, "$products.quantity"]
+                    }
+                },
+                // a product may be listed twice in the same order.
+                "orders": { "$addToSet": "$_id" },
+                "units": { "$sum": "$products.quantity" },
+            }
+        },
+        bson::doc! { "$set": { "orders": { "$size": "$orders" } } },
+        convert_sums(),
+        bson::doc! { "$sort": { "units": -1, "_id": 1 } },
+    ];
+
+    vec![
+        bson::doc! { "$match": filter },
+        bson::doc! {
+            "$facet": {
+                "total": total,
+                "periods": periods,
+                "products": products,
+            }
+        },
+    ]
+}
+
+fn csv_field(text: &str) -> String {
+    if text.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", text.replace('"', "\"\""))
+    } else {
+        text.to_string()
+    }
+}
+
+fn format_day(date: &FormattedDateTime) -> String {
+    let date = OffsetDateTime::from(bson::DateTime::from(date.clone()));
+
+    format!(
+        "{}-{:02}-{:02}",
+        date.year(),
+        u8::from(date.month()),
+        date.day()
+    )
+}
+
+impl ReportModel {
+    /// One row per period, then per product, then the total.
+    fn to_csv(&self) -> String {
+        let mut csv = String::from("section,key,name,orders,units,revenue,average_order_value\n");
+
+        let mut push = |section: &str, key: &str, name: &str, figures: &ReportFigures| {
+            csv.push_str(&format!(
+                "{section},{},{},{},{},{},{}\n",
+                csv_field(key),
+                csv_field(name),
+                figures.orders,
+                figures.units,
+                figures.revenue.0,
+                figures.average_order_value.0,
+            ));
+        };
+
+        for it in &self.periods {
+            push("period", &format_day(&it.start), "", &it.figures);
+        }
+        for it in &self.products {
+            push(
+                "product",
+                &ObjectId::from(it.product_id).to_hex(),
+                &it.name,
+                &it.figures,
+            );
+        }
+        push(
+            "total",
+            &format!("{}/{}", format_day(&self.from), format_day(&self.to)),
+            "",
+            &self.total,
+        );
+
+        csv
+    }
+}
+
+/// Sales report of the current user, or of any merchant for admins.
+pub async fn report(
+    State(transactions): State<TransactionCollection>,
+    user: UserAccess,
+    Query(query): Query<ReportQuery>,
+) -> Result<Response, Error> {
+    let merchant_id = match user.role {
+        UserRole::Courier => return Err(Error::Forbidden),
+        UserRole::Customer => match query.merchant_id {
+            Some(it) if ObjectId::from(it) != user.id => return Err(Error::Forbidden),
+            _ => Some(user.id),
+        },
+        UserRole::Admin => query.merchant_id.map(ObjectId::from),
+    };
+
+    let from = bson::DateTime::from(query.from.clone());
+    let to = bson::DateTime::from(query.to.clone());
+
+    if from >= to {
+        return Err(Error::CustomStr(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "The range must end after it starts",
+        ));
+    }
+
+    let mut cursor = transactions
+        .aggregate(pipeline(merchant_id, from, to, query.period), None)
+        .await?
+        .with_type::<ReportResult>();
+
+    let result = if cursor.advance().await? {
+        cursor.deserialize_current()?
+    } else {
+        ReportResult {
+            total: vec![],
+            periods: vec![],
+            products: vec![],
+        }
+    };
+
+    let empty = ReportRow {
+        id: None,
+        name: String::new(),
+        revenue: Decimal::ZERO,
+        orders: 0,
+        units: 0,
+    };
+
+    let report = ReportModel {
+        from: query.from,
+        to: query.to,
+        period: query.period,
+        total: ReportFigures::new(result.total.first().unwrap_or(&empty)),
+        periods: result
+            .periods
+            .iter()
+            .filter_map(|it| match &it.id {
+                Some(bson::Bson::DateTime(date)) => Some(ReportPeriodModel {
+                    start: (*date).into(),
+                    figures: ReportFigures::new(it),
+                }),
+                _ => None,
+            })
+            .collect(),
+        products: result
+            .products
+            .iter()
+            .filter_map(|it| match &it.id {
+                Some(bson::Bson::ObjectId(id)) => Some(ReportProductModel {
+                    product_id: (*id).into(),
+                    name: it.name.clone(),
+                    figures: ReportFigures::new(it),
+                }),
+                _ => None,
+            })
+            .collect(),
+    };
+
+    Ok(match query.format {
+        ReportFormat::Json => Json(report).into_response(),
+        ReportFormat::Csv => {
+            let headers: [(HeaderName, String); 2] = [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (
+                    header::CONTENT_DISPOSITION,
+                    format!(
+                        "attachment; filename=\"report-{}-{}.csv\"",
+                        format_day(&report.from),
+                        format_day(&report.to)
+                    ),
+                ),
+            ];
+
+            (headers, report.to_csv()).into_response()
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use axum::{
+        body::HttpBody,
+        extract::Query,
+        http::{header, StatusCode},
+    };
+    use bson::oid::ObjectId;
+    use rust_decimal::Decimal;
+    use time::OffsetDateTime;
+
+    use crate::{api::v1::tests::bootstrap, error::Error, util::PathObjectId};
+
+    use super::{
+        ReportFigures, ReportFormat, ReportModel, ReportPeriod, ReportPeriodModel,
+        ReportProductModel, ReportQuery, ReportRow,
+    };
+
+    fn figures(revenue: i64, orders: i64, units: i64) -> ReportFigures {
+        ReportFigures::new(&ReportRow {
+            id: None,
+            name: String::new(),
+            revenue: Decimal::from(revenue),
+            orders,
+            units,
+        })
+    }
+
+    #[test]
+    fn test_average_order_value() {
+        assert_eq!(
+            figures(1_000, 3, 3).average_order_value.0,
+            Decimal::new(33333, 2)
+        );
+        assert_eq!(figures(0, 0, 0).average_order_value.0, Decimal::ZERO);
+    }
+
+    #[test]
+    fn test_pipeline_scoped_to_merchant() {
+        let merchant = ObjectId::new();
+        let now = bson::DateTime::now();
+
+        let pipeline = super::pipeline(Some(merchant), now, now, ReportPeriod::Week);
+        let filter = pipeline[0].get_document("$match").unwrap();
+        assert_eq!(filter.get_object_id("merchant_id").unwrap(), merchant);
+
+        let pipeline = super::pipeline(None, now, now, ReportPeriod::Week);
+        let filter = pipeline[0].get_document("$match").unwrap();
+        assert!(!filter.contains_key("merchant_id"));
+    }
+
+    #[test]
+    fn test_csv() {
+        let date = OffsetDateTime::from_unix_timestamp(1_680_307_200).unwrap();
+
+        let report = ReportModel {
+            from: date.into(),
+            to: (date + time::Duration::days(1)).into(),
+            period: ReportPeriod::Day,
+            total: figures(2_000, 2, 2),
+            periods: vec![ReportPeriodModel {
+                start: date.into(),
+                figures: figures(2_000, 2, 2),
+            }],
+            products: vec![ReportProductModel {
+                product_id: ObjectId::from_bytes([0; 12]).into(),
+                name: "Kopi \"Susu\", 1L".to_string(),
+                figures: figures(2_000, 2, 2),
+            }],
+        };
+
+        assert_eq!(
+            report.to_csv(),
+            "section,key,name,orders,units,revenue,average_order_value\n\
+             period,2023-04-01,,2,2,2000,1000\n\
+             product,000000000000000000000000,\"Kopi \"\"Susu\"\", 1L\",2,2,2000,1000\n\
+             total,2023-04-01/2023-04-02,,2,2,2000,1000\n"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_report() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(10_000.into())
+            .await;
+        let merchant = bootstrap.derive_customer().await;
+        let other_merchant = bootstrap.derive_customer().await;
+
+        let from = OffsetDateTime::now_utc() - time::Duration::seconds(1);
+        customer.create_transaction(&merchant, 2).await;
+        customer.create_transaction(&merchant, 1).await;
+        customer.create_transaction(&other_merchant, 1).await;
+        let cancelled = customer.create_transaction(&merchant, 1).await;
+        let to = OffsetDateTime::now_utc() + time::Duration::seconds(1);
+
+        let _ = crate::api::v1::transaction::cancel_order(
+            bootstrap.state(),
+            customer.user_access(),
+            PathObjectId(*cancelled.id),
+        )
+        .await
+        .unwrap();
+
+        let query = |format| ReportQuery {
+            from: from.into(),
+            to: to.into(),
+            period: ReportPeriod::Month,
+            format,
+            merchant_id: None,
+        };
+
+        let response = super::report(
+            bootstrap.state(),
+            merchant.user_access(),
+            Query(query(ReportFormat::Json)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let body = response.into_body().data().await.unwrap().unwrap();
+        let report: ReportModel = serde_json::from_slice(&body).unwrap();
+
+        assert_eq!(report.total, figures(3_000, 2, 3));
+        assert_eq!(report.periods.len(), 1);
+        assert_eq!(report.periods[0].figures, figures(3_000, 2, 3));
+        assert_eq!(report.products.len(), 3);
+        assert_eq!(report.products[0].figures, figures(1_000, 1, 1));
+
+        let response = super::report(
+            bootstrap.state(),
+            merchant.user_access(),
+            Query(query(ReportFormat::Csv)),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            response.headers()[header::CONTENT_TYPE],
+            "text/csv; charset=utf-8"
+        );
+
+        let error = super::report(
+            bootstrap.state(),
+            merchant.user_access(),
+            Query(ReportQuery {
+                merchant_id: Some(other_merchant.user_model.id.into()),
+                ..query(ReportFormat::Json)
+            }),
+        )
+        .await
+        .expect_err("merchant only sees their own report");
+        assert_matches!(error, Error::Forbidden);
+    }
+}
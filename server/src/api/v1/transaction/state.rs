@@ -0,0 +1,427 @@
+//! Every legal [`TransactionStatusType`] transition, who is allowed to trigger it and what
+//! has to happen alongside it. Handlers must not push a status without going through
+//! [`transition`], or [`assignment`] when only the courier change.
+
+use bson::oid::ObjectId;
+
+use crate::{
+    api::v1::auth::{UserAccess, UserRole},
+    error::Error,
+};
+
+use super::{Transaction, TransactionError, TransactionStatusType};
+
+/// Relation between a user and a transaction.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Party {
+    Buyer,
+    Merchant,
+    /// Any courier that can take an unassigned delivery.
+    Courier,
+    /// The courier currently holding the parcel.
+    AssignedCourier,
+    Admin,
+    /// The server itself, e.g. the scheduler expiring stale orders.
+    System,
+}
+
+/// Side effect applied in the same mongo transaction as the status change.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    /// Give the price back to the buyer and restore the product stock.
+    Refund,
+    /// Release the escrowed price to the merchant.
+    Payout,
+    /// Release the escrowed shipping fee to the assigned courier.
+    PayCourier,
+    /// Assign the user triggering the transition as the courier.
+    AssignCourier,
+    UnassignCourier,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Rule {
+    pub parties: &'static [Party],
+    pub effects: &'static [Effect],
+}
+
+/// Returns the rule for changing the status from `from` to `to`, or `None` when the
+/// transition is illegal.
+pub fn rule(from: &TransactionStatusType, to: &TransactionStatusType) -> Option<Rule> {
+    use Effect::*;
+    use Party::*;
+    use TransactionStatusType as T;
+
+    let (parties, effects): (&'static [Party], &'static [Effect]) = match (from, to) {
+        (T::WaitingForMerchantConfirmation, T::ProcessingInMerchant) => (&[Merchant], &[]),
+        (
+            T::WaitingForMerchantConfirmation | T::ProcessingInMerchant,
+            T::RejectedByMerchant { .. },
+        ) => (&[Merchant], &[Refund]),
+        (T::WaitingForMerchantConfirmation | T::ProcessingInMerchant, T::Cancelled) => {
+            (&[Buyer, Admin], &[Refund])
+        }
+        (T::WaitingForMerchantConfirmation | T::ProcessingInMerchant, T::Expired) => {
+            (&[System], &[Refund])
+        }
+        (T::ProcessingInMerchant, T::WaitingForCourier) => (&[Merchant], &[]),
+        // an admin may have assigned the delivery to a courier beforehand.
+        (T::WaitingForCourier, T::PickedUpByCourier) => {
+            (&[Courier, AssignedCourier], &[AssignCourier])
+        }
+        (T::PickedUpByCourier, T::ArrivedInDestination) => {
+            (&[AssignedCourier], &[PayCourier, UnassignCourier])
+        }
+        (T::PickedUpByCourier, T::SendBackToMerchant { .. }) => (&[AssignedCourier], &[]),
+        (T::SendBackToMerchant { .. }, T::ArrivedInMerchant) => {
+            (&[AssignedCourier], &[UnassignCourier])
+        }
+        // the merchant decide what to do with a returned parcel.
+        (T::ArrivedInMerchant, T::WaitingForCourier) => (&[Merchant], &[]),
+        (T::ArrivedInMerchant, T::Returned) => (&[Merchant], &[Refund]),
+        // the scheduler confirm for buyers who forget to.
+        (T::ArrivedInDestination, T::ArrivedInDestinationConfirmed) => {
+            (&[Buyer, System], &[Payout])
+        }
+        _ => return None,
+    };
+
+    Some(Rule { parties, effects })
+}
+
+/// Returns every party `user` plays in `transaction`.
+pub fn parties(transaction: &Transaction, user: &UserAccess) -> Vec<Party> {
+    let mut parties = vec![];
+
+    if transaction.user_id == user.id {
+        parties.push(Party::Buyer);
+    }
+
+    if transaction.merchant_id == user.id {
+        parties.push(Party::Merchant);
+    }
+
+    match user.role {
+        UserRole::Courier | UserRole::Admin => {
+            if transaction.courier_id.is_none() {
+                parties.push(Party::Courier);
+            }
+        }
+        UserRole::Customer => {}
+    }
+
+    if transaction.courier_id == Some(user.id) {
+        parties.push(Party::AssignedCourier);
+    }
+
+    if user.role == UserRole::Admin {
+        parties.push(Party::Admin);
+    }
+
+    parties
+}
+
+/// Whether `user` is allowed to know the current status of `transaction`.
+fn can_see(transaction: &Transaction, parties: &[Party]) -> bool {
+    parties.iter().any(|it| match it {
+        Party::Buyer | Party::Merchant | Party::AssignedCourier | Party::Admin | Party::System => {
+            true
+        }
+        Party::Courier => transaction
+            .status
+            .last()
+            .filter(|it| matches!(it.r#type, TransactionStatusType::WaitingForCourier))
+            .is_some(),
+    })
+}
+
+/// Check whether `user` may move `transaction` to `to`, returning the rule to apply.
+pub fn transition(
+    transaction: &Transaction,
+    user: &UserAccess,
+    to: &TransactionStatusType,
+) -> Result<Rule, Error> {
+    transition_as(transaction, &parties(transaction, user), to)
+}
+
+/// Check whether the server itself may move `transaction` to `to`.
+pub fn system_transition(
+    transaction: &Transaction,
+    to: &TransactionStatusType,
+) -> Result<Rule, Error> {
+    transition_as(transaction, &[Party::System], to)
+}
+
+fn transition_as(
+    transaction: &Transaction,
+    parties: &[Party],
+    to: &TransactionStatusType,
+) -> Result<Rule, Error> {
+    if !can_see(transaction, parties) {
+        return Err(Error::Forbidden);
+    }
+
+    let from = transaction
+        .status
+        .last()
+        .map(|it| &it.r#type)
+        .ok_or(Error::Forbidden)?;
+
+    let rule = rule(from, to).ok_or_else(|| TransactionError::IllegalTransition {
+        from: from.clone(),
+        to: to.clone(),
+    })?;
+
+    if !rule.parties.iter().any(|it| parties.contains(it)) {
+        return Err(Error::Forbidden);
+    }
+
+    Ok(rule)
+}
+
+/// Change of the courier of a transaction made by an admin, e.g. when a courier goes missing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Assignment {
+    /// Give a delivery waiting for a courier to this courier.
+    Assign(ObjectId),
+    /// Take the delivery from its courier and give it to this one.
+    Reassign(ObjectId),
+    /// Take the delivery from its courier, so any courier can pick it up again.
+    Unassign,
+}
+
+/// Check whether `user` may apply `assignment` to `transaction`, returning the status to push
+/// along with the new courier.
+pub fn assignment(
+    transaction: &Transaction,
+    user: &UserAccess,
+    assignment: Assignment,
+) -> Result<TransactionStatusType, Error> {
+    use TransactionStatusType as T;
+
+    if !parties(transaction, user).contains(&Party::Admin) {
+        return Err(Error::Forbidden);
+    }
+
+    let from = transaction
+        .status
+        .last()
+        .map(|it| &it.r#type)
+        .ok_or(Error::Forbidden)?;
+
+    let to = match (from, transaction.courier_id, assignment) {
+        (T::WaitingForCourier, None, Assignment::Assign(_)) => Some(T::WaitingForCourier),
+        (
+            T::WaitingForCourier | T::PickedUpByCourier | T::SendBackToMerchant { .. },
+            Some(current),
+            Assignment::Reassign(courier),
+        ) if current != courier => Some(from.clone()),
+        // a parcel on its way back must still reach the merchant, so it can only be reassigned.
+        (T::WaitingForCourier | T::PickedUpByCourier, Some(_), Assignment::Unassign) => {
+            Some(T::WaitingForCourier)
+        }
+        _ => None,
+    };
+
+    Ok(to.ok_or_else(|| TransactionError::IllegalAssignment {
+        status: from.clone(),
+    })?)
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use bson::oid::ObjectId;
+
+    use super::{assignment, rule, Assignment, Effect, Party};
+    use crate::{
+        api::v1::{
+            auth::{UserAccess, UserRole},
+            tests::TransactionBuilder,
+            transaction::{Transaction, TransactionStatusType as T},
+        },
+        error::Error,
+    };
+
+    fn transaction(status: T, courier_id: Option<ObjectId>) -> Transaction {
+        TransactionBuilder::default()
+            .status(status, None)
+            .courier(courier_id)
+            .build()
+    }
+
+    fn user(role: UserRole) -> UserAccess {
+        UserAccess {
+            id: ObjectId::new(),
+            role,
+        }
+    }
+
+    #[test]
+    fn test_refund_only_before_courier() {
+        for from in [T::WaitingForMerchantConfirmation, T::ProcessingInMerchant] {
+            let cancel = rule(&from, &T::Cancelled).unwrap();
+            assert_eq!(cancel.effects, &[Effect::Refund]);
+            assert!(cancel.parties.contains(&Party::Buyer));
+
+            let reject = rule(
+                &from,
+                &T::RejectedByMerchant {
+                    reason: "".to_string(),
+                },
+            )
+            .unwrap();
+            assert_eq!(reject.effects, &[Effect::Refund]);
+            assert_eq!(reject.parties, &[Party::Merchant]);
+        }
+
+        for from in [
+            T::WaitingForCourier,
+            T::PickedUpByCourier,
+            T::ArrivedInDestination,
+        ] {
+            assert_eq!(rule(&from, &T::Cancelled), None);
+        }
+    }
+
+    #[test]
+    fn test_only_system_expire() {
+        for from in [T::WaitingForMerchantConfirmation, T::ProcessingInMerchant] {
+            let expire = rule(&from, &T::Expired).unwrap();
+            assert_eq!(expire.parties, &[Party::System]);
+            assert_eq!(expire.effects, &[Effect::Refund]);
+        }
+
+        for from in [
+            T::WaitingForCourier,
+            T::PickedUpByCourier,
+            T::ArrivedInDestination,
+        ] {
+            assert_eq!(rule(&from, &T::Expired), None);
+        }
+    }
+
+    #[test]
+    fn test_only_buyer_or_system_release_payout() {
+        let confirm = rule(&T::ArrivedInDestination, &T::ArrivedInDestinationConfirmed).unwrap();
+        assert_eq!(confirm.parties, &[Party::Buyer, Party::System]);
+        assert_eq!(confirm.effects, &[Effect::Payout]);
+
+        let arrived = rule(&T::PickedUpByCourier, &T::ArrivedInDestination).unwrap();
+        assert!(!arrived.effects.contains(&Effect::Payout));
+    }
+
+    #[test]
+    fn test_pickup_by_assigned_courier() {
+        let pickup = rule(&T::WaitingForCourier, &T::PickedUpByCourier).unwrap();
+        assert_eq!(pickup.parties, &[Party::Courier, Party::AssignedCourier]);
+        assert_eq!(pickup.effects, &[Effect::AssignCourier]);
+    }
+
+    #[test]
+    fn test_only_admin_assign() {
+        let admin = user(UserRole::Admin);
+        let courier = ObjectId::new();
+        let other = ObjectId::new();
+
+        let waiting = transaction(T::WaitingForCourier, None);
+        assert_eq!(
+            assignment(&waiting, &admin, Assignment::Assign(courier)).unwrap(),
+            T::WaitingForCourier
+        );
+        for role in [UserRole::Customer, UserRole::Courier] {
+            assert_matches!(
+                assignment(&waiting, &user(role), Assignment::Assign(courier)),
+                Err(Error::Forbidden)
+            );
+        }
+        assert_matches!(
+            assignment(&waiting, &admin, Assignment::Reassign(courier)),
+            Err(Error::TransactionError(..))
+        );
+
+        let picked_up = transaction(T::PickedUpByCourier, Some(courier));
+        assert_eq!(
+            assignment(&picked_up, &admin, Assignment::Reassign(other)).unwrap(),
+            T::PickedUpByCourier
+        );
+        assert_eq!(
+            assignment(&picked_up, &admin, Assignment::Unassign).unwrap(),
+            T::WaitingForCourier
+        );
+        assert_matches!(
+            assignment(&picked_up, &admin, Assignment::Assign(other)),
+            Err(Error::TransactionError(..))
+        );
+        assert_matches!(
+            assignment(&picked_up, &admin, Assignment::Reassign(courier)),
+            Err(Error::TransactionError(..))
+        );
+
+        let send_back = T::SendBackToMerchant {
+            reason: "".to_string(),
+        };
+        let sent_back = transaction(send_back.clone(), Some(courier));
+        assert_eq!(
+            assignment(&sent_back, &admin, Assignment::Reassign(other)).unwrap(),
+            send_back
+        );
+        assert_matches!(
+            assignment(&sent_back, &admin, Assignment::Unassign),
+            Err(Error::TransactionError(..))
+        );
+
+        let delivered = transaction(T::ArrivedInDestination, None);
+        assert_matches!(
+            assignment(&delivered, &admin, Assignment::Assign(courier)),
+            Err(Error::TransactionError(..))
+        );
+    }
+
+    #[test]
+    fn test_returned_parcel() {
+        let reship = rule(&T::ArrivedInMerchant, &T::WaitingForCourier).unwrap();
+        assert_eq!(reship.parties, &[Party::Merchant]);
+        assert_eq!(reship.effects, &[]);
+
+        let returned = rule(&T::ArrivedInMerchant, &T::Returned).unwrap();
+        assert_eq!(returned.parties, &[Party::Merchant]);
+        assert_eq!(returned.effects, &[Effect::Refund]);
+    }
+
+    #[test]
+    fn test_terminal_status() {
+        let all = [
+            T::WaitingForMerchantConfirmation,
+            T::ProcessingInMerchant,
+            T::WaitingForCourier,
+            T::PickedUpByCourier,
+            T::SendBackToMerchant {
+                reason: "".to_string(),
+            },
+            T::ArrivedInMerchant,
+            T::ArrivedInDestination,
+            T::ArrivedInDestinationConfirmed,
+            T::Cancelled,
+            T::RejectedByMerchant {
+                reason: "".to_string(),
+            },
+            T::Returned,
+            T::Expired,
+        ];
+
+        for from in [
+            T::ArrivedInDestinationConfirmed,
+            T::Cancelled,
+            T::RejectedByMerchant {
+                reason: "".to_string(),
+            },
+            T::Returned,
+            T::Expired,
+        ] {
+            for to in all.iter() {
+                assert_eq!(rule(&from, to), None);
+            }
+        }
+    }
+}
@@ -0,0 +1,1181 @@
+//! Outgoing webhooks. A merchant register endpoints, and the lifecycle events of their sales
+//! are queued for every endpoint in the same mongo transaction as the change. The scheduler
+//! then send them signed with the endpoint secret, retrying failed ones with an exponential
+//! backoff. Deliveries are kept as a log the merchant can inspect.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use axum::{
+    extract::{FromRef, State},
+    http::StatusCode,
+    Json,
+};
+use bson::oid::ObjectId;
+use hmac::{Hmac, Mac};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use time::OffsetDateTime;
+use validator::Validate;
+
+use crate::{
+    api::v1::auth::{UserAccess, UserRole},
+    app::AppState,
+    error::Error,
+    mongo_ext::Collection,
+    util::{FormattedDateTime, ObjectIdString, PathObjectId},
+};
+
+use super::{Transaction, TransactionModel, TransactionStatusType};
+
+/// Attempts made before a delivery is given up.
+pub const MAX_ATTEMPTS: u32 = 10;
+
+/// Wait before the first retry, doubled after every failed attempt.
+const BASE_BACKOFF: Duration = Duration::from_secs(60);
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// How long a delivery being sent is hidden from other runs. Way longer than
+/// [`REQUEST_TIMEOUT`], it only matters when an instance died while sending.
+const CLAIM_TIMEOUT: Duration = Duration::from_secs(5 * 60);
+
+/// Deliveries sent in one run of the scheduler.
+const BATCH_SIZE: usize = 100;
+
+/// Deliveries shown in the log of an endpoint.
+const LOG_SIZE: i64 = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEvent {
+    Created,
+    /// The merchant accepted the order.
+    Confirmed,
+    PickedUp,
+    Delivered,
+    /// Cancelled by the buyer, rejected by the merchant or expired.
+    Cancelled,
+}
+
+impl WebhookEvent {
+    /// Event sent when a transaction moves to `status`, `None` when merchants aren't told.
+    pub fn from_status(status: &TransactionStatusType) -> Option<Self> {
+        use TransactionStatusType::*;
+
+        match status {
+            WaitingForMerchantConfirmation => Some(Self::Created),
+            ProcessingInMerchant => Some(Self::Confirmed),
+            PickedUpByCourier => Some(Self::PickedUp),
+            ArrivedInDestination => Some(Self::Delivered),
+            Cancelled | RejectedByMerchant { .. } | Expired => Some(Self::Cancelled),
+            WaitingForCourier
+            | SendBackToMerchant { .. }
+            | ArrivedInMerchant
+            | ArrivedInDestinationConfirmed
+            | Returned => None,
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Created => "created",
+            Self::Confirmed => "confirmed",
+            Self::PickedUp => "picked_up",
+            Self::Delivered => "delivered",
+            Self::Cancelled => "cancelled",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookEndpointModel {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub merchant_id: ObjectId,
+    pub url: String,
+    /// Key of the signature of every payload sent to `url`.
+    pub secret: String,
+
+    pub created_at: bson::DateTime,
+    pub updated_at: bson::DateTime,
+    pub deleted_at: Option<bson::DateTime>,
+}
+
+#[derive(Clone)]
+pub struct WebhookEndpointCollection(pub Collection<WebhookEndpointModel>);
+
+impl std::ops::Deref for WebhookEndpointCollection {
+    type Target = Collection<WebhookEndpointModel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WebhookDeliveryStatus {
+    Pending,
+    Succeeded,
+    /// Given up after [`MAX_ATTEMPTS`], or the endpoint was deleted.
+    Failed,
+}
+
+/// One event queued for one endpoint, and what happened when sending it.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WebhookDeliveryModel {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub endpoint_id: ObjectId,
+    pub merchant_id: ObjectId,
+    pub transaction_id: ObjectId,
+    pub event: WebhookEvent,
+    /// Body sent on every attempt, so a retry is signed the same as the first one.
+    pub payload: String,
+
+    pub status: WebhookDeliveryStatus,
+    pub attempts: u32,
+    /// `None` once the delivery succeeded or failed.
+    pub next_attempt_at: Option<bson::DateTime>,
+    /// Status code of the last response.
+    pub last_status_code: Option<u16>,
+    /// Why the last attempt failed.
+    pub last_error: Option<String>,
+
+    pub created_at: bson::DateTime,
+    pub updated_at: bson::DateTime,
+}
+
+#[derive(Clone)]
+pub struct WebhookDeliveryCollection(pub Collection<WebhookDeliveryModel>);
+
+impl std::ops::Deref for WebhookDeliveryCollection {
+    type Target = Collection<WebhookDeliveryModel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+/// Body of every webhook request.
+#[derive(Serialize, Deserialize, Debug)]
+pub struct WebhookPayload {
+    /// Id of the delivery, the same on every retry so receivers can skip duplicates.
+    pub id: ObjectIdString,
+    pub event: WebhookEvent,
+    pub created_at: FormattedDateTime,
+    pub transaction: TransactionModel,
+}
+
+/// Hex encoded HMAC-SHA256 of `{timestamp}.{payload}` keyed by `secret`, sent in the
+/// `X-Webhook-Signature` header as `sha256=<signature>`.
+pub fn sign(secret: &str, timestamp: i64, payload: &str) -> String {
+    // HMAC accepts keys of any length.
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("any key length");
+    mac.update(format!("{timestamp}.{payload}").as_bytes());
+
+    format!("{:x}", mac.finalize().into_bytes())
+}
+
+/// Addresses webhooks may be sent to. Endpoints are given by merchants, so loopback,
+/// private, link-local and unspecified addresses are refused, otherwise they could make the
+/// server call itself or the internal network and read the outcome in the delivery log.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct WebhookPolicy {
+    /// Send to any address, only meant for tests and local development.
+    pub allow_private: bool,
+}
+
+impl WebhookPolicy {
+    /// Private addresses are only allowed when `WEBHOOK_ALLOW_PRIVATE` is `true`.
+    pub fn new_from_env() -> Self {
+        Self {
+            allow_private: std::env::var("WEBHOOK_ALLOW_PRIVATE").is_ok_and(|it| it == "true"),
+        }
+    }
+
+    pub fn is_allowed(&self, ip: IpAddr) -> bool {
+        self.allow_private || is_public(ip)
+    }
+
+    /// Client sending the webhooks. It doesn't follow redirects, and only connects to the
+    /// allowed addresses a host resolves to, so a host can't be pointed elsewhere after it
+    /// was checked.
+    pub fn client(&self) -> reqwest::Client {
+        reqwest::Client::builder()
+            .redirect(reqwest::redirect::Policy::none())
+            .dns_resolver(Arc::new(*self))
+            .build()
+            .expect("webhook client")
+    }
+
+    /// Whether `url` is an allowed address, or a host resolving only to allowed addresses.
+    pub async fn check_url(&self, url: &str) -> bool {
+        let Ok(url) = reqwest::Url::parse(url) else {
+            return false;
+        };
+
+        if let Some(ip) = literal_ip(&url) {
+            return self.is_allowed(ip);
+        }
+
+        let (host, port) = match (url.host_str(), url.port_or_known_default()) {
+            (Some(host), Some(port)) => (host.to_string(), port),
+            _ => return false,
+        };
+
+        match tokio::net::lookup_host((host, port)).await {
+            Ok(addrs) => {
+                let addrs = addrs.collect::<Vec<_>>();
+                !addrs.is_empty() && addrs.iter().all(|it| self.is_allowed(it.ip()))
+            }
+            Err(_) => false,
+        }
+    }
+}
+
+/// Address written as the host of `url`, `None` when it is a domain.
+fn literal_ip(url: &reqwest::Url) -> Option<IpAddr> {
+    url.host_str()?
+        .trim_start_matches('[')
+        .trim_end_matches(']')
+        .parse()
+        .ok()
+}
+
+impl reqwest::dns::Resolve for WebhookPolicy {
+    fn resolve(&self, name: hyper::client::connect::dns::Name) -> reqwest::dns::Resolving {
+        let policy = *self;
+
+        Box::pin(async move {
+            let addrs = tokio::net::lookup_host((name.as_str(), 0))
+                .await?
+                .filter(|it| policy.is_allowed(it.ip()))
+                .collect::<Vec<SocketAddr>>();
+
+            if addrs.is_empty() {
+                return Err(format!("{} has no public address", name.as_str()).into());
+            }
+
+            Ok(Box::new(addrs.into_iter()) as reqwest::dns::Addrs)
+        })
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+
+            !(ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_unspecified()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                // shared address space of carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && (b & 0xc0) == 64)
+                || a == 0)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(ip.into()),
+            None => {
+                !(ip.is_loopback()
+                    || ip.is_unspecified()
+                    || ip.is_multicast()
+                    || ip.is_unique_local()
+                    || ip.is_unicast_link_local())
+            }
+        },
+    }
+}
+
+/// Everything needed to queue and send webhooks.
+#[derive(Clone)]
+pub struct Webhooks {
+    pub endpoints: WebhookEndpointCollection,
+    pub deliveries: WebhookDeliveryCollection,
+    pub policy: WebhookPolicy,
+}
+
+impl FromRef<AppState> for Webhooks {
+    fn from_ref(input: &AppState) -> Self {
+        Self {
+            endpoints: input.webhook_endpoint_collection.clone(),
+            deliveries: input.webhook_delivery_collection.clone(),
+            policy: input.webhook_policy,
+        }
+    }
+}
+
+impl Webhooks {
+    /// Queue the event of the last status of `transaction` for every endpoint of its
+    /// merchant. Must be called in the same mongo transaction as the change.
+    pub async fn enqueue_with_session(
+        &self,
+        transaction: &Transaction,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<(), Error> {
+        let event = match transaction
+            .status
+            .last()
+            .and_then(|it| WebhookEvent::from_status(&it.r#type))
+        {
+            Some(it) => it,
+            None => return Ok(()),
+        };
+
+        let mut cursor = self
+            .endpoints
+            .find_with_session(
+                bson::doc! {
+                    "merchant_id": transaction.merchant_id,
+                    "deleted_at": null,
+                },
+                None,
+                session,
+            )
+            .await?;
+
+        let mut endpoints = vec![];
+
+        while cursor.advance(session).await? {
+            endpoints.push(cursor.deserialize_current()?);
+        }
+
+        if endpoints.is_empty() {
+            return Ok(());
+        }
+
+        let now = OffsetDateTime::now_utc();
+
+        let deliveries = endpoints
+            .into_iter()
+            .map(|endpoint| {
+                let id = ObjectId::new();
+
+                let payload = serde_json::to_string(&WebhookPayload {
+                    id: id.into(),
+                    event,
+                    created_at: now.into(),
+                    transaction: transaction.clone().into(),
+                })
+                .map_err(|err| {
+                    Error::CustomStatus(StatusCode::INTERNAL_SERVER_ERROR, err.into())
+                })?;
+
+                Ok(WebhookDeliveryModel {
+                    id,
+                    endpoint_id: endpoint.id,
+                    merchant_id: transaction.merchant_id,
+                    transaction_id: transaction.id,
+                    event,
+                    payload,
+                    status: WebhookDeliveryStatus::Pending,
+                    attempts: 0,
+                    next_attempt_at: Some(now.into()),
+                    last_status_code: None,
+                    last_error: None,
+                    created_at: now.into(),
+                    updated_at: now.into(),
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        self.deliveries
+            .insert_many_with_session(deliveries, None, session)
+            .await?;
+
+        Ok(())
+    }
+}
+
+/// How sending a delivery once ended.
+#[derive(Debug)]
+enum Attempt {
+    Response(reqwest::StatusCode),
+    Error(String),
+}
+
+impl Attempt {
+    fn succeeded(&self) -> bool {
+        matches!(self, Self::Response(it) if it.is_success())
+    }
+}
+
+/// Wait after the `attempts`-th failed attempt.
+fn backoff(attempts: u32) -> Duration {
+    BASE_BACKOFF * 2u32.saturating_pow(attempts.saturating_sub(1))
+}
+
+/// Status and next attempt of a delivery whose `attempts`-th attempt, made at `now`,
+/// succeeded or not.
+fn after_attempt(
+    attempts: u32,
+    succeeded: bool,
+    now: OffsetDateTime,
+) -> (WebhookDeliveryStatus, Option<OffsetDateTime>) {
+    if succeeded {
+        (WebhookDeliveryStatus::Succeeded, None)
+    } else if attempts >= MAX_ATTEMPTS {
+        (WebhookDeliveryStatus::Failed, None)
+    } else {
+        (
+            WebhookDeliveryStatus::Pending,
+            Some(now + backoff(attempts)),
+        )
+    }
+}
+
+/// Send `delivery` with a client made by [`WebhookPolicy::client`], which checks the
+/// addresses of hosts but not the addresses written in the url.
+async fn send(
+    client: &reqwest::Client,
+    policy: &WebhookPolicy,
+    endpoint: &WebhookEndpointModel,
+    delivery: &WebhookDeliveryModel,
+    now: OffsetDateTime,
+) -> Attempt {
+    let literal = reqwest::Url::parse(&endpoint.url)
+        .ok()
+        .and_then(|it| literal_ip(&it));
+
+    if literal.is_some_and(|it| !policy.is_allowed(it)) {
+        return Attempt::Error("Endpoint address is not allowed".to_string());
+    }
+
+    let timestamp = now.unix_timestamp();
+    let signature = sign(&endpoint.secret, timestamp, &delivery.payload);
+
+    let result = client
+        .post(&endpoint.url)
+        .timeout(REQUEST_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header("X-Webhook-Id", delivery.id.to_hex())
+        .header("X-Webhook-Event", delivery.event.as_str())
+        .header("X-Webhook-Timestamp", timestamp)
+        .header("X-Webhook-Signature", format!("sha256={signature}"))
+        .body(delivery.payload.clone())
+        .send()
+        .await;
+
+    match result {
+        Ok(it) => Attempt::Response(it.status()),
+        Err(err) => Attempt::Error(err.to_string()),
+    }
+}
+
+/// Claim the pending delivery due the earliest at `now`, pushing its `next_attempt_at` past
+/// [`CLAIM_TIMEOUT`] so no other run sends it meanwhile.
+async fn claim_due(
+    webhooks: &Webhooks,
+    now: OffsetDateTime,
+) -> Result<Option<WebhookDeliveryModel>, Error> {
+    let delivery = webhooks
+        .deliveries
+        .find_one_and_update(
+            bson::doc! {
+                "status": bson::to_bson(&WebhookDeliveryStatus::Pending)?,
+                "next_attempt_at": { "$lte": bson::DateTime::from(now) },
+            },
+            bson::doc! {
+                "$set": {
+                    "next_attempt_at": bson::DateTime::from(now + CLAIM_TIMEOUT),
+                }
+            },
+            mongodb::options::FindOneAndUpdateOptions::builder()
+                .sort(bson::doc! { "next_attempt_at": 1 })
+                .build(),
+        )
+        .await?;
+
+    Ok(delivery)
+}
+
+/// Send the pending deliveries due at `now`, claiming them one at a time. Returns how many
+/// succeeded.
+pub async fn deliver_due(
+    webhooks: &Webhooks,
+    client: &reqwest::Client,
+    now: OffsetDateTime,
+) -> Result<usize, Error> {
+    let mut succeeded = 0;
+
+    for _ in 0..BATCH_SIZE {
+        let delivery = match claim_due(webhooks, now).await? {
+            Some(it) => it,
+            None => break,
+        };
+
+        let attempts = delivery.attempts + 1;
+
+        let (status, next_attempt_at, last_status_code, last_error) = match webhooks
+            .endpoints
+            .find_exists_one_by_id(delivery.endpoint_id)
+            .await?
+        {
+            Some(endpoint) => {
+                let attempt = send(client, &webhooks.policy, &endpoint, &delivery, now).await;
+                let (status, next_attempt_at) = after_attempt(attempts, attempt.succeeded(), now);
+
+                match attempt {
+                    Attempt::Response(code) if code.is_success() => {
+                        (status, next_attempt_at, Some(code.as_u16()), None)
+                    }
+                    Attempt::Response(code) => (
+                        status,
+                        next_attempt_at,
+                        Some(code.as_u16()),
+                        Some(format!("Endpoint responded with {code}")),
+                    ),
+                    Attempt::Error(err) => (status, next_attempt_at, None, Some(err)),
+                }
+            }
+            None => (
+                WebhookDeliveryStatus::Failed,
+                None,
+                None,
+                Some("Endpoint was deleted".to_string()),
+            ),
+        };
+
+        if status == WebhookDeliveryStatus::Succeeded {
+            succeeded += 1;
+        } else {
+            tracing::debug!(id = %delivery.id, attempts, ?status, ?last_error, "webhook failed");
+        }
+
+        webhooks
+            .deliveries
+            .update_one(
+                bson::doc! { "_id": delivery.id },
+                bson::doc! {
+                    "$set": {
+                        "status": bson::to_bson(&status)?,
+                        "attempts": attempts,
+                        "next_attempt_at": next_attempt_at.map(bson::DateTime::from),
+                        "last_status_code": last_status_code.map(i32::from),
+                        "last_error": last_error,
+                        "updated_at": bson::DateTime::from(OffsetDateTime::now_utc()),
+                    }
+                },
+                None,
+            )
+            .await?;
+    }
+
+    Ok(succeeded)
+}
+
+/// The secret is only shown once, when the endpoint is registered.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WebhookEndpoint {
+    pub id: ObjectIdString,
+    pub url: String,
+
+    pub created_at: FormattedDateTime,
+    pub updated_at: FormattedDateTime,
+}
+
+impl From<WebhookEndpointModel> for WebhookEndpoint {
+    fn from(value: WebhookEndpointModel) -> Self {
+        Self {
+            id: value.id.into(),
+            url: value.url,
+            created_at: value.created_at.into(),
+            updated_at: value.updated_at.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WebhookDelivery {
+    pub id: ObjectIdString,
+    pub endpoint_id: ObjectIdString,
+    pub transaction_id: ObjectIdString,
+    pub event: WebhookEvent,
+    pub status: WebhookDeliveryStatus,
+    pub attempts: u32,
+    pub next_attempt_at: Option<FormattedDateTime>,
+    pub last_status_code: Option<u16>,
+    pub last_error: Option<String>,
+
+    pub created_at: FormattedDateTime,
+    pub updated_at: FormattedDateTime,
+}
+
+impl From<WebhookDeliveryModel> for WebhookDelivery {
+    fn from(value: WebhookDeliveryModel) -> Self {
+        Self {
+            id: value.id.into(),
+            endpoint_id: value.endpoint_id.into(),
+            transaction_id: value.transaction_id.into(),
+            event: value.event,
+            status: value.status,
+            attempts: value.attempts,
+            next_attempt_at: value.next_attempt_at.map(Into::into),
+            last_status_code: value.last_status_code,
+            last_error: value.last_error,
+            created_at: value.created_at.into(),
+            updated_at: value.updated_at.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct EndpointIndexResponse {
+    pub endpoints: Vec<WebhookEndpoint>,
+}
+
+#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+pub struct EndpointRequest {
+    #[validate(length(max = 2048), custom = "validate_endpoint_url")]
+    pub url: String,
+}
+
+fn validate_endpoint_url(url: &str) -> Result<(), validator::ValidationError> {
+    match reqwest::Url::parse(url) {
+        Ok(it) if matches!(it.scheme(), "http" | "https") && it.has_host() => Ok(()),
+        _ => Err(validator::ValidationError::new("url")),
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct CreateEndpointResponse {
+    #[serde(flatten)]
+    pub endpoint: WebhookEndpoint,
+    /// Verify the `X-Webhook-Signature` of the requests with it, see [`sign`].
+    pub secret: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct DeliveryLogResponse {
+    /// Newest first.
+    pub deliveries: Vec<WebhookDelivery>,
+}
+
+fn check_merchant(user: &UserAccess) -> Result<(), Error> {
+    match user.role {
+        UserRole::Courier => Err(Error::Forbidden),
+        UserRole::Customer | UserRole::Admin => Ok(()),
+    }
+}
+
+pub async fn index_endpoints(
+    State(endpoints): State<WebhookEndpointCollection>,
+    user: UserAccess,
+) -> Result<Json<EndpointIndexResponse>, Error> {
+    check_merchant(&user)?;
+
+    let mut cursor = endpoints
+        .find_exists(bson::doc! { "merchant_id": user.id }, None)
+        .await?;
+
+    let mut result = vec![];
+
+    while cursor.advance().await? {
+        result.push(cursor.deserialize_current()?.into());
+    }
+
+    Ok(Json(EndpointIndexResponse { endpoints: result }))
+}
+
+/// Register an endpoint for the sales of the current user, with a new secret.
+pub async fn create_endpoint(
+    State(webhooks): State<Webhooks>,
+    user: UserAccess,
+    Json(request): Json<EndpointRequest>,
+) -> Result<Json<CreateEndpointResponse>, Error> {
+    use base64::Engine;
+
+    check_merchant(&user)?;
+    request.validate()?;
+
+    if !webhooks.policy.check_url(&request.url).await {
+        return Err(Error::CustomStr(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Endpoint must be on a public address",
+        ));
+    }
+
+    let secret = format!(
+        "whsec_{}",
+        base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(rand::random::<[u8; 32]>())
+    );
+
+    let endpoint = WebhookEndpointModel {
+        id: ObjectId::new(),
+        merchant_id: user.id,
+        url: request.url,
+        secret: secret.clone(),
+        created_at: OffsetDateTime::now_utc().into(),
+        updated_at: OffsetDateTime::now_utc().into(),
+        deleted_at: None,
+    };
+
+    webhooks.endpoints.insert_one(&endpoint, None).await?;
+
+    Ok(Json(CreateEndpointResponse {
+        endpoint: endpoint.into(),
+        secret,
+    }))
+}
+
+/// Stop sending to endpoint `id`, its pending deliveries fail on their next attempt.
+pub async fn delete_endpoint(
+    State(endpoints): State<WebhookEndpointCollection>,
+    user: UserAccess,
+    PathObjectId(id): PathObjectId,
+) -> Result<(), Error> {
+    endpoints
+        .find_exists_one_by_id(id)
+        .await?
+        .filter(|it| it.merchant_id == user.id)
+        .ok_or(Error::NoResource)?;
+
+    endpoints.soft_delete_one_by_id(id).await?;
+
+    Ok(())
+}
+
+/// Latest deliveries of endpoint `id`, deleted endpoints included.
+pub async fn index_deliveries(
+    State(webhooks): State<Webhooks>,
+    user: UserAccess,
+    PathObjectId(id): PathObjectId,
+) -> Result<Json<DeliveryLogResponse>, Error> {
+    webhooks
+        .endpoints
+        .find_one(bson::doc! { "_id": id }, None)
+        .await?
+        .filter(|it| it.merchant_id == user.id)
+        .ok_or(Error::NoResource)?;
+
+    let mut cursor = webhooks
+        .deliveries
+        .find(
+            bson::doc! { "endpoint_id": id },
+            mongodb::options::FindOptions::builder()
+                .sort(bson::doc! { "created_at": -1, "_id": -1 })
+                .limit(LOG_SIZE)
+                .build(),
+        )
+        .await?;
+
+    let mut result = vec![];
+
+    while cursor.advance().await? {
+        result.push(cursor.deserialize_current()?.into());
+    }
+
+    Ok(Json(DeliveryLogResponse { deliveries: result }))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use assert_matches::assert_matches;
+    use axum::{
+        extract::State,
+        http::{HeaderMap, StatusCode},
+        routing, Json, Router,
+    };
+    use time::OffsetDateTime;
+
+    use crate::{
+        api::v1::{
+            tests::{bootstrap, Bootstrap},
+            transaction::TransactionStatusType,
+        },
+        error::Error,
+        util::PathObjectId,
+    };
+
+    use super::{
+        after_attempt, backoff, sign, EndpointRequest, WebhookDeliveryStatus, WebhookEvent,
+        WebhookPayload, WebhookPolicy, Webhooks, BASE_BACKOFF, CLAIM_TIMEOUT, MAX_ATTEMPTS,
+    };
+
+    /// Headers and body of every request received.
+    type Received = Arc<Mutex<Vec<(HeaderMap, String)>>>;
+
+    /// Local stand-in for a merchant endpoint, answering every request with `status`.
+    struct Receiver {
+        url: String,
+        received: Received,
+    }
+
+    async fn receiver(status: StatusCode) -> Receiver {
+        let received = Arc::new(Mutex::new(vec![]));
+
+        let app =
+            Router::new()
+                .route(
+                    "/",
+                    routing::post(
+                        move |State(received): State<Received>,
+                              headers: HeaderMap,
+                              body: String| async move {
+                            received.lock().unwrap().push((headers, body));
+                            status
+                        },
+                    ),
+                )
+                .with_state(received.clone());
+
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+
+        tokio::spawn(
+            axum::Server::from_tcp(listener)
+                .unwrap()
+                .serve(app.into_make_service()),
+        );
+
+        Receiver { url, received }
+    }
+
+    async fn register(merchant: &Bootstrap, url: &str) -> super::CreateEndpointResponse {
+        super::create_endpoint(
+            merchant.state(),
+            merchant.user_access(),
+            Json(EndpointRequest {
+                url: url.to_string(),
+            }),
+        )
+        .await
+        .unwrap()
+        .0
+    }
+
+    #[test]
+    fn test_sign() {
+        assert_eq!(
+            sign("whsec_test", 1700000000, r#"{"event":"created"}"#),
+            "bb0972fa7d1fd1c0f6a6f3c28404bad6221fdaeddd0373636195fee6675064a3"
+        );
+    }
+
+    #[test]
+    fn test_event_from_status() {
+        assert_eq!(
+            WebhookEvent::from_status(&TransactionStatusType::RejectedByMerchant {
+                reason: "out of stock".to_string()
+            }),
+            Some(WebhookEvent::Cancelled)
+        );
+        assert_eq!(
+            WebhookEvent::from_status(&TransactionStatusType::WaitingForCourier),
+            None
+        );
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        let now = OffsetDateTime::now_utc();
+
+        assert_eq!(backoff(1), BASE_BACKOFF);
+        assert_eq!(backoff(3), BASE_BACKOFF * 4);
+
+        assert_eq!(
+            after_attempt(1, true, now),
+            (WebhookDeliveryStatus::Succeeded, None)
+        );
+        assert_eq!(
+            after_attempt(2, false, now),
+            (WebhookDeliveryStatus::Pending, Some(now + BASE_BACKOFF * 2))
+        );
+        assert_eq!(
+            after_attempt(MAX_ATTEMPTS, false, now),
+            (WebhookDeliveryStatus::Failed, None)
+        );
+    }
+
+    #[test]
+    fn test_endpoint_url_must_be_http() {
+        use validator::Validate;
+
+        for url in ["ftp://example.com/hook", "not a url", "http://"] {
+            let request = EndpointRequest {
+                url: url.to_string(),
+            };
+            assert!(request.validate().is_err(), "{url} must be rejected");
+        }
+
+        let request = EndpointRequest {
+            url: "https://example.com/hook".to_string(),
+        };
+        assert!(request.validate().is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_private_endpoint_rejected() {
+        let policy = WebhookPolicy::default();
+
+        for url in [
+            "http://127.0.0.1:8000/hook",
+            "http://localhost/hook",
+            "http://169.254.169.254/latest/meta-data",
+            "http://10.0.0.1/hook",
+            "http://192.168.1.1/hook",
+            "http://100.64.0.1/hook",
+            "http://0.0.0.0/hook",
+            "http://2130706433/hook",
+            "http://[::1]/hook",
+            "http://[fd00::1]/hook",
+            "http://[fe80::1]/hook",
+            "http://[::ffff:127.0.0.1]/hook",
+        ] {
+            assert!(!policy.check_url(url).await, "{url} must be rejected");
+        }
+
+        assert!(policy.check_url("https://93.184.216.34/hook").await);
+        assert!(policy.check_url("https://[2606:4700::1111]/hook").await);
+
+        let policy = WebhookPolicy {
+            allow_private: true,
+        };
+        assert!(policy.check_url("http://127.0.0.1:8000/hook").await);
+    }
+
+    #[tokio::test]
+    async fn test_signed_event_delivered() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(1_000.into())
+            .await;
+        let merchant = bootstrap.derive_customer().await;
+
+        let receiver = receiver(StatusCode::OK).await;
+        let endpoint = register(&merchant, &receiver.url).await;
+
+        let transaction = customer.create_transaction(&merchant, 1).await;
+
+        let webhooks: State<Webhooks> = bootstrap.state();
+        let client = bootstrap.app_state.http_client.clone();
+        let now = OffsetDateTime::now_utc();
+
+        let sent = super::deliver_due(&webhooks, &client, now).await.unwrap();
+        assert_eq!(sent, 1);
+
+        let received = receiver.received.lock().unwrap().clone();
+        assert_eq!(received.len(), 1);
+
+        let (headers, body) = &received[0];
+        let timestamp: i64 = headers["X-Webhook-Timestamp"]
+            .to_str()
+            .unwrap()
+            .parse()
+            .unwrap();
+        assert_eq!(
+            headers["X-Webhook-Signature"].to_str().unwrap(),
+            format!("sha256={}", sign(&endpoint.secret, timestamp, body))
+        );
+        assert_eq!(headers["X-Webhook-Event"], "created");
+
+        let payload: WebhookPayload = serde_json::from_str(body).unwrap();
+        assert_eq!(payload.event, WebhookEvent::Created);
+        assert_eq!(payload.transaction.id, transaction.id);
+
+        // nothing left to send.
+        let sent = super::deliver_due(&webhooks, &client, now).await.unwrap();
+        assert_eq!(sent, 0);
+
+        let Json(log) = super::index_deliveries(
+            bootstrap.state(),
+            merchant.user_access(),
+            PathObjectId(*endpoint.endpoint.id),
+        )
+        .await
+        .unwrap();
+        assert_eq!(log.deliveries.len(), 1);
+        assert_eq!(log.deliveries[0].status, WebhookDeliveryStatus::Succeeded);
+        assert_eq!(log.deliveries[0].attempts, 1);
+        assert_eq!(log.deliveries[0].last_status_code, Some(200));
+    }
+
+    #[tokio::test]
+    async fn test_failed_delivery_retried_with_backoff() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(1_000.into())
+            .await;
+        let merchant = bootstrap.derive_customer().await;
+
+        let receiver = receiver(StatusCode::INTERNAL_SERVER_ERROR).await;
+        let endpoint = register(&merchant, &receiver.url).await;
+
+        customer.create_transaction(&merchant, 1).await;
+
+        let webhooks: State<Webhooks> = bootstrap.state();
+        let client = bootstrap.app_state.http_client.clone();
+        let now = OffsetDateTime::now_utc();
+
+        let sent = super::deliver_due(&webhooks, &client, now).await.unwrap();
+        assert_eq!(sent, 0);
+
+        // not due again before the backoff.
+        super::deliver_due(&webhooks, &client, now).await.unwrap();
+        assert_eq!(receiver.received.lock().unwrap().len(), 1);
+
+        let mut now = now;
+        for _ in 1..MAX_ATTEMPTS {
+            now += backoff(MAX_ATTEMPTS);
+            super::deliver_due(&webhooks, &client, now).await.unwrap();
+        }
+        assert_eq!(
+            receiver.received.lock().unwrap().len(),
+            MAX_ATTEMPTS as usize
+        );
+
+        // every attempt send the same payload.
+        let received = receiver.received.lock().unwrap().clone();
+        assert!(received.iter().all(|(_, body)| body == &received[0].1));
+
+        let Json(log) = super::index_deliveries(
+            bootstrap.state(),
+            merchant.user_access(),
+            PathObjectId(*endpoint.endpoint.id),
+        )
+        .await
+        .unwrap();
+        assert_eq!(log.deliveries[0].status, WebhookDeliveryStatus::Failed);
+        assert_eq!(log.deliveries[0].attempts, MAX_ATTEMPTS);
+        assert_eq!(log.deliveries[0].last_status_code, Some(500));
+        assert_eq!(log.deliveries[0].next_attempt_at, None);
+    }
+
+    #[tokio::test]
+    async fn test_claimed_delivery_not_sent_again() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(1_000.into())
+            .await;
+        let merchant = bootstrap.derive_customer().await;
+
+        let receiver = receiver(StatusCode::OK).await;
+        register(&merchant, &receiver.url).await;
+
+        customer.create_transaction(&merchant, 1).await;
+
+        let webhooks: State<Webhooks> = bootstrap.state();
+        let client = bootstrap.app_state.http_client.clone();
+        let now = OffsetDateTime::now_utc();
+
+        // another instance is sending it.
+        let claimed = super::claim_due(&webhooks, now).await.unwrap();
+        assert!(claimed.is_some());
+
+        let sent = super::deliver_due(&webhooks, &client, now).await.unwrap();
+        assert_eq!(sent, 0);
+        assert!(receiver.received.lock().unwrap().is_empty());
+
+        // it died while sending, the delivery is retried once the claim is over.
+        let sent = super::deliver_due(&webhooks, &client, now + CLAIM_TIMEOUT)
+            .await
+            .unwrap();
+        assert_eq!(sent, 1);
+        assert_eq!(receiver.received.lock().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_lifecycle_events_queued() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap
+            .derive_customer()
+            .await
+            .with_balance(1_000.into())
+            .await;
+        let merchant = bootstrap.derive_customer().await;
+        let courier = bootstrap.derive_courier().await;
+
+        let receiver = receiver(StatusCode::OK).await;
+        let endpoint = register(&merchant, &receiver.url).await;
+
+        customer
+            .create_delivered_transaction(&merchant, &courier, 1)
+            .await;
+
+        let cancelled = customer.create_transaction(&merchant, 1).await;
+        let _ = crate::api::v1::transaction::cancel_order(
+            bootstrap.state(),
+            customer.user_access(),
+            PathObjectId(*cancelled.id),
+        )
+        .await
+        .unwrap();
+
+        let Json(log) = super::index_deliveries(
+            bootstrap.state(),
+            merchant.user_access(),
+            PathObjectId(*endpoint.endpoint.id),
+        )
+        .await
+        .unwrap();
+
+        let mut events = log.deliveries.iter().map(|it| it.event).collect::<Vec<_>>();
+        events.reverse();
+
+        assert_eq!(
+            events,
+            vec![
+                WebhookEvent::Created,
+                WebhookEvent::Confirmed,
+                WebhookEvent::PickedUp,
+                WebhookEvent::Delivered,
+                WebhookEvent::Created,
+                WebhookEvent::Cancelled,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_only_owner_see_delivery_log() {
+        let bootstrap = bootstrap().await;
+        let merchant = bootstrap.derive_customer().await;
+        let other = bootstrap.derive_customer().await;
+        let courier = bootstrap.derive_courier().await;
+
+        let endpoint = register(&merchant, "https://example.com/hook").await;
+
+        let error = super::index_deliveries(
+            bootstrap.state(),
+            other.user_access(),
+            PathObjectId(*endpoint.endpoint.id),
+        )
+        .await
+        .expect_err("other merchant can't see the log");
+        assert_matches!(error, Error::NoResource);
+
+        let error = super::delete_endpoint(
+            bootstrap.state(),
+            other.user_access(),
+            PathObjectId(*endpoint.endpoint.id),
+        )
+        .await
+        .expect_err("other merchant can't delete the endpoint");
+        assert_matches!(error, Error::NoResource);
+
+        let error = super::create_endpoint(
+            bootstrap.state(),
+            courier.user_access(),
+            Json(EndpointRequest {
+                url: "https://example.com/hook".to_string(),
+            }),
+        )
+        .await
+        .expect_err("courier has no sales");
+        assert_matches!(error, Error::Forbidden);
+    }
+}
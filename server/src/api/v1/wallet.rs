@@ -0,0 +1,735 @@
+//! Wallet top-ups. A top-up is created pending and handed to the [`PaymentGateway`], the
+//! wallet is only credited once the provider confirms the payment with a signed callback.
+
+use axum::{
+    body::Bytes,
+    extract::{FromRef, State},
+    http::{HeaderMap, StatusCode},
+    Json,
+};
+use bson::oid::ObjectId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    app::AppState,
+    error::Error,
+    mongo_ext::{with_transaction, Collection},
+    payment::{PaymentGateway, PaymentNotification, PaymentRequest, PaymentStatus},
+    util::{DecimalString, FormattedDateTime, ObjectIdString, PathObjectId},
+};
+
+use super::{
+    auth::{UserAccess, UserRole},
+    idempotency::{self, IdempotencyCollection, IdempotencyKey, IdempotencyScope},
+    ledger::{Ledger, LedgerAccount, LedgerReason, Transfer},
+};
+
+/// Largest amount of a single top-up.
+pub const MAX_TOPUP: i64 = 100_000_000;
+
+/// Top-ups shown in the history.
+const HISTORY_SIZE: i64 = 50;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TopUpStatus {
+    /// Waiting for the provider to confirm the payment.
+    Pending,
+    Paid,
+    Failed,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopUpModel {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user_id: ObjectId,
+    pub amount: Decimal,
+    pub status: TopUpStatus,
+    /// [`crate::payment::PaymentProvider::name`] of the provider paid through.
+    pub provider: String,
+    /// Id of the payment at the provider, `None` until it is created.
+    pub reference: Option<String>,
+    pub redirect_url: Option<String>,
+
+    pub created_at: bson::DateTime,
+    pub updated_at: bson::DateTime,
+}
+
+#[derive(Clone)]
+pub struct TopUpCollection(pub Collection<TopUpModel>);
+
+impl std::ops::Deref for TopUpCollection {
+    type Target = Collection<TopUpModel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Clone)]
+pub struct WalletState {
+    pub topups: TopUpCollection,
+    pub ledger: Ledger,
+    pub gateway: PaymentGateway,
+    pub idempotency: IdempotencyCollection,
+    pub mongo: mongodb::Client,
+}
+
+impl FromRef<AppState> for WalletState {
+    fn from_ref(input: &AppState) -> Self {
+        Self {
+            topups: input.topup_collection.clone(),
+            ledger: Ledger::from_ref(input),
+            gateway: input.payment_gateway.clone(),
+            idempotency: input.idempotency_collection.clone(),
+            mongo: input.mongo_client.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TopUp {
+    pub id: ObjectIdString,
+    pub amount: DecimalString,
+    pub status: TopUpStatus,
+    pub provider: String,
+    /// Where the user pays the top-up, while it is pending.
+    pub redirect_url: Option<String>,
+
+    pub created_at: FormattedDateTime,
+    pub updated_at: FormattedDateTime,
+}
+
+impl From<TopUpModel> for TopUp {
+    fn from(value: TopUpModel) -> Self {
+        Self {
+            id: value.id.into(),
+            amount: value.amount.into(),
+            status: value.status,
+            provider: value.provider,
+            redirect_url: match value.status {
+                TopUpStatus::Pending => value.redirect_url,
+                TopUpStatus::Paid | TopUpStatus::Failed => None,
+            },
+            created_at: value.created_at.into(),
+            updated_at: value.updated_at.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopUpRequest {
+    pub amount: Decimal,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct TopUpIndexResponse {
+    /// Newest first.
+    pub topups: Vec<TopUp>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct FakePaymentRequest {
+    pub status: PaymentStatus,
+}
+
+async fn find_own(
+    topups: &TopUpCollection,
+    user: &UserAccess,
+    id: ObjectId,
+) -> Result<TopUpModel, Error> {
+    topups
+        .find_one(bson::doc! { "_id": id }, None)
+        .await?
+        .filter(|it| it.user_id == user.id)
+        .ok_or(Error::NoResource)
+}
+
+/// Start a top-up of the current user's wallet, to be paid at `redirect_url`. A request
+/// retried with the same `Idempotency-Key` gets the top-up it started.
+pub async fn create_topup(
+    State(wallet): State<WalletState>,
+    user: UserAccess,
+    IdempotencyKey(key): IdempotencyKey,
+    Json(request): Json<TopUpRequest>,
+) -> Result<Json<TopUp>, Error> {
+    if user.role == UserRole::Courier {
+        return Err(Error::Forbidden);
+    }
+
+    if request.amount <= Decimal::ZERO || request.amount > Decimal::from(MAX_TOPUP) {
+        return Err(Error::CustomStr(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Top up amount is out of range",
+        ));
+    }
+
+    let now = OffsetDateTime::now_utc();
+
+    let mut topup = TopUpModel {
+        id: ObjectId::new(),
+        user_id: user.id,
+        amount: request.amount,
+        status: TopUpStatus::Pending,
+        provider: wallet.gateway.name().to_string(),
+        reference: None,
+        redirect_url: None,
+        created_at: now.into(),
+        updated_at: now.into(),
+    };
+
+    let hash = idempotency::request_hash(&request)?;
+
+    let replayed = with_transaction!(wallet.mongo, |session| {
+        if let Some(key) = &key {
+            let response: Option<TopUp> = wallet
+                .idempotency
+                .find_response_with_session(
+                    user.id,
+                    IdempotencyScope::TopUp,
+                    key,
+                    &hash,
+                    &mut session,
+                )
+                .await?;
+
+            if let Some(response) = response {
+                return Ok(Some(response));
+            }
+        }
+
+        wallet
+            .topups
+            .insert_one_with_session(&topup, None, &mut session)
+            .await?;
+
+        if let Some(key) = &key {
+            wallet
+                .idempotency
+                .insert_response_with_session(
+                    user.id,
+                    IdempotencyScope::TopUp,
+                    key,
+                    &hash,
+                    &TopUp::from(topup.clone()),
+                    &mut session,
+                )
+                .await?;
+        }
+
+        Ok(None)
+    })?;
+
+    // the stored response is the top-up before the payment was created, show where it is
+    // now.
+    if let Some(response) = replayed {
+        return Ok(Json(
+            find_own(&wallet.topups, &user, *response.id).await?.into(),
+        ));
+    }
+
+    let session = wallet
+        .gateway
+        .create(&PaymentRequest {
+            topup_id: topup.id,
+            user_id: topup.user_id,
+            amount: topup.amount,
+        })
+        .await;
+
+    let mut set = match &session {
+        Ok(it) => {
+            topup.reference = Some(it.reference.clone());
+            topup.redirect_url = Some(it.redirect_url.clone());
+
+            bson::doc! {
+                "reference": &it.reference,
+                "redirect_url": &it.redirect_url,
+            }
+        }
+        Err(_) => {
+            topup.status = TopUpStatus::Failed;
+
+            bson::doc! { "status": bson::to_bson(&TopUpStatus::Failed)? }
+        }
+    };
+
+    topup.updated_at = OffsetDateTime::now_utc().into();
+
+    set.insert("updated_at", topup.updated_at);
+
+    wallet
+        .topups
+        .update_one(
+            bson::doc! { "_id": topup.id },
+            bson::doc! { "$set": set },
+            None,
+        )
+        .await?;
+
+    session?;
+
+    Ok(Json(topup.into()))
+}
+
+/// Latest top-ups of the current user.
+pub async fn index_topups(
+    State(topups): State<TopUpCollection>,
+    user: UserAccess,
+) -> Result<Json<TopUpIndexResponse>, Error> {
+    let mut cursor = topups
+        .find(
+            bson::doc! { "user_id": user.id },
+            mongodb::options::FindOptions::builder()
+                .sort(bson::doc! { "_id": -1 })
+                .limit(HISTORY_SIZE)
+                .build(),
+        )
+        .await?;
+
+    let mut result = vec![];
+
+    while cursor.advance().await? {
+        result.push(cursor.deserialize_current()?.into());
+    }
+
+    Ok(Json(TopUpIndexResponse { topups: result }))
+}
+
+pub async fn show_topup(
+    State(topups): State<TopUpCollection>,
+    user: UserAccess,
+    PathObjectId(id): PathObjectId,
+) -> Result<Json<TopUp>, Error> {
+    Ok(Json(find_own(&topups, &user, id).await?.into()))
+}
+
+/// Complete the top-up of a callback signed by the provider, crediting the wallet when it
+/// was paid. A top-up is only completed once, so the provider may retry the callback.
+pub async fn process_callback(
+    wallet: &WalletState,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> Result<TopUpModel, Error> {
+    let notification = wallet.gateway.verify(headers, body)?;
+
+    with_transaction!(wallet.mongo, |session| {
+        let topup = wallet
+            .topups
+            .find_one_with_session(
+                bson::doc! { "_id": notification.topup_id },
+                None,
+                &mut session,
+            )
+            .await?
+            .filter(|it| it.reference.as_deref() == Some(notification.reference.as_str()))
+            .ok_or(Error::NoResource)?;
+
+        if topup.status != TopUpStatus::Pending {
+            return Ok(topup);
+        }
+
+        if topup.amount != notification.amount {
+            return Err(Error::CustomStr(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Paid amount doesn't match the top up",
+            ));
+        }
+
+        let status = match notification.status {
+            PaymentStatus::Paid => TopUpStatus::Paid,
+            PaymentStatus::Failed => TopUpStatus::Failed,
+        };
+
+        let topup = wallet
+            .topups
+            .find_one_and_update_with_session(
+                bson::doc! {
+                    "_id": topup.id,
+                    "status": bson::to_bson(&TopUpStatus::Pending)?,
+                },
+                bson::doc! {
+                    "$set": {
+                        "status": bson::to_bson(&status)?,
+                        "updated_at": bson::DateTime::from(OffsetDateTime::now_utc()),
+                    }
+                },
+                mongodb::options::FindOneAndUpdateOptions::builder()
+                    .return_document(mongodb::options::ReturnDocument::After)
+                    .build(),
+                &mut session,
+            )
+            .await?
+            .ok_or(Error::NoResource)?;
+
+        if status == TopUpStatus::Paid {
+            wallet
+                .ledger
+                .transfer_with_session(
+                    Transfer::new(
+                        LedgerReason::TopUp,
+                        LedgerAccount::External,
+                        LedgerAccount::Wallet(topup.user_id),
+                        topup.amount,
+                    )
+                    .by(topup.user_id),
+                    &mut session,
+                )
+                .await?;
+        }
+
+        Ok(topup)
+    })
+}
+
+/// Called by the payment provider, authenticated by its signature instead of a user.
+pub async fn callback(
+    State(wallet): State<WalletState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(), Error> {
+    process_callback(&wallet, &headers, &body).await?;
+
+    Ok(())
+}
+
+/// Pay or fail pending top-up `id` of the current user, only with the
+/// [`crate::payment::FakeProvider`]. Goes through the same signed callback as the provider
+/// would make.
+pub async fn fake_pay(
+    State(wallet): State<WalletState>,
+    user: UserAccess,
+    PathObjectId(id): PathObjectId,
+    Json(request): Json<FakePaymentRequest>,
+) -> Result<Json<TopUp>, Error> {
+    if !wallet.gateway.is_fake() {
+        return Err(Error::NoResource);
+    }
+
+    let topup = find_own(&wallet.topups, &user, id).await?;
+
+    let (headers, body) = wallet
+        .gateway
+        .simulate(&PaymentNotification {
+            topup_id: topup.id,
+            reference: topup.reference.ok_or(Error::NoResource)?,
+            status: request.status,
+            amount: topup.amount,
+        })
+        .ok_or(Error::NoResource)?;
+
+    Ok(Json(
+        process_callback(&wallet, &headers, &body).await?.into(),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use assert_matches::assert_matches;
+    use axum::{
+        http::{HeaderMap, StatusCode},
+        Json,
+    };
+    use rust_decimal::Decimal;
+
+    use crate::{
+        api::v1::{
+            idempotency::IdempotencyKey,
+            tests::{bootstrap, Bootstrap},
+        },
+        error::Error,
+        payment::{
+            PaymentGateway, PaymentNotification, PaymentProvider, PaymentRequest, PaymentSession,
+            PaymentStatus,
+        },
+        util::PathObjectId,
+    };
+
+    use super::{FakePaymentRequest, TopUp, TopUpRequest, TopUpStatus, WalletState};
+
+    async fn create(user: &Bootstrap, amount: i64) -> TopUp {
+        super::create_topup(
+            user.state(),
+            user.user_access(),
+            IdempotencyKey(None),
+            Json(TopUpRequest {
+                amount: Decimal::from(amount),
+            }),
+        )
+        .await
+        .unwrap()
+        .0
+    }
+
+    async fn pay(user: &Bootstrap, topup: &TopUp, status: PaymentStatus) -> TopUp {
+        super::fake_pay(
+            user.state(),
+            user.user_access(),
+            PathObjectId(topup.id.into()),
+            Json(FakePaymentRequest { status }),
+        )
+        .await
+        .unwrap()
+        .0
+    }
+
+    #[tokio::test]
+    async fn test_credited_only_when_paid() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap.derive_customer().await;
+
+        let topup = create(&customer, 5_000).await;
+        assert_eq!(topup.status, TopUpStatus::Pending);
+        assert!(topup.redirect_url.is_some());
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::ZERO);
+
+        let topup = pay(&customer, &topup, PaymentStatus::Paid).await;
+        assert_eq!(topup.status, TopUpStatus::Paid);
+        assert!(topup.redirect_url.is_none());
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(5_000));
+    }
+
+    #[tokio::test]
+    async fn test_topup_replayed_with_same_idempotency_key() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap.derive_customer().await;
+
+        let topup = |amount: i64| {
+            super::create_topup(
+                customer.state(),
+                customer.user_access(),
+                IdempotencyKey(Some("topup-1".to_string())),
+                Json(TopUpRequest {
+                    amount: Decimal::from(amount),
+                }),
+            )
+        };
+
+        let Json(first) = topup(5_000).await.unwrap();
+        let Json(replayed) = topup(5_000).await.unwrap();
+        assert_eq!(replayed, first);
+
+        let error = topup(1_000).await.expect_err("same key for another amount");
+        assert_matches!(error, Error::IdempotencyKeyReused);
+
+        let Json(index) = super::index_topups(customer.state(), customer.user_access())
+            .await
+            .unwrap();
+        assert_eq!(index.topups.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_payment_not_credited() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap.derive_customer().await;
+
+        let topup = create(&customer, 5_000).await;
+        let topup = pay(&customer, &topup, PaymentStatus::Failed).await;
+        assert_eq!(topup.status, TopUpStatus::Failed);
+
+        // a late paid callback doesn't revive it.
+        let topup = pay(&customer, &topup, PaymentStatus::Paid).await;
+        assert_eq!(topup.status, TopUpStatus::Failed);
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_callback_credits_once() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap.derive_customer().await;
+        let wallet: WalletState = bootstrap.state().0;
+
+        let topup = create(&customer, 5_000).await;
+        let model = wallet
+            .topups
+            .find_one(
+                bson::doc! { "_id": bson::oid::ObjectId::from(topup.id) },
+                None,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        let (headers, body) = wallet
+            .gateway
+            .simulate(&PaymentNotification {
+                topup_id: model.id,
+                reference: model.reference.clone().unwrap(),
+                status: PaymentStatus::Paid,
+                amount: model.amount,
+            })
+            .unwrap();
+
+        for _ in 0..3 {
+            let topup = super::process_callback(&wallet, &headers, &body)
+                .await
+                .unwrap();
+            assert_eq!(topup.status, TopUpStatus::Paid);
+        }
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::from(5_000));
+    }
+
+    #[tokio::test]
+    async fn test_callback_must_match_topup() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap.derive_customer().await;
+        let wallet: WalletState = bootstrap.state().0;
+
+        let topup = create(&customer, 5_000).await;
+        let model = wallet
+            .topups
+            .find_one(
+                bson::doc! { "_id": bson::oid::ObjectId::from(topup.id) },
+                None,
+            )
+            .await
+            .unwrap()
+            .unwrap();
+
+        let notification = PaymentNotification {
+            topup_id: model.id,
+            reference: model.reference.clone().unwrap(),
+            status: PaymentStatus::Paid,
+            amount: Decimal::from(50_000),
+        };
+        let (headers, body) = wallet.gateway.simulate(&notification).unwrap();
+        let error = super::process_callback(&wallet, &headers, &body)
+            .await
+            .expect_err("paid a different amount");
+        assert_matches!(error, Error::CustomStr(StatusCode::UNPROCESSABLE_ENTITY, _));
+
+        let (headers, body) = wallet
+            .gateway
+            .simulate(&PaymentNotification {
+                reference: "other".to_string(),
+                amount: model.amount,
+                ..notification
+            })
+            .unwrap();
+        let error = super::process_callback(&wallet, &headers, &body)
+            .await
+            .expect_err("reference of another payment");
+        assert_matches!(error, Error::NoResource);
+
+        let error = super::process_callback(&wallet, &Default::default(), &body)
+            .await
+            .expect_err("unsigned");
+        assert_matches!(error, Error::CustomStr(StatusCode::UNAUTHORIZED, _));
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::ZERO);
+    }
+
+    /// Stands for a real gateway, which can't be paid from our app.
+    struct ExternalProvider;
+
+    #[axum::async_trait]
+    impl PaymentProvider for ExternalProvider {
+        fn name(&self) -> &'static str {
+            "external"
+        }
+
+        async fn create(&self, _request: &PaymentRequest) -> Result<PaymentSession, Error> {
+            Ok(PaymentSession {
+                reference: "external".to_string(),
+                redirect_url: "https://pay.example.com".to_string(),
+            })
+        }
+
+        fn verify(&self, _headers: &HeaderMap, _body: &[u8]) -> Result<PaymentNotification, Error> {
+            Err(Error::CustomStr(StatusCode::UNAUTHORIZED, "unsigned"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fake_pay_needs_fake_provider() {
+        let mut bootstrap = bootstrap().await;
+        bootstrap.app_state.payment_gateway = PaymentGateway(Arc::new(ExternalProvider));
+        let customer = bootstrap.derive_customer().await;
+
+        let topup = create(&customer, 5_000).await;
+        assert_eq!(topup.provider, "external");
+
+        let error = super::fake_pay(
+            customer.state(),
+            customer.user_access(),
+            PathObjectId(topup.id.into()),
+            Json(FakePaymentRequest {
+                status: PaymentStatus::Paid,
+            }),
+        )
+        .await
+        .expect_err("only the fake provider can be paid from our app");
+        assert_matches!(error, Error::NoResource);
+
+        let customer = customer.reload().await;
+        assert_eq!(customer.user_model.balance, Decimal::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_topup_only_seen_by_owner() {
+        let bootstrap = bootstrap().await;
+        let customer = bootstrap.derive_customer().await;
+        let other = bootstrap.derive_customer().await;
+        let courier = bootstrap.derive_courier().await;
+
+        let topup = create(&customer, 5_000).await;
+
+        let error = super::show_topup(
+            other.state(),
+            other.user_access(),
+            PathObjectId(topup.id.into()),
+        )
+        .await
+        .expect_err("not the owner");
+        assert_matches!(error, Error::NoResource);
+
+        let error = super::fake_pay(
+            other.state(),
+            other.user_access(),
+            PathObjectId(topup.id.into()),
+            Json(FakePaymentRequest {
+                status: PaymentStatus::Paid,
+            }),
+        )
+        .await
+        .expect_err("not the owner");
+        assert_matches!(error, Error::NoResource);
+
+        let error = super::create_topup(
+            courier.state(),
+            courier.user_access(),
+            IdempotencyKey(None),
+            Json(TopUpRequest {
+                amount: Decimal::from(5_000),
+            }),
+        )
+        .await
+        .expect_err("couriers can't top up");
+        assert_matches!(error, Error::Forbidden);
+
+        let error = super::create_topup(
+            customer.state(),
+            customer.user_access(),
+            IdempotencyKey(None),
+            Json(TopUpRequest {
+                amount: Decimal::ZERO,
+            }),
+        )
+        .await
+        .expect_err("nothing to top up");
+        assert_matches!(error, Error::CustomStr(StatusCode::UNPROCESSABLE_ENTITY, _));
+    }
+}
@@ -0,0 +1,658 @@
+//! Withdrawals of a merchant's balance to their bank account. The amount is held out of the
+//! wallet when requested, an admin then approves it, paying it out, or rejects it, giving
+//! the hold back.
+
+use axum::{
+    extract::{FromRef, State},
+    http::StatusCode,
+    Json,
+};
+use bson::oid::ObjectId;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+use validator::Validate;
+
+use crate::{
+    app::AppState,
+    error::Error,
+    mongo_ext::{with_transaction, Collection},
+    util::{DecimalString, FormattedDateTime, ObjectIdString, PathObjectId},
+};
+
+use super::{
+    auth::{UserAccess, UserRole},
+    ledger::{Ledger, LedgerAccount, LedgerReason, Transfer},
+};
+
+/// Withdrawals shown in the history of a merchant.
+const HISTORY_SIZE: i64 = 50;
+
+/// Withdrawals shown in the admin queue.
+const QUEUE_SIZE: i64 = 100;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(tag = "type", content = "content")]
+pub enum WithdrawalStatusType {
+    /// Requested by the merchant, the amount is held until an admin settles it.
+    Pending,
+    /// Paid out to the bank account.
+    Approved,
+    Rejected {
+        reason: String,
+    },
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WithdrawalStatus {
+    pub r#type: WithdrawalStatusType,
+    pub date: bson::DateTime,
+    /// User who made the change.
+    pub by: ObjectId,
+}
+
+#[derive(Serialize, Deserialize, Validate, Debug, Clone, PartialEq)]
+pub struct BankAccount {
+    #[validate(length(min = 1, max = 124))]
+    pub bank_name: String,
+
+    #[validate(length(min = 1, max = 64))]
+    pub account_number: String,
+
+    #[validate(length(min = 1, max = 124))]
+    pub account_name: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WithdrawalModel {
+    #[serde(rename = "_id")]
+    pub id: ObjectId,
+    pub user_id: ObjectId,
+    pub amount: Decimal,
+    pub bank: BankAccount,
+    /// Oldest first, the last one is the current status.
+    pub status: Vec<WithdrawalStatus>,
+
+    pub created_at: bson::DateTime,
+    pub updated_at: bson::DateTime,
+}
+
+impl WithdrawalModel {
+    pub fn current(&self) -> Option<&WithdrawalStatusType> {
+        self.status.last().map(|it| &it.r#type)
+    }
+}
+
+#[derive(Clone)]
+pub struct WithdrawalCollection(pub Collection<WithdrawalModel>);
+
+impl std::ops::Deref for WithdrawalCollection {
+    type Target = Collection<WithdrawalModel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+#[derive(Clone)]
+pub struct WithdrawalState {
+    pub withdrawals: WithdrawalCollection,
+    pub ledger: Ledger,
+    pub mongo: mongodb::Client,
+}
+
+impl FromRef<AppState> for WithdrawalState {
+    fn from_ref(input: &AppState) -> Self {
+        Self {
+            withdrawals: input.withdrawal_collection.clone(),
+            ledger: Ledger::from_ref(input),
+            mongo: input.mongo_client.clone(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct WithdrawalStatusModel {
+    pub r#type: WithdrawalStatusType,
+    pub date: FormattedDateTime,
+    pub by: ObjectIdString,
+}
+
+impl From<WithdrawalStatus> for WithdrawalStatusModel {
+    fn from(value: WithdrawalStatus) -> Self {
+        Self {
+            r#type: value.r#type,
+            date: value.date.into(),
+            by: value.by.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Withdrawal {
+    pub id: ObjectIdString,
+    pub user_id: ObjectIdString,
+    pub amount: DecimalString,
+    pub bank: BankAccount,
+    pub status: Vec<WithdrawalStatusModel>,
+
+    pub created_at: FormattedDateTime,
+    pub updated_at: FormattedDateTime,
+}
+
+impl From<WithdrawalModel> for Withdrawal {
+    fn from(value: WithdrawalModel) -> Self {
+        Self {
+            id: value.id.into(),
+            user_id: value.user_id.into(),
+            amount: value.amount.into(),
+            bank: value.bank,
+            status: value.status.into_iter().map(Into::into).collect(),
+            created_at: value.created_at.into(),
+            updated_at: value.updated_at.into(),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+pub struct WithdrawalRequest {
+    pub amount: Decimal,
+
+    #[validate]
+    pub bank: BankAccount,
+}
+
+#[derive(Serialize, Deserialize, Validate, Debug, Clone)]
+pub struct RejectRequest {
+    #[validate(length(min = 1, max = 1024))]
+    pub reason: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct WithdrawalIndexResponse {
+    pub withdrawals: Vec<Withdrawal>,
+}
+
+async fn collect(
+    mut cursor: mongodb::Cursor<WithdrawalModel>,
+) -> Result<Json<WithdrawalIndexResponse>, Error> {
+    let mut result = vec![];
+
+    while cursor.advance().await? {
+        result.push(cursor.deserialize_current()?.into());
+    }
+
+    Ok(Json(WithdrawalIndexResponse {
+        withdrawals: result,
+    }))
+}
+
+/// Request a withdrawal of `amount` from the current user's balance, holding it right away.
+pub async fn create(
+    State(state): State<WithdrawalState>,
+    user: UserAccess,
+    Json(request): Json<WithdrawalRequest>,
+) -> Result<Json<Withdrawal>, Error> {
+    match user.role {
+        UserRole::Customer | UserRole::Admin => {}
+        UserRole::Courier => return Err(Error::Forbidden),
+    }
+
+    request.validate()?;
+
+    if request.amount <= Decimal::ZERO {
+        return Err(Error::CustomStr(
+            StatusCode::UNPROCESSABLE_ENTITY,
+            "Withdrawal amount must be positive",
+        ));
+    }
+
+    let now = OffsetDateTime::now_utc().into();
+
+    let withdrawal = WithdrawalModel {
+        id: ObjectId::new(),
+        user_id: user.id,
+        amount: request.amount,
+        bank: request.bank,
+        status: vec![WithdrawalStatus {
+            r#type: WithdrawalStatusType::Pending,
+            date: now,
+            by: user.id,
+        }],
+        created_at: now,
+        updated_at: now,
+    };
+
+    with_transaction!(state.mongo, |session| {
+        state
+            .ledger
+            .transfer_with_session(
+                Transfer::new(
+                    LedgerReason::Withdrawal,
+                    LedgerAccount::Wallet(user.id),
+                    LedgerAccount::Withdrawal,
+                    withdrawal.amount,
+                )
+                .by(user.id),
+                &mut session,
+            )
+            .await?;
+
+        state
+            .withdrawals
+            .insert_one_with_session(&withdrawal, None, &mut session)
+            .await?;
+
+        Ok(())
+    })?;
+
+    Ok(Json(withdrawal.into()))
+}
+
+/// Latest withdrawals of the current user.
+pub async fn index(
+    State(withdrawals): State<WithdrawalCollection>,
+    user: UserAccess,
+) -> Result<Json<WithdrawalIndexResponse>, Error> {
+    let cursor = withdrawals
+        .find(
+            bson::doc! { "user_id": user.id },
+            mongodb::options::FindOptions::builder()
+                .sort(bson::doc! { "_id": -1 })
+                .limit(HISTORY_SIZE)
+                .build(),
+        )
+        .await?;
+
+    collect(cursor).await
+}
+
+/// Pending withdrawals, oldest first, for admins to settle.
+pub async fn queue(
+    State(withdrawals): State<WithdrawalCollection>,
+    user: UserAccess,
+) -> Result<Json<WithdrawalIndexResponse>, Error> {
+    match user.role {
+        UserRole::Admin => {}
+        UserRole::Customer | UserRole::Courier => return Err(Error::Forbidden),
+    }
+
+    let cursor = withdrawals
+        .find(
+            bson::doc! {
+                "$expr": {
+                    "$eq": [
+                        bson::to_bson(&WithdrawalStatusType::Pending)?,
+                        {
+                            "$getField": {
+                                "input": { "$last": "$status" },
+                                "field": "type"
+                            },
+                        }
+                    ]
+                },
+            },
+            mongodb::options::FindOptions::builder()
+                .sort(bson::doc! { "_id": 1 })
+                .limit(QUEUE_SIZE)
+                .build(),
+        )
+        .await?;
+
+    collect(cursor).await
+}
+
+/// Withdrawal `id`, seen by its merchant and admins.
+pub async fn show(
+    State(withdrawals): State<WithdrawalCollection>,
+    user: UserAccess,
+    PathObjectId(id): PathObjectId,
+) -> Result<Json<Withdrawal>, Error> {
+    let withdrawal = withdrawals
+        .find_one(bson::doc! { "_id": id }, None)
+        .await?
+        .filter(|it| user.role == UserRole::Admin || it.user_id == user.id)
+        .ok_or(Error::NoResource)?;
+
+    Ok(Json(withdrawal.into()))
+}
+
+/// How an admin settles a pending withdrawal.
+#[derive(Debug, Clone)]
+enum Settlement {
+    Approve,
+    Reject { reason: String },
+}
+
+impl From<Settlement> for WithdrawalStatusType {
+    fn from(value: Settlement) -> Self {
+        match value {
+            Settlement::Approve => Self::Approved,
+            Settlement::Reject { reason } => Self::Rejected { reason },
+        }
+    }
+}
+
+/// Settle pending withdrawal `id` as admin `user`, releasing its hold.
+async fn settle(
+    state: &WithdrawalState,
+    user: &UserAccess,
+    id: ObjectId,
+    settlement: Settlement,
+) -> Result<WithdrawalModel, Error> {
+    match user.role {
+        UserRole::Admin => {}
+        UserRole::Customer | UserRole::Courier => return Err(Error::Forbidden),
+    }
+
+    with_transaction!(state.mongo, |session| {
+        let mut withdrawal = state
+            .withdrawals
+            .find_one_with_session(bson::doc! { "_id": id }, None, &mut session)
+            .await?
+            .ok_or(Error::NoResource)?;
+
+        if withdrawal.current() != Some(&WithdrawalStatusType::Pending) {
+            return Err(Error::CustomStr(
+                StatusCode::CONFLICT,
+                "Withdrawal is already settled",
+            ));
+        }
+
+        // admins settle the withdrawals of others.
+        if withdrawal.user_id == user.id {
+            return Err(Error::Forbidden);
+        }
+
+        let transfer = match &settlement {
+            Settlement::Approve => Transfer::new(
+                LedgerReason::Withdrawal,
+                LedgerAccount::Withdrawal,
+                LedgerAccount::External,
+                withdrawal.amount,
+            ),
+            Settlement::Reject { .. } => Transfer::new(
+                LedgerReason::WithdrawalRejected,
+                LedgerAccount::Withdrawal,
+                LedgerAccount::Wallet(withdrawal.user_id),
+                withdrawal.amount,
+            ),
+        };
+
+        state
+            .ledger
+            .transfer_with_session(transfer.by(user.id), &mut session)
+            .await?;
+
+        let now = OffsetDateTime::now_utc().into();
+
+        withdrawal.status.push(WithdrawalStatus {
+            r#type: settlement.clone().into(),
+            date: now,
+            by: user.id,
+        });
+        withdrawal.updated_at = now;
+
+        state
+            .withdrawals
+            .update_one_with_session(
+                bson::doc! { "_id": withdrawal.id },
+                bson::doc! {
+                    "$set": {
+                        "status": bson::to_bson(&withdrawal.status)?,
+                        "updated_at": withdrawal.updated_at,
+                    }
+                },
+                None,
+                &mut session,
+            )
+            .await?;
+
+        Ok(withdrawal)
+    })
+}
+
+/// Pay out withdrawal `id`, once the admin transferred it to the bank account.
+pub async fn approve(
+    State(state): State<WithdrawalState>,
+    user: UserAccess,
+    PathObjectId(id): PathObjectId,
+) -> Result<Json<Withdrawal>, Error> {
+    let withdrawal = settle(&state, &user, id, Settlement::Approve).await?;
+
+    Ok(Json(withdrawal.into()))
+}
+
+/// Give the held amount of withdrawal `id` back to the merchant.
+pub async fn reject(
+    State(state): State<WithdrawalState>,
+    user: UserAccess,
+    PathObjectId(id): PathObjectId,
+    Json(request): Json<RejectRequest>,
+) -> Result<Json<Withdrawal>, Error> {
+    request.validate()?;
+
+    let withdrawal = settle(
+        &state,
+        &user,
+        id,
+        Settlement::Reject {
+            reason: request.reason,
+        },
+    )
+    .await?;
+
+    Ok(Json(withdrawal.into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use axum::{http::StatusCode, Json};
+    use rust_decimal::Decimal;
+
+    use crate::{
+        api::v1::tests::{bootstrap, Bootstrap},
+        error::Error,
+        util::PathObjectId,
+    };
+
+    use super::{BankAccount, RejectRequest, Withdrawal, WithdrawalRequest, WithdrawalStatusType};
+
+    fn request(amount: i64) -> Json<WithdrawalRequest> {
+        Json(WithdrawalRequest {
+            amount: Decimal::from(amount),
+            bank: BankAccount {
+                bank_name: "BCA".to_string(),
+                account_number: "1234567890".to_string(),
+                account_name: "merchant".to_string(),
+            },
+        })
+    }
+
+    async fn create(merchant: &Bootstrap, amount: i64) -> Result<Withdrawal, Error> {
+        super::create(merchant.state(), merchant.user_access(), request(amount))
+            .await
+            .map(|it| it.0)
+    }
+
+    fn statuses(withdrawal: &Withdrawal) -> Vec<WithdrawalStatusType> {
+        withdrawal
+            .status
+            .iter()
+            .map(|it| it.r#type.clone())
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_approved_withdrawal_stays_debited() {
+        let admin = bootstrap().await;
+        let merchant = admin
+            .derive_customer()
+            .await
+            .with_balance(10_000.into())
+            .await;
+
+        let withdrawal = create(&merchant, 4_000).await.unwrap();
+
+        // held right away.
+        let merchant = merchant.reload().await;
+        assert_eq!(merchant.user_model.balance, Decimal::from(6_000));
+
+        let Json(queue) = super::queue(admin.state(), admin.user_access())
+            .await
+            .unwrap();
+        assert_eq!(
+            queue.withdrawals.iter().map(|it| it.id).collect::<Vec<_>>(),
+            [withdrawal.id]
+        );
+
+        let Json(withdrawal) = super::approve(
+            admin.state(),
+            admin.user_access(),
+            PathObjectId(withdrawal.id.into()),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            statuses(&withdrawal),
+            [
+                WithdrawalStatusType::Pending,
+                WithdrawalStatusType::Approved
+            ]
+        );
+
+        let merchant = merchant.reload().await;
+        assert_eq!(merchant.user_model.balance, Decimal::from(6_000));
+
+        let Json(queue) = super::queue(admin.state(), admin.user_access())
+            .await
+            .unwrap();
+        assert!(queue.withdrawals.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_rejected_withdrawal_released() {
+        let admin = bootstrap().await;
+        let merchant = admin
+            .derive_customer()
+            .await
+            .with_balance(10_000.into())
+            .await;
+
+        let withdrawal = create(&merchant, 4_000).await.unwrap();
+
+        let Json(withdrawal) = super::reject(
+            admin.state(),
+            admin.user_access(),
+            PathObjectId(withdrawal.id.into()),
+            Json(RejectRequest {
+                reason: "wrong account number".to_string(),
+            }),
+        )
+        .await
+        .unwrap();
+        assert_eq!(
+            statuses(&withdrawal),
+            [
+                WithdrawalStatusType::Pending,
+                WithdrawalStatusType::Rejected {
+                    reason: "wrong account number".to_string()
+                }
+            ]
+        );
+
+        let merchant = merchant.reload().await;
+        assert_eq!(merchant.user_model.balance, Decimal::from(10_000));
+
+        // settled only once.
+        let error = super::approve(
+            admin.state(),
+            admin.user_access(),
+            PathObjectId(withdrawal.id.into()),
+        )
+        .await
+        .expect_err("already rejected");
+        assert_matches!(error, Error::CustomStr(StatusCode::CONFLICT, _));
+
+        let merchant = merchant.reload().await;
+        assert_eq!(merchant.user_model.balance, Decimal::from(10_000));
+    }
+
+    #[tokio::test]
+    async fn test_withdrawal_limited_to_balance() {
+        let admin = bootstrap().await;
+        let merchant = admin
+            .derive_customer()
+            .await
+            .with_balance(10_000.into())
+            .await;
+
+        let error = create(&merchant, 10_001)
+            .await
+            .expect_err("more than the balance");
+        assert_matches!(error, Error::InsufficientFund);
+
+        create(&merchant, 6_000).await.unwrap();
+
+        let error = create(&merchant, 6_000)
+            .await
+            .expect_err("the first one is held");
+        assert_matches!(error, Error::InsufficientFund);
+
+        let error = create(&merchant, 0).await.expect_err("nothing to withdraw");
+        assert_matches!(error, Error::CustomStr(StatusCode::UNPROCESSABLE_ENTITY, _));
+
+        let Json(history) = super::index(merchant.state(), merchant.user_access())
+            .await
+            .unwrap();
+        assert_eq!(history.withdrawals.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_only_admin_settle() {
+        let admin = bootstrap().await;
+        let merchant = admin
+            .derive_customer()
+            .await
+            .with_balance(10_000.into())
+            .await;
+        let other = admin.derive_customer().await;
+
+        let withdrawal = create(&merchant, 4_000).await.unwrap();
+
+        let error = super::approve(
+            merchant.state(),
+            merchant.user_access(),
+            PathObjectId(withdrawal.id.into()),
+        )
+        .await
+        .expect_err("merchants can't approve");
+        assert_matches!(error, Error::Forbidden);
+
+        let error = super::queue(merchant.state(), merchant.user_access())
+            .await
+            .expect_err("merchants can't see the queue");
+        assert_matches!(error, Error::Forbidden);
+
+        let error = super::show(
+            other.state(),
+            other.user_access(),
+            PathObjectId(withdrawal.id.into()),
+        )
+        .await
+        .expect_err("not the owner");
+        assert_matches!(error, Error::NoResource);
+
+        let admin = admin.with_balance(10_000.into()).await;
+        let own = create(&admin, 1_000).await.unwrap();
+        let error = super::approve(
+            admin.state(),
+            admin.user_access(),
+            PathObjectId(own.id.into()),
+        )
+        .await
+        .expect_err("own withdrawal");
+        assert_matches!(error, Error::Forbidden);
+    }
+}
@@ -2,13 +2,29 @@ use axum::extract::FromRef;
 
 use crate::{
     api::v1::{
+        address::AddressCollection,
         auth::UserCollection,
         cart::CartCollection,
+        commission::{CommissionRate, PlatformRevenueCollection},
+        earning::CourierEarningCollection,
+        idempotency::IdempotencyCollection,
+        ledger::LedgerEntryCollection,
         product::ProductCollection,
+        shipping::ShippingRate,
         token::{JwtState, RefreshTokenCollection},
-        transaction::TransactionCollection,
+        transaction::{
+            events::TransactionEvents,
+            invoice::{CounterCollection, InvoiceCollection},
+            webhook::{WebhookDeliveryCollection, WebhookEndpointCollection, WebhookPolicy},
+            TransactionCollection,
+        },
+        wallet::TopUpCollection,
+        withdrawal::WithdrawalCollection,
     },
     migrate::MigrationCollection,
+    payment::PaymentGateway,
+    scheduler::{LeaseCollection, Schedule},
+    storage::FileStorage,
 };
 
 #[derive(FromRef, Clone)]
@@ -23,12 +39,34 @@ pub struct AppState {
     pub product_collection: ProductCollection,
     pub transaction_collection: TransactionCollection,
     pub cart_collection: CartCollection,
+    pub idempotency_collection: IdempotencyCollection,
+    pub address_collection: AddressCollection,
+    pub courier_earning_collection: CourierEarningCollection,
+    pub shipping_rate: ShippingRate,
+    pub platform_revenue_collection: PlatformRevenueCollection,
+    pub commission_rate: CommissionRate,
+    pub schedule: Schedule,
+    pub lease_collection: LeaseCollection,
+    pub file_storage: FileStorage,
+    pub transaction_events: TransactionEvents,
+    pub webhook_endpoint_collection: WebhookEndpointCollection,
+    pub webhook_delivery_collection: WebhookDeliveryCollection,
+    pub webhook_policy: WebhookPolicy,
+    /// Made by [`WebhookPolicy::client`].
+    pub http_client: reqwest::Client,
+    pub invoice_collection: InvoiceCollection,
+    pub counter_collection: CounterCollection,
+    pub ledger_entry_collection: LedgerEntryCollection,
+    pub topup_collection: TopUpCollection,
+    pub payment_gateway: PaymentGateway,
+    pub withdrawal_collection: WithdrawalCollection,
 }
 
 impl AppState {
     pub async fn new(
         argon: argon2::Argon2<'static>,
         jwt_state: JwtState,
+        payment_gateway: PaymentGateway,
         mongo_url: &str,
         database_name: &str,
     ) -> Result<Self, Box<dyn std::error::Error>> {
@@ -39,6 +77,7 @@ impl AppState {
         let mongo_client = mongodb::Client::with_options(mongo_client_opt)?;
 
         let db = mongo_client.database(database_name);
+        let webhook_policy = WebhookPolicy::new_from_env();
 
         let this = Self {
             argon,
@@ -51,6 +90,34 @@ impl AppState {
             product_collection: ProductCollection(db.collection("products").into()),
             transaction_collection: TransactionCollection(db.collection("transactions").into()),
             cart_collection: CartCollection(db.collection("carts").into()),
+            idempotency_collection: IdempotencyCollection(db.collection("idempotency_keys").into()),
+            address_collection: AddressCollection(db.collection("addresses").into()),
+            courier_earning_collection: CourierEarningCollection(
+                db.collection("courier_earnings").into(),
+            ),
+            shipping_rate: ShippingRate::new_from_env(),
+            platform_revenue_collection: PlatformRevenueCollection(
+                db.collection("platform_revenues").into(),
+            ),
+            commission_rate: CommissionRate::new_from_env(),
+            schedule: Schedule::new_from_env(),
+            lease_collection: LeaseCollection(db.collection("scheduler_leases").into()),
+            file_storage: FileStorage::new_from_env(),
+            transaction_events: TransactionEvents::default(),
+            webhook_endpoint_collection: WebhookEndpointCollection(
+                db.collection("webhook_endpoints").into(),
+            ),
+            webhook_delivery_collection: WebhookDeliveryCollection(
+                db.collection("webhook_deliveries").into(),
+            ),
+            webhook_policy,
+            http_client: webhook_policy.client(),
+            invoice_collection: InvoiceCollection(db.collection("invoices").into()),
+            counter_collection: CounterCollection(db.collection("counters").into()),
+            ledger_entry_collection: LedgerEntryCollection(db.collection("ledger_entries").into()),
+            topup_collection: TopUpCollection(db.collection("topups").into()),
+            payment_gateway,
+            withdrawal_collection: WithdrawalCollection(db.collection("withdrawals").into()),
         };
 
         this.run_migration().await?;
@@ -67,7 +134,18 @@ impl AppState {
             .expect("Cannot retreive JWT_SECRET_KEY from environment variable.");
         let jwt_state = JwtState::new_from_env();
 
-        Self::new(argon2::Argon2::default(), jwt_state, mongodb_url, "ecommerce").await
+        let this = Self::new(
+            argon2::Argon2::default(),
+            jwt_state,
+            PaymentGateway::new_from_env(),
+            mongodb_url,
+            "ecommerce",
+        )
+        .await?;
+
+        this.start_scheduler();
+
+        Ok(this)
     }
 }
 
@@ -86,13 +164,16 @@ mod tests {
         let mongo_client = mongodb::Client::with_options(mongo_client_opt).unwrap();
 
         let databases = mongo_client.list_database_names(None, None).await.unwrap();
-        let bootstrap_name = BOOTSTRAP_LOCK.lock().unwrap();
-        for it in databases {
-            if it.starts_with("ecommerce-test") {
-                if bootstrap_name.get(&it).is_none() {
-                    mongo_client.database(&it).drop(None).await.unwrap();
-                }
-            }
+        let stale = {
+            let bootstrap_name = BOOTSTRAP_LOCK.lock().unwrap();
+            databases
+                .into_iter()
+                .filter(|it| it.starts_with("ecommerce-test") && bootstrap_name.get(it).is_none())
+                .collect::<Vec<_>>()
+        };
+
+        for it in stale {
+            mongo_client.database(&it).drop(None).await.unwrap();
         }
     }
 }
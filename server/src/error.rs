@@ -51,6 +51,16 @@ pub enum Error {
 
     #[error("Your balance is not sufficient to complete this transaction.")]
     InsufficientFund,
+
+    #[error("{0}")]
+    TransactionError(#[from] crate::api::v1::transaction::TransactionError),
+
+    #[error("Idempotency-Key has already been used for a different request")]
+    IdempotencyKeyReused,
+
+    /// Details are logged where it happened, they may tell server paths.
+    #[error("Failed accessing the file storage")]
+    StorageError,
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -90,6 +100,7 @@ impl From<Error> for ErrorJson {
 
         let errors = match err {
             Error::ValidationError(err) => serde_json::to_value(err).ok(),
+            Error::TransactionError(err) => serde_json::to_value(err).ok(),
             Error::NotFound(..)
             | Error::NoResource
             | Error::InsufficientFund
@@ -97,8 +108,10 @@ impl From<Error> for ErrorJson {
             | Error::PasswordHashError(..)
             | Error::DatabaseError(..)
             | Error::MismatchMerchant
+            | Error::IdempotencyKeyReused
             | Error::JWTError(..)
             | Error::BSONSerError(..)
+            | Error::StorageError
             | Error::MustUniqueError(..)
             | Error::Unauthorized(..)
             | Error::Forbidden
@@ -119,17 +132,20 @@ impl IntoResponse for Error {
         tracing::error!("error: {:?}", self);
         let status = match self {
             Self::Unauthorized(..) => StatusCode::UNAUTHORIZED,
-            Self::ValidationError(..) | Self::MustUniqueError(..) | Self::MismatchMerchant => {
-                StatusCode::UNPROCESSABLE_ENTITY
-            }
+            Self::ValidationError(..)
+            | Self::MustUniqueError(..)
+            | Self::MismatchMerchant
+            | Self::IdempotencyKeyReused => StatusCode::UNPROCESSABLE_ENTITY,
             Self::InsufficientFund => StatusCode::PAYMENT_REQUIRED,
             Self::Forbidden => StatusCode::FORBIDDEN,
+            Self::TransactionError(..) => StatusCode::CONFLICT,
             Self::NotFound(..) | Self::NoResource => StatusCode::NOT_FOUND,
             Self::PasswordHashError(..)
             | Self::ViteManifestNotFound
             | Self::DatabaseError(..)
             | Self::JWTError(..)
-            | Self::BSONSerError(..) => StatusCode::INTERNAL_SERVER_ERROR,
+            | Self::BSONSerError(..)
+            | Self::StorageError => StatusCode::INTERNAL_SERVER_ERROR,
             Self::CustomStatus(code, ..) | Self::CustomStr(code, ..) => code,
         };
 
@@ -172,15 +188,18 @@ impl Error {
             Forbidden!,
             MismatchMerchant!,
             InsufficientFund!,
+            IdempotencyKeyReused!,
             ValidationError(..),
             PasswordHashError(..),
             DatabaseError(..),
             JWTError(..),
             BSONSerError(..),
+            StorageError!,
             MustUniqueError(..),
             Unauthorized(..),
             CustomStatus(..),
-            CustomStr(..)
+            CustomStr(..),
+            TransactionError(..)
         }
         .to_string()
     }
@@ -191,3 +210,9 @@ impl From<axum::extract::rejection::PathRejection> for Error {
         Self::NoResource
     }
 }
+
+impl From<axum::extract::multipart::MultipartError> for Error {
+    fn from(value: axum::extract::multipart::MultipartError) -> Self {
+        Self::CustomStatus(StatusCode::BAD_REQUEST, anyhow::anyhow!("{value}"))
+    }
+}
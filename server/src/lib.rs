@@ -1,6 +1,9 @@
 pub mod api;
 pub mod app;
 pub mod error;
+pub mod migrate;
 pub mod mongo_ext;
+pub mod payment;
+pub mod scheduler;
+pub mod storage;
 pub mod util;
-pub mod migrate;
@@ -2,7 +2,7 @@ use std::{collections::HashMap, net::SocketAddr};
 
 use axum::{extract::State, handler::Handler, response::IntoResponse, routing, Router};
 use ecommerce::app::AppState;
-use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
+use tracing_subscriber::util::SubscriberInitExt;
 
 #[tokio::main]
 async fn main() {
@@ -27,6 +27,32 @@ async fn main() {
 
     let app_state = AppState::new_from_env().await.unwrap();
 
+    let mut wallet = Router::new()
+        .route(
+            "/topup",
+            routing::get(ecommerce::api::v1::wallet::index_topups),
+        )
+        .route(
+            "/topup",
+            routing::post(ecommerce::api::v1::wallet::create_topup),
+        )
+        .route(
+            "/topup/callback",
+            routing::post(ecommerce::api::v1::wallet::callback),
+        )
+        .route(
+            "/topup/:id",
+            routing::get(ecommerce::api::v1::wallet::show_topup),
+        );
+
+    // the fake provider lets users pay their own top-ups, only when explicitly picked.
+    if app_state.payment_gateway.is_fake() {
+        wallet = wallet.route(
+            "/topup/:id/fake",
+            routing::post(ecommerce::api::v1::wallet::fake_pay),
+        );
+    }
+
     let api = Router::new().nest(
         "/v1",
         Router::new()
@@ -61,7 +87,24 @@ async fn main() {
                     .route("/", routing::post(ecommerce::api::v1::account::create))
                     .route("/:id", routing::get(ecommerce::api::v1::account::show))
                     .route("/:id", routing::put(ecommerce::api::v1::account::update))
-                    .route("/:id", routing::delete(ecommerce::api::v1::account::delete)),
+                    .route("/:id", routing::delete(ecommerce::api::v1::account::delete))
+                    .route(
+                        "/me/ledger",
+                        routing::get(ecommerce::api::v1::ledger::index),
+                    )
+                    .nest(
+                        "/me/addresses",
+                        Router::new()
+                            .route("/", routing::get(ecommerce::api::v1::address::index))
+                            .route("/", routing::post(ecommerce::api::v1::address::create))
+                            .route("/:id", routing::get(ecommerce::api::v1::address::show))
+                            .route("/:id", routing::put(ecommerce::api::v1::address::update))
+                            .route("/:id", routing::delete(ecommerce::api::v1::address::delete))
+                            .route(
+                                "/:id/default",
+                                routing::post(ecommerce::api::v1::address::set_default),
+                            ),
+                    ),
             )
             .nest(
                 "/order",
@@ -77,6 +120,22 @@ async fn main() {
                     .route(
                         "/",
                         routing::post(ecommerce::api::v1::transaction::insert_order),
+                    )
+                    .route(
+                        "/:id/events",
+                        routing::get(ecommerce::api::v1::transaction::events::order_events),
+                    )
+                    .route(
+                        "/:id/invoice",
+                        routing::get(ecommerce::api::v1::transaction::invoice::show_invoice),
+                    )
+                    .route(
+                        "/:id/cancel",
+                        routing::post(ecommerce::api::v1::transaction::cancel_order),
+                    )
+                    .route(
+                        "/:id/confirm",
+                        routing::post(ecommerce::api::v1::transaction::confirm_order),
                     ),
             )
             .nest(
@@ -84,9 +143,37 @@ async fn main() {
                 Router::new()
                     .route("/", routing::get(ecommerce::api::v1::transaction::index))
                     .route("/:id", routing::get(ecommerce::api::v1::transaction::show))
+                    .route(
+                        "/reports",
+                        routing::get(ecommerce::api::v1::transaction::report::report),
+                    )
+                    .route(
+                        "/invoices",
+                        routing::get(ecommerce::api::v1::transaction::invoice::download_invoices),
+                    )
+                    .route(
+                        "/:id/packing-slip",
+                        routing::get(ecommerce::api::v1::transaction::invoice::show_packing_slip),
+                    )
+                    .route(
+                        "/:id/accept",
+                        routing::post(ecommerce::api::v1::transaction::accept),
+                    )
+                    .route(
+                        "/:id/reject",
+                        routing::post(ecommerce::api::v1::transaction::reject),
+                    )
                     .route(
                         "/:id/confirm",
                         routing::post(ecommerce::api::v1::transaction::confirm_processing),
+                    )
+                    .route(
+                        "/:id/reship",
+                        routing::post(ecommerce::api::v1::transaction::reship),
+                    )
+                    .route(
+                        "/:id/refund",
+                        routing::post(ecommerce::api::v1::transaction::refund),
                     ),
             )
             .nest(
@@ -95,8 +182,56 @@ async fn main() {
                     .route("/", routing::get(ecommerce::api::v1::cart::index))
                     .route("/:id", routing::get(ecommerce::api::v1::cart::show))
                     .route("/", routing::post(ecommerce::api::v1::cart::create))
+                    .route(
+                        "/checkout",
+                        routing::post(ecommerce::api::v1::cart::checkout),
+                    )
                     .route("/:id", routing::delete(ecommerce::api::v1::cart::delete)),
             )
+            .nest(
+                "/webhook",
+                Router::new()
+                    .route(
+                        "/",
+                        routing::get(ecommerce::api::v1::transaction::webhook::index_endpoints),
+                    )
+                    .route(
+                        "/",
+                        routing::post(ecommerce::api::v1::transaction::webhook::create_endpoint),
+                    )
+                    .route(
+                        "/:id",
+                        routing::delete(ecommerce::api::v1::transaction::webhook::delete_endpoint),
+                    )
+                    .route(
+                        "/:id/deliveries",
+                        routing::get(ecommerce::api::v1::transaction::webhook::index_deliveries),
+                    ),
+            )
+            .nest("/wallet", wallet)
+            .nest(
+                "/withdrawal",
+                Router::new()
+                    .route("/", routing::get(ecommerce::api::v1::withdrawal::index))
+                    .route("/", routing::post(ecommerce::api::v1::withdrawal::create))
+                    .route(
+                        "/queue",
+                        routing::get(ecommerce::api::v1::withdrawal::queue),
+                    )
+                    .route("/:id", routing::get(ecommerce::api::v1::withdrawal::show))
+                    .route(
+                        "/:id/approve",
+                        routing::post(ecommerce::api::v1::withdrawal::approve),
+                    )
+                    .route(
+                        "/:id/reject",
+                        routing::post(ecommerce::api::v1::withdrawal::reject),
+                    ),
+            )
+            .nest(
+                "/revenue",
+                Router::new().route("/", routing::get(ecommerce::api::v1::commission::index)),
+            )
             .nest(
                 "/delivery",
                 Router::new()
@@ -104,6 +239,14 @@ async fn main() {
                         "/",
                         routing::get(ecommerce::api::v1::transaction::index_delivery),
                     )
+                    .route(
+                        "/earnings",
+                        routing::get(ecommerce::api::v1::earning::index),
+                    )
+                    .route(
+                        "/events",
+                        routing::get(ecommerce::api::v1::transaction::events::delivery_events),
+                    )
                     .nest(
                         "/:id",
                         Router::new()
@@ -118,6 +261,30 @@ async fn main() {
                             .route(
                                 "/change",
                                 routing::post(ecommerce::api::v1::transaction::change_delivery),
+                            )
+                            .route(
+                                "/arrive",
+                                routing::post(ecommerce::api::v1::transaction::proof::arrive)
+                                    .layer(axum::extract::DefaultBodyLimit::max(
+                                        ecommerce::api::v1::transaction::proof::MAX_PHOTO_SIZE
+                                            + 64 * 1024,
+                                    )),
+                            )
+                            .route(
+                                "/proof",
+                                routing::get(ecommerce::api::v1::transaction::proof::show_proof),
+                            )
+                            .route(
+                                "/assign",
+                                routing::post(ecommerce::api::v1::transaction::assign_courier),
+                            )
+                            .route(
+                                "/reassign",
+                                routing::post(ecommerce::api::v1::transaction::reassign_courier),
+                            )
+                            .route(
+                                "/unassign",
+                                routing::post(ecommerce::api::v1::transaction::unassign_courier),
                             ),
                     ),
             ),
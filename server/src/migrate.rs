@@ -2,9 +2,15 @@ use std::collections::HashSet;
 
 use bson::oid::ObjectId;
 use mongodb::{options::IndexOptions, ClientSession, IndexModel};
+use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
 
-use crate::{app::AppState, mongo_ext::Collection};
+use crate::{
+    api::v1::{idempotency::IDEMPOTENCY_KEY_TTL, transaction::ProductTransaction},
+    app::AppState,
+    mongo_ext::Collection,
+    util::decimal128,
+};
 
 #[derive(Serialize, Deserialize)]
 pub struct MigrateModel {
@@ -74,6 +80,437 @@ impl AppState {
         Ok(())
     }
 
+    /// Product stock used to be stored as `[sign, [digits]]`, rewrite it as an `Int64` so
+    /// it can be decremented atomically with `$inc`. A stock too large for it fails the
+    /// migration rather than being clamped.
+    async fn v2_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        let mut cursor = self
+            .product_collection
+            .find_with_session(bson::doc! {"stock": {"$type": "array"}}, None, session)
+            .await?;
+
+        let mut products = vec![];
+
+        while cursor.advance(session).await? {
+            products.push(cursor.deserialize_current()?);
+        }
+
+        for product in products {
+            let stock = i64::try_from(&product.stock).map_err(|_| {
+                <bson::de::Error as serde::de::Error>::custom(format!(
+                    "stock of product {} does not fit in an Int64",
+                    product.id
+                ))
+            })?;
+
+            self.product_collection
+                .update_one_with_session(
+                    bson::doc! {"_id": product.id},
+                    bson::doc! {"$set": {"stock": stock}},
+                    None,
+                    session,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn v3_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        self.idempotency_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {
+                        "user_id": 1,
+                        "scope": 1,
+                        "key": 1,
+                    })
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        self.idempotency_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"created_at": 1})
+                    .options(
+                        IndexOptions::builder()
+                            .expire_after(IDEMPOTENCY_KEY_TTL)
+                            .build(),
+                    )
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn v4_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        self.address_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"user_id": 1})
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn v5_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        self.courier_earning_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"courier_id": 1, "created_at": -1})
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        self.courier_earning_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"transaction_id": 1})
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn v6_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        self.platform_revenue_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"transaction_id": 1})
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn v7_migrate(&self) -> Result<(), mongodb::error::Error> {
+        for field in ["user_id", "merchant_id", "courier_id"] {
+            self.transaction_collection
+                .create_index(
+                    IndexModel::builder()
+                        .keys(bson::doc! {field: 1, "created_at": -1, "_id": -1})
+                        .build(),
+                    None,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    async fn v8_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        self.webhook_endpoint_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"merchant_id": 1})
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        self.webhook_delivery_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"status": 1, "next_attempt_at": 1})
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        self.webhook_delivery_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"endpoint_id": 1, "created_at": -1, "_id": -1})
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn v9_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        self.invoice_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"number": 1})
+                    .options(IndexOptions::builder().unique(true).build())
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// Same as [`Self::v2_migrate`] for the quantity of transaction line items, so reports
+    /// can sum them in an aggregation.
+    async fn v10_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        /// Only the line items, the rest of a transaction may still be in an older shape,
+        /// see [`AppState::v14_migrate`].
+        #[derive(Deserialize)]
+        struct LineItems {
+            #[serde(rename = "_id")]
+            id: ObjectId,
+            products: Vec<ProductTransaction>,
+        }
+
+        let mut cursor = self
+            .transaction_collection
+            .clone_with_type::<LineItems>()
+            .find_with_session(
+                bson::doc! {"products.quantity": {"$type": "array"}},
+                mongodb::options::FindOptions::builder()
+                    .projection(bson::doc! {"products": 1})
+                    .build(),
+                session,
+            )
+            .await?;
+
+        let mut transactions = vec![];
+
+        while cursor.advance(session).await? {
+            transactions.push(cursor.deserialize_current()?);
+        }
+
+        for transaction in transactions {
+            self.transaction_collection
+                .update_one_with_session(
+                    bson::doc! {"_id": transaction.id},
+                    bson::doc! {"$set": {"products": bson::to_bson(&transaction.products)?}},
+                    None,
+                    session,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Balances used to be stored as strings, rewrite them as `Decimal128` so the ledger can
+    /// `$inc` them, and open the ledger of every wallet with its current balance and of the
+    /// escrow with what orders in flight hold. Delivered orders were paid out on arrival
+    /// back then, they are marked so confirming them doesn't pay the merchant again.
+    async fn v11_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        // built before the first entry creates the collection, mongo only builds indexes in a
+        // transaction on a collection created by it.
+        self.ledger_entry_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"account.user_id": 1, "_id": -1})
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        self.ledger_entry_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"transaction_id": 1})
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        self.user_collection
+            .update_many_with_session(
+                bson::doc! {"balance": {"$type": "string"}},
+                vec![bson::doc! {"$set": {"balance": {"$toDecimal": "$balance"}}}],
+                None,
+                session,
+            )
+            .await?;
+
+        let mut cursor = self
+            .user_collection
+            .find_with_session(
+                bson::doc! {"balance": {"$exists": true, "$ne": decimal128::to_bson(&Decimal::ZERO)}},
+                None,
+                session,
+            )
+            .await?;
+
+        let mut users = vec![];
+
+        while cursor.advance(session).await? {
+            users.push(cursor.deserialize_current()?);
+        }
+
+        for user in users {
+            self.ledger_entry_collection
+                .insert_opening_balance_with_session(&user, session)
+                .await?;
+        }
+
+        /// Only what tells how much is in escrow, statuses may still be in an older shape,
+        /// see [`AppState::v14_migrate`].
+        #[derive(Deserialize)]
+        struct Held {
+            #[serde(rename = "_id")]
+            id: ObjectId,
+            price: Decimal,
+            #[serde(default)]
+            shipping_fee: Decimal,
+            status: Vec<HeldStatus>,
+        }
+
+        #[derive(Deserialize)]
+        struct HeldStatus {
+            r#type: String,
+        }
+
+        let mut cursor = self
+            .transaction_collection
+            .clone_with_type::<Held>()
+            .find_with_session(
+                None,
+                mongodb::options::FindOptions::builder()
+                    .projection(bson::doc! {"price": 1, "shipping_fee": 1, "status.type": 1})
+                    .build(),
+                session,
+            )
+            .await?;
+
+        let mut transactions = vec![];
+
+        while cursor.advance(session).await? {
+            transactions.push(cursor.deserialize_current()?);
+        }
+
+        let mut paid_on_arrival = vec![];
+
+        for transaction in transactions {
+            // refunded or paid out orders hold nothing, and delivered ones paid both the
+            // merchant and the courier when they arrived.
+            let held = match transaction.status.last().map(|it| it.r#type.as_str()) {
+                Some(
+                    "Cancelled"
+                    | "RejectedByMerchant"
+                    | "Expired"
+                    | "Returned"
+                    | "ArrivedInDestinationConfirmed",
+                ) => Decimal::ZERO,
+                Some("ArrivedInDestination") => {
+                    paid_on_arrival.push(transaction.id);
+                    Decimal::ZERO
+                }
+                _ => transaction.price + transaction.shipping_fee,
+            };
+
+            if held > Decimal::ZERO {
+                self.ledger_entry_collection
+                    .insert_opening_escrow_with_session(transaction.id, held, session)
+                    .await?;
+            }
+        }
+
+        self.transaction_collection
+            .update_many_with_session(
+                bson::doc! {"_id": {"$in": paid_on_arrival}},
+                bson::doc! {"$set": {"paid_on_arrival": true}},
+                None,
+                session,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn v12_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        self.topup_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"user_id": 1, "_id": -1})
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn v13_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        self.withdrawal_collection
+            .create_index_with_session(
+                IndexModel::builder()
+                    .keys(bson::doc! {"user_id": 1, "_id": -1})
+                    .build(),
+                None,
+                session,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// `SendBackToMerchant` used to be a unit variant, give the statuses stored before it
+    /// had a reason an empty one.
+    async fn v14_migrate(&self, session: &mut ClientSession) -> Result<(), mongodb::error::Error> {
+        let legacy = bson::doc! {
+            "type": "SendBackToMerchant",
+            "content": {"$exists": false},
+        };
+
+        self.transaction_collection
+            .update_many_with_session(
+                bson::doc! {"status": {"$elemMatch": legacy}},
+                bson::doc! {"$set": {"status.$[legacy].content": {"reason": ""}}},
+                mongodb::options::UpdateOptions::builder()
+                    .array_filters(vec![bson::doc! {
+                        "legacy.type": "SendBackToMerchant",
+                        "legacy.content": {"$exists": false},
+                    }])
+                    .build(),
+                session,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    async fn v15_migrate(&self) -> Result<(), mongodb::error::Error> {
+        for field in ["user_id", "merchant_id", "courier_id"] {
+            self.transaction_collection
+                .create_index(
+                    IndexModel::builder()
+                        .keys(bson::doc! {field: 1, "updated_at": -1, "_id": -1})
+                        .build(),
+                    None,
+                )
+                .await?;
+        }
+
+        Ok(())
+    }
+
     async fn get_all_migration(&self) -> Result<Vec<MigrateModel>, mongodb::error::Error> {
         let mut cursor = self.migrate_collection.find(None, None).await?;
 
@@ -86,6 +523,7 @@ impl AppState {
         Ok(vec)
     }
 
+    /// Run the migrations not run yet, each in its own transaction.
     pub async fn run_migration(&self) -> Result<(), mongodb::error::Error> {
         let migration: HashSet<i64> = self
             .get_all_migration()
@@ -95,22 +533,153 @@ impl AppState {
             .collect();
 
         let mut session = self.mongo_client.start_session(None).await?;
-        session.start_transaction(None).await?;
 
         macro_rules! migrate {
             ($version:expr, $fun:ident) => {
                 if let None = migration.get($version) {
                     tracing::debug!("running migration version {}", $version);
+                    session.start_transaction(None).await?;
                     self.$fun(&mut session).await?;
                     self.migrate_collection
-                        .insert_version_with_session(1, &mut session)
+                        .insert_version_with_session(*$version, &mut session)
+                        .await?;
+                    session.commit_transaction().await?;
+                }
+            };
+            // mongo refuses to build an index in a transaction on a collection that already
+            // exists, those are built before the version is recorded. Building them again
+            // after a failed run is harmless.
+            ($version:expr, $fun:ident, indexes) => {
+                if let None = migration.get($version) {
+                    tracing::debug!("running migration version {}", $version);
+                    self.$fun().await?;
+                    session.start_transaction(None).await?;
+                    self.migrate_collection
+                        .insert_version_with_session(*$version, &mut session)
                         .await?;
+                    session.commit_transaction().await?;
                 }
             };
         }
 
         migrate!(&1, v1_migrate);
+        migrate!(&2, v2_migrate);
+        migrate!(&3, v3_migrate);
+        migrate!(&4, v4_migrate);
+        migrate!(&5, v5_migrate);
+        migrate!(&6, v6_migrate);
+        migrate!(&7, v7_migrate, indexes);
+        migrate!(&8, v8_migrate);
+        migrate!(&9, v9_migrate);
+        migrate!(&10, v10_migrate);
+        migrate!(&11, v11_migrate);
+        migrate!(&12, v12_migrate);
+        migrate!(&13, v13_migrate);
+        migrate!(&14, v14_migrate);
+        migrate!(&15, v15_migrate, indexes);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use axum::Json;
+    use rust_decimal::Decimal;
+
+    use crate::{
+        api::v1::{
+            tests::bootstrap,
+            transaction::{self, TransactionStatusType},
+        },
+        util::decimal128,
+    };
+
+    #[tokio::test]
+    async fn test_index_migrations_on_existing_collection() {
+        let admin = bootstrap().await;
+        let merchant = admin.derive_customer().await;
+        let customer = admin
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(1_000))
+            .await;
+
+        customer.create_transaction(&merchant, 1).await;
+
+        let app = &admin.app_state;
+        app.transaction_collection.drop_indexes(None).await.unwrap();
+        app.migrate_collection
+            .delete_many(bson::doc! {"version": {"$in": [7, 15]}}, None)
+            .await
+            .unwrap();
+
+        app.run_migration().await.unwrap();
+
+        // `_id` and the three by created_at and by updated_at.
+        let indexes = app.transaction_collection.list_index_names().await.unwrap();
+        assert_eq!(indexes.len(), 7);
+    }
+
+    #[tokio::test]
+    async fn test_order_delivered_before_ledger_paid_once() {
+        let admin = bootstrap().await;
+        let merchant = admin.derive_customer().await;
+        let courier = admin.derive_courier().await;
+        let customer = admin
+            .derive_customer()
+            .await
+            .with_balance(Decimal::from(1_000))
+            .await;
+
+        let order = customer
+            .create_delivered_transaction(&merchant, &courier, 1)
+            .await;
+
+        // as left before the ledger, when the merchant was paid on arrival.
+        let app = &admin.app_state;
+        app.ledger_entry_collection.drop(None).await.unwrap();
+        app.transaction_collection
+            .update_one(
+                bson::doc! {"_id": *order.id},
+                bson::doc! {"$unset": {"paid_on_arrival": ""}},
+                None,
+            )
+            .await
+            .unwrap();
+        app.user_collection
+            .update_one(
+                bson::doc! {"_id": merchant.user_id()},
+                bson::doc! {"$set": {"balance": decimal128::to_bson(&Decimal::from(1_000))}},
+                None,
+            )
+            .await
+            .unwrap();
+        app.migrate_collection
+            .delete_many(bson::doc! {"version": 11}, None)
+            .await
+            .unwrap();
+
+        app.run_migration().await.unwrap();
+
+        let escrow = app
+            .ledger_entry_collection
+            .count_documents(bson::doc! {"transaction_id": *order.id}, None)
+            .await
+            .unwrap();
+        assert_eq!(escrow, 0, "nothing is left in escrow for it");
+
+        let Json(result) =
+            transaction::confirm_order(customer.state(), customer.user_access(), order.id.into())
+                .await
+                .unwrap();
+        assert_matches!(
+            result.status.last().unwrap().r#type,
+            TransactionStatusType::ArrivedInDestinationConfirmed
+        );
 
-        session.commit_transaction().await
+        let merchant = merchant.reload().await;
+        assert_eq!(merchant.user_model.balance, Decimal::from(1_000));
     }
 }
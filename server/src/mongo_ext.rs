@@ -5,6 +5,114 @@ use serde::de::DeserializeOwned;
 
 use crate::error::Error;
 
+/// Options used by every multi document mongo transaction.
+pub fn transaction_options() -> mongodb::options::TransactionOptions {
+    mongodb::options::TransactionOptions::builder()
+        .read_concern(mongodb::options::ReadConcern::snapshot())
+        .write_concern(
+            mongodb::options::WriteConcern::builder()
+                .w(mongodb::options::Acknowledgment::Majority)
+                .build(),
+        )
+        .selection_criteria(mongodb::options::SelectionCriteria::ReadPreference(
+            mongodb::options::ReadPreference::Primary,
+        ))
+        .build()
+}
+
+/// How long a transaction and its commit are retried for, the same limit as the driver's own
+/// `with_transaction`.
+pub const TRANSACTION_RETRY_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+
+/// Whether retrying the whole transaction may succeed, e.g. after a write conflict with
+/// another transaction.
+pub fn is_transient(err: &Error) -> bool {
+    match err {
+        Error::DatabaseError(err) => {
+            err.contains_label(mongodb::error::TRANSIENT_TRANSACTION_ERROR)
+        }
+        _ => false,
+    }
+}
+
+/// Whether `err` is caused by inserting a document that violate a unique index.
+pub fn is_duplicate_key(err: &mongodb::error::Error) -> bool {
+    const DUPLICATE_KEY: i32 = 11000;
+
+    match err.kind.as_ref() {
+        mongodb::error::ErrorKind::Command(err) => err.code == DUPLICATE_KEY,
+        mongodb::error::ErrorKind::Write(mongodb::error::WriteFailure::WriteError(err)) => {
+            err.code == DUPLICATE_KEY
+        }
+        _ => false,
+    }
+}
+
+/// Commit the transaction on `session`, retrying while the commit result is unknown until
+/// [`TRANSACTION_RETRY_TIMEOUT`] passed since `started`.
+pub async fn commit_with_retry(
+    session: &mut mongodb::ClientSession,
+    started: std::time::Instant,
+) -> Result<(), Error> {
+    loop {
+        match session.commit_transaction().await {
+            Err(err)
+                if err.contains_label(mongodb::error::UNKNOWN_TRANSACTION_COMMIT_RESULT)
+                    && started.elapsed() < TRANSACTION_RETRY_TIMEOUT =>
+            {
+                tracing::debug!("retrying commit with unknown result");
+                continue;
+            }
+            result => return result.map_err(Into::into),
+        }
+    }
+}
+
+/// Evaluate `$body` inside a mongo transaction on a new session bound to `$session` and
+/// commit it, yielding `Result<T, Error>`.
+///
+/// The whole body is retried on a transient transaction error, so it must not have side
+/// effect outside of the session. Any other error, or one still happening after
+/// [`TRANSACTION_RETRY_TIMEOUT`], aborts the transaction.
+macro_rules! with_transaction {
+    ($mongo:expr, |$session:ident| $body:expr) => {{
+        let mut $session = $mongo.start_session(None).await?;
+        let started = std::time::Instant::now();
+
+        loop {
+            $session
+                .start_transaction($crate::mongo_ext::transaction_options())
+                .await?;
+
+            let result: Result<_, $crate::error::Error> = async { $body }.await;
+
+            let err = match result {
+                Ok(value) => {
+                    match $crate::mongo_ext::commit_with_retry(&mut $session, started).await {
+                        Ok(()) => break Ok(value),
+                        Err(err) => err,
+                    }
+                }
+                Err(err) => {
+                    // the transaction may already be aborted by the server.
+                    let _ = $session.abort_transaction().await;
+                    err
+                }
+            };
+
+            if !$crate::mongo_ext::is_transient(&err)
+                || started.elapsed() >= $crate::mongo_ext::TRANSACTION_RETRY_TIMEOUT
+            {
+                break Err(err);
+            }
+
+            tracing::debug!("retrying transient transaction error {:?}", err);
+        }
+    }};
+}
+
+pub(crate) use with_transaction;
+
 pub struct Collection<T>(pub mongodb::Collection<T>);
 
 impl<T> Clone for Collection<T> {
@@ -86,6 +194,23 @@ where
         .map_err(Into::into)
     }
 
+    pub async fn find_exists_one_by_id_with_session(
+        &self,
+        id: ObjectId,
+        session: &mut mongodb::ClientSession,
+    ) -> Result<Option<T>, Error> {
+        self.find_one_with_session(
+            bson::doc! {
+                "_id": id,
+                "deleted_at": null
+            },
+            None,
+            session,
+        )
+        .await
+        .map_err(Into::into)
+    }
+
     pub async fn update_exists_one_by_id(
         &self,
         id: ObjectId,
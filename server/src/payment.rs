@@ -0,0 +1,283 @@
+//! Payment gateways used to top up wallets. [`FakeProvider`] is a local stand-in paid from
+//! our own app, a real gateway only has to implement [`PaymentProvider`] and be put in
+//! [`PaymentGateway`].
+
+use std::sync::Arc;
+
+use axum::http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
+use bson::oid::ObjectId;
+use hmac::Mac;
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+
+use crate::{error::Error, util::DecimalString};
+
+pub const SIGNATURE_HEADER: &str = "x-payment-signature";
+
+/// A top-up to be paid by its user.
+#[derive(Debug, Clone)]
+pub struct PaymentRequest {
+    pub topup_id: ObjectId,
+    pub user_id: ObjectId,
+    pub amount: Decimal,
+}
+
+/// A payment started at the provider.
+#[derive(Debug, Clone)]
+pub struct PaymentSession {
+    /// Id of the payment at the provider.
+    pub reference: String,
+    /// Where the user goes to pay.
+    pub redirect_url: String,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PaymentStatus {
+    Paid,
+    Failed,
+}
+
+/// Outcome of a payment, as told by the provider callback.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PaymentNotification {
+    pub topup_id: ObjectId,
+    pub reference: String,
+    pub status: PaymentStatus,
+    pub amount: Decimal,
+}
+
+#[axum::async_trait]
+pub trait PaymentProvider: Send + Sync {
+    /// Stored with every top-up, e.g. `fake`.
+    fn name(&self) -> &'static str;
+
+    /// Start the payment of `request` at the provider.
+    async fn create(&self, request: &PaymentRequest) -> Result<PaymentSession, Error>;
+
+    /// Read a callback made by the provider, failing when it isn't signed by the provider.
+    fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<PaymentNotification, Error>;
+
+    /// Signed callback the provider would make for `notification`, only implemented by
+    /// providers that can be paid locally.
+    fn simulate(&self, _notification: &PaymentNotification) -> Option<(HeaderMap, Vec<u8>)> {
+        None
+    }
+}
+
+/// Provider used by the handlers.
+#[derive(Clone)]
+pub struct PaymentGateway(pub Arc<dyn PaymentProvider>);
+
+impl std::ops::Deref for PaymentGateway {
+    type Target = dyn PaymentProvider;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl PaymentGateway {
+    /// Provider named by `PAYMENT_PROVIDER`. Only `fake` exists for now, and it has to be
+    /// picked explicitly since it lets users pay their own top-ups, see [`FakeProvider`].
+    pub fn new_from_env() -> Self {
+        let provider = std::env::var("PAYMENT_PROVIDER")
+            .expect("Cannot retreive PAYMENT_PROVIDER from environment variable.");
+
+        match provider.as_str() {
+            FakeProvider::NAME => {
+                let secret = std::env::var("PAYMENT_FAKE_SECRET").unwrap_or_else(|_| {
+                    use base64::Engine;
+                    use rand::RngCore;
+
+                    let mut bytes = [0; 32];
+                    rand::thread_rng().fill_bytes(&mut bytes);
+                    base64::engine::general_purpose::STANDARD.encode(bytes)
+                });
+
+                Self(Arc::new(FakeProvider::new(secret)))
+            }
+            other => panic!("Unknown PAYMENT_PROVIDER {other:?}"),
+        }
+    }
+
+    /// Whether users may pay their own top-ups through [`PaymentProvider::simulate`].
+    pub fn is_fake(&self) -> bool {
+        self.name() == FakeProvider::NAME
+    }
+}
+
+type HmacSha256 = hmac::Hmac<sha2::Sha256>;
+
+fn mac(secret: &str, body: &[u8]) -> HmacSha256 {
+    // HMAC accepts keys of any length.
+    let mut mac = HmacSha256::new_from_slice(secret.as_bytes()).unwrap();
+    mac.update(body);
+    mac
+}
+
+/// Lowercase hex HMAC-SHA256 of `body` keyed with `secret`.
+pub fn sign(secret: &str, body: &[u8]) -> String {
+    format!("{:x}", mac(secret, body).finalize().into_bytes())
+}
+
+/// Compare `signature`, as made by [`sign`], in constant time.
+pub fn verify_signature(secret: &str, body: &[u8], signature: &str) -> bool {
+    if !signature.len().is_multiple_of(2) || !signature.is_ascii() {
+        return false;
+    }
+
+    let bytes = (0..signature.len())
+        .step_by(2)
+        .map(|it| u8::from_str_radix(&signature[it..it + 2], 16))
+        .collect::<Result<Vec<_>, _>>();
+
+    match bytes {
+        Ok(bytes) => mac(secret, body).verify_slice(&bytes).is_ok(),
+        Err(_) => false,
+    }
+}
+
+/// Body of a [`FakeProvider`] callback.
+#[derive(Serialize, Deserialize, Debug)]
+struct FakeCallback {
+    topup_id: String,
+    reference: String,
+    status: PaymentStatus,
+    amount: DecimalString,
+}
+
+/// Provider paid from the top-up page of our own app. Callbacks are signed with
+/// [`sign`] in the [`SIGNATURE_HEADER`] header, as a real gateway would. No money is
+/// involved, so it must only be used in development and tests.
+pub struct FakeProvider {
+    secret: String,
+}
+
+impl FakeProvider {
+    pub const NAME: &'static str = "fake";
+
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self {
+            secret: secret.into(),
+        }
+    }
+}
+
+#[axum::async_trait]
+impl PaymentProvider for FakeProvider {
+    fn name(&self) -> &'static str {
+        Self::NAME
+    }
+
+    async fn create(&self, request: &PaymentRequest) -> Result<PaymentSession, Error> {
+        Ok(PaymentSession {
+            reference: format!("fake_{}", ObjectId::new().to_hex()),
+            redirect_url: format!("/user/wallet/topup/{}", request.topup_id.to_hex()),
+        })
+    }
+
+    fn verify(&self, headers: &HeaderMap, body: &[u8]) -> Result<PaymentNotification, Error> {
+        let signature = headers
+            .get(SIGNATURE_HEADER)
+            .and_then(|it| it.to_str().ok())
+            .and_then(|it| it.strip_prefix("sha256="))
+            .unwrap_or_default();
+
+        if !verify_signature(&self.secret, body, signature) {
+            return Err(Error::CustomStr(
+                StatusCode::UNAUTHORIZED,
+                "Invalid payment signature",
+            ));
+        }
+
+        let invalid = || Error::CustomStr(StatusCode::UNPROCESSABLE_ENTITY, "Invalid callback");
+
+        let callback: FakeCallback = serde_json::from_slice(body).map_err(|_| invalid())?;
+
+        Ok(PaymentNotification {
+            topup_id: callback.topup_id.parse().map_err(|_| invalid())?,
+            reference: callback.reference,
+            status: callback.status,
+            amount: callback.amount.0,
+        })
+    }
+
+    fn simulate(&self, notification: &PaymentNotification) -> Option<(HeaderMap, Vec<u8>)> {
+        let body = serde_json::to_vec(&FakeCallback {
+            topup_id: notification.topup_id.to_hex(),
+            reference: notification.reference.clone(),
+            status: notification.status,
+            amount: notification.amount.into(),
+        })
+        .ok()?;
+
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            HeaderName::from_static(SIGNATURE_HEADER),
+            HeaderValue::from_str(&format!("sha256={}", sign(&self.secret, &body))).ok()?,
+        );
+
+        Some((headers, body))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+    use axum::http::{HeaderValue, StatusCode};
+    use bson::oid::ObjectId;
+    use rust_decimal::Decimal;
+
+    use crate::error::Error;
+
+    use super::{
+        FakeProvider, PaymentNotification, PaymentProvider, PaymentStatus, SIGNATURE_HEADER,
+    };
+
+    #[test]
+    fn test_signature() {
+        let signature = super::sign("secret", b"body");
+
+        assert!(super::verify_signature("secret", b"body", &signature));
+        assert!(!super::verify_signature("other", b"body", &signature));
+        assert!(!super::verify_signature("secret", b"changed", &signature));
+        assert!(!super::verify_signature("secret", b"body", "not hex"));
+        assert!(!super::verify_signature("secret", b"body", ""));
+    }
+
+    #[test]
+    fn test_fake_callback_verified() {
+        let provider = FakeProvider::new("secret");
+        let notification = PaymentNotification {
+            topup_id: ObjectId::new(),
+            reference: "fake_1".to_string(),
+            status: PaymentStatus::Paid,
+            amount: Decimal::new(10_050, 2),
+        };
+
+        let (mut headers, body) = provider.simulate(&notification).unwrap();
+        assert_eq!(provider.verify(&headers, &body).unwrap(), notification);
+
+        let error = FakeProvider::new("other")
+            .verify(&headers, &body)
+            .expect_err("signed with another secret");
+        assert_matches!(error, Error::CustomStr(StatusCode::UNAUTHORIZED, _));
+
+        let mut tampered = body.clone();
+        tampered.extend_from_slice(b" ");
+        let error = provider
+            .verify(&headers, &tampered)
+            .expect_err("body changed after signing");
+        assert_matches!(error, Error::CustomStr(StatusCode::UNAUTHORIZED, _));
+
+        headers.remove(SIGNATURE_HEADER);
+        let error = provider
+            .verify(&headers, &body)
+            .expect_err("unsigned callback");
+        assert_matches!(error, Error::CustomStr(StatusCode::UNAUTHORIZED, _));
+
+        headers.insert(SIGNATURE_HEADER, HeaderValue::from_static("sha256=00"));
+        assert!(provider.verify(&headers, &body).is_err());
+    }
+}
@@ -0,0 +1,301 @@
+//! Background jobs running alongside the http server.
+//!
+//! Every server instance run the scheduler, a job only run on the instance holding its
+//! [`LeaseModel`] so the instances don't do the same work at the same time.
+
+use std::{future::Future, time::Duration};
+
+use axum::extract::FromRef;
+use serde::{Deserialize, Serialize};
+use time::OffsetDateTime;
+
+use crate::{
+    api::v1::transaction::{self, webhook, ChangeStatusState},
+    app::AppState,
+    error::Error,
+    mongo_ext::{is_duplicate_key, Collection},
+};
+
+/// When the scheduler run and what it consider stale.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Schedule {
+    /// Time between two runs.
+    pub interval: Duration,
+    /// How long an order may wait for the merchant before it expire.
+    pub expire_order_after: Duration,
+    /// How long a delivered order wait for the buyer before it is confirmed for them.
+    pub confirm_order_after: Duration,
+}
+
+impl Default for Schedule {
+    fn default() -> Self {
+        const DAY: u64 = 24 * 60 * 60;
+
+        Self {
+            interval: Duration::from_secs(60),
+            expire_order_after: Duration::from_secs(2 * DAY),
+            confirm_order_after: Duration::from_secs(3 * DAY),
+        }
+    }
+}
+
+impl Schedule {
+    /// Read `SCHEDULER_INTERVAL_SECS`, `ORDER_EXPIRE_AFTER_SECS` and
+    /// `ORDER_CONFIRM_AFTER_SECS`, falling back to [`Schedule::default`].
+    pub fn new_from_env() -> Self {
+        let secs = |name: &str| {
+            std::env::var(name).ok().map(|it| {
+                Duration::from_secs(
+                    it.parse()
+                        .unwrap_or_else(|_| panic!("{name} must be a number of seconds.")),
+                )
+            })
+        };
+
+        let default = Self::default();
+
+        Self {
+            interval: secs("SCHEDULER_INTERVAL_SECS").unwrap_or(default.interval),
+            expire_order_after: secs("ORDER_EXPIRE_AFTER_SECS")
+                .unwrap_or(default.expire_order_after),
+            confirm_order_after: secs("ORDER_CONFIRM_AFTER_SECS")
+                .unwrap_or(default.confirm_order_after),
+        }
+    }
+}
+
+/// Which instance may run the job `id` until `expires_at`.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct LeaseModel {
+    #[serde(rename = "_id")]
+    pub id: String,
+    pub holder: String,
+    pub expires_at: bson::DateTime,
+}
+
+#[derive(Clone)]
+pub struct LeaseCollection(pub Collection<LeaseModel>);
+
+impl std::ops::Deref for LeaseCollection {
+    type Target = Collection<LeaseModel>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.0
+    }
+}
+
+impl LeaseCollection {
+    /// Take or renew the lease on `job` for `ttl`. Returns `false` when another holder has
+    /// an unexpired lease on it.
+    pub async fn acquire(&self, job: &str, holder: &str, ttl: Duration) -> Result<bool, Error> {
+        let now = OffsetDateTime::now_utc();
+
+        let result = self
+            .find_one_and_update(
+                bson::doc! {
+                    "_id": job,
+                    "$or": [
+                        { "holder": holder },
+                        { "expires_at": { "$lte": bson::DateTime::from(now) } },
+                    ],
+                },
+                bson::doc! {
+                    "$set": {
+                        "holder": holder,
+                        "expires_at": bson::DateTime::from(now + ttl),
+                    }
+                },
+                mongodb::options::FindOneAndUpdateOptions::builder()
+                    .upsert(true)
+                    .build(),
+            )
+            .await;
+
+        match result {
+            Ok(_) => Ok(true),
+            // the lease exists but is held by someone else, so the upsert clash on `_id`.
+            Err(err) if is_duplicate_key(&err) => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Give the lease on `job` up early, so another instance doesn't wait for it to expire.
+    pub async fn release(&self, job: &str, holder: &str) -> Result<(), Error> {
+        self.update_one(
+            bson::doc! {
+                "_id": job,
+                "holder": holder,
+            },
+            bson::doc! {
+                "$set": {
+                    "expires_at": bson::DateTime::from(OffsetDateTime::now_utc()),
+                }
+            },
+            None,
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+impl AppState {
+    /// Spawn the scheduler on the current tokio runtime. It keeps running until the runtime
+    /// shut down. Webhooks are sent on their own task, so slow endpoints don't hold up the
+    /// orders.
+    pub fn start_scheduler(&self) -> tokio::task::JoinHandle<()> {
+        let this = self.clone();
+        let holder = bson::oid::ObjectId::new().to_hex();
+
+        let webhooks = this.clone();
+        let webhooks_holder = holder.clone();
+        tokio::spawn(async move {
+            let mut interval = webhooks.interval();
+
+            loop {
+                interval.tick().await;
+                webhooks
+                    .run_leased(
+                        "deliver_webhooks",
+                        &webhooks_holder,
+                        webhooks.deliver_webhooks(),
+                    )
+                    .await;
+            }
+        });
+
+        tokio::spawn(async move {
+            let mut interval = this.interval();
+
+            loop {
+                interval.tick().await;
+                this.run_leased("expire_orders", &holder, this.expire_orders())
+                    .await;
+                this.run_leased("confirm_orders", &holder, this.confirm_orders())
+                    .await;
+            }
+        })
+    }
+
+    fn interval(&self) -> tokio::time::Interval {
+        let mut interval = tokio::time::interval(self.schedule.interval);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        interval
+    }
+
+    /// Run `job` only when this instance hold its lease.
+    async fn run_leased(&self, job: &str, holder: &str, run: impl Future<Output = ()>) {
+        // long enough for a run to finish, short enough to recover from a dead instance.
+        let ttl = self.schedule.interval * 5;
+
+        match self.lease_collection.acquire(job, holder, ttl).await {
+            Ok(true) => {
+                run.await;
+
+                if let Err(err) = self.lease_collection.release(job, holder).await {
+                    tracing::warn!(job, ?err, "failed releasing lease");
+                }
+            }
+            Ok(false) => tracing::debug!(job, "lease held by another instance, skipping"),
+            Err(err) => tracing::error!(job, ?err, "failed acquiring lease"),
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn expire_orders(&self) {
+        let before = OffsetDateTime::now_utc() - self.schedule.expire_order_after;
+
+        match transaction::expire_stale(&ChangeStatusState::from_ref(self), before).await {
+            Ok(expired) => tracing::info!(expired, %before, "expired stale orders"),
+            Err(err) => tracing::error!(?err, "failed expiring stale orders"),
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn confirm_orders(&self) {
+        let before = OffsetDateTime::now_utc() - self.schedule.confirm_order_after;
+
+        match transaction::confirm_stale(&ChangeStatusState::from_ref(self), before).await {
+            Ok(confirmed) => tracing::info!(confirmed, %before, "confirmed delivered orders"),
+            Err(err) => tracing::error!(?err, "failed confirming delivered orders"),
+        }
+    }
+
+    #[tracing::instrument(skip_all)]
+    async fn deliver_webhooks(&self) {
+        let webhooks = webhook::Webhooks::from_ref(self);
+
+        match webhook::deliver_due(&webhooks, &self.http_client, OffsetDateTime::now_utc()).await {
+            Ok(delivered) => tracing::info!(delivered, "delivered webhooks"),
+            Err(err) => tracing::error!(?err, "failed delivering webhooks"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::api::v1::tests::bootstrap;
+
+    #[tokio::test]
+    async fn test_lease_held_by_one_instance() {
+        let bootstrap = bootstrap().await;
+        let leases = &bootstrap.app_state.lease_collection;
+        let ttl = Duration::from_secs(60);
+
+        assert!(leases.acquire("job", "first", ttl).await.unwrap());
+        assert!(!leases.acquire("job", "second", ttl).await.unwrap());
+        // the holder can renew its own lease.
+        assert!(leases.acquire("job", "first", ttl).await.unwrap());
+        // other jobs have their own lease.
+        assert!(leases.acquire("other", "second", ttl).await.unwrap());
+
+        leases.release("job", "second").await.unwrap();
+        assert!(!leases.acquire("job", "second", ttl).await.unwrap());
+
+        leases.release("job", "first").await.unwrap();
+        assert!(leases.acquire("job", "second", ttl).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_expired_lease_taken_over() {
+        let bootstrap = bootstrap().await;
+        let leases = &bootstrap.app_state.lease_collection;
+
+        assert!(leases.acquire("job", "dead", Duration::ZERO).await.unwrap());
+        assert!(leases
+            .acquire("job", "alive", Duration::from_secs(60))
+            .await
+            .unwrap());
+        assert!(!leases
+            .acquire("job", "dead", Duration::from_secs(60))
+            .await
+            .unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_acquire_single_winner() {
+        let bootstrap = bootstrap().await;
+
+        let mut set = tokio::task::JoinSet::new();
+        for it in 0..10 {
+            let leases = bootstrap.app_state.lease_collection.clone();
+            set.spawn(async move {
+                leases
+                    .acquire("job", &it.to_string(), Duration::from_secs(60))
+                    .await
+                    .unwrap()
+            });
+        }
+
+        let mut acquired = 0;
+        while let Some(it) = set.join_next().await {
+            if it.unwrap() {
+                acquired += 1;
+            }
+        }
+
+        assert_eq!(acquired, 1);
+    }
+}
@@ -0,0 +1,161 @@
+//! Where uploaded files are kept. [`LocalStorage`] keeps them on the local disk, another
+//! backend only has to implement [`Storage`] and be put in [`FileStorage`].
+
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::Arc,
+};
+
+use axum::http::StatusCode;
+
+use crate::error::Error;
+
+#[axum::async_trait]
+pub trait Storage: Send + Sync {
+    /// Save `content` under `key`, replacing what was saved there before.
+    async fn put(&self, key: &str, content: Vec<u8>) -> Result<(), Error>;
+
+    /// Returns `None` when nothing is saved under `key`.
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error>;
+
+    /// Does nothing when nothing is saved under `key`.
+    async fn delete(&self, key: &str) -> Result<(), Error>;
+}
+
+/// Storage used by the handlers.
+#[derive(Clone)]
+pub struct FileStorage(pub Arc<dyn Storage>);
+
+impl std::ops::Deref for FileStorage {
+    type Target = dyn Storage;
+
+    fn deref(&self) -> &Self::Target {
+        self.0.as_ref()
+    }
+}
+
+impl FileStorage {
+    /// Local storage in the `STORAGE_DIR` directory, `storage` by default.
+    pub fn new_from_env() -> Self {
+        let root = std::env::var("STORAGE_DIR").unwrap_or_else(|_| "storage".to_string());
+
+        Self(Arc::new(LocalStorage::new(root)))
+    }
+}
+
+/// Keep every file under `root`, a key is a `/` separated path relative to it.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// Path of `key`, which can't point outside of `root`.
+    fn path(&self, key: &str) -> Result<PathBuf, Error> {
+        let relative = Path::new(key);
+
+        let is_normal = relative
+            .components()
+            .all(|it| matches!(it, Component::Normal(..)));
+
+        if key.is_empty() || !is_normal {
+            return Err(Error::CustomStr(
+                StatusCode::UNPROCESSABLE_ENTITY,
+                "Invalid storage key",
+            ));
+        }
+
+        Ok(self.root.join(relative))
+    }
+}
+
+/// Log `err` with `key`, the client only gets a generic [`Error::StorageError`].
+fn storage_error(key: &str, err: std::io::Error) -> Error {
+    tracing::error!(key, ?err, "failed accessing local storage");
+    Error::StorageError
+}
+
+#[axum::async_trait]
+impl Storage for LocalStorage {
+    async fn put(&self, key: &str, content: Vec<u8>) -> Result<(), Error> {
+        let path = self.path(key)?;
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .map_err(|err| storage_error(key, err))?;
+        }
+
+        tokio::fs::write(path, content)
+            .await
+            .map_err(|err| storage_error(key, err))
+    }
+
+    async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+        match tokio::fs::read(self.path(key)?).await {
+            Ok(it) => Ok(Some(it)),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(storage_error(key, err)),
+        }
+    }
+
+    async fn delete(&self, key: &str) -> Result<(), Error> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(storage_error(key, err)),
+            _ => Ok(()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use assert_matches::assert_matches;
+
+    use crate::error::Error;
+
+    use super::{LocalStorage, Storage};
+
+    fn storage() -> LocalStorage {
+        LocalStorage::new(
+            std::env::temp_dir().join(format!("ecommerce-test-{}", bson::oid::ObjectId::new())),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_roundtrip() {
+        let storage = storage();
+
+        assert_eq!(storage.get("proofs/a/photo").await.unwrap(), None);
+
+        storage
+            .put("proofs/a/photo", b"photo".to_vec())
+            .await
+            .unwrap();
+        assert_eq!(
+            storage.get("proofs/a/photo").await.unwrap(),
+            Some(b"photo".to_vec())
+        );
+
+        storage.delete("proofs/a/photo").await.unwrap();
+        assert_eq!(storage.get("proofs/a/photo").await.unwrap(), None);
+        storage.delete("proofs/a/photo").await.unwrap();
+
+        tokio::fs::remove_dir_all(&storage.root).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_storage_stay_in_root() {
+        let storage = storage();
+
+        for key in ["", "../photo", "proofs/../../photo", "/etc/passwd"] {
+            let error = storage
+                .put(key, b"photo".to_vec())
+                .await
+                .expect_err("key must stay inside the root");
+            assert_matches!(error, Error::CustomStr(..));
+        }
+    }
+}
@@ -60,6 +60,12 @@ impl From<ObjectIdString> for bson::Bson {
 
 pub struct PathObjectId(pub ObjectId);
 
+impl From<ObjectIdString> for PathObjectId {
+    fn from(value: ObjectIdString) -> Self {
+        Self(value.0)
+    }
+}
+
 #[axum::async_trait]
 impl<S> axum::extract::FromRequestParts<S> for PathObjectId {
     type Rejection = crate::error::Error;
@@ -259,7 +265,87 @@ impl<'de> Deserialize<'de> for BigIntString {
     }
 }
 
-#[derive(Debug, Clone)]
+/// Store a [`BigInt`] as a bson `Int64` so it can be compared and `$inc`-ed inside a
+/// query. Use with `#[serde(with = "crate::util::bigint_i64")]`.
+///
+/// The old `[sign, [digits]]` representation is still accepted when reading.
+pub mod bigint_i64 {
+    use num_bigint::BigInt;
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub fn serialize<S>(value: &BigInt, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        let value = i64::try_from(value).map_err(serde::ser::Error::custom)?;
+        serializer.serialize_i64(value)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<BigInt, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Repr {
+            Int(i64),
+            Legacy(BigInt),
+        }
+
+        Ok(match Repr::deserialize(deserializer)? {
+            Repr::Int(it) => it.into(),
+            Repr::Legacy(it) => it,
+        })
+    }
+}
+
+/// Store a [`Decimal`] as a bson `Decimal128` so it can be compared and `$inc`-ed inside a
+/// query. Use with `#[serde(with = "crate::util::decimal128")]`.
+///
+/// The old string representation is still accepted when reading.
+pub mod decimal128 {
+    use std::str::FromStr;
+
+    use bson::{Bson, Decimal128};
+    use rust_decimal::Decimal;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+    /// `value` as it is stored, to be used in a query.
+    pub fn to_bson(value: &Decimal) -> Bson {
+        // every decimal fit in a Decimal128.
+        Bson::Decimal128(Decimal128::from_str(&value.to_string()).unwrap())
+    }
+
+    pub fn serialize<S>(value: &Decimal, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+    {
+        to_bson(value).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D>(deserializer: D) -> Result<Decimal, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let text = match Bson::deserialize(deserializer)? {
+            Bson::Decimal128(it) => it.to_string(),
+            Bson::String(it) => it,
+            Bson::Int32(it) => it.to_string(),
+            Bson::Int64(it) => it.to_string(),
+            it => {
+                return Err(serde::de::Error::custom(format!(
+                    "expected a decimal, found {it}"
+                )))
+            }
+        };
+
+        Decimal::from_str(&text)
+            .or_else(|_| Decimal::from_scientific(&text))
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub struct DecimalString(pub Decimal);
 
 impl From<Decimal> for DecimalString {